@@ -95,8 +95,9 @@ fn effective_path_diversity(
         for idx in 0..num {
             let mut div_min_path_i = f32::MAX;
             let mut alternate_paths = k_shortest_paths[0..num].to_vec();
-            // the base path should be the only item returned by drain
-            if let Some(base_path) = alternate_paths.drain(idx..idx + 1).last() {
+            // remove the base path so only the alternates remain
+            if idx < alternate_paths.len() {
+                let base_path = alternate_paths.remove(idx);
                 for path in alternate_paths {
                     let div = simlib::sim::Simulation::calculate_path_diversity(&base_path, &path);
                     div_min_path_i = f32::min(div_min_path_i, div);
@@ -164,7 +165,7 @@ mod tests {
         let routing_metric = RoutingMetric::MinFee;
         let path = std::path::Path::new("../test_data/trivial_connected.json");
         let graph = Graph::to_sim_graph(
-            &network_parser::Graph::from_json_file(&path, network_parser::GraphSource::Lnresearch)
+            &network_parser::Graph::from_json_file(path, network_parser::GraphSource::Lnresearch)
                 .unwrap(),
             network_parser::GraphSource::Lnresearch,
         );
@@ -187,7 +188,7 @@ mod tests {
         let lambdas = [0.5];
         let path = std::path::Path::new("../test_data/trivial_connected.json");
         let graph = Graph::to_sim_graph(
-            &network_parser::Graph::from_json_file(&path, network_parser::GraphSource::Lnresearch)
+            &network_parser::Graph::from_json_file(path, network_parser::GraphSource::Lnresearch)
                 .unwrap(),
             network_parser::GraphSource::Lnresearch,
         );
@@ -218,7 +219,7 @@ mod tests {
     fn calculate_graph_diversity() {
         let path = std::path::Path::new("../test_data/trivial_connected.json");
         let graph = Graph::to_sim_graph(
-            &network_parser::Graph::from_json_file(&path, network_parser::GraphSource::Lnresearch)
+            &network_parser::Graph::from_json_file(path, network_parser::GraphSource::Lnresearch)
                 .unwrap(),
             network_parser::GraphSource::Lnresearch,
         );
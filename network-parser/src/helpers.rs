@@ -53,6 +53,10 @@ pub struct LnresearchRawEdge {
     pub htlc_minimim_msat: Option<u64>,
     pub htlc_maximum_msat: Option<u64>,
     pub cltv_expiry_delta: Option<u64>,
+    /// Announced channel capacity, denominated in msat
+    pub capacity: Option<u64>,
+    /// Spendable balance of the source, denominated in msat
+    pub balance: Option<u64>,
 }
 
 #[derive(Deserialize, Debug, Clone, Default)]
@@ -65,6 +69,12 @@ pub struct LndRawEdge {
     #[serde(deserialize_with = "deserialize_option_number_from_string")]
     /// Denominated in sat
     pub capacity: Option<u64>,
+    /// Spendable balance of node1, denominated in sat
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub node1_balance: Option<u64>,
+    /// Spendable balance of node2, denominated in sat
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub node2_balance: Option<u64>,
     pub node1_policy: Option<NodePolicy>,
     pub node2_policy: Option<NodePolicy>,
 }
@@ -141,24 +151,19 @@ impl Node {
 impl Edge {
     /// We remove "orphaned" edges - edges where the source node is not in the list of nodes
     pub(crate) fn from_lnresearch_raw(raw_edge: &LnresearchRawEdge) -> Option<Edge> {
-        if raw_edge.fee_base_msat.is_none()
-            || raw_edge.fee_proportional_millionths.is_none()
-            || raw_edge.htlc_maximum_msat.is_none()
-        {
-            None
-        } else {
-            Some(Edge {
+        match (
+            raw_edge.fee_base_msat,
+            raw_edge.fee_proportional_millionths,
+            raw_edge.htlc_maximum_msat,
+        ) {
+            (Some(fee_base_msat), Some(fee_proportional_millionths), Some(_)) => Some(Edge {
                 channel_id: raw_edge.channel_id.clone().expect("scid not found"),
                 source: raw_edge.source.clone().unwrap_or_default(),
                 destination: raw_edge.destination.clone().unwrap_or_default(),
-                fee_base_msat: raw_edge
-                    .fee_base_msat
-                    .expect("Error in fee_base_msat field")
+                fee_base_msat: fee_base_msat
                     .try_into()
                     .expect("Error in fee_base_msat field"),
-                fee_proportional_millionths: raw_edge
-                    .fee_proportional_millionths
-                    .expect("Error in fee_proportional_millionths field")
+                fee_proportional_millionths: fee_proportional_millionths
                     .try_into()
                     .expect("Error in fee_proportional_millionths field"),
                 htlc_minimim_msat: raw_edge
@@ -176,10 +181,20 @@ impl Edge {
                     .unwrap_or_default()
                     .try_into()
                     .unwrap_or(usize::default()),
-                balance: 0,
+                balance: raw_edge
+                    .balance
+                    .unwrap_or_default()
+                    .try_into()
+                    .unwrap_or(usize::default()),
                 liquidity: 0,
-                capacity: 0,
-            })
+                capacity: raw_edge
+                    .capacity
+                    .unwrap_or_default()
+                    .try_into()
+                    .unwrap_or(usize::default()),
+                inbound_fee: 0,
+            }),
+            _ => None,
         }
     }
     /// We remove "orphaned" edges - edges where the source node is not in the list of nodes
@@ -257,7 +272,12 @@ impl Edge {
                         .unwrap_or_default()
                         .try_into()
                         .unwrap_or(usize::default()),
-                    balance: 0,
+                    balance: raw_edge
+                        .node1_balance
+                        .unwrap_or_default()
+                        .try_into()
+                        .unwrap_or(usize::default())
+                        * 1000,
                     liquidity: 0,
                     capacity: raw_edge
                         .capacity
@@ -265,6 +285,7 @@ impl Edge {
                         .try_into()
                         .unwrap_or(usize::default())
                         * 1000,
+                    inbound_fee: 0,
                 },
                 Edge {
                     channel_id: raw_edge.channel_id.clone().expect("scid not found"),
@@ -295,7 +316,12 @@ impl Edge {
                         .unwrap_or_default()
                         .try_into()
                         .unwrap_or(usize::default()),
-                    balance: 0,
+                    balance: raw_edge
+                        .node2_balance
+                        .unwrap_or_default()
+                        .try_into()
+                        .unwrap_or(usize::default())
+                        * 1000,
                     liquidity: 0,
                     capacity: raw_edge
                         .capacity
@@ -303,6 +329,7 @@ impl Edge {
                         .try_into()
                         .unwrap_or(usize::default())
                         * 1000,
+                    inbound_fee: 0,
                 },
             ))
         }
@@ -480,6 +507,7 @@ mod tests {
             balance: 0,
             liquidity: 0,
             capacity: 0,
+            inbound_fee: 0,
         }]);
         let actual = graph.edges.get("validnode").unwrap().clone();
         assert_eq!(expected, actual);
@@ -502,7 +530,7 @@ mod tests {
             ],
             "edges": []
             }"##;
-        let graph = Graph::from_lnd_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnd_json_str(json_str).unwrap();
         assert_eq!(graph.nodes.len(), 3);
     }
 
@@ -550,7 +578,7 @@ mod tests {
                 }
               ]
             }"##;
-        let graph = Graph::from_lnd_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnd_json_str(json_str).unwrap();
         let actual = graph.edge_count();
         let expected = 0;
         assert_eq!(expected, actual);
@@ -601,7 +629,7 @@ mod tests {
             ]
             }"##;
         let expected = 1000 * 1000;
-        let graph = Graph::from_lnd_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnd_json_str(json_str).unwrap();
         assert_eq!(graph.nodes.len(), 2);
         assert_eq!(graph.edges.len(), 2);
         for e in graph.get_edges_as_vec_vec().into_iter().flatten() {
@@ -643,7 +671,7 @@ mod tests {
             ],
             "edges": []
             }"##;
-        let graph = Graph::from_lnd_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnd_json_str(json_str).unwrap();
         assert_eq!(graph.nodes.len(), 3);
         let expected: HashMap<ID, Vec<Address>> = HashMap::from([
             (
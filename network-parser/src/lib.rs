@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
 use std::hash::{Hash, Hasher};
@@ -22,20 +22,20 @@ pub struct Graph {
     pub edges: HashMap<ID, HashSet<Edge>>,
 }
 
-#[derive(Deserialize, Clone, Debug, Default)]
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
 pub struct Node {
     pub id: ID,
     pub alias: String,
     pub addresses: Vec<Address>,
 }
 
-#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
 pub struct Address {
     pub network: String,
     pub addr: String,
 }
 
-#[derive(Deserialize, Clone, Debug, Default)]
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
 pub struct Edge {
     /// Short channel id
     pub channel_id: String,
@@ -61,6 +61,10 @@ pub struct Edge {
     /// channel capacity which is either calculated after graph creation as the min of the involved nodes'
     /// max msat or available in LND graph as sats
     pub capacity: usize,
+    /// Fee charged (positive) or discount granted (negative) by destination for receiving via this
+    /// channel
+    #[serde(default)]
+    pub inbound_fee: isize,
 }
 
 pub type ID = String;
@@ -374,6 +378,7 @@ mod tests {
                     balance: 0,
                     capacity: 0,
                     liquidity: 0,
+                    inbound_fee: 0,
                 },
                 Edge {
                     channel_id: "714116x477x0/0".to_string(),
@@ -390,6 +395,7 @@ mod tests {
                     balance: 0,
                     liquidity: 0,
                     capacity: 0,
+                    inbound_fee: 0,
                 },
             ]),
         )]);
@@ -513,6 +519,7 @@ mod tests {
                 balance: 0,
                 liquidity: 0,
                 capacity: 0,
+                inbound_fee: 0,
             },
             Edge {
                 channel_id: "714116x477x0/0".to_string(),
@@ -528,6 +535,7 @@ mod tests {
                 balance: 0,
                 liquidity: 0,
                 capacity: 0,
+                inbound_fee: 0,
             },
         ];
         for edge in expected {
@@ -553,7 +561,7 @@ mod tests {
             "adjacency": [
               ]
             }"##;
-        let graph = Graph::from_lnresearch_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnresearch_json_str(json_str).unwrap();
         let actual = graph.get_edges_for_node(
             &"021f0f2a5b46871b23f690a5be893f5b3ec37cf5a0fd8b89872234e984df35ea32".to_string(),
         );
@@ -627,7 +635,7 @@ mod tests {
                 ]
               ]
             }"##;
-        let graph = Graph::from_lnresearch_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnresearch_json_str(json_str).unwrap();
         let actual = graph.edge_count();
         let expected = 2;
         assert_eq!(actual, expected);
@@ -683,7 +691,7 @@ mod tests {
                 ]
               ]
             }"##;
-        let graph = Graph::from_lnresearch_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnresearch_json_str(json_str).unwrap();
         let actual = graph.edge_count();
         let expected = 0;
         assert_eq!(expected, actual);
@@ -694,7 +702,7 @@ mod tests {
         let path_to_file = Path::new("../test_data/trivial_connected.json");
         let graph = Graph::from_json_file(path_to_file, GraphSource::Lnresearch).unwrap();
         let actual = graph.get_node_ids();
-        let expected = vec!["025".to_owned(), "034".to_owned(), "036".to_owned()];
+        let expected = ["025".to_owned(), "034".to_owned(), "036".to_owned()];
         assert_eq!(actual.len(), expected.len());
         for id in actual {
             assert!(expected.contains(&id));
@@ -712,7 +720,7 @@ mod tests {
         assert!(actual.is_ok());
         let actual = actual.unwrap();
         assert_eq!(actual.len(), nodes.len());
-        let expected = vec!["036".to_owned(), "025".to_owned(), "034".to_owned()];
+        let expected = ["036".to_owned(), "025".to_owned(), "034".to_owned()];
         assert_eq!(actual.len(), expected.len());
         for id in actual {
             assert!(expected.contains(&id));
@@ -723,7 +731,7 @@ mod tests {
         assert!(actual.is_ok());
         let actual = actual.unwrap();
         assert_eq!(actual.len(), nodes.len());
-        let expected = vec!["036".to_owned(), "025".to_owned(), "034".to_owned()];
+        let expected = ["036".to_owned(), "025".to_owned(), "034".to_owned()];
         assert_eq!(actual.len(), expected.len());
         for id in actual {
             assert!(expected.contains(&id));
@@ -774,7 +782,7 @@ mod tests {
                 }
             ]
             }"##;
-        let graph = Graph::from_lnd_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnd_json_str(json_str).unwrap();
         assert_eq!(graph.nodes.len(), 2);
         assert_eq!(graph.edges.len(), 2);
     }
@@ -815,7 +823,7 @@ mod tests {
                 }
             ]
             }"##;
-        let graph = Graph::from_lnd_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnd_json_str(json_str).unwrap();
         assert_eq!(graph.nodes.len(), 2);
         assert_eq!(graph.edges.len(), 0);
     }
@@ -1,14 +1,161 @@
-use crate::payment::Payment;
+use crate::payment::{FailureReason, Payment};
 use crate::time::Time;
+use crate::{PaymentId, ShardId, ID};
 
 use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::collections::VecDeque;
 
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub enum PaymentEvent {
-    Scheduled { payment: Payment },
-    UpdateFailed { payment: Payment },
-    UpdateSuccesful { payment: Payment },
+    Scheduled {
+        payment: Payment,
+    },
+    UpdateFailed {
+        payment: Payment,
+    },
+    UpdateSuccesful {
+        payment: Payment,
+    },
+    /// The sender gave up on the payment, e.g. because of its deadline, before the network ran
+    /// out of routes. Carries why and how many parts had arrived by then
+    Abandoned {
+        payment: Payment,
+        reason: FailureReason,
+        parts_delivered: usize,
+    },
+    /// A part of an MPP reached the destination. Carries the part's amount and the channels it
+    /// used
+    ShardSuccess {
+        shard_id: ShardId,
+        amount: usize,
+        channels: Vec<String>,
+    },
+    /// A part of an MPP could not be delivered. Carries the part's amount and the channels of the
+    /// last path it tried, if any
+    ShardFailed {
+        shard_id: ShardId,
+        amount: usize,
+        channels: Vec<String>,
+    },
+    /// Held HTLCs of the payment time out and the liquidity they locked (channel_id, amount)
+    /// is freed
+    HtlcTimeout {
+        payment_id: PaymentId,
+        reservations: Vec<(String, usize)>,
+    },
+    /// The node pays itself to move liquidity from one of its channels to another
+    Rebalance {
+        node: ID,
+        out_channel: String,
+        in_channel: String,
+        amount: usize,
+    },
+    /// A channel of capacity msat opens between the nodes, balance_a of it on node_a's side
+    ChannelOpen {
+        node_a: ID,
+        node_b: ID,
+        capacity: usize,
+        balance_a: usize,
+    },
+    /// Both directions of the channel close
+    ChannelClose {
+        channel_id: String,
+    },
+}
+
+/// An event that has been processed, kept to reconstruct when payments and their shards resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimelineEntry {
+    pub time: Time,
+    /// None for events not tied to a single payment
+    pub payment_id: Option<PaymentId>,
+    /// Channels of the shards or paths the event is about
+    pub channels: Vec<String>,
+    pub kind: &'static str,
+    /// Metadata of the payment the event is about, empty for the events of shards
+    pub metadata: HashMap<String, String>,
+}
+
+impl PaymentEvent {
+    /// Short name of the event's variant
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PaymentEvent::Scheduled { .. } => "scheduled",
+            PaymentEvent::UpdateFailed { .. } => "payment_failed",
+            PaymentEvent::UpdateSuccesful { .. } => "payment_succeeded",
+            PaymentEvent::Abandoned { .. } => "payment_abandoned",
+            PaymentEvent::ShardSuccess { .. } => "shard_succeeded",
+            PaymentEvent::ShardFailed { .. } => "shard_failed",
+            PaymentEvent::HtlcTimeout { .. } => "htlc_timeout",
+            PaymentEvent::Rebalance { .. } => "rebalance",
+            PaymentEvent::ChannelOpen { .. } => "channel_opened",
+            PaymentEvent::ChannelClose { .. } => "channel_closed",
+        }
+    }
+
+    /// The payment the event is about, None for rebalances and channel events
+    pub fn payment_id(&self) -> Option<PaymentId> {
+        match self {
+            PaymentEvent::Scheduled { payment }
+            | PaymentEvent::UpdateFailed { payment }
+            | PaymentEvent::UpdateSuccesful { payment }
+            | PaymentEvent::Abandoned { payment, .. } => Some(payment.payment_id),
+            PaymentEvent::ShardSuccess { shard_id, .. }
+            | PaymentEvent::ShardFailed { shard_id, .. } => Some(shard_id.0),
+            PaymentEvent::HtlcTimeout { payment_id, .. } => Some(*payment_id),
+            PaymentEvent::Rebalance { .. }
+            | PaymentEvent::ChannelOpen { .. }
+            | PaymentEvent::ChannelClose { .. } => None,
+        }
+    }
+
+    /// Channels the event is about
+    pub(crate) fn channels(&self) -> Vec<String> {
+        let path_channels = |payment: &Payment| -> Vec<String> {
+            payment
+                .used_paths
+                .iter()
+                .flat_map(|p| p.path.hops.iter().map(|h| h.3.clone()))
+                .collect()
+        };
+        match self {
+            PaymentEvent::Scheduled { .. }
+            | PaymentEvent::UpdateFailed { .. }
+            | PaymentEvent::Abandoned { .. } => vec![],
+            PaymentEvent::UpdateSuccesful { payment } => path_channels(payment),
+            PaymentEvent::ShardSuccess { channels, .. }
+            | PaymentEvent::ShardFailed { channels, .. } => channels.clone(),
+            PaymentEvent::HtlcTimeout { reservations, .. } => {
+                reservations.iter().map(|(c, _)| c.clone()).collect()
+            }
+            PaymentEvent::Rebalance {
+                out_channel,
+                in_channel,
+                ..
+            } => vec![out_channel.clone(), in_channel.clone()],
+            // the ids of an opening channel are only known once it opened
+            PaymentEvent::ChannelOpen { .. } => vec![],
+            PaymentEvent::ChannelClose { channel_id } => vec![channel_id.clone()],
+        }
+    }
+
+    pub(crate) fn to_timeline_entry(&self, time: Time) -> TimelineEntry {
+        let metadata = match self {
+            PaymentEvent::Scheduled { payment }
+            | PaymentEvent::UpdateFailed { payment }
+            | PaymentEvent::UpdateSuccesful { payment }
+            | PaymentEvent::Abandoned { payment, .. } => payment.metadata.clone(),
+            _ => HashMap::default(),
+        };
+        TimelineEntry {
+            time,
+            payment_id: self.payment_id(),
+            channels: self.channels(),
+            kind: self.kind(),
+            metadata,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -63,6 +210,57 @@ impl EventQueue {
         result
     }
 
+    /// Removes the pending shard successes of the payment, e.g. once it failed and the parts
+    /// that arrived have been reverted. Returns how many were removed
+    pub(crate) fn cancel_shard_successes(&mut self, id: PaymentId) -> usize {
+        let mut cancelled = 0;
+        for event_list in self.events.values_mut() {
+            let before = event_list.len();
+            event_list.retain(|event| {
+                !matches!(event, PaymentEvent::ShardSuccess { shard_id, .. } if shard_id.0 == id)
+            });
+            cancelled += before - event_list.len();
+        }
+        self.events.retain(|_, event_list| !event_list.is_empty());
+        cancelled
+    }
+
+    /// Time at which the last of the pending events resolving HTLCs over any of the channels is
+    /// due. None if there is no such event
+    pub(crate) fn last_resolution(&self, channel_ids: &[String]) -> Option<Time> {
+        self.events
+            .iter()
+            .rev()
+            .find(|(_, event_list)| {
+                event_list.iter().any(|event| {
+                    matches!(
+                        event,
+                        PaymentEvent::ShardSuccess { .. }
+                            | PaymentEvent::HtlcTimeout { .. }
+                            | PaymentEvent::UpdateSuccesful { .. }
+                    ) && event
+                        .channels()
+                        .iter()
+                        .any(|channel_id| channel_ids.contains(channel_id))
+                })
+            })
+            .map(|(time, _)| *time)
+    }
+
+    /// Removes the success event of the shard. False if there is none
+    pub(crate) fn cancel_shard_success(&mut self, id: ShardId) -> bool {
+        let mut cancelled = false;
+        for event_list in self.events.values_mut() {
+            let before = event_list.len();
+            event_list.retain(|event| {
+                !matches!(event, PaymentEvent::ShardSuccess { shard_id, .. } if *shard_id == id)
+            });
+            cancelled |= event_list.len() < before;
+        }
+        self.events.retain(|_, event_list| !event_list.is_empty());
+        cancelled
+    }
+
     pub(crate) fn now(&self) -> Time {
         self.last_tick
     }
@@ -180,7 +378,7 @@ mod tests {
         };
         let mut times = Vec::new();
         for _ in 1..100 {
-            let rand_time: f32 = rng.gen_range(0.0..u64::max_value() as f32) / 1000.0;
+            let rand_time: f32 = rng.gen_range(0.0..u64::MAX as f32) / 1000.0;
             times.push(rand_time);
 
             let t = Time::from_millis(rand_time);
@@ -5,14 +5,56 @@ use itertools::Itertools;
 use log::{debug, info, warn};
 use pathfinding::directed::strongly_connected_components::strongly_connected_components;
 use rand::{seq::SliceRandom, Rng};
-use serde::Deserialize;
-use std::{cmp, collections::HashMap};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    error::Error,
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    io::{BufReader, BufWriter},
+    path::Path,
+};
 
-#[derive(Clone, Deserialize, Debug)]
+/// Synthetic graphs for experiments that do not need a real snapshot
+pub mod generators;
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct Graph {
     pub(crate) nodes: Vec<Node>,
     #[serde(rename = "adjacency")]
     pub(crate) edges: HashMap<ID, Vec<Edge>>,
+    /// Amounts committed to each channel by shards that have not been resolved yet
+    /// Map of <channel_id, amount>
+    #[serde(skip)]
+    pub(crate) in_flight: HashMap<String, usize>,
+    /// Number of successful forwards and the msat forwarded per channel
+    /// Map of <channel_id, (count, volume)>
+    #[serde(skip)]
+    pub(crate) usage: HashMap<String, (u64, u64)>,
+    /// Unannounced channels. Pathfinding only uses them when an invoice hints at them
+    #[serde(default)]
+    pub(crate) private_channels: HashSet<String>,
+}
+
+/// An anomaly in a graph, usually caused by incomplete or inconsistent imported data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum GraphWarning {
+    /// A node without any channels
+    IsolatedNode(ID),
+    ZeroCapacity {
+        channel_id: String,
+    },
+    BalanceExceedsCapacity {
+        channel_id: String,
+        balance: usize,
+        capacity: usize,
+    },
+    /// Neither a timelock delta nor a max HTLC amount, so the source most likely never announced
+    /// a policy and the fees are unknown
+    MissingFeePolicy {
+        channel_id: String,
+    },
 }
 
 impl Graph {
@@ -28,13 +70,85 @@ impl Graph {
             .into_iter()
             .map(|(id, edge)| (id, Vec::from_iter(edge)))
             .collect();
-        let graph = Graph { nodes, edges };
+        let graph = Graph {
+            nodes,
+            edges,
+            in_flight: HashMap::default(),
+            usage: HashMap::default(),
+            private_channels: HashSet::default(),
+        }
+        .remove_self_loops();
         let greatest_scc = graph.reduce_to_greatest_scc();
         let mut greatest_scc = greatest_scc.remove_unidrectional_edges();
         greatest_scc.set_channel_balances(graph_source);
         greatest_scc
     }
 
+    /// Writes an already transformed graph to a compact binary snapshot
+    pub fn save_bincode(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let writer = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(writer, self)?;
+        info!("Graph snapshot written to {:?}.", path);
+        Ok(())
+    }
+
+    /// Reads a graph previously written using save_bincode
+    /// The graph is used as is, i.e. no SCC reduction or balance assignment takes place
+    pub fn load_bincode(path: &Path) -> Result<Graph, Box<dyn Error>> {
+        let reader = BufReader::new(File::open(path)?);
+        let graph: Graph = bincode::deserialize_from(reader)?;
+        info!(
+            "Loaded graph snapshot with {} nodes and {} edges from {:?}.",
+            graph.node_count(),
+            graph.edge_count(),
+            path
+        );
+        Ok(graph)
+    }
+
+    /// Replaces the fees of the outgoing edges of the nodes listed in a CSV file with columns
+    /// node_id,base_fee,fee_rate_ppm. Nodes that are not listed keep their fees
+    pub fn apply_fee_overrides(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        let mut overrides = HashMap::new();
+        for (idx, line) in contents.lines().enumerate() {
+            // the header is optional
+            if line.trim().is_empty() || (idx == 0 && line.trim_start().starts_with("node_id")) {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+            if fields.len() != 3 {
+                return Err(format!(
+                    "invalid fee override on line {}: expected 3 columns, got {}",
+                    idx + 1,
+                    fields.len()
+                )
+                .into());
+            }
+            overrides.insert(
+                fields[0].to_string(),
+                (fields[1].parse::<usize>()?, fields[2].parse::<usize>()?),
+            );
+        }
+        for (node, (base_fee, fee_rate)) in overrides.iter() {
+            match self.edges.get_mut(node) {
+                Some(edges) => {
+                    for edge in edges.iter_mut() {
+                        edge.fee_base_msat = *base_fee;
+                        edge.fee_proportional_millionths = *fee_rate;
+                    }
+                }
+                None => warn!("Fee override for unknown node {}.", node),
+            }
+        }
+        info!(
+            "Applied fee overrides for {} nodes from {:?}.",
+            overrides.len(),
+            path
+        );
+        Ok(())
+    }
+
     fn reduce_to_greatest_scc(&self) -> Graph {
         info!(
             "Reducing graph with {} nodes and {} edges to greatest SCC.",
@@ -66,6 +180,9 @@ impl Graph {
         let g = Graph {
             nodes: greatest_scc_nodes,
             edges: greatest_scc_edges,
+            in_flight: HashMap::default(),
+            usage: HashMap::default(),
+            private_channels: HashSet::default(),
         };
         info!(
             "Reduced to graph with {} nodes and {} edges.",
@@ -82,6 +199,89 @@ impl Graph {
         self.edges.clone().into_values().map(|v| v.len()).sum()
     }
 
+    /// Gini coefficient of the balances over all edges. 0 if liquidity is spread evenly and close
+    /// to 1 if it sits on a single edge
+    pub fn balance_gini(&self) -> f64 {
+        let mut balances: Vec<f64> = self
+            .edges
+            .values()
+            .flatten()
+            .map(|e| e.balance as f64)
+            .collect();
+        let n = balances.len() as f64;
+        let total: f64 = balances.iter().sum();
+        if balances.is_empty() || total == 0.0 {
+            return 0.0;
+        }
+        balances.sort_by(|a, b| a.total_cmp(b));
+        let weighted: f64 = balances
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (i + 1) as f64 * b)
+            .sum();
+        2.0 * weighted / (n * total) - (n + 1.0) / n
+    }
+
+    /// Hex digest of the nodes and the channels with their policies and balances, independent
+    /// of the order they are stored in. Equal graphs have equal hashes within a build of the
+    /// simulator
+    pub fn content_hash(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        let mut node_ids = self.get_node_ids();
+        node_ids.sort();
+        node_ids.hash(&mut hasher);
+        let mut edges: Vec<&Edge> = self.edges.values().flatten().collect();
+        edges.sort_by(|a, b| (&a.source, &a.channel_id).cmp(&(&b.source, &b.channel_id)));
+        for e in edges {
+            (
+                &e.channel_id,
+                &e.source,
+                &e.destination,
+                e.fee_base_msat,
+                e.fee_proportional_millionths,
+                e.htlc_maximum_msat,
+                e.cltv_expiry_delta,
+                e.balance,
+                e.capacity,
+                e.inbound_fee,
+            )
+                .hash(&mut hasher);
+        }
+        let mut private_channels: Vec<&String> = self.private_channels.iter().collect();
+        private_channels.sort();
+        private_channels.hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    /// Pearson correlation of the numbers of channels of the nodes at either end of each channel
+    /// Positive if well-connected nodes tend to have channels with each other, negative if they
+    /// connect to the periphery. 0 if all nodes have the same number of channels
+    pub fn degree_assortativity(&self) -> f64 {
+        let degree = |node: &ID| self.edges.get(node).map_or(0, |edges| edges.len()) as f64;
+        // each channel is counted in both directions, which makes the correlation symmetric
+        let pairs: Vec<(f64, f64)> = self
+            .edges
+            .values()
+            .flatten()
+            .map(|e| (degree(&e.source), degree(&e.destination)))
+            .collect();
+        if pairs.is_empty() {
+            return 0.0;
+        }
+        let n = pairs.len() as f64;
+        let (mean_x, mean_y) = (
+            pairs.iter().map(|(x, _)| x).sum::<f64>() / n,
+            pairs.iter().map(|(_, y)| y).sum::<f64>() / n,
+        );
+        let covariance: f64 = pairs.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+        let variance_x: f64 = pairs.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+        let variance_y: f64 = pairs.iter().map(|(_, y)| (y - mean_y).powi(2)).sum();
+        if variance_x == 0.0 || variance_y == 0.0 {
+            return 0.0;
+        }
+        covariance / (variance_x * variance_y).sqrt()
+    }
+
     pub fn get_node_ids(&self) -> Vec<ID> {
         self.nodes.iter().map(|n| n.id.clone()).collect()
     }
@@ -133,6 +333,195 @@ impl Graph {
         }
     }
 
+    /// Opens an unannounced channel. Each side is given as (node, channel_id, balance) where the
+    /// channel_id names the node's direction of the channel
+    pub fn add_private_channel(&mut self, side1: (&ID, &str, usize), side2: (&ID, &str, usize)) {
+        self.add_channel(side1, side2, true);
+    }
+
+    /// Opens a public channel with the given balance on either side. Its channel_ids are
+    /// "node1-node2" and "node2-node1", numbered if the nodes already have a channel by that name
+    /// Returns node1's and node2's channel_id
+    pub fn open_channel(
+        &mut self,
+        node1: &ID,
+        node2: &ID,
+        balance1: usize,
+        balance2: usize,
+    ) -> (String, String) {
+        let taken = |graph: &Self, channel_id: &str| {
+            graph
+                .edges
+                .values()
+                .flatten()
+                .any(|e| e.channel_id == channel_id)
+        };
+        let mut ids = (
+            format!("{}-{}", node1, node2),
+            format!("{}-{}", node2, node1),
+        );
+        let mut num = 1;
+        while taken(self, &ids.0) || taken(self, &ids.1) {
+            ids = (
+                format!("{}-{}-{}", node1, node2, num),
+                format!("{}-{}-{}", node2, node1, num),
+            );
+            num += 1;
+        }
+        self.add_channel((node1, &ids.0, balance1), (node2, &ids.1, balance2), false);
+        ids
+    }
+
+    /// The ids of both sides of the channel, starting with the given one. Empty if the channel
+    /// is not in the graph
+    pub(crate) fn channel_sides(&self, channel_id: &str) -> Vec<String> {
+        let Some(edge) = self
+            .edges
+            .values()
+            .flatten()
+            .find(|e| e.channel_id == channel_id)
+        else {
+            return vec![];
+        };
+        std::iter::once(edge.channel_id.clone())
+            .chain(self.counterpart(edge).map(|e| e.channel_id))
+            .collect()
+    }
+
+    /// Closes the channel the channel_id is one direction of, along with its counterpart.
+    /// Returns the channel_ids that were removed, none if there is no such channel
+    pub fn close_channel(&mut self, channel_id: &str) -> Vec<String> {
+        let closed = self.channel_sides(channel_id);
+        for channel_id in closed.iter() {
+            self.remove_channel(channel_id);
+            self.private_channels.remove(channel_id);
+        }
+        closed
+    }
+
+    fn add_channel(
+        &mut self,
+        (node1, channel1, balance1): (&ID, &str, usize),
+        (node2, channel2, balance2): (&ID, &str, usize),
+        private: bool,
+    ) {
+        for node in [node1, node2] {
+            if !self.nodes.iter().any(|n| n.id == *node) {
+                self.nodes.push(Node {
+                    id: node.clone(),
+                    ..Default::default()
+                });
+            }
+        }
+        let capacity = balance1 + balance2;
+        for (src, dest, channel_id, balance) in [
+            (node1, node2, channel1, balance1),
+            (node2, node1, channel2, balance2),
+        ] {
+            let edge = Edge {
+                channel_id: channel_id.to_string(),
+                source: src.clone(),
+                destination: dest.clone(),
+                htlc_maximum_msat: capacity,
+                balance,
+                liquidity: balance,
+                capacity,
+                ..Default::default()
+            };
+            self.edges.entry(src.clone()).or_default().push(edge);
+            if private {
+                self.private_channels.insert(channel_id.to_string());
+            }
+        }
+    }
+
+    /// Adds the nodes and channels of other, e.g. private channels on top of a public snapshot.
+    /// Channels both graphs have take their balances and fees from other
+    pub fn merge(&mut self, other: &Graph) {
+        for node in other.nodes.iter() {
+            if !self.nodes.iter().any(|n| n.id == node.id) {
+                self.nodes.push(node.clone());
+            }
+        }
+        let (mut num_added, mut num_updated) = (0, 0);
+        for edge in other.edges.values().flatten() {
+            let edges = self.edges.entry(edge.source.clone()).or_default();
+            match edges.iter_mut().find(|e| e.channel_id == edge.channel_id) {
+                Some(existing) => {
+                    *existing = edge.clone();
+                    num_updated += 1;
+                }
+                None => {
+                    edges.push(edge.clone());
+                    num_added += 1;
+                }
+            }
+        }
+        self.private_channels
+            .extend(other.private_channels.iter().cloned());
+        info!(
+            "Merged graph with {} new and {} updated edges.",
+            num_added, num_updated
+        );
+    }
+
+    /// Checks the graph for anomalies that hint at problems with the imported data
+    /// Warnings about channels are ordered by channel id
+    pub fn validate(&self) -> Vec<GraphWarning> {
+        let mut warnings = vec![];
+        let connected: HashSet<&ID> = self
+            .edges
+            .values()
+            .flatten()
+            .flat_map(|e| [&e.source, &e.destination])
+            .collect();
+        for node in self.nodes.iter() {
+            if !connected.contains(&node.id) {
+                warnings.push(GraphWarning::IsolatedNode(node.id.clone()));
+            }
+        }
+        for edge in self
+            .edges
+            .values()
+            .flatten()
+            .sorted_by(|a, b| a.channel_id.cmp(&b.channel_id))
+        {
+            let channel_id = edge.channel_id.clone();
+            if edge.capacity == 0 {
+                warnings.push(GraphWarning::ZeroCapacity {
+                    channel_id: channel_id.clone(),
+                });
+            }
+            if edge.balance > edge.capacity {
+                warnings.push(GraphWarning::BalanceExceedsCapacity {
+                    channel_id: channel_id.clone(),
+                    balance: edge.balance,
+                    capacity: edge.capacity,
+                });
+            }
+            if edge.cltv_expiry_delta == 0 && edge.htlc_maximum_msat == 0 {
+                warnings.push(GraphWarning::MissingFeePolicy { channel_id });
+            }
+        }
+        if !warnings.is_empty() {
+            warn!("Graph validation found {} anomalies.", warnings.len());
+        }
+        warnings
+    }
+
+    /// Removes the private channels except for the given ones
+    pub(crate) fn hide_private_channels(&mut self, except: &[String]) {
+        let hidden: Vec<String> = self
+            .private_channels
+            .iter()
+            .filter(|c| !except.contains(c))
+            .cloned()
+            .collect();
+        for channel_id in hidden.iter() {
+            self.remove_channel(channel_id);
+        }
+    }
+
     /// Discard the given node and its edges from the graph
     pub fn remove_node(&mut self, node: &ID) {
         self.nodes.retain(|n| *n.id != *node);
@@ -171,12 +560,54 @@ impl Graph {
             .unwrap_or_else(|| 0)
     }
 
+    /// Commits amount to the channel until the shard using it has been resolved
+    pub(crate) fn reserve_in_flight(&mut self, channel_id: &ID, amount: usize) {
+        *self.in_flight.entry(channel_id.clone()).or_insert(0) += amount;
+    }
+
+    /// Frees a previous reservation once the shard has been resolved
+    pub(crate) fn release_in_flight(&mut self, channel_id: &ID, amount: usize) {
+        if let Some(reserved) = self.in_flight.get_mut(channel_id) {
+            *reserved = reserved.saturating_sub(amount);
+            if *reserved == 0 {
+                self.in_flight.remove(channel_id);
+            }
+        }
+    }
+
+    /// Notes that a shard that has been delivered was forwarded over the channel
+    pub(crate) fn record_forward(&mut self, channel_id: &str, amount: usize) {
+        let (count, volume) = self.usage.entry(channel_id.to_string()).or_default();
+        *count += 1;
+        *volume = volume.saturating_add(amount as u64);
+    }
+
+    /// Number of delivered shards and msat each channel forwarded. Shards of payments that failed
+    /// are not counted
+    pub fn channel_usage(&self) -> HashMap<String, (u64, u64)> {
+        self.usage.clone()
+    }
+
+    /// The amount currently committed to the channel by unresolved shards
+    pub(crate) fn get_in_flight(&self, channel_id: &ID) -> usize {
+        self.in_flight.get(channel_id).copied().unwrap_or(0)
+    }
+
+    /// The channel's balance minus what is already in flight
+    pub(crate) fn get_available_balance(&self, edge: &Edge) -> usize {
+        edge.balance
+            .saturating_sub(self.get_in_flight(&edge.channel_id))
+    }
+
     /// True if the channel's balance after transferring the amount will not exceed the channel capacity
     pub(crate) fn channel_can_receive_amount(&self, channel_id: &ID, amount: usize) -> bool {
         for edges in self.get_edges().values() {
             for edge in edges {
                 if edge.channel_id.eq_ignore_ascii_case(channel_id) {
-                    return edge.capacity > (edge.balance + amount);
+                    return edge
+                        .balance
+                        .checked_add(amount)
+                        .is_some_and(|balance| edge.capacity > balance);
                 }
             }
         }
@@ -192,27 +623,99 @@ impl Graph {
         max_balance.unwrap_or(0)
     }
 
+    /// Sum of the balances of all edges. Payments only move liquidity between the two ends of
+    /// the channels they use so the total stays the same
+    pub fn total_liquidity(&self) -> usize {
+        self.edges
+            .values()
+            .flatten()
+            .fold(0, |total, e| total.saturating_add(e.balance))
+    }
+
     pub(crate) fn get_total_node_balance(&self, node: &ID) -> usize {
-        self.get_outedges(node).iter().map(|e| e.balance).sum()
+        self.get_outedges(node)
+            .iter()
+            .fold(0, |total, e| total.saturating_add(e.balance))
+    }
+
+    /// Sum of the balances of the node's channels from whose peer dest can be reached without
+    /// going back through the node. Intermediaries only need enough balance for each part they
+    /// forward, so this bounds how much the node can still deliver to dest in total
+    pub(crate) fn reachable_liquidity(&self, node: &ID, dest: &ID) -> usize {
+        let successors = |n: &ID| -> Vec<ID> {
+            self.get_outedges(n)
+                .into_iter()
+                .filter(|e| e.balance > 0 && e.destination != *node)
+                .map(|e| e.destination)
+                .collect()
+        };
+        self.get_outedges(node)
+            .iter()
+            .filter(|e| {
+                e.destination == *dest
+                    || pathfinding::prelude::bfs(&e.destination, successors, |n| n == dest)
+                        .is_some()
+            })
+            .fold(0, |total, e| total.saturating_add(e.balance))
     }
 
     // Get all edges going to 'node' then check how much of the channel capacity is already with
     // 'node'.
     pub(crate) fn get_max_receive_amount(&self, node: &ID) -> usize {
-        let mut max_receive = 0;
+        let mut max_receive: usize = 0;
         for n in self.get_node_ids() {
             if n != *node {
                 let edges_to_node = self.get_all_src_dest_edges(&n, node);
                 for e in edges_to_node {
-                    max_receive += e.capacity - e.balance;
+                    max_receive = max_receive.saturating_add(e.capacity - e.balance);
                 }
             }
         }
         max_receive
     }
 
-    /// We calculate balances based on the edges' max_sat values using a random uniform
-    /// distribution. We set the liquidity to the calculated balance
+    /// Most the node can receive as an MPP of up to max_parts parts. Every part arrives over one
+    /// of the channels into the node and carries no more than the channel's htlc_maximum_msat,
+    /// so a few large channels may not be enough even though the total inbound would be
+    pub fn mpp_receive_capacity(&self, dest: &str, max_parts: usize) -> u64 {
+        let dest = dest.to_string();
+        // (amount the channel can still take, largest part it carries) of every channel into dest
+        let mut channels: Vec<(usize, usize)> = self
+            .get_node_ids()
+            .iter()
+            .filter(|n| **n != dest)
+            .flat_map(|n| self.get_all_src_dest_edges(n, &dest))
+            .map(|e| {
+                let room = e.capacity.saturating_sub(e.balance);
+                match e.htlc_maximum_msat {
+                    0 => (room, room),
+                    htlc_maximum_msat => (room, room.min(htlc_maximum_msat)),
+                }
+            })
+            .collect();
+        let mut capacity: usize = 0;
+        // each part goes where it adds the most
+        for _ in 0..max_parts {
+            let Some(channel) = channels
+                .iter_mut()
+                .max_by_key(|(room, largest_part)| *room.min(largest_part))
+            else {
+                break;
+            };
+            let part = channel.0.min(channel.1);
+            if part == 0 {
+                break;
+            }
+            channel.0 -= part;
+            capacity = capacity.saturating_add(part);
+        }
+        capacity as u64
+    }
+
+    /// Capacities announced by the graph source are used as is, otherwise the channel capacity is
+    /// assumed to be the lower htlc_maximum_msat value. Balances read from the graph source are
+    /// kept, missing ones are a random uniform fraction of the capacity. We set the liquidity to
+    /// the balance
     fn set_channel_balances(&mut self, graph_source: network_parser::GraphSource) {
         info!("Calculating channel balances.");
         // hm
@@ -220,39 +723,58 @@ impl Graph {
         let mut rng = crate::RNG.lock().unwrap();
         for (src, edges) in self.edges.iter_mut() {
             for out_edge in edges.iter_mut() {
-                // means we haven't visited the edge before; might break if htlc_maximum_msat == 0
-                if out_edge.balance == usize::default() {
-                    // Channel capacity is assumed to be the lower htlc_maximum_msat value
-                    if let Some(mut reverse_edge) = graph_copy.get_edge(&out_edge.destination, src)
-                    {
-                        let src_capacity_dist: f32 = rng.gen();
-                        let capacity = match graph_source {
-                            network_parser::GraphSource::Lnresearch => {
-                                let max_src_htlc = &out_edge.htlc_maximum_msat;
-                                let max_dest_htlc = reverse_edge.htlc_maximum_msat;
-                                *cmp::min(max_src_htlc, &max_dest_htlc) as f32
-                            }
-                            network_parser::GraphSource::Lnd =>
-                            // should not be necessary since the library ensures both edges are
-                            // there
-                            {
-                                cmp::min(out_edge.capacity, reverse_edge.capacity) as f32
+                if let Some(reverse_edge) = graph_copy.get_edge(&out_edge.destination, src) {
+                    let src_capacity_dist: f32 = rng.gen();
+                    let capacity = match graph_source {
+                        network_parser::GraphSource::Lnresearch => {
+                            match (out_edge.capacity, reverse_edge.capacity) {
+                                (0, 0) => cmp::min(
+                                    out_edge.htlc_maximum_msat,
+                                    reverse_edge.htlc_maximum_msat,
+                                ),
+                                (0, announced) | (announced, 0) => announced,
+                                (src_capacity, dest_capacity) => {
+                                    cmp::min(src_capacity, dest_capacity)
+                                }
                             }
-                        };
-                        out_edge.capacity = capacity as usize;
-                        reverse_edge.capacity = capacity as usize;
-                        let src_balance = (src_capacity_dist * capacity).round();
-                        let dest_balance = capacity - src_balance;
-                        reverse_edge.balance = dest_balance as usize;
-                        reverse_edge.liquidity = reverse_edge.balance;
-                        out_edge.balance = src_balance as usize;
-                        out_edge.liquidity = out_edge.balance;
-                    }
+                        }
+                        network_parser::GraphSource::Lnd =>
+                        // should not be necessary since the library ensures both edges are
+                        // there
+                        {
+                            cmp::min(out_edge.capacity, reverse_edge.capacity)
+                        }
+                    };
+                    let src_balance = if out_edge.balance != usize::default() {
+                        cmp::min(out_edge.balance, capacity)
+                    } else if reverse_edge.balance != usize::default() {
+                        capacity.saturating_sub(reverse_edge.balance)
+                    } else {
+                        (src_capacity_dist * capacity as f32).round() as usize
+                    };
+                    out_edge.capacity = capacity;
+                    out_edge.balance = src_balance;
+                    out_edge.liquidity = out_edge.balance;
                 }
             }
         }
     }
 
+    /// Channels from a node to itself cannot be used for routing and are dropped
+    /// Parallel edges are kept since they are distinct channels identified by their channel id
+    fn remove_self_loops(mut self) -> Self {
+        let mut num_removed = 0;
+        for (src, edges) in self.edges.iter_mut() {
+            let num_edges = edges.len();
+            edges.retain(|edge| edge.destination != *src);
+            num_removed += num_edges - edges.len();
+        }
+        if num_removed > 0 {
+            warn!("Removed {} self-loops from graph.", num_removed);
+        }
+        self
+    }
+
     fn remove_unidrectional_edges(&self) -> Self {
         info!("Deleting unidirectional edges from graph.");
         let mut graph_copy = self.clone();
@@ -300,10 +822,38 @@ impl Graph {
             .collect()
     }
 
+    /// The other direction of the edge's channel. Directions are paired by their channel ids,
+    /// "scid/0" and "scid/1" or "src-dest" and "dest-src" followed by the same suffix. Without
+    /// such a pair the only edge back is taken, if there is one
+    pub(crate) fn counterpart(&self, edge: &Edge) -> Option<Edge> {
+        let edges_back = self.get_all_src_dest_edges(&edge.destination, &edge.source);
+        match Self::counterpart_id(edge)
+            .and_then(|id| edges_back.iter().find(|e| e.channel_id == id))
+        {
+            Some(back) => Some(back.clone()),
+            None if edges_back.len() == 1 => edges_back.into_iter().next(),
+            None => None,
+        }
+    }
+
+    /// Channel id of the edge's other direction if its id follows one of the pairings, see
+    /// counterpart
+    pub(crate) fn counterpart_id(edge: &Edge) -> Option<String> {
+        if let Some(scid) = edge.channel_id.strip_suffix("/0") {
+            Some(format!("{}/1", scid))
+        } else if let Some(scid) = edge.channel_id.strip_suffix("/1") {
+            Some(format!("{}/0", scid))
+        } else {
+            edge.channel_id
+                .strip_prefix(&format!("{}-{}", edge.source, edge.destination))
+                .map(|suffix| format!("{}-{}{}", edge.destination, edge.source, suffix))
+        }
+    }
+
     pub(crate) fn get_random_pairs_of_nodes(
         &self,
         num_nodes: usize,
-    ) -> (impl Iterator<Item = (ID, ID)> + Clone) {
+    ) -> impl Iterator<Item = (ID, ID)> + Clone {
         let mut node_ids = self.get_node_ids();
         assert!(
             !node_ids.is_empty(),
@@ -328,6 +878,105 @@ impl Graph {
         pairs.into_iter()
     }
 
+    /// True if dest can be reached from src, ignoring the balances
+    pub fn is_reachable(&self, src: &str, dest: &str) -> bool {
+        let successors = |node: &ID| -> Vec<ID> {
+            self.get_outedges(node)
+                .into_iter()
+                .map(|e| e.destination)
+                .collect()
+        };
+        pathfinding::prelude::bfs(&src.to_string(), successors, |n| n == dest).is_some()
+    }
+
+    /// True if dest can be reached from src over channels that can spend at least the amount,
+    /// i.e. a path exists in topology and liquidity. Channels without spendable balance never
+    /// count, even for an amount of 0
+    pub fn is_reachable_with_liquidity(&self, src: &str, dest: &str, amount: usize) -> bool {
+        let successors = |node: &ID| -> Vec<ID> {
+            self.get_outedges(node)
+                .into_iter()
+                .filter(|e| {
+                    let available = self.get_available_balance(e);
+                    available > 0 && available >= amount
+                })
+                .map(|e| e.destination)
+                .collect()
+        };
+        pathfinding::prelude::bfs(&src.to_string(), successors, |n| n == dest).is_some()
+    }
+
+    /// Largest amount a single path from src to dest can carry given the channels' capacities,
+    /// i.e. the capacity of the widest path. 0 if dest cannot be reached
+    pub(crate) fn largest_single_path_capacity(&self, src: &str, dest: &str) -> usize {
+        let mut capacities: Vec<usize> =
+            self.edges.values().flatten().map(|e| e.capacity).collect();
+        capacities.sort_unstable();
+        capacities.dedup();
+        let reachable_with = |min_capacity: usize| {
+            let successors = |node: &ID| -> Vec<ID> {
+                self.get_outedges(node)
+                    .into_iter()
+                    .filter(|e| e.capacity >= min_capacity)
+                    .map(|e| e.destination)
+                    .collect()
+            };
+            pathfinding::prelude::bfs(&src.to_string(), successors, |n| n == dest).is_some()
+        };
+        // the larger the capacity the fewer channels are left to reach dest with
+        match capacities.partition_point(|c| reachable_with(*c)) {
+            0 => 0,
+            widest => capacities[widest - 1],
+        }
+    }
+
+    /// Max amount that can be sent from src to dest with the edges' balances as capacities
+    /// (Edmonds-Karp). 0 if either node is not part of the graph
+    pub fn max_flow(&self, src: &str, dest: &str) -> u64 {
+        self.max_flow_and_min_cut(src, dest).0
+    }
+
+    /// The (source, destination) pairs of the saturated edges separating src from dest when the
+    /// max-flow is sent
+    pub(crate) fn min_cut(&self, src: &str, dest: &str) -> Vec<(ID, ID)> {
+        self.max_flow_and_min_cut(src, dest).1
+    }
+
+    fn max_flow_and_min_cut(&self, src: &str, dest: &str) -> (u64, Vec<(ID, ID)>) {
+        let node_ids = self.get_node_ids();
+        let index: HashMap<&str, usize> = node_ids
+            .iter()
+            .enumerate()
+            .map(|(idx, id)| (id.as_str(), idx))
+            .collect();
+        let (Some(&src_idx), Some(&dest_idx)) = (index.get(src), index.get(dest)) else {
+            return (0, vec![]);
+        };
+        if src_idx == dest_idx {
+            return (0, vec![]);
+        }
+        // parallel edges add up to a single capacity
+        let mut capacities: HashMap<(usize, usize), i64> = HashMap::new();
+        for edge in self.edges.values().flatten() {
+            if let (Some(&from), Some(&to)) = (
+                index.get(edge.source.as_str()),
+                index.get(edge.destination.as_str()),
+            ) {
+                let capacity = capacities.entry((from, to)).or_default();
+                *capacity = capacity.saturating_add(edge.balance as i64);
+            }
+        }
+        let vertices: Vec<usize> = (0..node_ids.len()).collect();
+        let (_, max_flow, cut) = pathfinding::directed::edmonds_karp::edmonds_karp_sparse(
+            &vertices, &src_idx, &dest_idx, capacities,
+        );
+        let cut = cut
+            .into_iter()
+            .map(|((from, to), _)| (node_ids[from].clone(), node_ids[to].clone()))
+            .collect();
+        (max_flow as u64, cut)
+    }
+
     pub(crate) fn node_is_in_graph(&self, node: &ID) -> bool {
         self.get_node_ids().contains(node)
     }
@@ -351,7 +1000,8 @@ impl Graph {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::Path;
+    use crate::{PaymentParts, RoutingMetric};
+    use approx::assert_abs_diff_eq;
 
     fn json_str() -> String {
         let json_str = r##"{
@@ -539,7 +1189,7 @@ mod tests {
             .unwrap(),
             network_parser::GraphSource::Lnresearch,
         );
-        let random_pair: Vec<(ID, ID)> = graph.get_random_pairs_of_nodes(n).into_iter().collect();
+        let random_pair: Vec<(ID, ID)> = graph.get_random_pairs_of_nodes(n).collect();
         assert!(graph.get_node_ids().contains(&random_pair[0].0));
         assert!(graph.get_node_ids().contains(&random_pair[0].1));
     }
@@ -573,6 +1223,7 @@ mod tests {
             balance: actual.clone().unwrap().balance, // hacky because it depends on the RNG
             liquidity: 0,
             capacity: 0,
+            inbound_fee: 0,
         });
         assert_eq!(actual, expected);
     }
@@ -602,6 +1253,7 @@ mod tests {
             balance: 0,
             liquidity: 0,
             capacity: 0,
+            inbound_fee: 0,
         }];
         assert_eq!(actual, expected);
     }
@@ -656,7 +1308,7 @@ mod tests {
     fn all_edges_between_two_nodes() {
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &Path::new("../test_data/trivial_connected.json"),
+                Path::new("../test_data/trivial_connected.json"),
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -667,7 +1319,7 @@ mod tests {
             let from = node;
             let to = nodes[idx + 1 % nodes.len() - 1].clone();
             if *from != to {
-                let actual = graph.get_all_src_dest_edges(&from, &to);
+                let actual = graph.get_all_src_dest_edges(from, &to);
                 assert_eq!(actual.len(), 1);
             }
         }
@@ -678,7 +1330,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -702,7 +1354,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -720,7 +1372,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -743,7 +1395,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -786,7 +1438,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -813,7 +1465,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -838,12 +1490,42 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    // alice's only inbound channel forwards parts of at most 200 msat
+    #[test]
+    fn mpp_receive_capacity_is_bounded_by_htlc_maximum_and_parts() {
+        let json_file = std::path::Path::new("../test_data/lnbook_example.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for edges in graph.edges.values_mut() {
+            for e in edges {
+                e.capacity = 5000;
+                e.balance = 2500;
+                if e.channel_id == "bob1" {
+                    e.htlc_maximum_msat = 200;
+                }
+            }
+        }
+        let alice = "alice".to_string();
+        let amount = 2000;
+        assert!(graph.get_max_receive_amount(&alice) >= amount);
+        assert_eq!(graph.mpp_receive_capacity(&alice, 4), 800);
+        assert!(graph.mpp_receive_capacity(&alice, 4) < amount as u64);
+        // with enough parts the channel's liquidity is the limit
+        assert_eq!(graph.mpp_receive_capacity(&alice, 100), 2500);
+    }
+
     #[test]
     fn delete_node_from_graph() {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -861,7 +1543,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -872,4 +1554,455 @@ mod tests {
         graph.remove_node(&node);
         assert!(!graph.node_is_in_graph(&node));
     }
+
+    #[test]
+    fn bincode_snapshot_round_trip() {
+        let mut simulator = crate::attempt::tests::init_sim(None, None);
+        let snapshot = tempfile::NamedTempFile::new().unwrap();
+        simulator.graph.save_bincode(snapshot.path()).unwrap();
+        let loaded = Graph::load_bincode(snapshot.path()).unwrap();
+        assert_eq!(loaded.edge_count(), simulator.graph.edge_count());
+        assert_eq!(loaded.node_count(), simulator.graph.node_count());
+        let mut from_snapshot = simulator.clone();
+        from_snapshot.graph = loaded;
+        let (source, dest, amount) = ("alice".to_string(), "chan".to_string(), 1000);
+        let mut expected =
+            crate::payment::Payment::new(0, source.clone(), dest.clone(), amount, None);
+        let mut actual = expected.clone();
+        simulator.add_invoice(crate::Invoice::new(0, amount, &source, &dest));
+        from_snapshot.add_invoice(crate::Invoice::new(0, amount, &source, &dest));
+        assert!(simulator.send_single_payment(&mut expected));
+        assert!(from_snapshot.send_single_payment(&mut actual));
+        assert_eq!(actual.used_paths, expected.used_paths);
+        assert_eq!(actual.htlc_attempts, expected.htlc_attempts);
+    }
+
+    #[test]
+    fn in_flight_reservations() {
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                Path::new("../test_data/lnbook_example.json"),
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let channel = String::from("alice1");
+        graph.update_channel_balance(&channel, 5000);
+        graph.reserve_in_flight(&channel, 3000);
+        graph.reserve_in_flight(&channel, 1000);
+        assert_eq!(graph.get_in_flight(&channel), 4000);
+        let edge = graph
+            .get_outedges(&String::from("alice"))
+            .into_iter()
+            .find(|e| e.channel_id == channel)
+            .unwrap();
+        assert_eq!(graph.get_available_balance(&edge), 1000);
+        graph.release_in_flight(&channel, 4000);
+        assert_eq!(graph.get_in_flight(&channel), 0);
+        assert!(graph.in_flight.is_empty());
+    }
+
+    #[test]
+    fn balance_gini_coefficient() {
+        let graph_with_balances = |balances: &[usize]| {
+            let edges = balances
+                .iter()
+                .enumerate()
+                .map(|(i, balance)| Edge {
+                    channel_id: format!("chan{i}"),
+                    source: "a".to_string(),
+                    destination: "b".to_string(),
+                    balance: *balance,
+                    ..Default::default()
+                })
+                .collect();
+            Graph {
+                nodes: vec![],
+                edges: HashMap::from([("a".to_string(), edges)]),
+                in_flight: HashMap::default(),
+                usage: HashMap::default(),
+                private_channels: HashSet::default(),
+            }
+        };
+        assert_eq!(graph_with_balances(&[]).balance_gini(), 0.0);
+        assert_abs_diff_eq!(
+            graph_with_balances(&[500, 500, 500, 500]).balance_gini(),
+            0.0,
+            epsilon = 1e-9
+        );
+        // sum of |xi - xj| over all pairs / (2 n^2 mean) = 20 / 80
+        assert_abs_diff_eq!(
+            graph_with_balances(&[1, 2, 3, 4]).balance_gini(),
+            0.25,
+            epsilon = 1e-9
+        );
+        // (n - 1) / n when a single edge holds everything
+        assert_abs_diff_eq!(
+            graph_with_balances(&[0, 0, 0, 1000]).balance_gini(),
+            0.75,
+            epsilon = 1e-9
+        );
+        let mut single_holder = vec![0; 999];
+        single_holder.push(1000);
+        assert!(graph_with_balances(&single_holder).balance_gini() > 0.99);
+    }
+
+    #[test]
+    fn degree_assortativity_of_path_and_star() {
+        let graph_with_channels = |channels: &[(&str, &str)]| {
+            let mut edges: HashMap<ID, Vec<Edge>> = HashMap::new();
+            for (a, b) in channels {
+                for (src, dest) in [(a, b), (b, a)] {
+                    edges.entry(src.to_string()).or_default().push(Edge {
+                        channel_id: format!("{src}-{dest}"),
+                        source: src.to_string(),
+                        destination: dest.to_string(),
+                        ..Default::default()
+                    });
+                }
+            }
+            Graph {
+                nodes: vec![],
+                edges,
+                in_flight: HashMap::default(),
+                usage: HashMap::default(),
+                private_channels: HashSet::default(),
+            }
+        };
+        // a - b - c - d, the ends connect to the middle and the middle with each other
+        let path = graph_with_channels(&[("a", "b"), ("b", "c"), ("c", "d")]);
+        assert_abs_diff_eq!(path.degree_assortativity(), -0.5, epsilon = 1e-9);
+        // every channel connects the hub to a leaf
+        let star = graph_with_channels(&[("hub", "a"), ("hub", "b"), ("hub", "c")]);
+        assert_abs_diff_eq!(star.degree_assortativity(), -1.0, epsilon = 1e-9);
+        // all nodes of a ring have two channels
+        let ring = graph_with_channels(&[("a", "b"), ("b", "c"), ("c", "a")]);
+        assert_eq!(ring.degree_assortativity(), 0.0);
+        assert_eq!(graph_with_channels(&[]).degree_assortativity(), 0.0);
+    }
+
+    #[test]
+    fn fee_overrides_make_carol_free() {
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                Path::new("../test_data/trivial_multipath.json"),
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for edges in graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+            }
+        }
+        let amount = 1000;
+        let route_fees = |graph: &Graph| {
+            let mut path_finder = crate::PathFinder::new(
+                "bob".to_string(),
+                "alice".to_string(),
+                amount,
+                graph,
+                crate::RoutingMetric::MinFee,
+                crate::PaymentParts::Single,
+            );
+            match path_finder.find_path() {
+                crate::RouteResult::Found(candidate_path) => {
+                    assert_eq!(candidate_path.path.get_involved_nodes()[1], "carol");
+                    candidate_path.amount - amount
+                }
+                _ => panic!("Expected a route"),
+            }
+        };
+        let fees_before = route_fees(&graph);
+        let bob_carol = graph.get_edge(&"bob".to_string(), &"carol".to_string());
+        graph
+            .apply_fee_overrides(Path::new("../test_data/fee_overrides.csv"))
+            .unwrap();
+        assert!(graph
+            .get_outedges(&"carol".to_string())
+            .iter()
+            .all(|e| e.fee_base_msat == 0 && e.fee_proportional_millionths == 0));
+        // unlisted nodes keep their fees
+        assert_eq!(
+            graph.get_edge(&"bob".to_string(), &"carol".to_string()),
+            bob_carol
+        );
+        assert!(route_fees(&graph) < fees_before);
+        assert!(graph
+            .apply_fee_overrides(Path::new("../test_data/payments.csv"))
+            .is_err());
+    }
+
+    // bob can only reach alice via carol and dave
+    #[test]
+    fn max_flow_is_bounded_by_the_min_cut() {
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                Path::new("../test_data/trivial_multipath.json"),
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for edges in graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+            }
+        }
+        assert_eq!(graph.max_flow("bob", "alice"), 140000);
+        graph.update_channel_balance(&"dave-alice".to_string(), 1000);
+        assert_eq!(graph.max_flow("bob", "alice"), 71000);
+        assert_eq!(graph.max_flow("bob", "bob"), 0);
+        assert_eq!(graph.max_flow("bob", "mallory"), 0);
+    }
+
+    #[test]
+    fn reachable_in_topology_but_not_with_liquidity() {
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                Path::new("../test_data/trivial_multipath.json"),
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for edges in graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+            }
+        }
+        graph.update_channel_balance(&"carol-alice".to_string(), 1000);
+        graph.update_channel_balance(&"dave-alice".to_string(), 0);
+        assert!(graph.is_reachable("bob", "alice"));
+        assert!(graph.is_reachable_with_liquidity("bob", "alice", 1000));
+        assert!(!graph.is_reachable_with_liquidity("bob", "alice", 5000));
+        graph.update_channel_balance(&"carol-alice".to_string(), 0);
+        assert!(graph.is_reachable("bob", "alice"));
+        assert!(!graph.is_reachable_with_liquidity("bob", "alice", 0));
+    }
+
+    // a reaches d via b or c. Only the channels via b are large enough to be likely to succeed
+    #[test]
+    fn announced_capacities_are_kept_and_balances_default_to_a_fraction() {
+        let json_str = r##"{
+            "nodes": [
+                { "id": "a" }, { "id": "b" }, { "id": "c" }, { "id": "d" }
+            ],
+            "adjacency": [
+                [
+                    { "scid": "a-b", "source": "a", "destination": "b", "fee_base_msat": 0, "fee_proportional_millionths": 0, "htlc_maximum_msat": 5000000, "cltv_expiry_delta": 40, "capacity": 1000000 },
+                    { "scid": "a-c", "source": "a", "destination": "c", "fee_base_msat": 0, "fee_proportional_millionths": 0, "htlc_maximum_msat": 5000000, "cltv_expiry_delta": 40, "capacity": 10000 }
+                ],
+                [
+                    { "scid": "a-b", "source": "b", "destination": "a", "fee_base_msat": 0, "fee_proportional_millionths": 0, "htlc_maximum_msat": 5000000, "cltv_expiry_delta": 40, "capacity": 1000000 },
+                    { "scid": "b-d", "source": "b", "destination": "d", "fee_base_msat": 0, "fee_proportional_millionths": 0, "htlc_maximum_msat": 5000000, "cltv_expiry_delta": 40, "capacity": 1000000 }
+                ],
+                [
+                    { "scid": "a-c", "source": "c", "destination": "a", "fee_base_msat": 0, "fee_proportional_millionths": 0, "htlc_maximum_msat": 5000000, "cltv_expiry_delta": 40, "capacity": 10000 },
+                    { "scid": "c-d", "source": "c", "destination": "d", "fee_base_msat": 0, "fee_proportional_millionths": 0, "htlc_maximum_msat": 5000000, "cltv_expiry_delta": 40, "capacity": 10000, "balance": 3000 }
+                ],
+                [
+                    { "scid": "b-d", "source": "d", "destination": "b", "fee_base_msat": 0, "fee_proportional_millionths": 0, "htlc_maximum_msat": 5000000, "cltv_expiry_delta": 40, "capacity": 1000000 },
+                    { "scid": "c-d", "source": "d", "destination": "c", "fee_base_msat": 0, "fee_proportional_millionths": 0, "htlc_maximum_msat": 5000000, "cltv_expiry_delta": 40, "capacity": 10000 }
+                ]
+            ]
+        }"##;
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_str(
+                json_str,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for e in graph.edges.values().flatten() {
+            let expected = if e.channel_id == "a-b" || e.channel_id == "b-d" {
+                1000000
+            } else {
+                10000
+            };
+            assert_eq!(e.capacity, expected);
+            assert!(e.balance <= e.capacity);
+            assert_eq!(e.liquidity, e.balance);
+        }
+        // the imported balance is kept and the other side holds the rest
+        assert_eq!(
+            graph.get_channel_balance(&"c".to_string(), &"c-d".to_string()),
+            3000
+        );
+        assert_eq!(
+            graph.get_channel_balance(&"d".to_string(), &"c-d".to_string()),
+            7000
+        );
+        // with the same balances everywhere only the capacities tell the paths apart
+        for e in graph.edges.values_mut().flatten() {
+            e.balance = 5000;
+            e.liquidity = 5000;
+        }
+        let mut path_finder = crate::traversal::pathfinding::PathFinder::new(
+            "a".to_string(),
+            "d".to_string(),
+            4000,
+            &graph,
+            RoutingMetric::MaxProb,
+            PaymentParts::Single,
+        );
+        match path_finder.find_path() {
+            crate::traversal::pathfinding::RouteResult::Found(candidate_path) => {
+                assert_eq!(
+                    candidate_path.path.get_involved_nodes(),
+                    vec!["a", "b", "d"]
+                );
+            }
+            _ => panic!("Expected a path from a to d."),
+        }
+    }
+
+    #[test]
+    fn validation_reports_broken_channels_and_isolated_nodes() {
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                Path::new("../test_data/lnbook_example.json"),
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        assert!(graph.validate().is_empty());
+        graph.nodes.push(Node {
+            id: "erin".to_string(),
+            ..Default::default()
+        });
+        for e in graph.edges.values_mut().flatten() {
+            match e.channel_id.as_str() {
+                "alice1" => e.balance = e.capacity + 1,
+                "bob1" => {
+                    e.capacity = 0;
+                    e.balance = 0;
+                }
+                "chan2" => {
+                    e.cltv_expiry_delta = 0;
+                    e.htlc_maximum_msat = 0;
+                }
+                _ => {}
+            }
+        }
+        let warnings = graph.validate();
+        let alice1 = graph
+            .get_edge(&"alice".to_string(), &"bob".to_string())
+            .unwrap();
+        let expected = vec![
+            GraphWarning::IsolatedNode("erin".to_string()),
+            GraphWarning::BalanceExceedsCapacity {
+                channel_id: "alice1".to_string(),
+                balance: alice1.capacity + 1,
+                capacity: alice1.capacity,
+            },
+            GraphWarning::ZeroCapacity {
+                channel_id: "bob1".to_string(),
+            },
+            GraphWarning::MissingFeePolicy {
+                channel_id: "chan2".to_string(),
+            },
+        ];
+        assert_eq!(warnings, expected);
+    }
+
+    // frank is only known from the overlay, which also makes bob-carol free
+    #[test]
+    fn merged_channels_are_routable() {
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                Path::new("../test_data/trivial_multipath.json"),
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let overlay_json = r##"{
+            "nodes": [{ "id": "alice" }, { "id": "frank" }],
+            "adjacency": [
+                [{ "scid": "alice-frank", "source": "alice", "destination": "frank", "fee_base_msat": 0, "fee_proportional_millionths": 0, "htlc_maximum_msat": 100000, "cltv_expiry_delta": 40, "capacity": 100000, "balance": 50000 }],
+                [{ "scid": "frank-alice", "source": "frank", "destination": "alice", "fee_base_msat": 0, "fee_proportional_millionths": 0, "htlc_maximum_msat": 100000, "cltv_expiry_delta": 40, "capacity": 100000, "balance": 50000 }]
+            ]
+        }"##;
+        let overlay = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_str(
+                overlay_json,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let mut bob_carol = graph
+            .get_edge(&"bob".to_string(), &"carol".to_string())
+            .unwrap();
+        bob_carol.fee_base_msat = 0;
+        bob_carol.balance = 60000;
+        let mut update = overlay.clone();
+        update
+            .edges
+            .entry("bob".to_string())
+            .or_default()
+            .push(bob_carol);
+        let (num_nodes, num_edges) = (graph.node_count(), graph.edge_count());
+        graph.merge(&update);
+        assert_eq!(graph.node_count(), num_nodes + 1);
+        assert_eq!(graph.edge_count(), num_edges + 2);
+        let bob_carol = graph
+            .get_edge(&"bob".to_string(), &"carol".to_string())
+            .unwrap();
+        assert_eq!(bob_carol.fee_base_msat, 0);
+        assert_eq!(bob_carol.balance, 60000);
+        // merging again changes nothing
+        graph.merge(&update);
+        assert_eq!(graph.edge_count(), num_edges + 2);
+        for e in graph.edges.values_mut().flatten() {
+            e.balance = e.balance.max(50000);
+        }
+        let mut path_finder = crate::traversal::pathfinding::PathFinder::new(
+            "bob".to_string(),
+            "frank".to_string(),
+            1000,
+            &graph,
+            RoutingMetric::MinFee,
+            PaymentParts::Single,
+        );
+        match path_finder.find_path() {
+            crate::traversal::pathfinding::RouteResult::Found(candidate_path) => {
+                assert_eq!(
+                    candidate_path.path.get_involved_nodes(),
+                    vec!["bob", "carol", "alice", "frank"]
+                );
+            }
+            _ => panic!("Expected a path from bob to frank."),
+        }
+    }
+
+    #[test]
+    fn close_channel_removes_the_counterpart_of_parallel_channels() {
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                Path::new("../test_data/trivial_multipath.json"),
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let (bob, alice) = ("bob".to_string(), "alice".to_string());
+        // both channels have a capacity of 10000 msat
+        let first = graph.open_channel(&bob, &alice, 4000, 6000);
+        let second = graph.open_channel(&bob, &alice, 6000, 4000);
+        assert_eq!(graph.close_channel(&second.1), vec![second.1, second.0]);
+        let remaining: Vec<String> = graph
+            .get_all_src_dest_edges(&bob, &alice)
+            .into_iter()
+            .chain(graph.get_all_src_dest_edges(&alice, &bob))
+            .map(|e| e.channel_id)
+            .collect();
+        assert_eq!(remaining, vec![first.0, first.1]);
+        assert!(graph.close_channel("bob-mallory").is_empty());
+    }
 }
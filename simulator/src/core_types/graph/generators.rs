@@ -0,0 +1,171 @@
+use crate::{core_types::graph::Graph, to_millisatoshi, ID};
+use network_parser::{Edge, Node};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
+
+/// Range of the capacities of generated channels, in sat
+const CAPACITY_SAT: (usize, usize) = (100_000, 10_000_000);
+/// Max base fee in msat and proportional fee in ppm of generated channels
+const MAX_FEE_BASE_MSAT: usize = 1000;
+const MAX_FEE_PPM: usize = 1000;
+/// Range of the timelock deltas of generated channels
+const CLTV_EXPIRY_DELTA: (usize, usize) = (18, 144);
+
+/// Scale-free graph of n nodes. Starts with m + 1 nodes that all have channels with each other,
+/// every further node opens channels to m existing nodes picked with a probability proportional
+/// to their number of channels
+/// Nodes are named by their index, balances and fees are drawn from the seeded RNG
+pub fn barabasi_albert(n: usize, m: usize, seed: u64) -> Graph {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let m = m.clamp(1, n.saturating_sub(1).max(1));
+    let mut channels = vec![];
+    for i in 0..(m + 1).min(n) {
+        for j in 0..i {
+            channels.push((j, i));
+        }
+    }
+    // every node appears once per channel it has, so picking uniformly from this list prefers
+    // nodes with many channels
+    let mut endpoints: Vec<usize> = channels.iter().flat_map(|(a, b)| [*a, *b]).collect();
+    for node in (m + 1)..n {
+        let mut peers = vec![];
+        while peers.len() < m {
+            let peer = endpoints[rng.gen_range(0..endpoints.len())];
+            if !peers.contains(&peer) {
+                peers.push(peer);
+            }
+        }
+        for peer in peers {
+            channels.push((peer, node));
+            endpoints.extend([peer, node]);
+        }
+    }
+    from_channels(n, &channels, &mut rng)
+}
+
+/// Small-world graph of n nodes. Each node starts with channels to its k nearest nodes on a
+/// ring, k / 2 on either side, and each channel is rewired to a random node with probability p
+/// Nodes are named by their index, balances and fees are drawn from the seeded RNG
+pub fn watts_strogatz(n: usize, k: usize, p: f64, seed: u64) -> Graph {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let half = (k / 2).min(n.saturating_sub(1) / 2);
+    let mut channels = vec![];
+    for i in 0..n {
+        for j in 1..=half {
+            channels.push((i, (i + j) % n));
+        }
+    }
+    let key = |a: usize, b: usize| (a.min(b), a.max(b));
+    let mut existing: HashSet<(usize, usize)> = channels.iter().map(|(a, b)| key(*a, *b)).collect();
+    for channel in channels.iter_mut() {
+        if !rng.gen_bool(p.clamp(0.0, 1.0)) {
+            continue;
+        }
+        let (a, b) = *channel;
+        // a node connected to every other one has nobody to rewire to
+        if (0..n).all(|c| c == a || existing.contains(&key(a, c))) {
+            continue;
+        }
+        let mut c = rng.gen_range(0..n);
+        while c == a || existing.contains(&key(a, c)) {
+            c = rng.gen_range(0..n);
+        }
+        existing.remove(&key(a, b));
+        existing.insert(key(a, c));
+        *channel = (a, c);
+    }
+    from_channels(n, &channels, &mut rng)
+}
+
+/// Graph of the nodes 0..n with a public channel per (node, node) pair
+fn from_channels(n: usize, channels: &[(usize, usize)], rng: &mut StdRng) -> Graph {
+    let nodes: Vec<Node> = (0..n)
+        .map(|i| Node {
+            id: i.to_string(),
+            ..Default::default()
+        })
+        .collect();
+    let mut edges: HashMap<ID, Vec<Edge>> = HashMap::new();
+    for (a, b) in channels {
+        let capacity = to_millisatoshi(rng.gen_range(CAPACITY_SAT.0..=CAPACITY_SAT.1));
+        let balance = rng.gen_range(0..=capacity);
+        for (src, dest, balance) in [(a, b, balance), (b, a, capacity - balance)] {
+            let edge = Edge {
+                channel_id: format!("{}-{}", src, dest),
+                source: src.to_string(),
+                destination: dest.to_string(),
+                fee_base_msat: rng.gen_range(0..=MAX_FEE_BASE_MSAT),
+                fee_proportional_millionths: rng.gen_range(0..=MAX_FEE_PPM),
+                htlc_maximum_msat: capacity,
+                cltv_expiry_delta: rng.gen_range(CLTV_EXPIRY_DELTA.0..=CLTV_EXPIRY_DELTA.1),
+                balance,
+                liquidity: balance,
+                capacity,
+                ..Default::default()
+            };
+            edges.entry(src.to_string()).or_default().push(edge);
+        }
+    }
+    Graph {
+        nodes,
+        edges,
+        in_flight: HashMap::default(),
+        usage: HashMap::default(),
+        private_channels: HashSet::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{payment::Payment, time::Time, PaymentParts, RoutingMetric, Simulation};
+    use rand::seq::SliceRandom;
+
+    #[test]
+    fn barabasi_albert_graph_is_connected_and_routable() {
+        let graph = barabasi_albert(50, 2, 7);
+        assert_eq!(graph.node_count(), 50);
+        // m + 1 initial channels and then m per node, each in both directions
+        assert_eq!(graph.edge_count(), 2 * (3 + 47 * 2));
+        let node_ids = graph.get_node_ids();
+        assert!(node_ids.iter().all(|node| graph.is_reachable("0", node)));
+        assert_eq!(
+            barabasi_albert(50, 2, 7).get_edges()["0"],
+            graph.get_edges()["0"]
+        );
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut node_ids = node_ids;
+        node_ids.sort();
+        let pair: Vec<&ID> = node_ids.choose_multiple(&mut rng, 2).collect();
+        let (source, dest) = (pair[0].clone(), pair[1].clone());
+        let mut simulator = Simulation::new(
+            7,
+            graph,
+            1000,
+            RoutingMetric::MinFee,
+            PaymentParts::Split,
+            None,
+            &[],
+        );
+        let payment = Payment::new(0, source, dest, 1000, None);
+        let result = simulator.run_scheduled(vec![(Time::from_secs(10.0), payment)]);
+        assert_eq!(result.num_succesful, 1);
+    }
+
+    #[test]
+    fn watts_strogatz_keeps_the_number_of_channels() {
+        let graph = watts_strogatz(30, 4, 0.2, 3);
+        assert_eq!(graph.node_count(), 30);
+        assert_eq!(graph.edge_count(), 2 * 30 * 2);
+        // without rewiring each node has channels to its two neighbours on either side
+        let ring = watts_strogatz(30, 4, 0.0, 3);
+        let mut peers: Vec<ID> = ring
+            .get_outedges(&"0".to_string())
+            .into_iter()
+            .map(|e| e.destination)
+            .collect();
+        peers.sort();
+        assert_eq!(peers, vec!["1", "2", "28", "29"]);
+    }
+}
@@ -1,3 +1,3 @@
 pub(crate) mod event;
 pub mod graph;
-pub(crate) mod time;
+pub mod time;
@@ -1,17 +1,18 @@
 // Mostly from https://git.tu-berlin.de/rohrer/cdt-data/-/blob/master/simulator/lnsim/src/simtime.rs
+use serde::Serialize;
 use std::cmp::Ordering;
 use std::ops::{Add, AddAssign, Sub};
 
 use std::fmt;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default, Serialize)]
 pub struct Time(u64);
 
 static SIMTIME_SCALING_FACTOR_SECS: f32 = 1000000.0; // in nano secs.
 static SIMTIME_SCALING_FACTOR_MILLIS: f32 = 1000.0;
 
 impl Time {
-    pub(crate) fn as_secs(&self) -> f32 {
+    pub fn as_secs(&self) -> f32 {
         self.0 as f32 / SIMTIME_SCALING_FACTOR_SECS
     }
 
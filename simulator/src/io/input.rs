@@ -0,0 +1,153 @@
+use crate::{payment::Payment, PaymentId, Simulation, ID};
+
+use log::info;
+use serde::Deserialize;
+use std::{error::Error, fmt, fs, path::Path};
+
+/// Why a payment workload could not be loaded
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    /// A CSV line that does not match id,source,dest,amount_msat
+    Csv {
+        line: usize,
+        reason: String,
+    },
+    /// Only .csv and .json files are supported
+    UnsupportedFormat(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "could not read payments: {e}"),
+            LoadError::Json(e) => write!(f, "invalid JSON payments: {e}"),
+            LoadError::Csv { line, reason } => write!(f, "invalid CSV line {line}: {reason}"),
+            LoadError::UnsupportedFormat(ext) => write!(f, "unsupported payments format {ext:?}"),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for LoadError {
+    fn from(e: serde_json::Error) -> Self {
+        LoadError::Json(e)
+    }
+}
+
+/// A payment as it is described in a workload file
+#[derive(Debug, Deserialize)]
+struct PaymentRecord {
+    id: PaymentId,
+    source: ID,
+    dest: ID,
+    amount_msat: usize,
+    #[serde(default)]
+    min_shard_amt: Option<usize>,
+    #[serde(default)]
+    target_parts: Option<usize>,
+}
+
+impl PaymentRecord {
+    fn to_payment(&self) -> Payment {
+        let mut payment = Payment::new(
+            self.id,
+            self.source.clone(),
+            self.dest.clone(),
+            self.amount_msat,
+            self.min_shard_amt,
+        );
+        payment.target_parts = self.target_parts;
+        payment
+    }
+
+    /// Parses a line of the form id,source,dest,amount_msat
+    fn from_csv_line(line: &str, line_num: usize) -> Result<Self, LoadError> {
+        let invalid = |reason: String| LoadError::Csv {
+            line: line_num,
+            reason,
+        };
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        if fields.len() != 4 {
+            return Err(invalid(format!("expected 4 columns, got {}", fields.len())));
+        }
+        let parse = |field: &str, name: &str| {
+            field
+                .parse::<usize>()
+                .map_err(|e| invalid(format!("{name} {field:?}: {e}")))
+        };
+        Ok(PaymentRecord {
+            id: parse(fields[0], "id")?,
+            source: fields[1].to_string(),
+            dest: fields[2].to_string(),
+            amount_msat: parse(fields[3], "amount_msat")?,
+            min_shard_amt: None,
+            target_parts: None,
+        })
+    }
+}
+
+impl Simulation {
+    /// Reads payments from a CSV file with columns id,source,dest,amount_msat or a JSON array of
+    /// payment objects. Fields not given in the file are set to their defaults
+    pub fn load_payments(path: &Path) -> Result<Vec<Payment>, LoadError> {
+        let contents = fs::read_to_string(path)?;
+        let records = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str::<Vec<PaymentRecord>>(&contents)?,
+            Some("csv") => contents
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                // the header is optional
+                .filter(|(idx, line)| !(*idx == 0 && line.trim_start().starts_with("id")))
+                .map(|(idx, line)| PaymentRecord::from_csv_line(line, idx + 1))
+                .collect::<Result<Vec<PaymentRecord>, LoadError>>()?,
+            other => {
+                return Err(LoadError::UnsupportedFormat(
+                    other.unwrap_or_default().to_string(),
+                ))
+            }
+        };
+        info!("Loaded {} payments from {:?}.", records.len(), path);
+        Ok(records.iter().map(PaymentRecord::to_payment).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_payments_from_csv_and_json() {
+        let csv = Simulation::load_payments(Path::new("../test_data/payments.csv")).unwrap();
+        let ids_and_amounts: Vec<(PaymentId, usize)> =
+            csv.iter().map(|p| (p.payment_id, p.amount_msat)).collect();
+        assert_eq!(ids_and_amounts, vec![(0, 1000), (1, 2500), (2, 40000)]);
+        assert_eq!(csv[1].source, "bob");
+        assert_eq!(csv[1].dest, "chan");
+        assert_eq!(csv[1].min_shard_amt, crate::MIN_SHARD_AMOUNT);
+        let json = Simulation::load_payments(Path::new("../test_data/payments.json")).unwrap();
+        let ids_and_amounts: Vec<(PaymentId, usize)> =
+            json.iter().map(|p| (p.payment_id, p.amount_msat)).collect();
+        assert_eq!(ids_and_amounts, vec![(0, 1000), (1, 2500)]);
+        assert_eq!(json[0].target_parts, None);
+        assert_eq!(json[1].target_parts, Some(2));
+        assert_eq!(json[1].min_shard_amt, 500);
+        assert!(matches!(
+            Simulation::load_payments(Path::new("../test_data/trivial.txt")),
+            Err(LoadError::Io(_))
+        ));
+        assert!(matches!(
+            Simulation::load_payments(Path::new("../test_data/lnbook_example.json")),
+            Err(LoadError::Json(_))
+        ));
+    }
+}
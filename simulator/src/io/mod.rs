@@ -1,11 +1,14 @@
 use crate::{
     payment::Payment,
     stats::{Adversaries, Diversity},
+    time::Time,
     traversal::pathfinding::CandidatePath,
     WeightPartsCombi,
 };
 use serde::Serialize;
+use std::collections::HashMap;
 
+pub mod input;
 pub mod output;
 
 #[derive(Debug, Serialize)]
@@ -44,6 +47,11 @@ pub struct PaymentInfo {
     pub htlc_attempts: usize,
     pub used_paths: Vec<PathInfo>,
     pub failed_paths: Vec<PathInfo>,
+    /// Simulation time at which the slowest part resolved
+    pub completion_time: Time,
+    /// The payment's metadata, exported as an object of its own so that its keys cannot clash
+    /// with the other fields
+    pub metadata: HashMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, PartialEq, Eq)]
@@ -59,9 +67,11 @@ pub struct PathInfo {
 }
 
 impl PathInfo {
-    pub(super) fn from_payment(paths: &[CandidatePath]) -> Vec<Self> {
+    pub(super) fn from_payment<'a>(
+        paths: impl IntoIterator<Item = &'a CandidatePath>,
+    ) -> Vec<Self> {
         paths
-            .iter()
+            .into_iter()
             .filter(|p| !p.path.hops.is_empty())
             .map(|path| Self {
                 amount: crate::to_sat(path.path_amount()),
@@ -76,7 +86,8 @@ impl PathInfo {
 impl PaymentInfo {
     pub fn from_payment(payment: &Payment) -> Self {
         let used_paths = PathInfo::from_payment(&payment.used_paths);
-        let failed_paths = PathInfo::from_payment(&payment.failed_paths);
+        let failed_paths =
+            PathInfo::from_payment(payment.failed_paths.iter().map(|(path, ..)| path));
         Self {
             id: payment.payment_id,
             succeeded: payment.succeeded,
@@ -84,6 +95,8 @@ impl PaymentInfo {
             htlc_attempts: payment.htlc_attempts,
             used_paths,
             failed_paths,
+            completion_time: payment.completion_time,
+            metadata: payment.metadata.clone(),
         }
     }
 }
@@ -110,6 +123,7 @@ mod tests {
                 weight: 1010.0,
                 amount: 2010,
                 time: 5,
+                shard_id: None,
             },
             CandidatePath {
                 path: Path {
@@ -125,24 +139,16 @@ mod tests {
                 weight: 3000.0,
                 amount: 5030,
                 time: 10,
+                shard_id: None,
             },
         ];
         let source = "bob".to_string();
         let dest = "alice".to_string();
         let amount_msat = 2000;
         let payment = Payment {
-            payment_id: 0,
-            source: source.clone(),
-            dest: dest.clone(),
-            amount_msat,
-            succeeded: false,
-            min_shard_amt: 10,
             htlc_attempts: 2,
-            num_parts: 1,
             used_paths,
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-            failed_paths: vec![],
+            ..Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10))
         };
         let actual = PaymentInfo::from_payment(&payment);
         let expected = PaymentInfo {
@@ -166,7 +172,34 @@ mod tests {
                 },
             ],
             failed_paths: vec![],
+            completion_time: Time::default(),
+            metadata: HashMap::default(),
         };
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn payment_metadata_is_exported_with_the_payment() {
+        let tagged =
+            crate::payment::PaymentBuilder::new(0, "bob".to_string(), "alice".to_string(), 1000)
+                .metadata("experiment_arm", "b")
+                .metadata("wallet_id", "42")
+                .metadata("id", "b-42")
+                .build();
+        let untagged = Payment::new(1, "bob".to_string(), "alice".to_string(), 1000, None);
+        let sim_result = crate::sim::SimResult {
+            total_num: 2,
+            num_failed: 2,
+            failed_payments: vec![tagged, untagged],
+            ..Default::default()
+        };
+        let report = Report::sim_result_to_report(&sim_result);
+        let json = serde_json::to_value(&report).unwrap();
+        let payments = json["payments"].as_array().unwrap();
+        assert_eq!(payments[0]["metadata"]["experiment_arm"], "b");
+        assert_eq!(payments[0]["metadata"]["wallet_id"], "42");
+        assert_eq!(payments[0]["metadata"]["id"], "b-42");
+        assert_eq!(payments[0]["id"], 0);
+        assert!(payments[1]["metadata"].as_object().unwrap().is_empty());
+    }
 }
@@ -5,9 +5,45 @@ use log::{error, info};
 use std::{
     error::Error,
     fs::{self, File},
+    io::Write,
     path::PathBuf,
 };
 
+/// Writes rows of comma separated fields. Fields holding a comma, a quote or a line break are
+/// quoted, with their quotes doubled
+pub(crate) struct CsvWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub(crate) fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub(crate) fn write_record<I, F>(&mut self, fields: I) -> std::io::Result<()>
+    where
+        I: IntoIterator<Item = F>,
+        F: AsRef<str>,
+    {
+        for (idx, field) in fields.into_iter().enumerate() {
+            if idx > 0 {
+                self.writer.write_all(b",")?;
+            }
+            let field = field.as_ref();
+            if field.contains([',', '"', '\n', '\r']) {
+                write!(self.writer, "\"{}\"", field.replace('"', "\"\""))?;
+            } else {
+                self.writer.write_all(field.as_bytes())?;
+            }
+        }
+        self.writer.write_all(b"\n")
+    }
+
+    pub(crate) fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
 impl Output {
     /// Converts a vector of SimResult to Result in preparation for output
     pub fn to_results_type(
@@ -84,3 +120,21 @@ impl Report {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        let mut writer = CsvWriter::new(vec![]);
+        writer
+            .write_record(["plain", "a,b", "say \"hi\"", "two\nlines", ""])
+            .unwrap();
+        writer.write_record(["last"]).unwrap();
+        assert_eq!(
+            String::from_utf8(writer.writer).unwrap(),
+            "plain,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",\nlast\n"
+        );
+    }
+}
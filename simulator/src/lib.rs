@@ -1,6 +1,6 @@
 use lazy_static::lazy_static;
 use rand::{rngs::StdRng, SeedableRng};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{fmt, path::PathBuf, sync::Mutex};
 
 pub mod core_types;
@@ -17,11 +17,20 @@ pub use traversal::pathfinding::*;
 
 pub type ID = String;
 pub type PaymentId = usize;
+/// A part of a payment, (payment_id, index of the part in the order the parts were attempted)
+pub type ShardId = (PaymentId, usize);
 pub type Node = network_parser::Node;
 pub type Edge = network_parser::Edge;
 pub type EdgeWeight = ordered_float::OrderedFloat<f32>;
 
 pub(crate) static SIM_DELAY_IN_SECS: f32 = 120.0;
+/// Default time after which an HTLC held by a griefing node fails
+pub(crate) static GRIEFING_TIMEOUT_IN_SECS: f32 = 600.0;
+/// Default time after which half of a channel's failure penalty is gone
+pub(crate) static PENALTY_HALF_LIFE_IN_SECS: f32 = 1800.0;
+/// Default weight added to a channel into dest that earlier parts of the payment arrived via. A
+/// sat under the fee metric, enough to prefer another inbound channel of similar cost
+pub(crate) static LAST_HOP_PENALTY: f32 = 1000.0;
 /// Max number of hops in a path from an adversary's adjacent node
 pub(crate) static _DEPTH: usize = 3;
 /// Minimum amount of msats that can be sent in a shard
@@ -35,8 +44,18 @@ pub(crate) static MIN_SHARD_AMOUNT: usize = 1000;
 pub(crate) static MAX_PARTS: usize = 16;
 /// Scaling factor used to convert msats and sats
 pub static SAT_SCALE: usize = 1000;
+/// Number of sats in a bitcoin
+pub static BTC_SCALE: usize = 100_000_000;
 /// Up to 20 intermediaries (vaious [LND code snippets](https://github.com/lightningnetwork/lnd/blob/bbbf7d33fb1527acebb44e2a69d16fbcf24cc2fa/routing/pathfind_test.go#LL1690C34-L1690C34)
 pub static MAX_HOPS: usize = 20;
+/// Max number of next shortest paths searched for one the path filter accepts
+pub(crate) static MAX_FILTERED_PATHS: usize = 64;
+/// Max number of paths per part the expected fee accounts for
+pub(crate) static MAX_FEE_ATTEMPTS: usize = 8;
+/// Number of times the factor the payments saturate the network at is narrowed down
+pub(crate) static SATURATION_BISECTIONS: usize = 8;
+/// Max number of hops the onion packet has room for. Every intermediary and dest take up one hop
+pub static ONION_HOP_LIMIT: usize = 20;
 
 /// Metric to use when looking for a route
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
@@ -45,16 +64,142 @@ pub enum RoutingMetric {
     MinFee,
     /// Route based on probabilty of success
     MaxProb,
+    /// Use Dijkstra to minimise the number of hops along a route
+    MinHops,
+    /// Use Dijkstra to minimise the total timelock along a route
+    MinTime,
+    /// Avoid using up most of a channel's balance, see BalancedScorer
+    Balanced,
+}
+
+/// Built-in routing policies for users who only want to choose what a route should optimise for
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoutingPolicy {
+    /// Cheapest route
+    #[default]
+    MinFee,
+    /// Route with the fewest hops
+    MinHops,
+    /// Route with the lowest total timelock
+    MinTime,
+    /// Route most likely to succeed
+    MaxReliability,
+    /// Route that leaves the most balance in its channels
+    Balanced,
+}
+
+/// Which path to prefer among paths of equal weight
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum TieBreak {
+    /// The path with the lowest total timelock
+    #[default]
+    LowerLatency,
+    /// Any of the paths
+    Random,
+}
+
+/// Which of the pending parts of an MPP to attempt next
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum ShardOrder {
+    /// The part that was split off last
+    #[default]
+    Lifo,
+    /// The largest part, which fails fast on bottlenecks
+    LargestFirst,
+    /// The smallest part
+    SmallestFirst,
+}
+
+/// What happens to a split whose parts fall below min_shard_amt, e.g. after rounding them to
+/// the shard quantum
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DustPolicy {
+    /// The split is not made, min_shard_amt is a hard limit. A payment whose initial split
+    /// would leave dust is attempted as a whole instead
+    #[default]
+    Fail,
+    /// The dust part is added to its sibling, keeping the sum
+    FoldIntoSibling,
+}
+
+/// How forwarding nodes round the fees they charge
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum FeeRounding {
+    /// Fees are charged to the msat
+    #[default]
+    None,
+    /// Fees are rounded up to whole sats
+    CeilSat,
+    /// Fees are rounded down to whole sats
+    FloorSat,
+}
+
+impl FeeRounding {
+    /// The fee in msat rounded as set
+    pub(crate) fn apply(&self, fee_msat: usize) -> usize {
+        match self {
+            Self::None => fee_msat,
+            Self::CeilSat => fee_msat.div_ceil(SAT_SCALE).saturating_mul(SAT_SCALE),
+            Self::FloorSat => fee_msat / SAT_SCALE * SAT_SCALE,
+        }
+    }
 }
 
 /// How should the payment be sent
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PaymentParts {
     /// Send the whole payment at once
     Single,
     /// Split the payment into multiple payments and route independently
     Split,
+    /// Like Split, but a failed part is only halved if that is cheaper than splitting off the
+    /// largest part a single path can carry, since every additional part pays base fees again
+    FeeOptimalSplit,
+    /// Like Split, but not atomic. The parts that arrived are kept even if others fail
+    Amp,
+}
+/// Presets of the splitting behaviour of common wallet implementations
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum WalletProfile {
+    Ldk,
+    /// Pays via a trampoline node in a single part
+    Phoenix,
+    Lnd,
+}
+
+impl WalletProfile {
+    /// Smallest part the wallet splits a payment into, in msat
+    pub fn min_shard_amt(&self) -> usize {
+        match self {
+            Self::Ldk => 1000 * SAT_SCALE,
+            Self::Phoenix | Self::Lnd => 10000 * SAT_SCALE,
+        }
+    }
+
+    pub fn max_parts(&self) -> usize {
+        match self {
+            Self::Ldk | Self::Lnd => 16,
+            Self::Phoenix => 1,
+        }
+    }
+
+    /// Max number of intermediaries of a path
+    pub fn max_hops(&self) -> usize {
+        match self {
+            Self::Ldk => 19,
+            Self::Phoenix | Self::Lnd => 20,
+        }
+    }
+
+    pub fn payment_parts(&self) -> PaymentParts {
+        match self {
+            Self::Ldk => PaymentParts::FeeOptimalSplit,
+            Self::Phoenix => PaymentParts::Single,
+            Self::Lnd => PaymentParts::Split,
+        }
+    }
 }
+
 /// Enum combining RoutingMetric and PaymentParts enums- used to eval different scnerios
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
 pub enum WeightPartsCombi {
@@ -93,13 +238,67 @@ lazy_static! {
 
 impl clap::ValueEnum for RoutingMetric {
     fn value_variants<'a>() -> &'a [Self] {
-        &[Self::MinFee, Self::MaxProb]
+        &[
+            Self::MinFee,
+            Self::MaxProb,
+            Self::MinHops,
+            Self::MinTime,
+            Self::Balanced,
+        ]
     }
 
     fn to_possible_value<'a>(&self) -> Option<clap::builder::PossibleValue> {
         match self {
             Self::MinFee => Some(clap::builder::PossibleValue::new("minfee")),
             Self::MaxProb => Some(clap::builder::PossibleValue::new("maxprob")),
+            Self::MinHops => Some(clap::builder::PossibleValue::new("minhops")),
+            Self::MinTime => Some(clap::builder::PossibleValue::new("mintime")),
+            Self::Balanced => Some(clap::builder::PossibleValue::new("balanced")),
+        }
+    }
+}
+
+impl From<RoutingPolicy> for RoutingMetric {
+    fn from(policy: RoutingPolicy) -> Self {
+        match policy {
+            RoutingPolicy::MinFee => Self::MinFee,
+            RoutingPolicy::MinHops => Self::MinHops,
+            RoutingPolicy::MinTime => Self::MinTime,
+            RoutingPolicy::MaxReliability => Self::MaxProb,
+            RoutingPolicy::Balanced => Self::Balanced,
+        }
+    }
+}
+
+/// An amount of bitcoin, kept in msat
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct Amount(usize);
+
+impl Amount {
+    pub fn from_msat(msat: usize) -> Self {
+        Self(msat)
+    }
+
+    pub fn from_sat(sat: usize) -> Self {
+        Self(to_millisatoshi(sat))
+    }
+
+    /// Rounded to the msat
+    pub fn from_btc(btc: f64) -> Self {
+        Self((btc * (BTC_SCALE * SAT_SCALE) as f64).round() as usize)
+    }
+
+    pub fn as_msat(&self) -> usize {
+        self.0
+    }
+}
+
+/// In sats, with the msat as decimals if there are any
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 % SAT_SCALE {
+            0 => write!(f, "{} sat", to_sat(self.0)),
+            msat => write!(f, "{}.{:03} sat", to_sat(self.0), msat),
         }
     }
 }
@@ -1,8 +1,10 @@
 use crate::{
-    payment::{Payment, PaymentShard},
-    traversal::pathfinding::{CandidatePath, PathFinder},
-    Simulation, ID,
+    core_types::{event::PaymentEvent, time::Time},
+    payment::{FailureReason, Payment, PaymentShard, ShardRef},
+    traversal::pathfinding::{CandidatePath, PathFinder, RouteResult},
+    PaymentId, Simulation, ID,
 };
+use std::time::Instant;
 
 #[cfg(not(test))]
 use log::{debug, error, info, trace};
@@ -16,11 +18,26 @@ impl Simulation {
     pub(crate) fn send_one_payment(
         &mut self,
         payment: &mut Payment,
+    ) -> (bool, Vec<(ID, String, usize)>) {
+        self.send_one_payment_with_path(payment, None)
+    }
+
+    /// Like send_one_payment but the first attempt takes the given path instead of searching
+    /// for one. Expects the path to have been found for the payment
+    pub(crate) fn send_one_payment_with_path(
+        &mut self,
+        payment: &mut Payment,
+        found_path: Option<CandidatePath>,
     ) -> (bool, Vec<(ID, String, usize)>) {
         let graph = self.graph.clone();
         let mut succeeded = false;
         let mut failed = false;
+        let mut failure_reason = None;
         let mut to_revert = Vec::new();
+        payment.failing_channel = None;
+        let mut excluded_channels = payment.excluded_channels.clone();
+        let mut held = false;
+        let mut num_cancelled = 0;
         // fail immediately if sender's balance on each of their edges < amount
         // Checked for single-path payments earlier already but the check is necessary here for
         // MPP.
@@ -28,25 +45,44 @@ impl Simulation {
         if max_out_balance < payment.amount_msat {
             error!("Payment shard failing. Sender {} does not have sufficient balance. Amount {}, max balance {}",  payment.source, payment.amount_msat, max_out_balance);
             failed = true;
+            failure_reason = Some(FailureReason::InsufficientLiquidity);
         }
-        let graph_copy = self.graph.clone();
         if !failed {
-            let mut path_finder = PathFinder::new(
-                payment.source.clone(),
-                payment.dest.clone(),
-                payment.amount_msat,
-                &graph_copy,
-                self.routing_metric,
-                self.payment_parts,
-            );
-            path_finder
-                .graph
-                .set_edges(PathFinder::remove_inadequate_edges(
-                    &graph_copy,
-                    payment.amount_msat,
-                ));
+            let mut path_finder = self.shard_path_finder(payment);
+            path_finder.route_cache = std::mem::take(&mut self.route_cache);
+            // a direct channel is tried first, the search only runs if it does not work out
+            let mut direct_path = found_path.or_else(|| path_finder.find_direct_path());
             while !succeeded && !failed {
-                if let Some(candidate_path) = path_finder.find_path() {
+                let pathfinding_start = Instant::now();
+                let route = match direct_path.take() {
+                    Some(candidate_path) => RouteResult::Found(candidate_path),
+                    None => path_finder.find_path(),
+                };
+                self.timings.pathfinding += pathfinding_start.elapsed().as_nanos();
+                if let RouteResult::Found(mut candidate_path) = route {
+                    let mut attempt_failing_channel = None;
+                    let mut cancelled = false;
+                    if candidate_path.checked_total_amount().is_none() {
+                        error!(
+                            "Payment {} failing as its amount plus fees overflows. Amount {}.",
+                            payment.payment_id, payment.amount_msat
+                        );
+                        failed = true;
+                        failure_reason = Some(FailureReason::AmountOverflow);
+                    }
+                    // fees coming out of the amount leave less for dest
+                    if !failed
+                        && !payment.fees_on_top
+                        && !path_finder.deduct_fees_from_amount(&mut candidate_path)
+                    {
+                        error!(
+                            "Payment shard failing. Fees of {} msat exceed the amount of {} msat.",
+                            candidate_path.path_fees(),
+                            payment.amount_msat
+                        );
+                        failed = true;
+                        failure_reason = Some(FailureReason::FeesExceedAmount);
+                    }
                     let hops = candidate_path.path.hops.clone();
                     for hop in hops.iter().take(hops.len() - 1).skip(1) {
                         // not source and dest
@@ -60,16 +96,18 @@ impl Simulation {
                     // path's fees
                     let (sender, out_channel) = (&hops[0].0, &hops[0].3);
                     let channel_balance = self.graph.get_channel_balance(sender, out_channel);
-                    if channel_balance < candidate_path.amount {
+                    if !failed && channel_balance < candidate_path.amount {
                         error!("Payment shard failing. Sender does not have sufficient balance to cover fees. Amount {}, channel balance {}", candidate_path.amount, channel_balance);
                         succeeded = false;
                         failed = true;
+                        failure_reason = Some(FailureReason::InsufficientLiquidity);
                     }
                     // edge's receive capacity not sufficient?
                     let receive_channel = &hops[hops.len() - 1].3;
-                    if !self
-                        .graph
-                        .channel_can_receive_amount(receive_channel, payment.amount_msat)
+                    if !failed
+                        && !self
+                            .graph
+                            .channel_can_receive_amount(receive_channel, payment.amount_msat)
                     {
                         error!(
                             "Payment {} of {} msat failing at destination due to max capacity. Not trying to deliver..",
@@ -77,31 +115,98 @@ impl Simulation {
                         );
                         succeeded = false;
                         failed = true;
+                        failure_reason = Some(FailureReason::InsufficientLiquidity);
                     }
                     if !failed {
+                        if let Some(griefer_idx) = self.griefing_hop(&candidate_path) {
+                            // the sender only learns about the failure once the HTLC times out
+                            // so the amount cannot be retried along another path
+                            let stuck_channel =
+                                self.hold_htlc(payment.payment_id, &candidate_path, griefer_idx);
+                            payment.htlc_attempts += 1;
+                            payment.failing_channel = Some(stuck_channel.clone());
+                            attempt_failing_channel = Some(stuck_channel.clone());
+                            excluded_channels.push(stuck_channel.clone());
+                            held = true;
+                            if self.cancels_held_htlcs(payment) {
+                                // unless the sender gives up on the HTLC once it is slow and
+                                // routes the amount around the stuck channel
+                                info!(
+                                    "Cancelling HTLC of payment {} stuck at {}.",
+                                    payment.payment_id, stuck_channel
+                                );
+                                path_finder.graph.remove_channel(&stuck_channel);
+                                path_finder.route_cache.invalidate_channel(&stuck_channel);
+                                cancelled = true;
+                                num_cancelled += 1;
+                            } else {
+                                failed = true;
+                                failure_reason = Some(FailureReason::HtlcTimeout);
+                            }
+                        }
+                    }
+                    if !failed && !cancelled && self.is_slow(payment, &candidate_path) {
+                        // the HTLC would resolve only after the slow threshold so the sender
+                        // gives up on it and routes the amount around the path's slowest channel
+                        let slowest_channel =
+                            self.hold_slow_htlc(payment.payment_id, &candidate_path);
+                        info!(
+                            "Cancelling HTLC of payment {} slowed down at {}.",
+                            payment.payment_id, slowest_channel
+                        );
+                        payment.htlc_attempts += 1;
+                        payment.failing_channel = Some(slowest_channel.clone());
+                        attempt_failing_channel = Some(slowest_channel.clone());
+                        excluded_channels.push(slowest_channel.clone());
+                        path_finder.graph.remove_channel(&slowest_channel);
+                        path_finder.route_cache.invalidate_channel(&slowest_channel);
+                        cancelled = true;
+                        num_cancelled += 1;
+                    }
+                    if !failed && !cancelled {
                         let mut payment_shard = payment.to_shard(payment.amount_msat);
+                        let balance_start = Instant::now();
                         (succeeded, to_revert) = self.attempt_payment(
                             &mut payment_shard,
                             &candidate_path,
                             &mut path_finder,
                         );
-                        *payment = payment_shard.to_payment(1);
+                        payment.apply_attempt(&payment_shard);
                         if !succeeded {
+                            attempt_failing_channel = payment.failing_channel.clone();
                             self.revert_payment(&to_revert);
                         }
+                        self.timings.balance_updates += balance_start.elapsed().as_nanos();
                     }
                     // note paths that were attempted but failed for some reason
                     if failed || !succeeded {
-                        payment.failed_paths.push(candidate_path);
+                        // an attempt that went out failed for lack of liquidity along the path
+                        let reason = failure_reason.unwrap_or(FailureReason::InsufficientLiquidity);
+                        payment.failed_paths.push((
+                            candidate_path,
+                            reason,
+                            attempt_failing_channel.clone(),
+                        ));
+                        payment.failed_channels.extend(attempt_failing_channel);
                         payment.used_paths.clear();
                     }
                 } else {
-                    error!("No paths to destination found.");
+                    error!("No paths to destination found. {:?}", route);
                     succeeded = false;
                     failed = true;
+                    failure_reason = match route {
+                        RouteResult::NoTopology => Some(FailureReason::NoTopology),
+                        _ => Some(FailureReason::InsufficientLiquidity),
+                    };
                 }
             }
+            self.channel_penalties = path_finder.penalties;
+            self.route_cache = path_finder.route_cache;
         }
+        payment.failure_reason = if succeeded { None } else { failure_reason };
+        payment.excluded_channels = excluded_channels;
+        payment.held_htlcs = usize::from(held);
+        payment.cancelled_htlcs = num_cancelled;
         if succeeded {
             (succeeded, to_revert)
         } else {
@@ -110,6 +215,60 @@ impl Simulation {
         }
     }
 
+    /// PathFinder from src to dest for the amount with the simulation's settings, searching the
+    /// whole graph. It starts with an empty route cache
+    pub(crate) fn path_finder(&self, src: &ID, dest: &ID, amount: usize) -> PathFinder {
+        let mut path_finder = PathFinder::new(
+            src.clone(),
+            dest.clone(),
+            amount,
+            &self.graph,
+            self.routing_metric,
+            self.payment_parts,
+        );
+        // channels that failed recently are less likely to be tried again
+        path_finder.penalties = self.channel_penalties.clone();
+        path_finder.now = self.event_queue.now();
+        path_finder.half_life = self.penalty_half_life;
+        path_finder.tie_break = self.tie_break;
+        path_finder.fee_rounding = self.fee_rounding;
+        path_finder.decisions = self.decisions.share();
+        path_finder.onion_hop_limit = self.onion_hop_limit;
+        path_finder.max_hops = self.max_hops;
+        path_finder.path_filter = self.path_filter.clone();
+        path_finder.reliability = self.channel_reliability.clone();
+        path_finder.last_hop_penalty = self.last_hop_penalty;
+        path_finder.perturb_fees(self.fee_volatility);
+        path_finder
+    }
+
+    /// PathFinder for the payment with the simulation's settings, avoiding the payment's
+    /// excluded channels. It starts with an empty route cache
+    pub(crate) fn shard_path_finder(&self, payment: &Payment) -> PathFinder {
+        let mut path_finder = self.path_finder(&payment.source, &payment.dest, payment.amount_msat);
+        path_finder.prefilter_edges();
+        let invoice = self
+            .get_invoices_for_node(&payment.dest)
+            .and_then(|invoices| invoices.get(&payment.payment_id));
+        let route_hints = invoice
+            .map(|invoice| invoice.route_hints.clone())
+            .unwrap_or_default();
+        path_finder.final_cltv_expiry = invoice
+            .map(|invoice| invoice.min_final_cltv_expiry)
+            .unwrap_or_default();
+        path_finder.apply_route_hints(&route_hints, &self.graph);
+        for channel_id in payment.excluded_channels.iter() {
+            path_finder.graph.remove_channel(channel_id);
+        }
+        if let Some(first_hop) = payment.first_hop.as_ref() {
+            path_finder.restrict_first_hop(first_hop);
+        }
+        path_finder.exclude_nodes(&payment.exclude_nodes);
+        path_finder.used_last_hops = payment.last_hops.clone();
+        path_finder.max_hops = payment.max_hops.min(self.max_hops);
+        path_finder
+    }
+
     /// Tries to move the funds as is specified in the shard.
     /// This is the actual transaction
     pub(crate) fn attempt_payment(
@@ -132,7 +291,7 @@ impl Simulation {
         for (idx, node) in hops.iter().enumerate() {
             let (id, fees, _timelock, channel_id) = node.clone();
             // Subtract payment amount (includes fees) from source
-            if id == payment_shard.source {
+            if idx == 0 {
                 let current_balance = self.graph.get_channel_balance(&id, &channel_id);
                 if current_balance > candidate_path.amount {
                     self.graph.update_channel_balance(
@@ -149,9 +308,12 @@ impl Simulation {
                     );
                     payment_shard.htlc_attempts += 1;
                     payment_shard.succeeded = false;
+                    // otherwise the same path is found again and again
+                    path_finder.record_failure(&channel_id);
+                    path_finder.graph.remove_channel(&channel_id);
                     return (payment_shard.succeeded, transferred_amounts);
                 }
-            } else if id == payment_shard.dest {
+            } else if idx == hops.len() - 1 {
                 // add remaining_amount to the node balance / or capacity
                 // check if we have such an invoice and received amount matches
                 // if yes: success = true
@@ -173,10 +335,12 @@ impl Simulation {
                                         payment_shard.payment_id
                                     );
                                     payment_shard.succeeded = false;
+                                    payment_shard.failing_channel = Some(channel_id.clone());
                                     let src = &id;
                                     let dest = hops[idx - 1].0.clone();
                                     // this is the failing edge
                                     trace!("Discarding channel {} due to max capacity", channel_id,);
+                                    path_finder.record_failure(&channel_id);
                                     path_finder.graph.remove_channel(&channel_id);
                                     path_finder.graph.remove_edge(src, &dest);
                                 } else {
@@ -217,6 +381,7 @@ impl Simulation {
                         // we remove the edge because we otherwise risk running into an endless
                         // loop
                         let src = &id;
+                        path_finder.record_failure(&channel_id);
                         path_finder.graph.remove_channel(&channel_id);
                         path_finder.graph.remove_edge(src, &hops[idx - 1].0);
                         payment_shard.succeeded = false;
@@ -231,6 +396,7 @@ impl Simulation {
                     && self
                         .graph
                         .channel_can_receive_amount(&channel_id, remaining_transferable_amount)
+                    && !self.forward_fails_randomly(&id)
                 {
                     self.graph
                         .update_channel_balance(&channel_id, current_balance + fees);
@@ -250,9 +416,11 @@ impl Simulation {
                         dest,
                     );
                     // this is the failing edge
+                    path_finder.record_failure(&channel_id);
                     path_finder.graph.remove_channel(&channel_id);
                     path_finder.graph.remove_edge(src, &hops[idx - 1].0);
                     payment_shard.succeeded = false;
+                    payment_shard.failing_channel = Some(channel_id);
                     return (payment_shard.succeeded, transferred_amounts);
                 }
             }
@@ -260,7 +428,172 @@ impl Simulation {
         (payment_shard.succeeded, transferred_amounts)
     }
 
+    /// True if the intermediary fails to forward an HTLC by chance, see failure_probability
+    /// and node_reliability
+    fn forward_fails_randomly(&self, node: &ID) -> bool {
+        let fails = self.failure_probability > 0.0
+            && self.decisions.lock().unwrap().failure_roll() < self.failure_probability;
+        if fails {
+            debug!("{} failed to forward an HTLC by chance.", node);
+            return true;
+        }
+        let drop_probability = self
+            .node_reliability
+            .get(node)
+            .map_or(0.0, |reliability| 1.0 - reliability);
+        let drops = drop_probability > 0.0
+            && self.decisions.lock().unwrap().failure_roll() < drop_probability;
+        if drops {
+            debug!("{} dropped an HTLC as it is unreliable.", node);
+        }
+        drops
+    }
+
+    /// Commits the amounts forwarded along the payment's paths until the payment is resolved so that
+    /// concurrent payments cannot use the same funds
+    pub(crate) fn reserve_in_flight(&mut self, paths: &[CandidatePath]) {
+        for path in paths {
+            for (channel_id, amount) in path.forwarded_amounts() {
+                self.graph.reserve_in_flight(&channel_id, amount);
+            }
+        }
+    }
+
+    /// Counts the forwards of the delivered paths, including the sender's channel
+    pub(crate) fn record_channel_usage(&mut self, paths: &[CandidatePath]) {
+        for path in paths {
+            if let Some(first_hop) = path.path.hops.front() {
+                self.graph.record_forward(&first_hop.3, path.amount);
+            }
+            for (channel_id, amount) in path.forwarded_amounts() {
+                self.graph.record_forward(&channel_id, amount);
+            }
+        }
+    }
+
+    /// Index of the first intermediary in the path that griefs, if any
+    fn griefing_hop(&self, candidate_path: &CandidatePath) -> Option<usize> {
+        let hops = &candidate_path.path.hops;
+        (1..hops.len().saturating_sub(1)).find(|idx| self.griefing_nodes.contains(&hops[*idx].0))
+    }
+
+    /// Locks the liquidity of all channels leading up to the griefing node until the HTLC times
+    /// out. The balances are left untouched as the HTLC is never settled.
+    /// Returns the channel into the griefing node
+    fn hold_htlc(
+        &mut self,
+        payment_id: PaymentId,
+        candidate_path: &CandidatePath,
+        griefer_idx: usize,
+    ) -> String {
+        let hops = &candidate_path.path.hops;
+        let mut reservations = vec![(hops[0].3.clone(), candidate_path.amount)];
+        reservations.extend(
+            candidate_path
+                .forwarded_amounts()
+                .into_iter()
+                .take(griefer_idx - 1),
+        );
+        info!(
+            "{} holds HTLC of payment routed via {:?}.",
+            hops[griefer_idx].0, reservations
+        );
+        self.lock_htlc(payment_id, reservations, self.griefing_timeout);
+        hops[griefer_idx - 1].3.clone()
+    }
+
+    /// Locks the liquidity of the whole path until the HTLC resolves after the path's total
+    /// timelock. The balances are left untouched as the sender cancelled the HTLC.
+    /// Returns the intermediaries' channel with the largest timelock, the sender's if there are
+    /// no intermediaries
+    fn hold_slow_htlc(&mut self, payment_id: PaymentId, candidate_path: &CandidatePath) -> String {
+        let hops = &candidate_path.path.hops;
+        let mut reservations = vec![(hops[0].3.clone(), candidate_path.amount)];
+        reservations.extend(candidate_path.forwarded_amounts());
+        // the hops of src and dest hold the path's total timelock
+        let slowest_channel = hops
+            .iter()
+            .take(hops.len() - 1)
+            .skip(1)
+            .rev()
+            .max_by_key(|hop| hop.2)
+            .unwrap_or(&hops[0])
+            .3
+            .clone();
+        self.lock_htlc(
+            payment_id,
+            reservations,
+            Time::from_secs(candidate_path.time as f32),
+        );
+        slowest_channel
+    }
+
+    /// Reserves the amounts of the channels until the HTLC times out after the given time
+    fn lock_htlc(
+        &mut self,
+        payment_id: PaymentId,
+        reservations: Vec<(String, usize)>,
+        timeout: Time,
+    ) {
+        for (channel_id, amount) in reservations.iter() {
+            self.graph.reserve_in_flight(channel_id, *amount);
+        }
+        self.event_queue.schedule(
+            timeout,
+            PaymentEvent::HtlcTimeout {
+                payment_id,
+                reservations,
+            },
+        );
+    }
+
+    /// The payment's slow threshold, or the simulation's if it does not set one
+    fn slow_threshold(&self, payment: &Payment) -> Option<Time> {
+        payment.slow_threshold.or(self.slow_threshold)
+    }
+
+    /// Whether the sender cancels held HTLCs as they take longer than the slow threshold to
+    /// resolve
+    fn cancels_held_htlcs(&self, payment: &Payment) -> bool {
+        self.slow_threshold(payment)
+            .is_some_and(|threshold| threshold < self.griefing_timeout)
+    }
+
+    /// Whether the path resolves only after the slow threshold. A path resolves after its total
+    /// timelock in secs
+    fn is_slow(&self, payment: &Payment, candidate_path: &CandidatePath) -> bool {
+        self.slow_threshold(payment)
+            .is_some_and(|threshold| Time::from_secs(candidate_path.time as f32) > threshold)
+    }
+
+    /// A payment whose HTLCs were held resolves only after they time out, or once the sender
+    /// cancels them at the slow threshold. Held shards of the same payment time out concurrently
+    pub(crate) fn held_htlc_delay(&self, payment: &Payment) -> Time {
+        match self.slow_threshold(payment) {
+            Some(threshold) if payment.cancelled_htlcs > 0 => threshold,
+            _ if payment.held_htlcs > 0 => self.griefing_timeout,
+            _ => Time::default(),
+        }
+    }
+
+    /// Frees the liquidity locked by held HTLCs once they time out
+    pub(crate) fn release_held_htlcs(&mut self, reservations: &[(String, usize)]) {
+        for (channel_id, amount) in reservations {
+            self.graph.release_in_flight(channel_id, *amount);
+        }
+    }
+
+    /// Frees the reservations once the payment has been resolved
+    pub(crate) fn release_in_flight(&mut self, paths: &[CandidatePath]) {
+        for path in paths {
+            for (channel_id, amount) in path.forwarded_amounts() {
+                self.graph.release_in_flight(&channel_id, amount);
+            }
+        }
+    }
+
     /// Credits all edges in the path (Source gains whereas the rest lose)
+    /// The amounts are the transfers of a single shard, the first of which is the source's
     pub(crate) fn revert_payment(&mut self, amounts: &[(ID, String, usize)]) {
         let total: usize = amounts.iter().map(|t| t.2).sum::<usize>();
         debug!("Reverting {} msat.", total);
@@ -277,6 +610,85 @@ impl Simulation {
             }
         }
     }
+
+    /// Reverts the given parts of a payment only, the balance changes of its other parts stay
+    pub(crate) fn revert_shards(&mut self, shards: &[ShardRef]) {
+        for shard in shards {
+            trace!("Reverting shard {:?}.", shard.shard_id);
+            self.revert_payment(&shard.transfers);
+        }
+    }
+
+    /// Pays the payment's service fee: the fee of a JIT channel opened for it to the LSP and the
+    /// rest to the first intermediary of its first path, or dest if there is none. False if the
+    /// source's channel cannot cover it, in which case no balance changes
+    pub(crate) fn pay_service_fees(&mut self, payment: &Payment) -> bool {
+        let mut rest = payment.service_fee_msat as usize;
+        let mut due = vec![];
+        if let Some((lsp, channel_id, jit_fee)) = self.jit_fee_due(payment) {
+            let jit_fee = jit_fee.min(rest);
+            due.push((lsp, channel_id, jit_fee));
+            rest -= jit_fee;
+        }
+        if rest > 0 {
+            let Some(next) = payment.used_paths.first().and_then(|p| p.path.hops.get(1)) else {
+                return false;
+            };
+            due.push((next.0.clone(), next.3.clone(), rest));
+        }
+        let total: usize = due.iter().map(|(_, _, fee)| fee).sum();
+        if total == 0 {
+            return true;
+        }
+        if !self.can_pay_service_fee(payment, total) {
+            return false;
+        }
+        for (recipient, channel_id, fee) in due {
+            self.pay_service_fee(payment, fee, &recipient, &channel_id);
+        }
+        true
+    }
+
+    /// Moves the fee from the source's channel of the payment's first path to the recipient's
+    /// side of the channel. False if the source's channel cannot cover it, in which case no
+    /// balance changes
+    pub(crate) fn pay_service_fee(
+        &mut self,
+        payment: &Payment,
+        fee_msat: usize,
+        recipient: &ID,
+        channel_id: &String,
+    ) -> bool {
+        if fee_msat == 0 {
+            return true;
+        }
+        if !self.can_pay_service_fee(payment, fee_msat) {
+            return false;
+        }
+        let first = &payment.used_paths[0].path.hops[0];
+        let balance = self.graph.get_channel_balance(&first.0, &first.3);
+        self.graph
+            .update_channel_balance(&first.3, balance - fee_msat);
+        let recipient_balance = self.graph.get_channel_balance(recipient, channel_id);
+        self.graph
+            .update_channel_balance(channel_id, recipient_balance + fee_msat);
+        true
+    }
+
+    /// Whether the source's channel of the payment's first path can pay the fee
+    fn can_pay_service_fee(&self, payment: &Payment, fee_msat: usize) -> bool {
+        let Some(first) = payment.used_paths.first().and_then(|p| p.path.hops.front()) else {
+            return false;
+        };
+        if self.graph.get_channel_balance(&first.0, &first.3) <= fee_msat {
+            error!(
+                "Payment {} failing as {} cannot pay the service fee of {} msat.",
+                payment.payment_id, payment.source, fee_msat
+            );
+            return false;
+        }
+        true
+    }
 }
 
 #[cfg(test)]
@@ -297,7 +709,7 @@ pub(crate) mod tests {
         };
         let path = std::path::Path::new(&path);
         let mut graph = Graph::to_sim_graph(
-            &network_parser::Graph::from_json_file(&path, network_parser::GraphSource::Lnresearch)
+            &network_parser::Graph::from_json_file(path, network_parser::GraphSource::Lnresearch)
                 .unwrap(),
             network_parser::GraphSource::Lnresearch,
         );
@@ -322,6 +734,48 @@ pub(crate) mod tests {
         )
     }
 
+    /// Keeps the records logged through the log crate by all tests
+    struct CaptureLogger;
+
+    static CAPTURED_LOGS: std::sync::Mutex<Vec<(log::Level, String)>> =
+        std::sync::Mutex::new(Vec::new());
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            CAPTURED_LOGS
+                .lock()
+                .unwrap()
+                .push((record.level(), record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Starts capturing what is logged through the log crate. Tests run concurrently, so the
+    /// captured records are to be told apart by their contents
+    pub fn capture_logs() {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&CaptureLogger).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+    }
+
+    /// The records captured so far that contain the pattern
+    pub fn captured_logs(pattern: &str) -> Vec<(log::Level, String)> {
+        CAPTURED_LOGS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, line)| line.contains(pattern))
+            .cloned()
+            .collect()
+    }
+
     #[test]
     fn reverse_payment() {
         let balance = 4711;
@@ -368,7 +822,7 @@ pub(crate) mod tests {
             RoutingMetric::MinFee,
             PaymentParts::Single,
         );
-        let candidate_paths = path_finder.find_path().unwrap();
+        let candidate_paths = path_finder.find_path().path().unwrap();
         let payment_shard = &mut PaymentShard {
             payment_id: 0,
             source,
@@ -379,6 +833,9 @@ pub(crate) mod tests {
             min_shard_amt: 10,
             htlc_attempts: 0,
             failed_paths: vec![],
+            failing_channel: None,
+            fees_on_top: true,
+            failed_channels: Vec::default(),
         };
         assert!(
             simulator
@@ -418,7 +875,7 @@ pub(crate) mod tests {
             RoutingMetric::MinFee,
             PaymentParts::Single,
         );
-        let candidate_paths = path_finder.find_path().unwrap();
+        let candidate_paths = path_finder.find_path().path().unwrap();
         let payment_shard = &mut PaymentShard {
             payment_id: 0,
             source,
@@ -429,6 +886,9 @@ pub(crate) mod tests {
             min_shard_amt: 10,
             htlc_attempts: 0,
             failed_paths: vec![],
+            failing_channel: None,
+            fees_on_top: true,
+            failed_channels: Vec::default(),
         };
         let (success, transferred) =
             simulator.attempt_payment(payment_shard, &candidate_paths, &mut path_finder);
@@ -461,7 +921,7 @@ pub(crate) mod tests {
             RoutingMetric::MinFee,
             PaymentParts::Single,
         );
-        let candidate_paths = path_finder.find_path().unwrap();
+        let candidate_paths = path_finder.find_path().path().unwrap();
         let payment_shard = &mut PaymentShard {
             payment_id: 0,
             source,
@@ -472,6 +932,9 @@ pub(crate) mod tests {
             min_shard_amt: 10,
             htlc_attempts: 0,
             failed_paths: vec![],
+            failing_channel: None,
+            fees_on_top: true,
+            failed_channels: Vec::default(),
         };
         let (success, transferred) =
             simulator.attempt_payment(payment_shard, &candidate_paths, &mut path_finder);
@@ -503,7 +966,7 @@ pub(crate) mod tests {
             RoutingMetric::MinFee,
             PaymentParts::Single,
         );
-        let candidate_paths = path_finder.find_path().unwrap();
+        let candidate_paths = path_finder.find_path().path().unwrap();
         let payment_shard = &mut PaymentShard {
             payment_id: 0,
             source,
@@ -514,6 +977,9 @@ pub(crate) mod tests {
             min_shard_amt: 10,
             htlc_attempts: 0,
             failed_paths: vec![],
+            failing_channel: None,
+            fees_on_top: true,
+            failed_channels: Vec::default(),
         };
         assert!(
             !simulator
@@ -525,10 +991,10 @@ pub(crate) mod tests {
             .graph
             .get_edge(&String::from("alice"), &String::from("bob"))
             .is_some());
-        assert!(!path_finder
+        assert!(path_finder
             .graph
             .get_edge(&String::from("alice"), &String::from("bob"))
-            .is_some());
+            .is_none());
         // 0 because edges have been removed and get_balance returns 0 if edge is not found
         assert_eq!(
             path_finder
@@ -551,7 +1017,7 @@ pub(crate) mod tests {
         let amount = 500000;
         let path = std::path::Path::new("../data/gossip-20210906_1000UTC.json");
         let graph = Graph::to_sim_graph(
-            &network_parser::Graph::from_json_file(&path, network_parser::GraphSource::Lnresearch)
+            &network_parser::Graph::from_json_file(path, network_parser::GraphSource::Lnresearch)
                 .unwrap(),
             network_parser::GraphSource::Lnresearch,
         );
@@ -570,20 +1036,7 @@ pub(crate) mod tests {
         let source =
             "03c45cf25622ec07c56d13b7043e59c8c27ca822be58140b213edaea6849380349".to_string();
         let dest = "0329ae9a574b7120456d2ebf6626506e6a75255edd91ac4ea03ea008b9bad67bd2".to_string();
-        let payment = &mut Payment {
-            payment_id: 0,
-            source: source.clone(),
-            dest: dest.clone(),
-            amount_msat: amount,
-            succeeded: false,
-            min_shard_amt: 10,
-            htlc_attempts: 0,
-            num_parts: 1,
-            used_paths: Vec::default(),
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-            failed_paths: vec![],
-        };
+        let payment = &mut Payment::new(0, source.clone(), dest.clone(), amount, Some(10));
         simulator.add_invoice(Invoice::new(0, amount, &source, &dest));
         assert!(simulator.send_single_payment(payment));
     }
@@ -598,20 +1051,244 @@ pub(crate) mod tests {
         let capacity = graph.get_edge(&hop, &dest).unwrap().capacity;
         let amount = capacity * 2;
         simulator.add_invoice(Invoice::new(0, amount, &source, &dest));
-        let payment = &mut Payment {
-            payment_id: 0,
-            source: source.clone(),
-            dest: dest.clone(),
-            amount_msat: amount,
-            succeeded: false,
-            min_shard_amt: 10,
-            htlc_attempts: 0,
-            num_parts: 1,
-            used_paths: Vec::default(),
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-            failed_paths: vec![],
-        };
+        let payment = &mut Payment::new(0, source.clone(), dest.clone(), amount, Some(10));
         assert!(!simulator.send_single_payment(payment));
     }
+
+    // erin can only be reached via dina's unannounced channel
+    #[test]
+    fn route_hints_make_private_channels_usable() {
+        let mut simulator = init_sim(None, None);
+        let (dina, erin) = ("dina".to_string(), "erin".to_string());
+        simulator
+            .graph
+            .add_private_channel((&dina, "dina-erin", 5000), (&erin, "erin-dina", 5000));
+        let amount = 1000;
+        let source = "alice".to_string();
+        let mut without_hint = Payment::new(0, source.clone(), erin.clone(), amount, None);
+        simulator.add_invoice(Invoice::new(0, amount, &source, &erin));
+        assert!(!simulator.send_one_payment(&mut without_hint).0);
+        assert_eq!(without_hint.failure_reason, Some(FailureReason::NoTopology));
+        let mut with_hint = Payment::new(1, source.clone(), erin.clone(), amount, None);
+        let mut invoice = Invoice::new(1, amount, &source, &erin);
+        invoice.route_hints = vec![crate::RouteHint::new(&dina, "dina-erin", 5)];
+        simulator.add_invoice(invoice);
+        assert!(simulator.send_one_payment(&mut with_hint).0);
+        let used_path = &with_hint.used_paths[0];
+        assert_eq!(
+            used_path.path.hops[3],
+            (dina, 5, 0, "dina-erin".to_string())
+        );
+        assert_eq!(
+            simulator
+                .graph
+                .get_channel_balance(&erin, &"erin-dina".to_string()),
+            5000 + amount
+        );
+    }
+
+    // the simulation does not know dina's private channel to erin, only the invoice does
+    #[test]
+    fn route_hints_add_channels_missing_from_the_graph() {
+        let mut simulator = init_sim(None, None);
+        let (dina, erin) = ("dina".to_string(), "erin".to_string());
+        assert!(!simulator.graph.node_is_in_graph(&erin));
+        let amount = 1000;
+        let source = "alice".to_string();
+        let mut invoice = Invoice::new(0, amount, &source, &erin);
+        invoice.route_hints = vec![crate::RouteHint::new(&dina, "dina-erin", 5)];
+        simulator.add_invoice(invoice);
+        let payment = Payment::new(0, source, erin.clone(), amount, None);
+        let mut path_finder = simulator.shard_path_finder(&payment);
+        let candidate_path = path_finder.find_k_shortest_paths(1).pop().unwrap();
+        let hops = &candidate_path.path.hops;
+        assert_eq!(hops[hops.len() - 2], (dina, 5, 0, "dina-erin".to_string()));
+        assert_eq!(hops.back().unwrap().3, "erin-dina");
+    }
+
+    // the path via dave is cheaper, but bob insists on carol
+    #[test]
+    fn forced_first_hop_starts_every_path() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+                if e.channel_id == "dave-alice" {
+                    e.fee_base_msat = 0;
+                    e.fee_proportional_millionths = 0;
+                }
+            }
+        }
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let amount = 10000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount, None);
+        simulator.add_invoice(Invoice::new(0, amount, &source, &dest));
+        assert!(simulator.send_one_payment(&mut payment).0);
+        assert_eq!(payment.used_paths[0].path.hops[0].3, "bob-dave");
+        let mut payment =
+            crate::payment::PaymentBuilder::new(1, source.clone(), dest.clone(), amount)
+                .first_hop("bob-carol")
+                .build();
+        simulator.add_invoice(Invoice::new(1, amount, &source, &dest));
+        assert!(simulator.send_one_payment(&mut payment).0);
+        assert_eq!(payment.used_paths[0].path.hops[0].3, "bob-carol");
+        // bob's other channels are not used even though they could carry the amount
+        simulator
+            .graph
+            .update_channel_balance(&"bob-carol".to_string(), 5000);
+        let mut payment =
+            crate::payment::PaymentBuilder::new(2, source.clone(), dest.clone(), amount)
+                .first_hop("bob-carol")
+                .build();
+        simulator.add_invoice(Invoice::new(2, amount, &source, &dest));
+        assert!(!simulator.send_one_payment(&mut payment).0);
+        assert!(payment.used_paths.is_empty());
+    }
+
+    // eve only reaches alice via carol, so without carol bob has to pay dave's higher fee
+    #[test]
+    fn excluded_node_is_routed_around() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+                if e.channel_id == "dave-alice" {
+                    e.fee_base_msat = 100;
+                    e.fee_proportional_millionths = 0;
+                }
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let amount = 60000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount, None);
+        simulator.add_invoice(Invoice::new(0, amount, &source, &dest));
+        assert!(simulator.send_mpp_payment(&mut payment));
+        assert_eq!(
+            payment.used_paths[0].path.get_involved_nodes(),
+            vec!["bob", "carol", "alice"]
+        );
+        let mut payment =
+            crate::payment::PaymentBuilder::new(1, source.clone(), dest.clone(), amount)
+                .exclude_node("carol")
+                .build();
+        simulator.add_invoice(Invoice::new(1, amount, &source, &dest));
+        assert!(simulator.send_mpp_payment(&mut payment));
+        assert!(!payment.used_paths.is_empty());
+        for path in payment.used_paths.iter() {
+            assert_eq!(path.path.get_involved_nodes(), vec!["bob", "dave", "alice"]);
+        }
+        // no other way around carol is left once dave's channel is drained
+        let mut payment =
+            crate::payment::PaymentBuilder::new(2, source.clone(), dest.clone(), amount)
+                .exclude_node("carol")
+                .build();
+        simulator.add_invoice(Invoice::new(2, amount, &source, &dest));
+        assert!(!simulator.send_mpp_payment(&mut payment));
+    }
+
+    #[test]
+    fn path_time_includes_the_final_cltv_expiry() {
+        let mut simulator = init_sim(None, None);
+        let (source, dest) = ("alice".to_string(), "dina".to_string());
+        let amount = 1000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount, None);
+        let mut invoice = Invoice::new(0, amount, &source, &dest);
+        invoice.min_final_cltv_expiry = 18;
+        simulator.add_invoice(invoice);
+        assert!(simulator.send_one_payment(&mut payment).0);
+        let used_path = &payment.used_paths[0];
+        // the sender's own channel adds no delta
+        let hops = &used_path.path.hops;
+        let hop_deltas: usize = hops
+            .iter()
+            .skip(1)
+            .take(hops.len() - 2)
+            .filter_map(|hop| {
+                simulator
+                    .graph
+                    .get_outedges(&hop.0)
+                    .into_iter()
+                    .find(|e| e.channel_id == hop.3)
+                    .map(|e| e.cltv_expiry_delta)
+            })
+            .sum();
+        assert!(hop_deltas > 0);
+        assert_eq!(used_path.time, hop_deltas + 18);
+    }
+
+    // carol is on the cheapest paths from bob to alice but drops every HTLC, so the payment
+    // has to go via dave
+    #[test]
+    fn unreliable_node_fails_the_shards_routed_through_it() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+                if e.channel_id == "dave-alice" {
+                    e.fee_base_msat = 100;
+                    e.fee_proportional_millionths = 0;
+                }
+            }
+        }
+        simulator.set_node_reliability("carol", 0.0);
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let amount = 1000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount, None);
+        simulator.add_invoice(Invoice::new(0, amount, &source, &dest));
+        assert!(simulator.send_one_payment(&mut payment).0);
+        let carol = "carol".to_string();
+        assert!(!payment.failed_paths.is_empty());
+        for (failed_path, ..) in payment.failed_paths.iter() {
+            assert!(failed_path.path.get_involved_nodes().contains(&carol));
+        }
+        assert_eq!(
+            payment.used_paths[0].path.get_involved_nodes(),
+            vec!["bob".to_string(), "dave".to_string(), "alice".to_string()]
+        );
+        // carol's balances are untouched as she never forwarded
+        assert_eq!(
+            simulator
+                .graph
+                .get_channel_balance(&carol, &"carol-alice".to_string()),
+            70000
+        );
+    }
+
+    // alice pays bob and chan's service fee to chan rather than to bob, her first hop
+    #[test]
+    fn service_fee_goes_to_the_given_recipient() {
+        let (source, dest) = ("alice".to_string(), "chan".to_string());
+        let mut simulator = init_sim(None, None);
+        let (amount, balance) = (1000, 4711);
+        simulator.add_invoice(Invoice::new(0, amount, &source, &dest));
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount, None);
+        assert!(simulator.send_one_payment(&mut payment).0);
+        assert!(simulator.pay_service_fee(&payment, 200, &dest, &"chan1".to_string()));
+        assert!(!simulator.pay_service_fee(&payment, balance, &dest, &"chan1".to_string()));
+        assert_eq!(
+            simulator
+                .graph
+                .get_channel_balance(&source, &"alice1".to_string()),
+            balance - 1100 - 200
+        );
+        assert_eq!(
+            simulator
+                .graph
+                .get_channel_balance(&"bob".to_string(), &"bob2".to_string()),
+            balance + 100
+        );
+        assert_eq!(
+            simulator
+                .graph
+                .get_channel_balance(&dest, &"chan1".to_string()),
+            balance + amount + 200
+        );
+    }
 }
@@ -16,6 +16,28 @@ pub(crate) struct Invoice {
     pub(crate) source: ID,
     /// payment recipient and issuer of invoice
     pub(crate) destination: ID,
+    /// Unannounced channels towards the destination the sender may use
+    pub(crate) route_hints: Vec<RouteHint>,
+    /// Time-lock delta the recipient requires for the last hop
+    pub(crate) min_final_cltv_expiry: usize,
+}
+
+/// A private channel from node to the invoice's destination along with the fee node charges
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteHint {
+    pub node: ID,
+    pub channel_id: String,
+    pub fee_msat: usize,
+}
+
+impl RouteHint {
+    pub fn new(node: &ID, channel_id: &str, fee_msat: usize) -> Self {
+        Self {
+            node: node.clone(),
+            channel_id: channel_id.to_string(),
+            fee_msat,
+        }
+    }
 }
 
 impl Invoice {
@@ -25,6 +47,8 @@ impl Invoice {
             amount,
             source: source.clone(),
             destination: destination.clone(),
+            route_hints: Vec::default(),
+            min_final_cltv_expiry: 0,
         }
     }
 }
@@ -52,6 +76,8 @@ mod tests {
             source,
             destination,
             amount,
+            route_hints: vec![],
+            min_final_cltv_expiry: 0,
         };
         assert_eq!(actual, expected);
     }
@@ -1,7 +1,11 @@
-use crate::{traversal::pathfinding::CandidatePath, PaymentId, ID};
+use crate::{
+    core_types::graph::Graph, time::Time, traversal::pathfinding::CandidatePath, Amount, PaymentId,
+    PaymentParts, ShardId, WalletProfile, ID,
+};
 
 use log::error;
 use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 #[derive(Debug, Clone, Default, Serialize)]
 pub struct Payment {
@@ -22,7 +26,236 @@ pub struct Payment {
     /// Payment amounts that have already succeed, used for MPP payments
     pub(crate) failed_amounts: Vec<usize>,
     pub(crate) successful_shards: Vec<(ID, String, usize)>,
-    pub(crate) failed_paths: Vec<CandidatePath>,
+    pub(crate) failed_paths: Vec<FailedPath>,
+    /// Set when the payment could not be delivered
+    pub failure_reason: Option<FailureReason>,
+    /// Number of parts the sender would like to split the payment into. Soft target
+    pub target_parts: Option<usize>,
+    /// The channel the latest attempt failed at, if any
+    pub(crate) failing_channel: Option<String>,
+    /// Simulation time at which the slowest part of the payment resolves
+    pub completion_time: Time,
+    /// Number of shards a griefing node held until they timed out
+    pub held_htlcs: usize,
+    /// Number of shards the sender cancelled as they were slower than the slow threshold. Their
+    /// HTLCs stay locked until they resolve
+    pub cancelled_htlcs: usize,
+    /// Channels the sender avoids for the remaining shards after HTLCs got stuck there
+    pub(crate) excluded_channels: Vec<String>,
+    /// Simulation time by which all parts must have resolved, otherwise the payment is abandoned
+    pub deadline: Option<Time>,
+    /// Probes may be sent without an amount, regular payments are rejected
+    pub is_probe: bool,
+    /// If true, amount_msat is what dest receives and the sender pays the fees on top. Otherwise
+    /// the fees come out of amount_msat and dest receives less
+    pub fees_on_top: bool,
+    /// Channels at which the payment's attempts failed, in the order they failed
+    pub(crate) failed_channels: Vec<String>,
+    /// Amounts of the parts in the order they were attempted
+    pub shard_amounts: Vec<usize>,
+    /// Channels into dest used by the parts delivered so far
+    pub(crate) last_hops: Vec<String>,
+    /// Max number of parts the payment is split into
+    pub max_parts: usize,
+    /// Max number of intermediaries of each path
+    pub max_hops: usize,
+    /// How the payment is sent if it differs from the simulation's setting
+    pub payment_parts: Option<PaymentParts>,
+    /// Amount that reached dest. Below the amount for AMP payments that partially failed and if
+    /// the fees come out of the amount
+    pub delivered_msat: usize,
+    /// Channel of the source every path has to start with
+    pub first_hop: Option<String>,
+    /// Nodes no path may route through, e.g. sanctioned or unreliable ones
+    pub exclude_nodes: HashSet<ID>,
+    /// Fees paid to the intermediaries of the delivered parts plus the service fee. The source
+    /// is debited the delivered amount plus these
+    pub total_fees_msat: usize,
+    /// Max number of parts in flight at once. Further parts wait until earlier ones resolve
+    /// 0 for no limit
+    pub max_concurrent_shards: usize,
+    /// Fixed fee the source pays regardless of the route, e.g. to an LSP or a swap service. It
+    /// goes to the first intermediary of the payment's first path, the fee of a JIT channel
+    /// opened for the payment to its LSP
+    pub service_fee_msat: u64,
+    /// Most the source pays in fees, including the service fee. None for no limit
+    pub max_fee_msat: Option<u64>,
+    /// Parts whose HTLCs take longer than this to resolve are cancelled and their amount routed
+    /// along other paths. None to use the simulation's, see Simulation::set_slow_threshold
+    pub slow_threshold: Option<Time>,
+    /// Attributes to tag the payment with, e.g. the experiment arm. Exported along with the
+    /// payment but never looked at while routing
+    pub metadata: HashMap<String, String>,
+}
+
+/// The public outcome of a payment, without the bookkeeping used while sending it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PaymentResult {
+    pub source: ID,
+    pub dest: ID,
+    pub amount_msat: usize,
+    pub succeeded: bool,
+    /// Number of parts this payment has been split into
+    pub num_parts: usize,
+    pub htlc_attempts: usize,
+    /// Fees paid over all used paths, in msat
+    pub total_fees: usize,
+    pub used_paths: Vec<CandidatePath>,
+}
+
+/// Builds payments with optional settings, for example those of a wallet profile
+#[derive(Debug, Clone)]
+pub struct PaymentBuilder {
+    payment: Payment,
+}
+
+impl PaymentBuilder {
+    pub fn new(payment_id: PaymentId, source: ID, dest: ID, amount_msat: usize) -> Self {
+        Self {
+            payment: Payment::new(payment_id, source, dest, amount_msat, None),
+        }
+    }
+
+    /// Replaces the amount in msat given to new
+    pub fn amount(mut self, amount: Amount) -> Self {
+        self.payment.amount_msat = amount.as_msat();
+        self
+    }
+
+    pub fn min_shard_amt(mut self, min_shard_amt: usize) -> Self {
+        self.payment.min_shard_amt = min_shard_amt;
+        self
+    }
+
+    pub fn max_parts(mut self, max_parts: usize) -> Self {
+        self.payment.max_parts = max_parts;
+        self
+    }
+
+    pub fn max_hops(mut self, max_hops: usize) -> Self {
+        self.payment.max_hops = max_hops;
+        self
+    }
+
+    pub fn payment_parts(mut self, payment_parts: PaymentParts) -> Self {
+        self.payment.payment_parts = Some(payment_parts);
+        self
+    }
+
+    pub fn max_concurrent_shards(mut self, max_concurrent_shards: usize) -> Self {
+        self.payment.max_concurrent_shards = max_concurrent_shards;
+        self
+    }
+
+    pub fn service_fee_msat(mut self, service_fee_msat: u64) -> Self {
+        self.payment.service_fee_msat = service_fee_msat;
+        self
+    }
+
+    pub fn max_fee_msat(mut self, max_fee_msat: u64) -> Self {
+        self.payment.max_fee_msat = Some(max_fee_msat);
+        self
+    }
+
+    pub fn slow_threshold(mut self, threshold_secs: f32) -> Self {
+        self.payment.slow_threshold = Some(Time::from_secs(threshold_secs));
+        self
+    }
+
+    pub fn metadata(mut self, key: &str, value: &str) -> Self {
+        self.payment
+            .metadata
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn first_hop(mut self, channel_id: &str) -> Self {
+        self.payment.first_hop = Some(channel_id.to_string());
+        self
+    }
+
+    /// Keeps every path of the payment clear of the node
+    pub fn exclude_node(mut self, node: &str) -> Self {
+        self.payment.exclude_nodes.insert(node.to_string());
+        self
+    }
+
+    /// Applies the min shard amount, max parts, max hops and splitting of the wallet
+    pub fn with_profile(self, profile: WalletProfile) -> Self {
+        self.min_shard_amt(profile.min_shard_amt())
+            .max_parts(profile.max_parts())
+            .max_hops(profile.max_hops())
+            .payment_parts(profile.payment_parts())
+    }
+
+    pub fn build(self) -> Payment {
+        self.payment
+    }
+}
+
+/// A path that was attempted without delivering the amount along with why it failed and the
+/// channel it failed at, if known
+pub type FailedPath = (CandidatePath, FailureReason, Option<String>);
+
+/// Why a payment could not be delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum FailureReason {
+    /// No path between sender and receiver exists regardless of the balances
+    NoTopology,
+    /// Paths exist but none of them has enough liquidity for the amount
+    InsufficientLiquidity,
+    /// Consecutive splits kept failing at the same channel
+    PersistentBottleneck,
+    /// A shard of the same amount failed at the same channel as an earlier one
+    SplitCycle,
+    /// A griefing node held the HTLC until it timed out
+    HtlcTimeout,
+    /// Splitting a part further would have left one below min_shard_amt, which the dust
+    /// policy rejects
+    DustPart,
+    /// A part would have resolved after the payment's deadline
+    DeadlineExceeded,
+    /// Payments of 0 msat are only allowed for probes
+    ZeroAmount,
+    /// The amount including fees exceeds the largest amount that can be represented
+    AmountOverflow,
+    /// The fees come out of the amount and would leave nothing of it for dest
+    FeesExceedAmount,
+    /// The routing fees plus the service fee exceed the sender's max fee
+    FeeBudgetExceeded,
+    /// The simulation's budget of HTLC attempts across all payments was used up before the
+    /// payment was sent
+    AttemptBudgetExhausted,
+}
+
+impl FailureReason {
+    /// The sender gave up on the payment rather than running out of routes
+    pub fn is_abandonment(&self) -> bool {
+        matches!(
+            self,
+            FailureReason::DeadlineExceeded
+                | FailureReason::FeeBudgetExceeded
+                | FailureReason::AttemptBudgetExhausted
+        )
+    }
+}
+
+/// A part of a payment that reached dest along with the balance changes it made
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShardRef {
+    /// None if the payment was not split
+    pub shard_id: Option<ShardId>,
+    /// (node, channel_id, amount) in the order of the path, starting with the source
+    pub(crate) transfers: Vec<(ID, String, usize)>,
+}
+
+impl From<&CandidatePath> for ShardRef {
+    fn from(path: &CandidatePath) -> Self {
+        Self {
+            shard_id: path.shard_id,
+            transfers: path.transfers(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -37,7 +270,10 @@ pub struct PaymentShard {
     pub(crate) used_path: CandidatePath,
     pub(crate) min_shard_amt: usize,
     pub(crate) htlc_attempts: usize,
-    pub(crate) failed_paths: Vec<CandidatePath>,
+    pub(crate) failed_paths: Vec<FailedPath>,
+    pub(crate) failing_channel: Option<String>,
+    pub(crate) fees_on_top: bool,
+    pub(crate) failed_channels: Vec<String>,
 }
 
 impl Payment {
@@ -65,14 +301,237 @@ impl Payment {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: Vec::default(),
+            failure_reason: None,
+            target_parts: None,
+            failing_channel: None,
+            completion_time: Time::default(),
+            held_htlcs: 0,
+            cancelled_htlcs: 0,
+            excluded_channels: Vec::default(),
+            deadline: None,
+            is_probe: false,
+            fees_on_top: true,
+            failed_channels: Vec::default(),
+            shard_amounts: Vec::default(),
+            last_hops: Vec::default(),
+            max_parts: crate::MAX_PARTS,
+            max_hops: crate::MAX_HOPS,
+            payment_parts: None,
+            delivered_msat: 0,
+            first_hop: None,
+            exclude_nodes: Default::default(),
+            total_fees_msat: 0,
+            max_concurrent_shards: 0,
+            service_fee_msat: 0,
+            max_fee_msat: None,
+            slow_threshold: None,
+            metadata: HashMap::default(),
+        }
+    }
+
+    /// A payment that has not been sent yet with the same source, dest, amount and sender
+    /// settings as this one
+    pub(crate) fn unsent_copy(&self, payment_id: PaymentId) -> Self {
+        Self {
+            target_parts: self.target_parts,
+            deadline: self.deadline,
+            is_probe: self.is_probe,
+            fees_on_top: self.fees_on_top,
+            max_parts: self.max_parts,
+            max_hops: self.max_hops,
+            payment_parts: self.payment_parts,
+            first_hop: self.first_hop.clone(),
+            exclude_nodes: self.exclude_nodes.clone(),
+            max_concurrent_shards: self.max_concurrent_shards,
+            service_fee_msat: self.service_fee_msat,
+            max_fee_msat: self.max_fee_msat,
+            slow_threshold: self.slow_threshold,
+            metadata: self.metadata.clone(),
+            ..Self::new(
+                payment_id,
+                self.source.clone(),
+                self.dest.clone(),
+                self.amount_msat,
+                Some(self.min_shard_amt),
+            )
+        }
+    }
+
+    /// The parts that reached dest so far
+    pub fn delivered_shards(&self) -> Vec<ShardRef> {
+        self.used_paths.iter().map(ShardRef::from).collect()
+    }
+
+    /// Sets the completion time to the resolution of the slowest used path given the time the
+    /// payment was sent at. A path resolves after its total timelock in secs
+    pub(crate) fn set_completion_time(&mut self, sent_at: Time) {
+        self.completion_time = self
+            .used_paths
+            .iter()
+            .map(|p| sent_at + Time::from_secs(p.time as f32))
+            .max()
+            .unwrap_or(sent_at);
+    }
+
+    /// A copy of the payment's outcome without the state used while sending
+    pub fn result_view(&self) -> PaymentResult {
+        PaymentResult {
+            source: self.source.clone(),
+            dest: self.dest.clone(),
+            amount_msat: self.amount_msat,
+            succeeded: self.succeeded,
+            num_parts: self.num_parts,
+            htlc_attempts: self.htlc_attempts,
+            total_fees: self.paid_fees(),
+            used_paths: self.used_paths.clone(),
         }
     }
 
+    /// Fees paid over all used paths, in msat
+    fn paid_fees(&self) -> usize {
+        self.used_paths.iter().map(|p| p.path_fees()).sum()
+    }
+
+    /// Records what dest received over the used paths and the fees paid on top
+    pub(crate) fn settle(&mut self) {
+        self.delivered_msat = self.used_paths.iter().map(|p| p.path_amount()).sum();
+        self.total_fees_msat = self.paid_fees() + self.service_fee_msat as usize;
+    }
+
+    /// True if the service fee exceeds the max fee before any routing fees
+    pub(crate) fn exceeds_service_fee_budget(&self) -> bool {
+        self.max_fee_msat
+            .is_some_and(|max_fee| self.service_fee_msat > max_fee)
+    }
+
+    /// True if the fees over the used paths plus the service fee exceed the max fee
+    pub(crate) fn exceeds_fee_budget(&self) -> bool {
+        match self.max_fee_msat {
+            Some(max_fee) => self.paid_fees() as u64 + self.service_fee_msat > max_fee,
+            None => false,
+        }
+    }
+
+    /// Fraction of the channels used by the payment that more than one of its paths share
+    /// 0 if the paths are disjoint
+    pub fn path_overlap(&self) -> f64 {
+        let mut uses: HashMap<&String, usize> = HashMap::new();
+        for path in self.used_paths.iter() {
+            let channels: HashSet<&String> = path.path.hops.iter().map(|h| &h.3).collect();
+            for channel in channels {
+                *uses.entry(channel).or_insert(0) += 1;
+            }
+        }
+        if uses.is_empty() {
+            return 0.0;
+        }
+        let shared = uses.values().filter(|n| **n > 1).count();
+        shared as f64 / uses.len() as f64
+    }
+
+    /// How much the used paths have in common, as a proxy for how likely their parts fail
+    /// together. Each pair of paths scores the mean of the Jaccard similarities of their channels
+    /// and of their intermediaries, and the pairs are weighted by the product of their amounts
+    /// 0 for fewer than two paths or disjoint ones, 1 for identical ones
+    pub fn shard_correlation(&self) -> f64 {
+        let jaccard = |a: &HashSet<&String>, b: &HashSet<&String>| {
+            let union = a.union(b).count();
+            if union == 0 {
+                0.0
+            } else {
+                a.intersection(b).count() as f64 / union as f64
+            }
+        };
+        let paths: Vec<(f64, HashSet<&String>, HashSet<&String>)> = self
+            .used_paths
+            .iter()
+            .map(|p| {
+                let hops = &p.path.hops;
+                let channels = hops.iter().map(|h| &h.3).collect();
+                let intermediaries = hops
+                    .iter()
+                    .skip(1)
+                    .take(hops.len().saturating_sub(2))
+                    .map(|h| &h.0)
+                    .collect();
+                (p.path_amount() as f64, channels, intermediaries)
+            })
+            .collect();
+        let (mut weighted, mut total) = (0.0, 0.0);
+        for (i, (amount1, channels1, nodes1)) in paths.iter().enumerate() {
+            for (amount2, channels2, nodes2) in paths.iter().skip(i + 1) {
+                let similarity = (jaccard(channels1, channels2) + jaccard(nodes1, nodes2)) / 2.0;
+                weighted += amount1 * amount2 * similarity;
+                total += amount1 * amount2;
+            }
+        }
+        if total == 0.0 {
+            0.0
+        } else {
+            weighted / total
+        }
+    }
+
+    /// How much the payment drained channels that are close to empty. Sums, over the channels
+    /// the used paths send over, the share of the channel's capacity the payment moved weighted
+    /// by the share of its capacity that is depleted in the graph now. Channels no longer in the
+    /// graph are left out
+    pub fn depletion_pressure(&self, graph: &Graph) -> f64 {
+        let mut sent: BTreeMap<String, u64> = BTreeMap::new();
+        for path in self.used_paths.iter() {
+            for (channel_id, amount) in path.hop_amounts() {
+                *sent.entry(channel_id).or_insert(0) += amount;
+            }
+        }
+        let edges: HashMap<&String, _> = graph
+            .get_edges()
+            .values()
+            .flatten()
+            .map(|e| (&e.channel_id, e))
+            .collect();
+        sent.iter()
+            .filter_map(|(channel_id, amount)| {
+                let edge = edges.get(channel_id).filter(|e| e.capacity > 0)?;
+                let capacity = edge.capacity as f64;
+                let depleted = 1.0 - (edge.balance as f64 / capacity).min(1.0);
+                Some(*amount as f64 / capacity * depleted)
+            })
+            .sum()
+    }
+
+    /// Mean number of hops, including source and dest, of the used paths weighted by the
+    /// amount each delivered. 0 if there are none
+    pub fn weighted_path_length(&self) -> f64 {
+        let total: usize = self.used_paths.iter().map(|p| p.path_amount()).sum();
+        if total == 0 {
+            return 0.0;
+        }
+        let weighted: f64 = self
+            .used_paths
+            .iter()
+            .map(|p| p.path_amount() as f64 * p.path.hops.len() as f64)
+            .sum();
+        weighted / total as f64
+    }
+
     /// All payments are sent as shards, regardless of mpp or single
     pub(crate) fn to_shard(&self, amount: usize) -> PaymentShard {
         PaymentShard::new(self, amount)
     }
 
+    /// Takes over the outcome of an attempt made with the shard. The payment's own settings,
+    /// such as min_shard_amt or max_parts, are kept for the parts it may be split into
+    pub(crate) fn apply_attempt(&mut self, shard: &PaymentShard) {
+        let attempted = shard.to_payment(1);
+        self.succeeded = attempted.succeeded;
+        self.num_parts = attempted.num_parts;
+        self.used_paths = attempted.used_paths;
+        self.htlc_attempts = attempted.htlc_attempts;
+        self.failed_paths = attempted.failed_paths;
+        self.failing_channel = attempted.failing_channel;
+        self.failed_channels = attempted.failed_channels;
+    }
+
     /// Split payment and return two shards
     pub(crate) fn split_payment(payment: &Payment) -> Option<(Payment, Payment)> {
         let amt_to_split = payment.amount_msat;
@@ -92,7 +551,7 @@ impl Payment {
         } else {
             // ceil one, floor the either
             let prev_amt = amt_to_split;
-            let shard1_amount = (prev_amt + 2 - 1) / 2;
+            let shard1_amount = prev_amt.div_ceil(2);
             let shard2_amount = prev_amt / 2;
             assert_eq!(
                 shard1_amount + shard2_amount,
@@ -114,6 +573,136 @@ impl Payment {
             Some((shard1, shard2))
         }
     }
+
+    /// Splits off a shard of the given amount, the other shard carries the rest
+    pub(crate) fn split_payment_at(payment: &Payment, amount: usize) -> (Payment, Payment) {
+        let shard1 = Payment {
+            amount_msat: amount,
+            htlc_attempts: 0,
+            ..payment.clone()
+        };
+        let shard2 = Payment {
+            amount_msat: payment.amount_msat - amount,
+            htlc_attempts: 0,
+            ..payment.clone()
+        };
+        (shard1, shard2)
+    }
+
+    /// Rounds the shards' amounts down to multiples of quantum. The last shard takes the
+    /// remainder so that the shards still add up to the same amount
+    /// The shards are left as they are if one of them would be rounded down to 0
+    pub(crate) fn round_to_quantum(mut shards: Vec<Payment>, quantum: usize) -> Vec<Payment> {
+        if quantum <= 1 || shards.iter().any(|s| s.amount_msat < quantum) {
+            return shards;
+        }
+        let total: usize = shards.iter().map(|s| s.amount_msat).sum();
+        if let Some((last, rest)) = shards.split_last_mut() {
+            for shard in rest.iter_mut() {
+                shard.amount_msat -= shard.amount_msat % quantum;
+            }
+            last.amount_msat = total - rest.iter().map(|s| s.amount_msat).sum::<usize>();
+        }
+        shards
+    }
+
+    /// Whether one of the shards is below min_shard_amt
+    pub(crate) fn has_dust(shards: &[Payment]) -> bool {
+        shards.iter().any(|s| s.amount_msat < s.min_shard_amt)
+    }
+
+    /// Adds each shard below min_shard_amt to the shard following it, the last one to the shard
+    /// before it. The shards still add up to the same amount
+    pub(crate) fn fold_dust(shards: Vec<Payment>) -> Vec<Payment> {
+        let is_dust = |shard: &Payment| shard.amount_msat < shard.min_shard_amt;
+        let mut folded: Vec<Payment> = vec![];
+        for shard in shards {
+            match folded.last_mut() {
+                Some(prev) if is_dust(prev) => prev.amount_msat += shard.amount_msat,
+                _ => folded.push(shard),
+            }
+        }
+        if folded.len() > 1 && folded.last().is_some_and(is_dust) {
+            if let Some(dust) = folded.pop() {
+                if let Some(prev) = folded.last_mut() {
+                    prev.amount_msat += dust.amount_msat;
+                }
+            }
+        }
+        folded
+    }
+
+    /// The transfers of the successful shards, one slice per shard
+    /// Each shard's transfers start with the source's entry and end with dest's, so a shard ends
+    /// at the first entry of dest after its start, even if source and dest are the same node
+    pub(crate) fn shard_transfers(&self) -> Vec<&[(ID, String, usize)]> {
+        let mut shards = vec![];
+        let mut start = 0;
+        for (idx, transfer) in self.successful_shards.iter().enumerate() {
+            if idx > start && transfer.0 == self.dest {
+                shards.push(&self.successful_shards[start..=idx]);
+                start = idx + 1;
+            }
+        }
+        if start < self.successful_shards.len() {
+            shards.push(&self.successful_shards[start..]);
+        }
+        shards
+    }
+
+    /// Orders the successful shards' transfers and used paths independent of the order in which
+    /// the shards completed
+    /// The transfers of one shard are kept together and start with the source's entry which the
+    /// revert logic relies on
+    pub(crate) fn sort_shards(&mut self) {
+        let mut shards: Vec<Vec<(ID, String, usize)>> = self
+            .shard_transfers()
+            .into_iter()
+            .map(|transfers| transfers.to_vec())
+            .collect();
+        shards.sort_by(|a, b| {
+            let key = |shard: &Vec<(ID, String, usize)>| {
+                (
+                    shard.iter().map(|t| t.1.clone()).collect::<Vec<String>>(),
+                    shard.first().map(|t| t.2),
+                )
+            };
+            key(a).cmp(&key(b))
+        });
+        self.successful_shards = shards.into_iter().flatten().collect();
+        self.used_paths.sort_by(|a, b| {
+            let key = |p: &CandidatePath| {
+                (
+                    p.path
+                        .hops
+                        .iter()
+                        .map(|h| h.3.clone())
+                        .collect::<Vec<String>>(),
+                    p.amount,
+                )
+            };
+            key(a).cmp(&key(b))
+        });
+    }
+
+    /// Divides the payment into (at most) the given number of nearly equal parts
+    /// The number of parts is reduced if the parts would otherwise be smaller than min_shard_amt
+    pub(crate) fn split_payment_into(payment: &Payment, parts: usize) -> Vec<Payment> {
+        let max_parts = payment
+            .amount_msat
+            .checked_div(payment.min_shard_amt)
+            .unwrap_or(parts);
+        let parts = parts.min(max_parts).min(payment.max_parts).max(1);
+        let (part_amount, remainder) = (payment.amount_msat / parts, payment.amount_msat % parts);
+        (0..parts)
+            .map(|i| Payment {
+                amount_msat: part_amount + usize::from(i < remainder),
+                htlc_attempts: 0,
+                target_parts: None,
+                ..payment.clone()
+            })
+            .collect()
+    }
 }
 
 impl PaymentShard {
@@ -124,10 +713,13 @@ impl PaymentShard {
             dest: payment.dest.clone(),
             amount,
             used_path: CandidatePath::default(),
-            min_shard_amt: crate::MIN_SHARD_AMOUNT,
+            min_shard_amt: payment.min_shard_amt,
             succeeded: payment.succeeded,
             htlc_attempts: payment.htlc_attempts,
             failed_paths: payment.failed_paths.clone(),
+            failing_channel: payment.failing_channel.clone(),
+            fees_on_top: payment.fees_on_top,
+            failed_channels: payment.failed_channels.clone(),
         }
     }
 
@@ -145,6 +737,31 @@ impl PaymentShard {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: self.failed_paths.clone(),
+            failure_reason: None,
+            target_parts: None,
+            failing_channel: self.failing_channel.clone(),
+            completion_time: Default::default(),
+            held_htlcs: 0,
+            cancelled_htlcs: 0,
+            excluded_channels: vec![],
+            deadline: None,
+            is_probe: false,
+            fees_on_top: self.fees_on_top,
+            failed_channels: self.failed_channels.clone(),
+            shard_amounts: Vec::default(),
+            last_hops: Vec::default(),
+            max_parts: crate::MAX_PARTS,
+            max_hops: crate::MAX_HOPS,
+            payment_parts: None,
+            delivered_msat: 0,
+            first_hop: None,
+            exclude_nodes: Default::default(),
+            total_fees_msat: 0,
+            max_concurrent_shards: 0,
+            service_fee_msat: 0,
+            max_fee_msat: None,
+            slow_threshold: None,
+            metadata: Default::default(),
         }
     }
 }
@@ -181,12 +798,8 @@ mod tests {
             amount_msat: amount,
             succeeded: false,
             min_shard_amt: crate::MIN_SHARD_AMOUNT,
-            used_paths: Vec::default(),
-            num_parts: 1,
             htlc_attempts: 0,
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-            failed_paths: vec![],
+            ..Default::default()
         };
         assert_eq!(actual, expected);
         assert_eq!(actual.succeeded, expected.succeeded);
@@ -202,18 +815,9 @@ mod tests {
         let amount = 10000;
         let num_parts = 1;
         let payment = Payment {
-            payment_id: id,
-            source: source.clone(),
-            dest,
-            amount_msat: amount,
             succeeded: true,
-            min_shard_amt: crate::MIN_SHARD_AMOUNT,
-            used_paths: Vec::default(),
-            num_parts: 1,
             htlc_attempts: 1,
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-            failed_paths: vec![],
+            ..Payment::new(id, source.clone(), dest, amount, None)
         };
         let shard = payment.to_shard(amount);
         assert_eq!(shard.payment_id, id);
@@ -231,18 +835,8 @@ mod tests {
         let dest = "dest".to_string();
         let amount = crate::MIN_SHARD_AMOUNT * 2 + 1;
         let payment = Payment {
-            payment_id: 0,
-            source: source.clone(),
-            dest,
-            amount_msat: amount,
-            succeeded: false,
-            min_shard_amt: crate::MIN_SHARD_AMOUNT,
-            used_paths: Vec::default(),
-            num_parts: 1,
             htlc_attempts: 1,
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-            failed_paths: vec![],
+            ..Payment::new(0, source.clone(), dest, amount, None)
         };
         let actual = Payment::split_payment(&payment).unwrap();
         let expected = (
@@ -265,18 +859,8 @@ mod tests {
         let dest = "dest".to_string();
         let amount = crate::MIN_SHARD_AMOUNT + 1;
         let payment = Payment {
-            payment_id: 0,
-            source: source.clone(),
-            dest,
-            amount_msat: amount,
-            succeeded: false,
-            min_shard_amt: crate::MIN_SHARD_AMOUNT,
-            used_paths: Vec::default(),
-            num_parts: 1,
             htlc_attempts: 1,
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-            failed_paths: vec![],
+            ..Payment::new(0, source.clone(), dest, amount, None)
         };
         assert!(Payment::split_payment(&payment).is_none());
     }
@@ -287,18 +871,14 @@ mod tests {
         let dest = "dest".to_string();
         let amount = crate::MIN_SHARD_AMOUNT;
         let payment = Payment {
-            payment_id: 0,
-            source: source.clone(),
-            dest,
-            amount_msat: amount,
-            succeeded: false,
-            min_shard_amt: crate::MIN_SHARD_AMOUNT / 2,
-            used_paths: Vec::default(),
-            num_parts: 1,
             htlc_attempts: 1,
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-            failed_paths: vec![],
+            ..Payment::new(
+                0,
+                source.clone(),
+                dest,
+                amount,
+                Some(crate::MIN_SHARD_AMOUNT / 2),
+            )
         };
         let actual = Payment::split_payment(&payment).unwrap();
         let expected = (
@@ -336,16 +916,193 @@ mod tests {
             amount_msat: amount,
             succeeded: false,
             min_shard_amt,
-            used_paths: Vec::default(),
-            num_parts: 1,
             htlc_attempts: 0,
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-            failed_paths: vec![],
+            ..Default::default()
         };
         assert_eq!(actual, expected);
         assert_eq!(actual.succeeded, expected.succeeded);
         assert_eq!(actual.min_shard_amt, expected.min_shard_amt);
         assert_eq!(actual.htlc_attempts, expected.htlc_attempts);
     }
+
+    #[test]
+    fn split_into_target_parts() {
+        let mut payment = Payment::new(
+            0,
+            "source".to_string(),
+            "dest".to_string(),
+            4003,
+            Some(1000),
+        );
+        let parts = Payment::split_payment_into(&payment, 4);
+        assert_eq!(
+            parts.iter().map(|p| p.amount_msat).collect::<Vec<usize>>(),
+            vec![1001, 1001, 1001, 1000]
+        );
+        // parts would fall below min_shard_amt
+        payment.min_shard_amt = 2000;
+        assert_eq!(Payment::split_payment_into(&payment, 4).len(), 2);
+    }
+
+    #[test]
+    fn wallet_profile_presets() {
+        let amount_msat = 100000 * crate::SAT_SCALE;
+        let payment = PaymentBuilder::new(0, "source".to_string(), "dest".to_string(), amount_msat)
+            .with_profile(WalletProfile::Ldk)
+            .build();
+        assert_eq!(payment.max_parts, 16);
+        assert_eq!(payment.min_shard_amt, 1000 * crate::SAT_SCALE);
+        assert_eq!(payment.max_hops, 19);
+        assert_eq!(payment.payment_parts, Some(PaymentParts::FeeOptimalSplit));
+        assert_eq!(payment.amount_msat, amount_msat);
+        // 100 parts would still be above the min shard amount
+        assert_eq!(Payment::split_payment_into(&payment, 32).len(), 16);
+        let payment = PaymentBuilder::new(1, "source".to_string(), "dest".to_string(), amount_msat)
+            .with_profile(WalletProfile::Phoenix)
+            .build();
+        assert_eq!(Payment::split_payment_into(&payment, 32).len(), 1);
+    }
+
+    #[test]
+    fn result_view_of_a_completed_payment() {
+        let (source, dest) = ("alice".to_string(), "dina".to_string());
+        let amount_msat = 5000;
+        let mut simulator = crate::attempt::tests::init_sim(None, None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+            }
+        }
+        simulator.add_invoice(crate::Invoice::new(0, amount_msat, &source, &dest));
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, None);
+        assert!(simulator.send_mpp_payment(&mut payment));
+        let result = payment.result_view();
+        assert_eq!(result.source, source);
+        assert_eq!(result.dest, dest);
+        assert_eq!(result.amount_msat, amount_msat);
+        assert!(result.succeeded);
+        assert_eq!(result.num_parts, payment.num_parts);
+        assert_eq!(result.htlc_attempts, payment.htlc_attempts);
+        assert_eq!(result.used_paths, payment.used_paths);
+        // bob and chan charge 100 and 75 msat
+        assert_eq!(result.total_fees, 175);
+        let json = serde_json::to_value(&result).unwrap();
+        let fields: Vec<&String> = json.as_object().unwrap().keys().collect();
+        assert_eq!(fields.len(), 8);
+        assert!(serde_json::to_value(&payment)
+            .unwrap()
+            .get("successful_shards")
+            .is_some());
+        assert!(json.get("successful_shards").is_none());
+        assert!(json.get("failed_amounts").is_none());
+    }
+
+    #[test]
+    fn depletion_pressure_of_thin_and_fat_channels() {
+        let pressure = |balance: usize| {
+            let (source, dest) = ("bob".to_string(), "alice".to_string());
+            let amount_msat = 1000;
+            let json_file = "../test_data/trivial_multipath.json";
+            let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+            for edges in simulator.graph.edges.values_mut() {
+                for e in edges {
+                    e.balance = balance;
+                    e.capacity = 2 * balance;
+                }
+            }
+            simulator.add_invoice(crate::Invoice::new(0, amount_msat, &source, &dest));
+            let mut payment = Payment::new(0, source, dest, amount_msat, None);
+            assert!(simulator.send_mpp_payment(&mut payment));
+            payment.depletion_pressure(&simulator.graph)
+        };
+        // bob -> carol -> alice leaves bob's and carol's side of the thin channels almost empty
+        let thin = pressure(1100);
+        let fat = pressure(1000000);
+        assert!(thin > fat);
+        assert!(fat > 0.0);
+    }
+
+    #[test]
+    fn shard_order_is_independent_of_completion_order() {
+        use crate::traversal::pathfinding::Path;
+        use std::collections::VecDeque;
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let shard = |channels: [&str; 2], amount: usize| {
+            let path = CandidatePath {
+                path: Path {
+                    src: source.clone(),
+                    dest: dest.clone(),
+                    hops: VecDeque::from([
+                        (source.clone(), amount + 10, 5, channels[0].to_string()),
+                        ("carol".to_string(), 10, 5, channels[1].to_string()),
+                        (dest.clone(), amount, 0, "alice-carol".to_string()),
+                    ]),
+                },
+                weight: 10.0,
+                amount: amount + 10,
+                time: 5,
+                shard_id: None,
+            };
+            let transfers = vec![
+                (source.clone(), channels[0].to_string(), amount + 10),
+                ("carol".to_string(), channels[1].to_string(), 10),
+                (dest.clone(), "alice-carol".to_string(), amount),
+            ];
+            (path, transfers)
+        };
+        let shards = [
+            shard(["bob-eve", "eve-carol"], 3000),
+            shard(["bob-carol", "carol-alice"], 6000),
+            shard(["bob-carol", "carol-alice"], 3000),
+        ];
+        let mut in_order = Payment::new(0, source.clone(), dest.clone(), 12000, None);
+        let mut shuffled = in_order.clone();
+        for (path, transfers) in shards.iter() {
+            in_order.used_paths.push(path.clone());
+            in_order.successful_shards.extend(transfers.clone());
+        }
+        for (path, transfers) in shards.iter().rev() {
+            shuffled.used_paths.push(path.clone());
+            shuffled.successful_shards.extend(transfers.clone());
+        }
+        in_order.sort_shards();
+        shuffled.sort_shards();
+        assert_eq!(in_order.used_paths, shuffled.used_paths);
+        assert_eq!(in_order.successful_shards, shuffled.successful_shards);
+        assert_eq!(in_order.successful_shards[0].0, source);
+    }
+
+    #[test]
+    fn shard_transfers_of_a_circular_payment() {
+        let node = "alice".to_string();
+        let mut payment = Payment::new(0, node.clone(), node.clone(), 2000, None);
+        let shard = |via: &str, amount: usize| {
+            vec![
+                (node.clone(), format!("alice-{}", via), amount + 10),
+                (via.to_string(), format!("{}-carol", via), 10),
+                ("carol".to_string(), "carol-alice".to_string(), 0),
+                (node.clone(), "alice-carol".to_string(), amount),
+            ]
+        };
+        payment.successful_shards.extend(shard("bob", 1000));
+        payment.successful_shards.extend(shard("dave", 1000));
+        let shards = payment.shard_transfers();
+        assert_eq!(shards.len(), 2);
+        assert_eq!(shards[0], shard("bob", 1000).as_slice());
+        assert_eq!(shards[1], shard("dave", 1000).as_slice());
+    }
+
+    #[test]
+    fn amounts_in_sat_and_btc() {
+        assert_eq!(Amount::from_sat(1).as_msat(), 1000);
+        assert_eq!(Amount::from_sat(1), Amount::from_msat(1000));
+        assert_eq!(Amount::from_btc(0.001), Amount::from_sat(100000));
+        assert_eq!(Amount::from_sat(21).to_string(), "21 sat");
+        assert_eq!(Amount::from_msat(1500).to_string(), "1.500 sat");
+        let payment = PaymentBuilder::new(0, "bob".to_string(), "alice".to_string(), 0)
+            .amount(Amount::from_sat(1))
+            .build();
+        assert_eq!(payment.amount_msat, 1000);
+    }
 }
@@ -0,0 +1,116 @@
+use crate::{PaymentParts, RoutingPolicy};
+use serde::{Deserialize, Serialize};
+
+/// All parameters of a simulation so that runs can be reproduced from a file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SimulationConfig {
+    /// Seeds the random number generator
+    pub seed: u64,
+    /// Amount of the payments the simulation creates, in msat
+    pub amount: usize,
+    /// Single or multi-path
+    pub payment_parts: PaymentParts,
+    /// Max number of parts a payment is split into
+    pub max_parts: usize,
+    /// Smallest part a payment is split into, in msat
+    pub min_shard_amt: usize,
+    /// Time between payments and until a payment resolves
+    pub sim_delay_in_secs: f32,
+    /// What routes optimise for
+    pub routing_policy: RoutingPolicy,
+    /// Chance of an intermediary failing to forward an HTLC regardless of its balance
+    pub failure_probability: f32,
+    /// Parts of an MPP avoid the channels used by the parts delivered before them
+    pub disjoint_paths: bool,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            amount: 0,
+            payment_parts: PaymentParts::Split,
+            max_parts: crate::MAX_PARTS,
+            min_shard_amt: crate::MIN_SHARD_AMOUNT,
+            sim_delay_in_secs: crate::SIM_DELAY_IN_SECS,
+            routing_policy: RoutingPolicy::default(),
+            failure_probability: 0.0,
+            disjoint_paths: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{graph::Graph, RoutingMetric, Simulation};
+    use std::path::Path;
+
+    #[test]
+    fn simulation_from_config_read_from_json() {
+        let config = SimulationConfig {
+            seed: 7,
+            amount: 5000,
+            payment_parts: PaymentParts::Split,
+            max_parts: 4,
+            min_shard_amt: 2000,
+            sim_delay_in_secs: 60.0,
+            routing_policy: RoutingPolicy::MinHops,
+            failure_probability: 1.0,
+            disjoint_paths: true,
+        };
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(
+            serde_json::from_str::<SimulationConfig>(&json).unwrap(),
+            config
+        );
+        // missing settings take their defaults
+        let partial: SimulationConfig = serde_json::from_str(r#"{"seed": 7}"#).unwrap();
+        assert_eq!(partial.max_parts, crate::MAX_PARTS);
+        assert_eq!(partial.sim_delay_in_secs, crate::SIM_DELAY_IN_SECS);
+        let graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                Path::new("../test_data/trivial_multipath.json"),
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        // splitting needs parts of some size
+        let no_parts = SimulationConfig {
+            min_shard_amt: 0,
+            ..config.clone()
+        };
+        assert!(Simulation::from_config(graph.clone(), &no_parts).is_err());
+        // probabilities out of range are clamped like set_failure_probability does
+        let certain_failure = SimulationConfig {
+            failure_probability: 1.5,
+            ..config.clone()
+        };
+        let simulator = Simulation::from_config(graph.clone(), &certain_failure).unwrap();
+        assert_eq!(simulator.failure_probability, 1.0);
+        let mut simulator = Simulation::from_config(graph, &config).unwrap();
+        simulator.set_record_events(true);
+        assert_eq!(simulator.routing_metric, RoutingMetric::MinHops);
+        // alice can only be reached via intermediaries, all of which fail to forward
+        let pairs = vec![
+            ("bob".to_string(), "alice".to_string()),
+            ("eve".to_string(), "alice".to_string()),
+        ];
+        let result = simulator.run(pairs.into_iter(), None, false);
+        assert_eq!(result.amount, 5000);
+        assert_eq!(result.num_failed, 2);
+        assert!(result
+            .failed_payments
+            .iter()
+            .all(|p| p.max_parts == 4 && p.min_shard_amt == 2000));
+        let scheduled: Vec<f32> = simulator
+            .timeline()
+            .iter()
+            .filter(|e| e.kind == "scheduled")
+            .map(|e| e.time.as_secs())
+            .collect();
+        assert_eq!(scheduled, vec![0.0, 60.0]);
+    }
+}
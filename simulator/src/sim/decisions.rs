@@ -0,0 +1,339 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    f64::consts::PI,
+    fmt,
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(not(test))]
+use log::{error, warn};
+#[cfg(test)]
+use std::{println as warn, println as error};
+
+/// A choice of a simulation run that was left to the RNG
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Decision {
+    /// Tie-break value of an edge when ties are broken randomly
+    TieBreak(usize),
+    /// Roll against the failure probability of a forwarding node
+    FailureRoll(f32),
+    /// Standard gaussian sample a channel's fee is perturbed by
+    FeeNoise(f64),
+}
+
+impl Decision {
+    /// Name of the kind of choice, regardless of its value
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::TieBreak(_) => "tie break",
+            Self::FailureRoll(_) => "failure roll",
+            Self::FeeNoise(_) => "fee noise",
+        }
+    }
+}
+
+/// Why a replay could not reproduce the recorded run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayError {
+    /// The run asked for a different kind of choice than the one recorded at the position
+    Diverged {
+        position: usize,
+        recorded: Decision,
+        requested: &'static str,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Diverged {
+                position,
+                recorded,
+                requested,
+            } => write!(
+                f,
+                "replay diverged at decision {position}: recorded a {}, the run asked for a {requested}",
+                recorded.kind()
+            ),
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+/// What the log does with the choices of the run
+#[derive(Debug, Clone, Default)]
+enum LogState {
+    #[default]
+    Off,
+    Recording(Vec<Decision>),
+    Replaying {
+        decisions: VecDeque<Decision>,
+        replayed: usize,
+    },
+    /// A replay that stopped as the run took a different turn than the recorded one. The RNG
+    /// decides from then on
+    Diverged(ReplayError),
+}
+
+/// Draws the choices of a run from its own seeded RNG, records them or hands out recorded ones
+/// instead of drawing new ones
+#[derive(Debug, Clone)]
+pub(crate) struct DecisionLog {
+    state: LogState,
+    rng: StdRng,
+}
+
+impl Default for DecisionLog {
+    /// Seeded from the global RNG, for PathFinders that are not created by a simulation
+    fn default() -> Self {
+        let seed = crate::RNG.lock().unwrap().gen();
+        Self::seeded(seed)
+    }
+}
+
+/// The simulation's decision log, shared with the PathFinders it creates. Cloning it copies the
+/// log, so that dry runs on a copy of the simulation neither record into nor consume the
+/// original's decisions
+#[derive(Debug)]
+pub(crate) struct SharedDecisions(Arc<Mutex<DecisionLog>>);
+
+impl Clone for SharedDecisions {
+    fn clone(&self) -> Self {
+        Self(Arc::new(Mutex::new(self.0.lock().unwrap().clone())))
+    }
+}
+
+impl Deref for SharedDecisions {
+    type Target = Mutex<DecisionLog>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl SharedDecisions {
+    pub(crate) fn seeded(seed: u64) -> Self {
+        Self(Arc::new(Mutex::new(DecisionLog::seeded(seed))))
+    }
+
+    /// The same log rather than a copy of it
+    pub(crate) fn share(&self) -> Arc<Mutex<DecisionLog>> {
+        Arc::clone(&self.0)
+    }
+}
+
+impl DecisionLog {
+    pub(crate) fn seeded(seed: u64) -> Self {
+        Self {
+            state: LogState::Off,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Starts the RNG over from the seed, keeping what the log records or replays
+    pub(crate) fn reseed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub(crate) fn start_recording(&mut self) {
+        self.state = LogState::Recording(vec![]);
+    }
+
+    pub(crate) fn start_replay(&mut self, decisions: Vec<Decision>) {
+        self.state = LogState::Replaying {
+            decisions: decisions.into(),
+            replayed: 0,
+        };
+    }
+
+    pub(crate) fn tie_break(&mut self) -> usize {
+        match self.replay("tie break") {
+            Some(Decision::TieBreak(value)) => value,
+            _ => {
+                let value = self.rng.gen_range(0..1000);
+                self.record(Decision::TieBreak(value));
+                value
+            }
+        }
+    }
+
+    pub(crate) fn failure_roll(&mut self) -> f32 {
+        match self.replay("failure roll") {
+            Some(Decision::FailureRoll(value)) => value,
+            _ => {
+                let value = self.rng.gen::<f32>();
+                self.record(Decision::FailureRoll(value));
+                value
+            }
+        }
+    }
+
+    pub(crate) fn fee_noise(&mut self) -> f64 {
+        match self.replay("fee noise") {
+            Some(Decision::FeeNoise(value)) => value,
+            _ => {
+                // Box-Muller transform of two uniform samples
+                let (u1, u2): (f64, f64) = (self.rng.gen(), self.rng.gen());
+                let value = (-2.0 * (1.0 - u1).ln()).sqrt() * (2.0 * PI * u2).cos();
+                self.record(Decision::FeeNoise(value));
+                value
+            }
+        }
+    }
+
+    /// The recorded decisions, empty unless recording
+    pub(crate) fn recorded(&self) -> Vec<Decision> {
+        match &self.state {
+            LogState::Recording(decisions) => decisions.clone(),
+            _ => vec![],
+        }
+    }
+
+    /// Why the replay stopped, if it did before running out of decisions
+    pub(crate) fn replay_error(&self) -> Option<ReplayError> {
+        match &self.state {
+            LogState::Diverged(error) => Some(*error),
+            _ => None,
+        }
+    }
+
+    fn record(&mut self, decision: Decision) {
+        if let LogState::Recording(decisions) = &mut self.state {
+            decisions.push(decision);
+        }
+    }
+
+    /// The next recorded decision if it is of the requested kind. Once the replay runs out the
+    /// RNG decides again. If the run takes a different turn than the recorded one, the
+    /// recorded decision is left in place and the replay stops with an error
+    fn replay(&mut self, requested: &'static str) -> Option<Decision> {
+        let LogState::Replaying {
+            decisions,
+            replayed,
+        } = &mut self.state
+        else {
+            return None;
+        };
+        match decisions.front() {
+            None => {
+                warn!("Replay ran out of decisions, drawing from the RNG again.");
+                self.state = LogState::Off;
+                None
+            }
+            Some(decision) if decision.kind() == requested => {
+                *replayed += 1;
+                decisions.pop_front()
+            }
+            Some(decision) => {
+                let replay_error = ReplayError::Diverged {
+                    position: *replayed,
+                    recorded: *decision,
+                    requested,
+                };
+                error!("{}", replay_error);
+                self.state = LogState::Diverged(replay_error);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{payment::Payment, Invoice, PaymentParts, Simulation, TieBreak};
+
+    fn init_sim() -> Simulation {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        simulator.set_tie_break(TieBreak::Random);
+        simulator.set_failure_probability(0.2);
+        simulator
+    }
+
+    fn send_payments(simulator: &mut Simulation) -> Vec<Payment> {
+        (0..10)
+            .map(|id| {
+                let (source, dest) = if id % 2 == 0 {
+                    ("bob".to_string(), "alice".to_string())
+                } else {
+                    ("alice".to_string(), "bob".to_string())
+                };
+                let amount_msat = 30000;
+                simulator.add_invoice(Invoice::new(id, amount_msat, &source, &dest));
+                let mut payment = Payment::new(id, source, dest, amount_msat, None);
+                simulator.send_mpp_payment(&mut payment);
+                payment
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replayed_run_takes_the_recorded_paths() {
+        let mut simulator = init_sim();
+        simulator.record_decisions();
+        let recorded = send_payments(&mut simulator);
+        let decisions = simulator.recorded_decisions();
+        assert!(!decisions.is_empty());
+        let mut simulator = init_sim();
+        simulator.replay_decisions(decisions);
+        let replayed = send_payments(&mut simulator);
+        assert!(simulator.check_replay().is_ok());
+        for (recorded, replayed) in recorded.iter().zip(replayed.iter()) {
+            assert_eq!(recorded.used_paths, replayed.used_paths);
+            assert_eq!(recorded.succeeded, replayed.succeeded);
+        }
+    }
+
+    #[test]
+    fn replayed_failure_rolls_decide_the_outcome() {
+        let mut simulator = init_sim();
+        simulator.set_tie_break(TieBreak::LowerLatency);
+        simulator.set_failure_probability(0.5);
+        // every intermediary fails to forward
+        simulator.replay_decisions(vec![Decision::FailureRoll(0.0); 1000]);
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        simulator.add_invoice(Invoice::new(0, 30000, &source, &dest));
+        let mut payment = Payment::new(0, source, dest, 30000, None);
+        assert!(!simulator.send_mpp_payment(&mut payment));
+        assert!(simulator.check_replay().is_ok());
+    }
+
+    #[test]
+    fn replay_reports_a_diverging_run() {
+        let mut simulator = init_sim();
+        simulator.replay_decisions(vec![Decision::FailureRoll(0.9); 1000]);
+        send_payments(&mut simulator);
+        let Err(ReplayError::Diverged {
+            position,
+            recorded,
+            requested,
+        }) = simulator.check_replay()
+        else {
+            panic!("expected the replay to diverge");
+        };
+        assert_eq!(position, 0);
+        assert_eq!(recorded, Decision::FailureRoll(0.9));
+        assert_eq!(requested, "tie break");
+    }
+
+    #[test]
+    fn dry_runs_leave_the_recording_alone() {
+        let mut simulator = init_sim();
+        simulator.record_decisions();
+        simulator.plan_mpp(&"bob".to_string(), &"alice".to_string(), 30000);
+        assert!(simulator.recorded_decisions().is_empty());
+    }
+}
@@ -0,0 +1,315 @@
+use crate::{
+    core_types::event::PaymentEvent, graph::Graph, payment::Payment, time::Time, DustPolicy,
+    PaymentId, PaymentParts, RoutingMetric, RoutingPolicy, Simulation, SimulationConfig, ID,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashSet},
+    error::Error,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+/// Everything needed to run an experiment again: the graph it ran on, its settings and the
+/// payments it sent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    /// Content hash of the graph at the start of the run, see Graph::content_hash
+    pub graph_hash: String,
+    pub config: SimulationConfig,
+    /// Settings of the run a SimulationConfig does not hold
+    #[serde(default)]
+    pub settings: ManifestSettings,
+    /// In the order they were sent
+    pub payments: Vec<ManifestPayment>,
+}
+
+/// The simulation's settings besides its SimulationConfig that change how payments are routed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ManifestSettings {
+    pub dust_policy: DustPolicy,
+    pub penalty_half_life_secs: f32,
+    pub persistent_penalties: bool,
+    pub last_hop_penalty: f32,
+    pub attempt_budget: Option<u64>,
+    pub stop_on_bottleneck: bool,
+    pub slow_threshold_secs: Option<f32>,
+}
+
+impl Default for ManifestSettings {
+    fn default() -> Self {
+        Self {
+            dust_policy: DustPolicy::default(),
+            penalty_half_life_secs: crate::PENALTY_HALF_LIFE_IN_SECS,
+            persistent_penalties: false,
+            last_hop_penalty: crate::LAST_HOP_PENALTY,
+            attempt_budget: None,
+            stop_on_bottleneck: false,
+            slow_threshold_secs: None,
+        }
+    }
+}
+
+/// A payment of the workload along with when it was sent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestPayment {
+    pub time_secs: f32,
+    pub payment_id: PaymentId,
+    pub source: ID,
+    pub dest: ID,
+    pub amount_msat: usize,
+    pub min_shard_amt: usize,
+    pub max_parts: usize,
+    pub target_parts: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payment_parts: Option<PaymentParts>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub exclude_nodes: BTreeSet<ID>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_hop: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_fee_msat: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline_secs: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slow_threshold_secs: Option<f32>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Manifest, Box<dyn Error>> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    /// The workload as it can be passed to Simulation::run_scheduled
+    pub fn payments(&self) -> Vec<(Time, Payment)> {
+        self.payments
+            .iter()
+            .map(|p| {
+                let mut payment = Payment::new(
+                    p.payment_id,
+                    p.source.clone(),
+                    p.dest.clone(),
+                    p.amount_msat,
+                    Some(p.min_shard_amt),
+                );
+                payment.max_parts = p.max_parts;
+                payment.target_parts = p.target_parts;
+                payment.payment_parts = p.payment_parts;
+                payment.exclude_nodes = p.exclude_nodes.iter().cloned().collect();
+                payment.first_hop = p.first_hop.clone();
+                payment.max_fee_msat = p.max_fee_msat;
+                payment.deadline = p.deadline_secs.map(Time::from_secs);
+                payment.slow_threshold = p.slow_threshold_secs.map(Time::from_secs);
+                (Time::from_secs(p.time_secs), payment)
+            })
+            .collect()
+    }
+
+    /// Sets up the simulation the manifest was written for. Fails if the graph is not the one
+    /// the run started with
+    pub fn simulation(&self, graph: Graph) -> Result<Simulation, Box<dyn Error>> {
+        let graph_hash = graph.content_hash();
+        if graph_hash != self.graph_hash {
+            return Err(format!(
+                "graph hash {} does not match the manifest's {}",
+                graph_hash, self.graph_hash
+            )
+            .into());
+        }
+        let mut simulation = Simulation::from_config(graph, &self.config)?;
+        simulation.set_dust_policy(self.settings.dust_policy);
+        simulation.set_penalty_half_life(self.settings.penalty_half_life_secs);
+        simulation.set_persistent_penalties(self.settings.persistent_penalties);
+        simulation.set_last_hop_penalty(self.settings.last_hop_penalty);
+        simulation.attempt_budget = self.settings.attempt_budget;
+        simulation.set_stop_on_bottleneck(self.settings.stop_on_bottleneck);
+        simulation.slow_threshold = self.settings.slow_threshold_secs.map(Time::from_secs);
+        Ok(simulation)
+    }
+}
+
+impl Simulation {
+    /// The simulation's settings that a SimulationConfig holds
+    pub fn config(&self) -> SimulationConfig {
+        SimulationConfig {
+            seed: self.run,
+            amount: self.amount,
+            payment_parts: self.payment_parts,
+            max_parts: self.max_parts,
+            min_shard_amt: self.min_shard_amt,
+            sim_delay_in_secs: self.sim_delay.as_secs(),
+            routing_policy: match self.routing_metric {
+                RoutingMetric::MinFee => RoutingPolicy::MinFee,
+                RoutingMetric::MinHops => RoutingPolicy::MinHops,
+                RoutingMetric::MinTime => RoutingPolicy::MinTime,
+                RoutingMetric::MaxProb => RoutingPolicy::MaxReliability,
+                RoutingMetric::Balanced => RoutingPolicy::Balanced,
+            },
+            failure_probability: self.failure_probability,
+            disjoint_paths: self.disjoint_paths,
+        }
+    }
+
+    fn manifest_settings(&self) -> ManifestSettings {
+        ManifestSettings {
+            dust_policy: self.dust_policy,
+            penalty_half_life_secs: self.penalty_half_life.as_secs(),
+            persistent_penalties: self.persistent_penalties,
+            last_hop_penalty: self.last_hop_penalty,
+            attempt_budget: self.attempt_budget,
+            stop_on_bottleneck: self.stop_on_bottleneck,
+            slow_threshold_secs: self.slow_threshold.map(|threshold| threshold.as_secs()),
+        }
+    }
+
+    /// Writes the manifest of the run so far as JSON: the hash of the graph the simulation
+    /// started with, its config and other settings and the payments processed so far
+    /// Manifest::simulation with the same graph, running the manifest's payments, reproduces the
+    /// results
+    /// The payments are taken from the recorded events, so events have to be recorded from the
+    /// start of the run
+    pub fn write_manifest(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if !self.record_events {
+            return Err("events are not recorded, see Simulation::set_record_events".into());
+        }
+        let mut seen = HashSet::new();
+        // a payment is scheduled again when it is retried
+        let payments = self
+            .processed_events
+            .iter()
+            .filter_map(|(time, event)| match event {
+                PaymentEvent::Scheduled { payment } if seen.insert(payment.payment_id) => {
+                    Some(ManifestPayment {
+                        time_secs: time.as_secs(),
+                        payment_id: payment.payment_id,
+                        source: payment.source.clone(),
+                        dest: payment.dest.clone(),
+                        amount_msat: payment.amount_msat,
+                        min_shard_amt: payment.min_shard_amt,
+                        max_parts: payment.max_parts,
+                        target_parts: payment.target_parts,
+                        payment_parts: payment.payment_parts,
+                        exclude_nodes: payment.exclude_nodes.iter().cloned().collect(),
+                        first_hop: payment.first_hop.clone(),
+                        max_fee_msat: payment.max_fee_msat,
+                        deadline_secs: payment.deadline.map(|deadline| deadline.as_secs()),
+                        slow_threshold_secs: payment
+                            .slow_threshold
+                            .map(|threshold| threshold.as_secs()),
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        let manifest = Manifest {
+            graph_hash: self.initial_graph_hash.clone(),
+            config: self.config(),
+            settings: self.manifest_settings(),
+            payments,
+        };
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &manifest)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{graph::Graph, PaymentParts};
+
+    fn trivial_multipath() -> Graph {
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                Path::new("../test_data/trivial_multipath.json"),
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for edges in graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 10000;
+                e.capacity = 1000000;
+            }
+        }
+        graph
+    }
+
+    #[test]
+    fn manifest_reproduces_the_run() {
+        let config = SimulationConfig {
+            seed: 11,
+            payment_parts: PaymentParts::Split,
+            ..Default::default()
+        };
+        let mut simulator = Simulation::from_config(trivial_multipath(), &config).unwrap();
+        simulator.set_record_events(true);
+        simulator.set_routing_policy(RoutingPolicy::Balanced);
+        simulator.set_dust_policy(DustPolicy::FoldIntoSibling);
+        simulator.set_persistent_penalties(true);
+        simulator.set_last_hop_penalty(50.0);
+        simulator.set_attempt_budget(100);
+        simulator.set_slow_threshold(400.0);
+        let mut payments: Vec<(Time, Payment)> =
+            [(0, "bob", 4000), (1, "carol", 9000), (2, "bob", 30000)]
+                .into_iter()
+                .map(|(id, source, amount)| {
+                    let payment =
+                        Payment::new(id, source.to_string(), "alice".to_string(), amount, None);
+                    (Time::from_secs(10.0 * id as f32), payment)
+                })
+                .collect();
+        payments[0].1.payment_parts = Some(PaymentParts::Single);
+        payments[0].1.first_hop = Some("bob-carol".to_string());
+        payments[1].1.exclude_nodes = HashSet::from(["dave".to_string()]);
+        payments[1].1.slow_threshold = Some(Time::from_secs(300.0));
+        payments[2].1.max_fee_msat = Some(1000);
+        payments[2].1.deadline = Some(Time::from_secs(500.0));
+        let result = simulator.run_scheduled(payments);
+        let file = tempfile::NamedTempFile::new().unwrap();
+        simulator.write_manifest(file.path()).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_reader(File::open(file.path()).unwrap()).unwrap();
+        assert_eq!(json["config"]["seed"], 11);
+        assert_eq!(json["config"]["routingPolicy"], "Balanced");
+        assert_eq!(json["settings"]["dustPolicy"], "FoldIntoSibling");
+        assert!(!json["graphHash"].as_str().unwrap().is_empty());
+        let manifest = Manifest::load(file.path()).unwrap();
+        assert_eq!(manifest.graph_hash, trivial_multipath().content_hash());
+        assert_eq!(manifest.settings, simulator.manifest_settings());
+        assert_eq!(manifest.payments.len(), 3);
+        assert_eq!(manifest.payments[0].first_hop.as_deref(), Some("bob-carol"));
+        assert_eq!(manifest.payments[2].deadline_secs, Some(500.0));
+        // a different graph is rejected
+        let mut other_graph = trivial_multipath();
+        other_graph.update_channel_balance(&"bob-carol".to_string(), 1);
+        assert!(manifest.simulation(other_graph).is_err());
+        let mut rerun = manifest.simulation(trivial_multipath()).unwrap();
+        assert_eq!(rerun.routing_metric, RoutingMetric::Balanced);
+        assert_eq!(rerun.manifest_settings(), simulator.manifest_settings());
+        let rerun_payments = manifest.payments();
+        assert_eq!(
+            rerun_payments[1].1.exclude_nodes,
+            HashSet::from(["dave".to_string()])
+        );
+        assert_eq!(
+            rerun_payments[1].1.slow_threshold,
+            Some(Time::from_secs(300.0))
+        );
+        assert_eq!(rerun_payments[2].1.max_fee_msat, Some(1000));
+        let rerun_result = rerun.run_scheduled(rerun_payments);
+        assert_eq!(rerun_result.num_succesful, result.num_succesful);
+        assert_eq!(rerun_result.num_failed, result.num_failed);
+        for (rerun, original) in rerun_result
+            .successful_payments
+            .iter()
+            .zip(result.successful_payments.iter())
+        {
+            assert_eq!(rerun.used_paths, original.used_paths);
+        }
+    }
+}
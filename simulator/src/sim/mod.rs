@@ -2,10 +2,21 @@ use crate::{
     payment::Payment,
     stats::{Adversaries, PathDistances, PathDiversity},
 };
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use serde::Serialize;
+use std::collections::BTreeMap;
 
+mod config;
+mod decisions;
+mod manifest;
 mod simulator;
+mod sweep;
+mod topology;
+pub use config::*;
+pub use decisions::*;
+pub use manifest::*;
 pub use simulator::*;
+pub use sweep::*;
 
 #[derive(Debug, Default, Serialize, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
@@ -21,3 +32,257 @@ pub struct SimResult {
     pub path_distances: PathDistances,
     pub path_diversity: PathDiversity,
 }
+
+impl SimResult {
+    /// Maps the number of parts to the number of successful payments that used as many.
+    /// Failed payments are not included
+    pub fn parts_histogram(&self) -> BTreeMap<usize, usize> {
+        let mut histogram = BTreeMap::new();
+        for payment in self.successful_payments.iter() {
+            *histogram.entry(payment.num_parts).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Share of the attempted msat that reached the recipients. Each part counts as attempted,
+    /// including the failed ones and those of failed payments that were reverted. Payments
+    /// rejected before sending any HTLC attempted nothing. 0 without attempts
+    pub fn goodput(&self) -> f64 {
+        let payments = self
+            .successful_payments
+            .iter()
+            .chain(self.failed_payments.iter());
+        let (delivered, attempted) = payments.fold((0, 0), |(delivered, attempted), payment| {
+            (
+                delivered + payment.delivered_msat,
+                attempted + Self::attempted_msat(payment),
+            )
+        });
+        if attempted == 0 {
+            0.0
+        } else {
+            delivered as f64 / attempted as f64
+        }
+    }
+
+    /// Share of the payments that succeeded. 0 without payments
+    pub fn success_rate(&self) -> f64 {
+        if self.total_num == 0 {
+            0.0
+        } else {
+            self.num_succesful as f64 / self.total_num as f64
+        }
+    }
+
+    /// Bounds of the confidence interval of the success rate, e.g. 0.95 for a 95% interval.
+    /// Bootstraps iters resamples of the payments' outcomes seeded with the run so the interval
+    /// is reproducible. Collapses to the success rate without payments or iterations
+    pub fn success_rate_ci(&self, iters: usize, confidence: f64) -> (f64, f64) {
+        let success_rate = self.success_rate();
+        if self.total_num == 0 || iters == 0 {
+            return (success_rate, success_rate);
+        }
+        let mut rng = StdRng::seed_from_u64(self.run);
+        let mut rates: Vec<f64> = (0..iters)
+            .map(|_| {
+                let succeeded = (0..self.total_num)
+                    .filter(|_| rng.gen_range(0..self.total_num) < self.num_succesful)
+                    .count();
+                succeeded as f64 / self.total_num as f64
+            })
+            .collect();
+        rates.sort_by(|a, b| a.total_cmp(b));
+        let tail = (1.0 - confidence.clamp(0.0, 1.0)) / 2.0;
+        let quantile = |q: f64| rates[((iters - 1) as f64 * q).round() as usize];
+        (quantile(tail), quantile(1.0 - tail))
+    }
+
+    /// Mean number of parts of the successful payments. 0 if none succeeded
+    pub fn mean_parts(&self) -> f64 {
+        self.mean_of_successful(|payment| payment.num_parts)
+    }
+
+    /// Mean fees in msat the successful payments paid. 0 if none succeeded
+    pub fn mean_fee(&self) -> f64 {
+        self.mean_of_successful(|payment| payment.total_fees_msat)
+    }
+
+    /// How the other stats differ from these, positive if the other's are higher
+    pub fn diff(&self, other: &SimResult) -> StatsDiff {
+        StatsDiff {
+            success_rate: other.success_rate() - self.success_rate(),
+            mean_parts: other.mean_parts() - self.mean_parts(),
+            mean_fee: other.mean_fee() - self.mean_fee(),
+            goodput: other.goodput() - self.goodput(),
+        }
+    }
+
+    fn mean_of_successful(&self, value: impl Fn(&Payment) -> usize) -> f64 {
+        if self.successful_payments.is_empty() {
+            return 0.0;
+        }
+        let total: usize = self.successful_payments.iter().map(value).sum();
+        total as f64 / self.successful_payments.len() as f64
+    }
+
+    fn attempted_msat(payment: &Payment) -> usize {
+        if !payment.shard_amounts.is_empty() {
+            payment.shard_amounts.iter().sum()
+        } else if payment.htlc_attempts == 0 {
+            0
+        } else if payment.succeeded {
+            payment.delivered_msat
+        } else {
+            // sent along a single path
+            payment.amount_msat
+        }
+    }
+}
+
+/// Signed differences between the stats of two runs, see SimResult::diff
+#[derive(Debug, Default, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsDiff {
+    pub success_rate: f64,
+    pub mean_parts: f64,
+    pub mean_fee: f64,
+    pub goodput: f64,
+}
+
+/// Time in nanoseconds spent in the different phases of a simulation
+#[derive(Debug, Default, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Timings {
+    /// Looking for routes
+    pub pathfinding: u128,
+    /// Moving and reverting balances
+    pub balance_updates: u128,
+    /// Creating, scheduling and handling events
+    pub event_processing: u128,
+    /// Wall time of the whole simulation run
+    pub total: u128,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core_types::time::Time, PaymentParts};
+
+    #[test]
+    fn parts_histogram_counts_successful_payments() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let alice = "alice".to_string();
+        let payments = [
+            (1000, "bob"),
+            (1000, "eve"),
+            (6000, "bob"),
+            (100000, "carol"),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(id, (amount, src))| {
+            let payment = Payment::new(id, src.to_string(), alice.clone(), amount, None);
+            (Time::from_secs(10.0 * id as f32), payment)
+        })
+        .collect();
+        let result: SimResult = simulator.run_scheduled(payments);
+        assert_eq!(result.num_failed, 1);
+        assert_eq!(result.parts_histogram(), BTreeMap::from([(1, 2), (4, 1)]));
+    }
+
+    // bob's payment of 6000 msat only gets through once its failed parts are split
+    #[test]
+    fn goodput_counts_the_failed_parts() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let alice = "alice".to_string();
+        let payments = [(1000, "bob"), (6000, "bob")]
+            .into_iter()
+            .enumerate()
+            .map(|(id, (amount, src))| {
+                let payment = Payment::new(id, src.to_string(), alice.clone(), amount, None);
+                (Time::from_secs(10.0 * id as f32), payment)
+            })
+            .collect();
+        let result = simulator.run_scheduled(payments);
+        assert_eq!(result.num_succesful, 2);
+        assert!(result
+            .successful_payments
+            .iter()
+            .any(|p| !p.failed_amounts.is_empty()));
+        let goodput = result.goodput();
+        assert!(goodput > 0.0);
+        assert!(goodput < 1.0);
+        let failed: usize = result
+            .successful_payments
+            .iter()
+            .flat_map(|p| p.failed_amounts.iter())
+            .sum();
+        assert_eq!(goodput, 7000.0 / (7000 + failed) as f64);
+    }
+
+    fn stats_of(payments: Vec<Payment>, num_failed: usize) -> SimResult {
+        SimResult {
+            total_num: payments.len() + num_failed,
+            num_succesful: payments.len(),
+            num_failed,
+            successful_payments: payments,
+            ..Default::default()
+        }
+    }
+
+    fn delivered(id: usize, num_parts: usize, fees: usize) -> Payment {
+        let mut payment = Payment::new(id, "bob".to_string(), "alice".to_string(), 1000, None);
+        payment.succeeded = true;
+        payment.num_parts = num_parts;
+        payment.htlc_attempts = num_parts;
+        payment.delivered_msat = 1000;
+        payment.total_fees_msat = fees;
+        payment
+    }
+
+    #[test]
+    fn diff_reports_signed_differences() {
+        // half of the payments succeed in one part each
+        let a = stats_of(vec![delivered(0, 1, 10), delivered(1, 1, 30)], 2);
+        // all succeed in three parts, paying more
+        let b = stats_of(vec![delivered(0, 3, 50), delivered(1, 3, 70)], 0);
+        let diff = a.diff(&b);
+        assert_eq!(diff.success_rate, 0.5);
+        assert_eq!(diff.mean_parts, 2.0);
+        assert_eq!(diff.mean_fee, 40.0);
+        assert_eq!(diff.goodput, 0.0);
+        let reversed = b.diff(&a);
+        assert_eq!(reversed.success_rate, -0.5);
+        assert_eq!(reversed.mean_parts, -2.0);
+        assert_eq!(reversed.mean_fee, -40.0);
+        assert_eq!(a.diff(&a), StatsDiff::default());
+    }
+
+    #[test]
+    fn success_rate_ci_narrows_with_more_payments() {
+        let ci_of = |num_payments: usize| {
+            let payments = (0..num_payments / 2)
+                .map(|id| delivered(id, 1, 0))
+                .collect();
+            let stats = stats_of(payments, num_payments / 2);
+            let (lower, upper) = stats.success_rate_ci(1000, 0.95);
+            assert!(lower <= stats.success_rate() && stats.success_rate() <= upper);
+            upper - lower
+        };
+        let (few, many) = (ci_of(10), ci_of(1000));
+        assert!(few > 0.0);
+        assert!(many < few);
+        // same seed, same interval
+        let stats = stats_of(vec![delivered(0, 1, 0)], 3);
+        assert_eq!(
+            stats.success_rate_ci(100, 0.9),
+            stats.success_rate_ci(100, 0.9)
+        );
+        assert_eq!(stats_of(vec![], 0).success_rate_ci(100, 0.9), (0.0, 0.0));
+        assert_eq!(stats.success_rate_ci(0, 0.9), (0.25, 0.25));
+    }
+}
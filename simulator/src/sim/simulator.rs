@@ -1,24 +1,42 @@
 use crate::{
     core_types::graph::Graph,
     event::*,
-    payment::Payment,
-    sim::SimResult,
+    io::output::CsvWriter,
+    payment::{FailureReason, Payment},
+    sim::{SimResult, Timings},
     stats::{Adversaries, PathDistances, PathDiversity},
     time::Time,
-    AdversarySelection, Invoice, PaymentId, PaymentParts, RoutingMetric, WeightPartsCombi, ID,
+    traversal::{
+        cache::RouteCache,
+        pathfinding::{CandidatePath, ChannelPenalty, PathFilter, PathFinder, RouteResult},
+    },
+    AdversarySelection, Decision, DustPolicy, FeeRounding, Invoice, PaymentId, PaymentParts,
+    ReplayError, RouteHint, RoutingMetric, RoutingPolicy, ShardOrder, SharedDecisions,
+    SimulationConfig, TieBreak, WeightPartsCombi, ID,
 };
 use log::{debug, error, info};
 use rand::{seq::IteratorRandom, SeedableRng};
-use std::collections::{BTreeMap, HashMap};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    error::Error,
+    fmt,
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
 
 #[derive(Clone)]
 pub struct Simulation {
     /// Graph describing LN topology
     pub(crate) graph: Graph,
+    /// Content hash of the graph the simulation started with
+    pub(crate) initial_graph_hash: String,
     /// Payment amount to simulate
     pub(crate) amount: usize,
     /// Sim seed
-    run: u64,
+    pub(crate) run: u64,
     /// Fee minimisation or probability maximisation
     pub(crate) routing_metric: RoutingMetric,
     /// Single or multi-path
@@ -42,8 +60,128 @@ pub struct Simulation {
     pub(crate) path_distances: PathDistances,
     pub(crate) path_diversity: PathDiversity,
     pub(crate) adversary_selection: Vec<AdversarySelection>,
+    /// Time spent in the different phases
+    pub(crate) timings: Timings,
+    /// Nodes that hold the HTLCs routed through them instead of resolving them
+    pub(crate) griefing_nodes: HashSet<ID>,
+    /// How long a griefing node holds an HTLC before it fails
+    pub(crate) griefing_timeout: Time,
+    /// HTLCs taking longer than this to resolve are cancelled by the sender and their amount
+    /// routed around the channel that slowed them down, for payments without a threshold of
+    /// their own
+    pub(crate) slow_threshold: Option<Time>,
+    /// Failure history of the channels used to penalise them in pathfinding
+    pub(crate) channel_penalties: HashMap<String, ChannelPenalty>,
+    /// Time after which half of a channel's penalty is gone
+    pub(crate) penalty_half_life: Time,
+    /// Whether the penalties carry over from one payment to the next or only hold for the payment
+    /// at hand, the default
+    pub(crate) persistent_penalties: bool,
+    /// Verify that the network's total liquidity is the same before and after every payment
+    pub(crate) check_invariants: bool,
+    /// Violated invariants panic. Otherwise they are logged and the payment fails
+    pub(crate) strict: bool,
+    /// Which path to take among those of equal weight
+    pub(crate) tie_break: TieBreak,
+    /// How forwarding nodes round their fees
+    pub(crate) fee_rounding: FeeRounding,
+    /// Which of the pending parts of an MPP is attempted next
+    pub(crate) shard_order: ShardOrder,
+    /// What happens to splits with parts below min_shard_amt
+    pub(crate) dust_policy: DustPolicy,
+    /// Weight added to a channel into the destination that earlier parts of the same payment used
+    pub(crate) last_hop_penalty: f32,
+    /// Paths found for earlier payments between the same nodes and of similar amounts
+    pub(crate) route_cache: RouteCache,
+    /// Every event processed so far along with the simulation time it was processed at, in the
+    /// order they were processed. Only kept if events are recorded
+    pub(crate) processed_events: Vec<(Time, PaymentEvent)>,
+    /// Whether processed events are kept
+    pub(crate) record_events: bool,
+    /// Log a summary line per payment once it resolved
+    pub(crate) summary_logging: bool,
+    /// Total number of HTLC attempts the payments may make. Later payments are abandoned
+    pub(crate) attempt_budget: Option<u64>,
+    /// HTLC attempts made by all payments sent so far
+    pub(crate) htlc_attempts_spent: u64,
+    /// Max number of parts the payments created by the simulation are split into
+    pub(crate) max_parts: usize,
+    /// Smallest part the payments created by the simulation are split into
+    pub(crate) min_shard_amt: usize,
+    /// Time between the payments created by the simulation and until a payment resolves
+    pub(crate) sim_delay: Time,
+    /// Chance of an intermediary failing to forward an HTLC regardless of its balance
+    pub(crate) failure_probability: f32,
+    /// Chance of the node forwarding an HTLC instead of dropping it. Nodes without an entry
+    /// never drop HTLCs
+    pub(crate) node_reliability: HashMap<ID, f32>,
+    /// Parts of an MPP avoid the channels used by the parts delivered before them
+    pub(crate) disjoint_paths: bool,
+    /// An MPP is abandoned once a split part fails at the same channel as the shard it was
+    /// split from
+    pub(crate) stop_on_bottleneck: bool,
+    /// Max number of hops of a path, given by the size of the onion packet
+    pub(crate) onion_hop_limit: usize,
+    /// Paths with more intermediaries are discarded. Payments may restrict them further
+    pub(crate) max_hops: usize,
+    /// Candidate paths it rejects are not used by any search
+    pub(crate) path_filter: Option<PathFilter>,
+    /// Historical success rate per channel, used instead of the capacity-based probability
+    pub(crate) channel_reliability: HashMap<String, f32>,
+    /// Shards are multiples of this amount, except for the last part of a split. 0 disables
+    /// the rounding
+    pub(crate) shard_quantum_msat: u64,
+    /// Paths for the initial parts of an MPP are searched concurrently
+    pub(crate) parallel_pathfinding: bool,
+    /// Standard deviation of the factor the fees are scaled by for every pathfinding. 0 keeps
+    /// the fees as they are
+    pub(crate) fee_volatility: f64,
+    /// Random choices of the run, recorded or replayed. Copies of the simulation get their own
+    /// copy of the log
+    pub(crate) decisions: SharedDecisions,
+    /// Opens channels to recipients that lack the inbound liquidity for a payment
+    pub(crate) jit_lsp: Option<JitLsp>,
+    /// Liquidity of the channels the JIT LSP opened, which payments do not conserve
+    pub(crate) jit_liquidity_msat: usize,
+}
+
+/// An LSP that provides just-in-time inbound liquidity, see Simulation::set_jit_lsp
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct JitLsp {
+    pub(crate) lsp: ID,
+    /// Most the LSP puts into a single channel
+    pub(crate) max_provide: u64,
+    /// Charged once to each payment that needs the channel
+    pub(crate) fee_msat: u64,
+}
+
+/// Why the simulation's settings do not allow for a PaymentParts mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaymentPartsError {
+    /// Splitting needs parts of at least 1 msat
+    ZeroMinShardAmount,
+    /// Splitting needs at least one part
+    ZeroMaxParts,
+}
+
+impl fmt::Display for PaymentPartsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentPartsError::ZeroMinShardAmount => {
+                write!(
+                    f,
+                    "splitting payments needs a min shard amount above 0 msat"
+                )
+            }
+            PaymentPartsError::ZeroMaxParts => {
+                write!(f, "splitting payments needs a max number of parts above 0")
+            }
+        }
+    }
 }
 
+impl Error for PaymentPartsError {}
+
 impl Simulation {
     pub fn new(
         run: u64,
@@ -60,12 +198,13 @@ impl Simulation {
         let event_queue = EventQueue::new();
         let outstanding_invoices: BTreeMap<String, HashMap<usize, Invoice>> = BTreeMap::new();
         let successful_payments = Vec::new();
-        Self {
+        let mut simulation = Self {
+            initial_graph_hash: graph.content_hash(),
             graph,
             amount,
             run,
             routing_metric,
-            payment_parts,
+            payment_parts: PaymentParts::Single,
             event_queue,
             current_payment_id: 0,
             outstanding_invoices,
@@ -80,7 +219,69 @@ impl Simulation {
             path_distances: PathDistances(vec![]),
             adversary_selection: adversary_selection.to_owned(),
             path_diversity: PathDiversity(vec![]),
-        }
+            timings: Timings::default(),
+            griefing_nodes: HashSet::default(),
+            griefing_timeout: Time::from_secs(crate::GRIEFING_TIMEOUT_IN_SECS),
+            slow_threshold: None,
+            channel_penalties: HashMap::default(),
+            penalty_half_life: Time::from_secs(crate::PENALTY_HALF_LIFE_IN_SECS),
+            persistent_penalties: false,
+            check_invariants: false,
+            strict: true,
+            tie_break: TieBreak::default(),
+            fee_rounding: FeeRounding::default(),
+            shard_order: ShardOrder::default(),
+            dust_policy: DustPolicy::default(),
+            last_hop_penalty: crate::LAST_HOP_PENALTY,
+            route_cache: RouteCache::default(),
+            processed_events: vec![],
+            record_events: false,
+            summary_logging: false,
+            attempt_budget: None,
+            htlc_attempts_spent: 0,
+            max_parts: crate::MAX_PARTS,
+            min_shard_amt: crate::MIN_SHARD_AMOUNT,
+            sim_delay: Time::from_secs(crate::SIM_DELAY_IN_SECS),
+            failure_probability: 0.0,
+            node_reliability: HashMap::default(),
+            disjoint_paths: false,
+            stop_on_bottleneck: false,
+            onion_hop_limit: crate::ONION_HOP_LIMIT,
+            max_hops: crate::MAX_HOPS,
+            path_filter: None,
+            channel_reliability: HashMap::new(),
+            shard_quantum_msat: 0,
+            parallel_pathfinding: false,
+            fee_volatility: 0.0,
+            decisions: SharedDecisions::seeded(run),
+            jit_lsp: None,
+            jit_liquidity_msat: 0,
+        };
+        simulation
+            .set_payment_parts(payment_parts)
+            .expect("The default min_shard_amt and max_parts allow splitting.");
+        simulation
+    }
+
+    /// Sets up a simulation of the graph with the settings of the config. Fails if the config
+    /// splits payments without allowing for any parts
+    pub fn from_config(graph: Graph, config: &SimulationConfig) -> Result<Self, PaymentPartsError> {
+        let mut simulation = Self::new(
+            config.seed,
+            graph,
+            config.amount,
+            config.routing_policy.into(),
+            PaymentParts::Single,
+            None,
+            &[],
+        );
+        simulation.max_parts = config.max_parts;
+        simulation.min_shard_amt = config.min_shard_amt;
+        simulation.set_payment_parts(config.payment_parts)?;
+        simulation.sim_delay = Time::from_secs(config.sim_delay_in_secs);
+        simulation.set_failure_probability(config.failure_probability);
+        simulation.set_disjoint_paths(config.disjoint_paths);
+        Ok(simulation)
     }
 
     pub fn new_batch_simulator(
@@ -120,15 +321,23 @@ impl Simulation {
             self.routing_metric,
             self.payment_parts
         );
+        let start = Instant::now();
         let mut now = Time::from_secs(0.0); // start simulation at (0)
         for (src, dest) in payment_pairs {
             let payment_id = self.next_payment_id();
             let invoice = Invoice::new(payment_id, self.amount, &src, &dest);
             self.add_invoice(invoice);
-            let payment = Payment::new(payment_id, src, dest, self.amount, min_shard_amt);
+            let mut payment = Payment::new(
+                payment_id,
+                src,
+                dest,
+                self.amount,
+                Some(min_shard_amt.unwrap_or(self.min_shard_amt)),
+            );
+            payment.max_parts = self.max_parts;
             let event = PaymentEvent::Scheduled { payment };
             self.event_queue.schedule(now, event);
-            now += Time::from_secs(crate::SIM_DELAY_IN_SECS);
+            now += self.sim_delay;
         }
         self.total_num_payments = self.event_queue.queue_length();
         debug!(
@@ -138,7 +347,141 @@ impl Simulation {
 
         info!("Starting simulation.");
         // this is where the actual simulation happens
+        self.process_events();
+        self.timings.total += start.elapsed().as_nanos();
+        self.check_invariant(
+            self.num_successful + self.num_failed == self.total_num_payments,
+            "Something went wrong. Expected a different number simulation events.",
+        );
+        info!(
+            "Completed simulation after {} simulation secs.",
+            now.as_secs(),
+        );
+        info!(
+            "# Total payments = {}, # successful {}, # failed = {}.",
+            self.total_num_payments, self.num_successful, self.num_failed
+        );
+        self.eval_adversaries(run_all_adversary_scenarios);
+        self.eval_path_similarity();
+        self.sim_result()
+    }
+
+    /// Simulates payments arriving at the given times, relative to the current simulation time.
+    /// Payments compete for liquidity so balance changes of earlier payments are visible to later
+    /// ones
+    pub fn run_scheduled(&mut self, payments: Vec<(Time, Payment)>) -> SimResult {
+        let start = Instant::now();
+        self.total_num_payments += payments.len();
+        for (arrival, payment) in payments {
+            let invoice = Invoice::new(
+                payment.payment_id,
+                payment.amount_msat,
+                &payment.source,
+                &payment.dest,
+            );
+            self.add_invoice(invoice);
+            self.event_queue
+                .schedule(arrival, PaymentEvent::Scheduled { payment });
+        }
+        info!(
+            "Starting simulation of {} scheduled payments.",
+            self.total_num_payments
+        );
+        self.process_events();
+        self.timings.total += start.elapsed().as_nanos();
+        self.check_invariant(
+            self.num_successful + self.num_failed == self.total_num_payments,
+            "Something went wrong. Expected a different number simulation events.",
+        );
+        self.eval_path_similarity();
+        self.sim_result()
+    }
+
+    /// Simulates the payments one after another. Each payment has resolved before the next one is
+    /// sent and progress is incremented once it has, so that other threads can poll how many
+    /// payments are done
+    pub fn run_batch_with_progress(
+        &mut self,
+        payments: Vec<Payment>,
+        progress: &AtomicUsize,
+    ) -> SimResult {
+        let start = Instant::now();
+        self.total_num_payments += payments.len();
+        for payment in payments {
+            let invoice = Invoice::new(
+                payment.payment_id,
+                payment.amount_msat,
+                &payment.source,
+                &payment.dest,
+            );
+            self.add_invoice(invoice);
+            self.event_queue
+                .schedule(self.sim_delay, PaymentEvent::Scheduled { payment });
+            self.process_events();
+            progress.fetch_add(1, Ordering::Relaxed);
+        }
+        self.timings.total += start.elapsed().as_nanos();
+        self.check_invariant(
+            self.num_successful + self.num_failed == self.total_num_payments,
+            "Something went wrong. Expected a different number simulation events.",
+        );
+        self.eval_path_similarity();
+        self.sim_result()
+    }
+
+    /// Panics if the invariant does not hold in strict mode. Otherwise the violation is logged
+    /// and false returned so that the caller can fail the payment instead
+    pub(crate) fn check_invariant(&self, holds: bool, violation: &str) -> bool {
+        if !holds {
+            assert!(!self.strict, "{violation}");
+            error!("Invariant violated: {}", violation);
+        }
+        holds
+    }
+
+    /// Sends the payment as a single or multi-path payment. The payment's own setting takes
+    /// precedence over the simulation's
+    pub(crate) fn send_payment(&mut self, payment: &mut Payment) -> bool {
+        if self
+            .attempt_budget
+            .is_some_and(|budget| self.htlc_attempts_spent >= budget)
+        {
+            info!(
+                "Abandoning payment {} as the budget of {} HTLC attempts is used up.",
+                payment.payment_id, self.htlc_attempts_spent
+            );
+            let reason = FailureReason::AttemptBudgetExhausted;
+            payment.succeeded = false;
+            payment.failure_reason = Some(reason);
+            self.event_queue.schedule(
+                self.event_queue.now() + self.sim_delay,
+                PaymentEvent::Abandoned {
+                    payment: payment.to_owned(),
+                    reason,
+                    parts_delivered: 0,
+                },
+            );
+            return false;
+        }
+        if !self.persistent_penalties {
+            self.channel_penalties.clear();
+        }
+        // retried payments carry the attempts of their earlier tries
+        let attempts_before = payment.htlc_attempts;
+        let succeeded = match payment.payment_parts.unwrap_or(self.payment_parts) {
+            PaymentParts::Single => self.send_single_payment(payment),
+            PaymentParts::Split | PaymentParts::FeeOptimalSplit | PaymentParts::Amp => {
+                self.send_mpp_payment(payment)
+            }
+        };
+        self.htlc_attempts_spent += payment.htlc_attempts.saturating_sub(attempts_before) as u64;
+        succeeded
+    }
+
+    /// Handles the queued events in the order of their simulation time
+    pub(crate) fn process_events(&mut self) {
         while let Some(event) = self.event_queue.next() {
+            self.record_event(&event);
             match event {
                 PaymentEvent::Scheduled { mut payment } => {
                     debug!(
@@ -146,36 +489,81 @@ impl Simulation {
                         payment.payment_id,
                         self.event_queue.now()
                     );
-                    let _ = match self.payment_parts {
-                        PaymentParts::Single => self.send_single_payment(&mut payment),
-                        PaymentParts::Split => self.send_mpp_payment(&mut payment),
+                    // channels the JIT LSP opens bring in new liquidity
+                    let liquidity = |sim: &Self| {
+                        sim.graph
+                            .total_liquidity()
+                            .saturating_sub(sim.jit_liquidity_msat)
                     };
+                    let liquidity_before = self.check_invariants.then(|| liquidity(self));
+                    let _ = self.send_payment(&mut payment);
+                    if let Some(liquidity_before) = liquidity_before {
+                        self.check_invariant(
+                            liquidity_before == liquidity(self),
+                            &format!(
+                                "Payment {} did not conserve the network's liquidity.",
+                                payment.payment_id
+                            ),
+                        );
+                    }
                 }
-                PaymentEvent::UpdateFailed { payment } => {
+                PaymentEvent::UpdateFailed { payment }
+                | PaymentEvent::Abandoned { payment, .. } => {
+                    let event_start = Instant::now();
                     self.num_failed += 1;
                     self.failed_payments.push(payment.to_owned());
+                    self.timings.event_processing += event_start.elapsed().as_nanos();
                 }
                 PaymentEvent::UpdateSuccesful { payment } => {
+                    let event_start = Instant::now();
+                    self.release_in_flight(&payment.used_paths);
                     self.num_successful += 1;
                     self.successful_payments.push(payment.to_owned());
+                    self.timings.event_processing += event_start.elapsed().as_nanos();
+                }
+                PaymentEvent::HtlcTimeout { reservations, .. } => {
+                    self.release_held_htlcs(&reservations);
+                }
+                PaymentEvent::Rebalance {
+                    node,
+                    out_channel,
+                    in_channel,
+                    amount,
+                } => {
+                    let _ = self.rebalance(&node, &out_channel, &in_channel, amount);
+                }
+                PaymentEvent::ChannelOpen {
+                    node_a,
+                    node_b,
+                    capacity,
+                    balance_a,
+                } => {
+                    self.open_channel(&node_a, &node_b, capacity, balance_a);
+                }
+                PaymentEvent::ChannelClose { channel_id } => {
+                    self.close_channel(&channel_id);
+                }
+                PaymentEvent::ShardSuccess {
+                    shard_id, amount, ..
+                } => {
+                    debug!(
+                        "Shard {} of payment {} worth {} msat delivered.",
+                        shard_id.1, shard_id.0, amount
+                    );
+                }
+                PaymentEvent::ShardFailed {
+                    shard_id, amount, ..
+                } => {
+                    debug!(
+                        "Shard {} of payment {} worth {} msat failed.",
+                        shard_id.1, shard_id.0, amount
+                    );
                 }
             }
         }
-        assert_eq!(
-            self.num_successful + self.num_failed,
-            self.total_num_payments,
-            "Something went wrong. Expected a different number simulation events."
-        );
-        info!(
-            "Completed simulation after {} simulation secs.",
-            now.as_secs(),
-        );
-        info!(
-            "# Total payments = {}, # successful {}, # failed = {}.",
-            self.total_num_payments, self.num_successful, self.num_failed
-        );
-        self.eval_adversaries(run_all_adversary_scenarios);
-        self.eval_path_similarity();
+    }
+
+    fn sim_result(&self) -> SimResult {
         SimResult {
             run: self.run,
             amount: self.amount,
@@ -190,16 +578,490 @@ impl Simulation {
         }
     }
 
+    /// Sends the payments, usually invoices of the same merchant, one after another out of the
+    /// sources' shared liquidity. Later payments only see what earlier ones left so they fail if
+    /// the budget is used up. Returns whether each payment succeeded
+    pub fn send_bundle(&mut self, payments: Vec<Payment>) -> Vec<bool> {
+        let start = Instant::now();
+        self.total_num_payments += payments.len();
+        let mut succeeded = Vec::with_capacity(payments.len());
+        for mut payment in payments {
+            let invoice = Invoice::new(
+                payment.payment_id,
+                payment.amount_msat,
+                &payment.source,
+                &payment.dest,
+            );
+            self.add_invoice(invoice);
+            succeeded.push(self.send_payment(&mut payment));
+        }
+        debug!(
+            "{} of {} bundled payments succeeded.",
+            succeeded.iter().filter(|s| **s).count(),
+            succeeded.len()
+        );
+        self.process_events();
+        self.timings.total += start.elapsed().as_nanos();
+        succeeded
+    }
+
+    /// Sends the amount from a to b and right after from b back to a. The payment back sees the
+    /// liquidity the first one shifted. Returns whether each of the two succeeded
+    pub fn send_round_trip(&mut self, a: &str, b: &str, amount: u64) -> (bool, bool) {
+        let (a, b) = (a.to_string(), b.to_string());
+        let amount = amount as usize;
+        let forward = Payment::new(self.next_payment_id(), a.clone(), b.clone(), amount, None);
+        let reverse = Payment::new(self.next_payment_id(), b, a, amount, None);
+        let succeeded = self.send_bundle(vec![forward, reverse]);
+        (succeeded[0], succeeded[1])
+    }
+
+    /// Sends the recorded payment again with a new id against the graph as it is now, e.g. after
+    /// editing a channel, regardless of how it went the first time
+    pub fn replay_payment(&mut self, recorded: &Payment) -> bool {
+        let payment = recorded.unsent_copy(self.next_payment_id());
+        self.send_bundle(vec![payment])[0]
+    }
+
+    /// Writes the processed events as CSV rows of event_time (in secs), payment_id,
+    /// shard_channel_ids and event_kind. The channel ids are separated by spaces. Each key of
+    /// the payments' metadata adds a column, empty for events without it. Fields are quoted
+    /// where they would break the row
+    /// The timeline is made of the recorded events, see set_record_events
+    pub fn export_timeline_csv(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if !self.record_events {
+            return Err("events are not recorded, see Simulation::set_record_events".into());
+        }
+        let timeline = self.timeline();
+        let mut writer = CsvWriter::new(BufWriter::new(File::create(path)?));
+        let metadata_keys: BTreeSet<&String> = timeline
+            .iter()
+            .flat_map(|entry| entry.metadata.keys())
+            .collect();
+        writer.write_record(
+            [
+                "event_time",
+                "payment_id",
+                "shard_channel_ids",
+                "event_kind",
+            ]
+            .into_iter()
+            .chain(metadata_keys.iter().map(|key| key.as_str())),
+        )?;
+        for entry in timeline.iter() {
+            let fields = [
+                entry.time.as_secs().to_string(),
+                entry
+                    .payment_id
+                    .map(|id| id.to_string())
+                    .unwrap_or_default(),
+                entry.channels.join(" "),
+                entry.kind.to_string(),
+            ];
+            writer.write_record(
+                fields.into_iter().chain(
+                    metadata_keys
+                        .iter()
+                        .map(|key| entry.metadata.get(*key).cloned().unwrap_or_default()),
+                ),
+            )?;
+        }
+        writer.flush()?;
+        info!(
+            "Timeline of {} events written to {:?}.",
+            timeline.len(),
+            path
+        );
+        Ok(())
+    }
+
+    /// Time spent in pathfinding, balance updates and event processing so far
+    pub fn timings(&self) -> Timings {
+        self.timings
+    }
+
+    /// Makes the node hold every shard routed through it until the griefing timeout
+    pub fn set_node_griefing(&mut self, node: String) {
+        self.griefing_nodes.insert(node);
+    }
+
+    /// Sets how long griefing nodes hold HTLCs before they fail
+    pub fn set_griefing_timeout(&mut self, timeout_secs: f32) {
+        self.griefing_timeout = Time::from_secs(timeout_secs);
+    }
+
+    /// Makes senders cancel an HTLC once it takes longer than the threshold to resolve, be it
+    /// held by a griefing node or sent along a path with a long total timelock, and route its
+    /// amount along another path. Payments may set their own threshold
+    pub fn set_slow_threshold(&mut self, threshold_secs: f32) {
+        self.slow_threshold = Some(Time::from_secs(threshold_secs));
+    }
+
+    /// Sets how quickly the penalties of failed channels decay
+    pub fn set_penalty_half_life(&mut self, half_life_secs: f32) {
+        self.penalty_half_life = Time::from_secs(half_life_secs);
+    }
+
+    /// Whether later payments avoid the channels that failed earlier ones or each payment starts
+    /// without any penalties, the default
+    pub fn set_persistent_penalties(&mut self, persistent: bool) {
+        self.persistent_penalties = persistent;
+    }
+
+    /// Panics if a payment changes the network's total liquidity. Meant for debugging
+    pub fn set_check_invariants(&mut self, check_invariants: bool) {
+        self.check_invariants = check_invariants;
+    }
+
+    /// Whether violated invariants panic, the default, or only fail the payment at hand so that
+    /// long batch runs carry on
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Sets which path pathfinding takes among those of equal weight
+    pub fn set_tie_break(&mut self, tie_break: TieBreak) {
+        self.tie_break = tie_break;
+    }
+
+    /// Sets how forwarding nodes round their fees, which changes both the fee weights and the
+    /// fees paid
+    pub fn set_fee_rounding(&mut self, fee_rounding: FeeRounding) {
+        self.fee_rounding = fee_rounding;
+    }
+
+    /// Sets which of the pending parts of an MPP is attempted next
+    pub fn set_shard_order(&mut self, shard_order: ShardOrder) {
+        self.shard_order = shard_order;
+    }
+
+    /// Sets whether payments are sent in one part or split. The modes that split need the
+    /// simulation's min_shard_amt and max_parts to be above 0, otherwise the mode is kept
+    pub fn set_payment_parts(
+        &mut self,
+        payment_parts: PaymentParts,
+    ) -> Result<(), PaymentPartsError> {
+        if payment_parts != PaymentParts::Single {
+            if self.min_shard_amt == 0 {
+                return Err(PaymentPartsError::ZeroMinShardAmount);
+            }
+            if self.max_parts == 0 {
+                return Err(PaymentPartsError::ZeroMaxParts);
+            }
+        }
+        self.payment_parts = payment_parts;
+        Ok(())
+    }
+
+    /// How payments are sent unless they set it themselves
+    pub fn payment_parts(&self) -> PaymentParts {
+        self.payment_parts
+    }
+
+    /// Sets whether splits with parts below min_shard_amt are not made or have the dust folded
+    /// into a sibling part
+    pub fn set_dust_policy(&mut self, dust_policy: DustPolicy) {
+        self.dust_policy = dust_policy;
+    }
+
+    /// Abandons the payments sent once the payments sent so far made the given number of HTLC
+    /// attempts in total, bounding the cost of large runs
+    pub fn set_attempt_budget(&mut self, total: u64) {
+        self.attempt_budget = Some(total);
+    }
+
+    /// Logs one line per MPP payment with its outcome, see Simulation::summary_line
+    pub fn set_summary_logging(&mut self, summary_logging: bool) {
+        self.summary_logging = summary_logging;
+    }
+
+    /// Makes parts of an MPP avoid the channels into dest that earlier parts arrived via. The
+    /// penalty is in units of the routing metric's weight, 0 disables it. Defaults to a sat under
+    /// the fee metric
+    pub fn set_last_hop_penalty(&mut self, penalty: f32) {
+        self.last_hop_penalty = penalty;
+    }
+
+    /// Makes intermediaries fail to forward an HTLC with the given probability even if they have
+    /// the balance. 0 disables random failures, which is the default
+    pub fn set_failure_probability(&mut self, probability: f32) {
+        self.failure_probability = probability.clamp(0.0, 1.0);
+    }
+
+    /// Makes the node drop the HTLCs it is asked to forward with probability 1 - reliability,
+    /// on top of the failure probability every intermediary has
+    pub fn set_node_reliability(&mut self, node: &str, reliability: f32) {
+        self.node_reliability
+            .insert(node.to_string(), reliability.clamp(0.0, 1.0));
+    }
+
+    /// Makes parts of an MPP avoid the channels that earlier parts were delivered over
+    pub fn set_disjoint_paths(&mut self, disjoint_paths: bool) {
+        self.disjoint_paths = disjoint_paths;
+    }
+
+    /// Abandons an MPP with FailureReason::PersistentBottleneck once a split part fails at the
+    /// same channel as its parent instead of splitting it further
+    pub fn set_stop_on_bottleneck(&mut self, stop_on_bottleneck: bool) {
+        self.stop_on_bottleneck = stop_on_bottleneck;
+    }
+
+    /// Sets the number of hops the onion packet has room for. Longer paths are never used
+    pub fn set_onion_hop_limit(&mut self, limit: usize) {
+        self.onion_hop_limit = limit;
+    }
+
+    /// Sets the max number of intermediaries of the paths searched for payments, probes, routes
+    /// and rebalances. A payment's own max_hops applies if it is lower
+    pub fn set_max_hops(&mut self, max_hops: usize) {
+        self.max_hops = max_hops;
+    }
+
+    /// Called on every candidate path of the simulation's searches before it is used. Paths it
+    /// returns false for are excluded and the search continues with the next shortest ones
+    pub fn set_path_filter(&mut self, f: Box<dyn Fn(&CandidatePath) -> bool + Send + Sync>) {
+        self.path_filter = Some(PathFilter::new(f));
+        // the cached paths were not checked against it
+        self.route_cache.clear();
+    }
+
+    /// Reads historical success rates from a CSV file of channel_id,success_rate rows with an
+    /// optional header. Every search uses the listed channels' success rate as their
+    /// probability from then on, the others keep the one derived from their capacity
+    pub fn load_reliability(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        self.channel_reliability
+            .extend(PathFinder::read_reliability(path)?);
+        // the cached paths were chosen with the old probabilities
+        self.route_cache.clear();
+        Ok(())
+    }
+
+    /// Rounds the amounts of split payments to multiples of the quantum, e.g. 1000 for whole
+    /// sats. The remainder goes to the last part of each split
+    pub fn set_shard_quantum(&mut self, quantum_msat: u64) {
+        self.shard_quantum_msat = quantum_msat;
+    }
+
+    /// Searches the paths for the parts an MPP is initially split into concurrently. The parts
+    /// are still sent one after the other, a part whose path lost its liquidity to an earlier
+    /// part searches again. Random tie breaks and fee volatility always search serially
+    pub fn set_parallel_pathfinding(&mut self, parallel: bool) {
+        self.parallel_pathfinding = parallel;
+    }
+
+    /// Lets the LSP open a channel to a recipient that cannot receive an MPP otherwise. The
+    /// channel holds up to max_provide on the LSP's side and is announced in the invoice only.
+    /// The LSP's fee is charged to the payment on top of the routing fees
+    pub fn set_jit_lsp(&mut self, lsp: String, max_provide: u64, fee_msat: u64) {
+        self.jit_lsp = Some(JitLsp {
+            lsp,
+            max_provide,
+            fee_msat,
+        });
+    }
+
+    /// Makes every pathfinding see the fees scaled by a random factor around 1 with standard
+    /// deviation sigma, modelling stale fee gossip. The graph's fees stay as they are
+    pub fn enable_fee_volatility(&mut self, sigma: f64) {
+        self.fee_volatility = sigma;
+    }
+
+    /// Records every choice left to the RNG from now on, see recorded_decisions
+    pub fn record_decisions(&mut self) {
+        self.decisions.lock().unwrap().start_recording();
+    }
+
+    /// The choices recorded since record_decisions, in the order they were made
+    pub fn recorded_decisions(&self) -> Vec<Decision> {
+        self.decisions.lock().unwrap().recorded()
+    }
+
+    /// Makes the same choices as a recorded run instead of drawing them from the RNG, which
+    /// reproduces the run regardless of the seed
+    pub fn replay_decisions(&mut self, decisions: Vec<Decision>) {
+        self.decisions.lock().unwrap().start_replay(decisions);
+    }
+
+    /// Err if the replay stopped because the run asked for a different kind of choice than the
+    /// recorded one. The choices after that were drawn from the RNG
+    pub fn check_replay(&self) -> Result<(), ReplayError> {
+        match self.decisions.lock().unwrap().replay_error() {
+            Some(replay_error) => Err(replay_error),
+            None => Ok(()),
+        }
+    }
+
+    /// Selects the metric pathfinding optimises for from the built-in policies
+    pub fn set_routing_policy(&mut self, policy: RoutingPolicy) {
+        self.routing_metric = policy.into();
+    }
+
+    /// Caches up to capacity paths so that payments between the same nodes and of similar
+    /// amounts skip pathfinding. 0 disables the cache, which is the default
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.route_cache = RouteCache::new(capacity);
+    }
+
+    /// Number of pathfinding lookups answered from the route cache
+    pub fn route_cache_hits(&self) -> usize {
+        self.route_cache.hits
+    }
+
+    /// Share of pathfinding lookups answered from the route cache
+    pub fn route_cache_hit_rate(&self) -> f64 {
+        self.route_cache.hit_rate()
+    }
+
+    /// Ids of the completed payments with a part delivered over the channel, e.g. to see which
+    /// payments taking it offline would have affected. Failed AMP payments count with the parts
+    /// that arrived
+    pub fn payments_using_channel(&self, channel_id: &str) -> Vec<u64> {
+        self.successful_payments
+            .iter()
+            .chain(self.failed_payments.iter())
+            .filter(|payment| {
+                payment
+                    .used_paths
+                    .iter()
+                    .any(|p| p.path.hops.iter().any(|hop| hop.3 == channel_id))
+            })
+            .map(|payment| payment.payment_id as u64)
+            .collect()
+    }
+
+    /// Keeps every event processed from now on, e.g. for payment_events, the timeline or
+    /// write_manifest. Off by default as the events of a large run take up a lot of memory
+    pub fn set_record_events(&mut self, record_events: bool) {
+        self.record_events = record_events;
+    }
+
+    /// Keeps the event along with the current simulation time if events are recorded
+    pub(crate) fn record_event(&mut self, event: &PaymentEvent) {
+        if self.record_events {
+            self.processed_events
+                .push((self.event_queue.now(), event.clone()));
+        }
+    }
+
+    /// The recorded events as timeline entries, in the order they were processed
+    pub(crate) fn timeline(&self) -> Vec<TimelineEntry> {
+        self.processed_events
+            .iter()
+            .map(|(time, event)| event.to_timeline_entry(*time))
+            .collect()
+    }
+
+    /// Events processed so far that are about the payment or one of its parts, in the order
+    /// they were processed. Empty unless events are recorded
+    pub fn payment_events(&self, payment_id: u64) -> Vec<PaymentEvent> {
+        self.processed_events
+            .iter()
+            .map(|(_, event)| event)
+            .filter(|event| event.payment_id().map(|id| id as u64) == Some(payment_id))
+            .cloned()
+            .collect()
+    }
+
+    /// Total msat sent over each channel by the successful payments, summed over all their
+    /// parts. Unlike the channel usage it is derived from the payments that are kept, e.g. to
+    /// export the edges a batch used
+    pub fn used_edges(&self) -> HashMap<String, u64> {
+        let mut volumes = HashMap::new();
+        for path in self
+            .successful_payments
+            .iter()
+            .flat_map(|p| p.used_paths.iter())
+        {
+            for (channel_id, amount) in path.hop_amounts() {
+                *volumes.entry(channel_id).or_insert(0) += amount;
+            }
+        }
+        volumes
+    }
+
+    /// Routing fees each intermediary kept from the successful payments, summed over all their
+    /// parts. Nodes that never forwarded a delivered part are left out
+    pub fn node_fee_revenue(&self) -> HashMap<String, u64> {
+        let mut revenue = HashMap::new();
+        for path in self
+            .successful_payments
+            .iter()
+            .flat_map(|p| p.used_paths.iter())
+        {
+            let hops = &path.path.hops;
+            // the first hop is the source and the last one dest
+            for (node, fee, _, _) in hops.iter().skip(1).take(hops.len().saturating_sub(2)) {
+                *revenue.entry(node.clone()).or_insert(0) += *fee as u64;
+            }
+        }
+        revenue
+    }
+
+    /// Looks for a route from src to dest able to carry the amount without sending a payment
+    /// Balances are not touched but the route cache is used and filled
+    pub fn find_route(&mut self, src: &ID, dest: &ID, amount: usize) -> RouteResult {
+        let mut path_finder = self.path_finder(src, dest, amount);
+        path_finder.prefilter_edges();
+        path_finder.route_cache = std::mem::take(&mut self.route_cache);
+        let route = path_finder.find_path();
+        self.route_cache = path_finder.route_cache;
+        route
+    }
+
+    /// Up to k candidate paths from src to dest able to carry the amount, cheapest first
+    /// Works on a copy of the graph so balances are not touched
+    pub fn candidate_paths(
+        &self,
+        src: &ID,
+        dest: &ID,
+        amount: usize,
+        k: usize,
+    ) -> Vec<CandidatePath> {
+        let mut path_finder = self.path_finder(src, dest, amount);
+        path_finder.prefilter_edges();
+        path_finder.find_k_shortest_paths(k)
+    }
+
+    /// Whether the path could deliver its amount as the graph is now. Every channel has to have
+    /// the balance, minus what is in flight, to forward what it has to and accept an HTLC of
+    /// that size, and dest's channel room to receive the amount. Nothing is changed
+    pub fn is_path_viable(&self, path: &CandidatePath) -> bool {
+        let Some(last_hop) = path.path.hops.back() else {
+            return false;
+        };
+        let forwards =
+            path.path
+                .hops
+                .iter()
+                .zip(path.hop_amounts())
+                .all(|(hop, (channel_id, amount))| {
+                    let amount = amount as usize;
+                    self.graph
+                        .get_outedges(&hop.0)
+                        .into_iter()
+                        .find(|e| e.channel_id == channel_id)
+                        .is_some_and(|e| {
+                            self.graph.get_available_balance(&e) > amount
+                                && amount >= e.htlc_minimim_msat
+                                && (e.htlc_maximum_msat == 0 || amount <= e.htlc_maximum_msat)
+                        })
+                });
+        forwards
+            && self
+                .graph
+                .channel_can_receive_amount(&last_hop.3, path.path_amount())
+    }
+
     pub fn draw_n_pairs_for_simulation(
         graph: &Graph,
         n: usize,
-    ) -> (impl Iterator<Item = (ID, ID)> + Clone) {
+    ) -> impl Iterator<Item = (ID, ID)> + Clone {
         info!("Drawing {} sender-receiver pairs for simulation.", n,);
         let g = graph.clone();
         g.get_random_pairs_of_nodes(n)
     }
 
-    pub fn draw_adversaries(nodes: &[ID], num_adv: usize) -> (impl Iterator<Item = ID> + Clone) {
+    pub fn draw_adversaries(nodes: &[ID], num_adv: usize) -> impl Iterator<Item = ID> + Clone {
         let mut rng = crate::RNG.lock().unwrap();
         nodes
             .iter()
@@ -231,6 +1093,21 @@ impl Simulation {
         }
     }
 
+    /// Adds the hint to the invoice dest issued for the payment. False if there is no such invoice
+    pub(crate) fn add_route_hint(&mut self, dest: &ID, invoice_id: usize, hint: RouteHint) -> bool {
+        match self
+            .outstanding_invoices
+            .get_mut(dest)
+            .and_then(|invoices| invoices.get_mut(&invoice_id))
+        {
+            Some(invoice) => {
+                invoice.route_hints.push(hint);
+                true
+            }
+            None => false,
+        }
+    }
+
     #[allow(unused)]
     pub(crate) fn remove_invoice(&mut self, invoice: &Invoice) {
         let id = invoice.id;
@@ -248,6 +1125,71 @@ impl Simulation {
         self.current_payment_id += 1;
         current_id
     }
+
+    /// Copy of the simulation to send payments on without touching its state. Only the network,
+    /// the pending events and the settings are copied, the payments and stats recorded so far
+    /// and the invoices are left out as a dry run does not read them
+    pub(crate) fn dry_run_copy(&self) -> Self {
+        Self {
+            graph: self.graph.clone(),
+            initial_graph_hash: self.initial_graph_hash.clone(),
+            amount: self.amount,
+            run: self.run,
+            routing_metric: self.routing_metric,
+            payment_parts: self.payment_parts,
+            event_queue: self.event_queue.clone(),
+            current_payment_id: self.current_payment_id,
+            outstanding_invoices: BTreeMap::new(),
+            total_num_payments: 0,
+            num_successful: 0,
+            successful_payments: vec![],
+            num_failed: 0,
+            failed_payments: vec![],
+            number_of_adversaries: self.number_of_adversaries.clone(),
+            adversaries: vec![],
+            node_hits: HashMap::default(),
+            path_distances: PathDistances(vec![]),
+            path_diversity: PathDiversity(vec![]),
+            adversary_selection: self.adversary_selection.clone(),
+            timings: Timings::default(),
+            griefing_nodes: self.griefing_nodes.clone(),
+            griefing_timeout: self.griefing_timeout,
+            slow_threshold: self.slow_threshold,
+            channel_penalties: self.channel_penalties.clone(),
+            penalty_half_life: self.penalty_half_life,
+            persistent_penalties: self.persistent_penalties,
+            check_invariants: self.check_invariants,
+            strict: self.strict,
+            tie_break: self.tie_break,
+            fee_rounding: self.fee_rounding,
+            shard_order: self.shard_order,
+            dust_policy: self.dust_policy,
+            last_hop_penalty: self.last_hop_penalty,
+            route_cache: self.route_cache.clone(),
+            processed_events: vec![],
+            record_events: false,
+            summary_logging: false,
+            attempt_budget: self.attempt_budget,
+            htlc_attempts_spent: self.htlc_attempts_spent,
+            max_parts: self.max_parts,
+            min_shard_amt: self.min_shard_amt,
+            sim_delay: self.sim_delay,
+            failure_probability: self.failure_probability,
+            node_reliability: self.node_reliability.clone(),
+            disjoint_paths: self.disjoint_paths,
+            stop_on_bottleneck: self.stop_on_bottleneck,
+            onion_hop_limit: self.onion_hop_limit,
+            max_hops: self.max_hops,
+            path_filter: self.path_filter.clone(),
+            channel_reliability: self.channel_reliability.clone(),
+            shard_quantum_msat: self.shard_quantum_msat,
+            parallel_pathfinding: self.parallel_pathfinding,
+            fee_volatility: self.fee_volatility,
+            decisions: self.decisions.clone(),
+            jit_lsp: self.jit_lsp.clone(),
+            jit_liquidity_msat: self.jit_liquidity_msat,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -263,7 +1205,7 @@ mod tests {
         let path_to_file = Path::new("../test_data/trivial.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &path_to_file,
+                path_to_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -290,7 +1232,7 @@ mod tests {
         let path_to_file = Path::new("../test_data/trivial.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &path_to_file,
+                path_to_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -306,7 +1248,7 @@ mod tests {
         let path_to_file = Path::new("../test_data/trivial_connected.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &path_to_file,
+                path_to_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -328,7 +1270,7 @@ mod tests {
         let path_to_file = Path::new("../test_data/trivial.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &path_to_file,
+                path_to_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -361,11 +1303,7 @@ mod tests {
         );
         simulator.add_invoice(invoice2.clone());
         assert_eq!(simulator.outstanding_invoices.len(), 1);
-        let actual = simulator
-            .outstanding_invoices
-            .get(&"dina".to_owned())
-            .unwrap()
-            .clone();
+        let actual = simulator.outstanding_invoices.get("dina").unwrap().clone();
         let expected = HashMap::from([(invoice.id, invoice), (invoice2.id, invoice2)]);
         assert_eq!(actual, expected);
     }
@@ -377,7 +1315,7 @@ mod tests {
         let path_to_file = Path::new("../test_data/trivial.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &path_to_file,
+                path_to_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -423,7 +1361,7 @@ mod tests {
         let path_to_file = Path::new("../test_data/trivial.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &path_to_file,
+                path_to_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -458,7 +1396,7 @@ mod tests {
         let path_to_file = Path::new("../test_data/lnbook_example.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &path_to_file,
+                path_to_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -500,4 +1438,680 @@ mod tests {
         }
         assert_eq!(expected_hits, simulator.node_hits);
     }
+
+    // carol-alice can only carry one of the payments while the other one is still in flight
+    #[test]
+    fn scheduled_payments_compete_for_liquidity() {
+        let contend = |bob_arrival: f32, eve_arrival: f32| {
+            let json_file = "../test_data/trivial_multipath.json";
+            let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+            let amount = 3000;
+            let alice = "alice".to_string();
+            let from_bob = Payment::new(0, "bob".to_string(), alice.clone(), amount, None);
+            let from_eve = Payment::new(1, "eve".to_string(), alice.clone(), amount, None);
+            let result = simulator.run_scheduled(vec![
+                (Time::from_secs(bob_arrival), from_bob),
+                (Time::from_secs(eve_arrival), from_eve),
+            ]);
+            assert_eq!(result.total_num, 2);
+            assert_eq!(result.num_succesful, 1);
+            assert_eq!(result.num_failed, 1);
+            assert!(simulator.graph.in_flight.is_empty());
+            result.successful_payments[0].source.clone()
+        };
+        assert_eq!(contend(0.0, 10.0), "bob");
+        assert_eq!(contend(10.0, 0.0), "eve");
+    }
+
+    #[test]
+    fn mpp_conserves_liquidity() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        simulator.set_check_invariants(true);
+        let total_liquidity = simulator.graph.total_liquidity();
+        let payment = Payment::new(0, "bob".to_string(), "alice".to_string(), 6000, None);
+        let result = simulator.run_scheduled(vec![(Time::from_secs(0.0), payment)]);
+        assert_eq!(result.num_succesful, 1);
+        assert!(result.successful_payments[0].num_parts > 1);
+        assert_eq!(simulator.graph.total_liquidity(), total_liquidity);
+    }
+
+    #[test]
+    fn timeline_lists_shard_resolutions_in_time_order() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let payment = Payment::new(0, "bob".to_string(), "alice".to_string(), 6000, None);
+        let mut unrecorded = simulator.clone();
+        unrecorded.run_scheduled(vec![(Time::from_secs(0.0), payment.clone())]);
+        assert!(unrecorded.export_timeline_csv(file.path()).is_err());
+        simulator.set_record_events(true);
+        let result = simulator.run_scheduled(vec![(Time::from_secs(0.0), payment)]);
+        assert_eq!(result.successful_payments[0].num_parts, 2);
+        simulator.export_timeline_csv(file.path()).unwrap();
+        let contents = std::fs::read_to_string(file.path()).unwrap();
+        let mut rows = contents.lines();
+        assert_eq!(
+            rows.next(),
+            Some("event_time,payment_id,shard_channel_ids,event_kind")
+        );
+        let rows: Vec<Vec<&str>> = rows.map(|r| r.split(',').collect()).collect();
+        let shard_rows: Vec<&Vec<&str>> =
+            rows.iter().filter(|r| r[3] == "shard_succeeded").collect();
+        assert_eq!(shard_rows.len(), 2);
+        for row in shard_rows {
+            assert_eq!(row[1], "0");
+            assert!(row[2].starts_with("bob-"));
+            assert!(row[2].ends_with("alice-carol"));
+        }
+        let times: Vec<f32> = rows.iter().map(|r| r[0].parse().unwrap()).collect();
+        assert!(times.windows(2).all(|t| t[0] <= t[1]));
+        assert_eq!(rows[0][3], "scheduled");
+        assert_eq!(rows.last().unwrap()[3], "payment_succeeded");
+    }
+
+    #[test]
+    fn timeline_quotes_metadata_that_would_break_the_row() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        simulator.set_record_events(true);
+        let payment =
+            crate::payment::PaymentBuilder::new(0, "bob".to_string(), "alice".to_string(), 1000)
+                .metadata("note", "refund, \"late\"")
+                .build();
+        let result = simulator.run_scheduled(vec![(Time::from_secs(0.0), payment)]);
+        assert_eq!(result.num_succesful, 1);
+        let file = tempfile::NamedTempFile::new().unwrap();
+        simulator.export_timeline_csv(file.path()).unwrap();
+        let contents = std::fs::read_to_string(file.path()).unwrap();
+        let mut rows = contents.lines();
+        assert_eq!(
+            rows.next(),
+            Some("event_time,payment_id,shard_channel_ids,event_kind,note")
+        );
+        let scheduled = rows.next().unwrap();
+        assert!(scheduled.ends_with(",scheduled,\"refund, \"\"late\"\"\""));
+    }
+
+    #[test]
+    fn payments_using_channel_lists_the_mpp() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let payments = vec![
+            (
+                Time::from_secs(0.0),
+                Payment::new(7, "bob".to_string(), "alice".to_string(), 6000, None),
+            ),
+            (
+                Time::from_secs(1.0),
+                Payment::new(8, "carol".to_string(), "eve".to_string(), 1000, None),
+            ),
+        ];
+        let result = simulator.run_scheduled(payments);
+        let mpp = result
+            .successful_payments
+            .iter()
+            .find(|p| p.payment_id == 7)
+            .unwrap();
+        assert_eq!(mpp.used_paths.len(), 2);
+        let first_hops: Vec<&String> = mpp.used_paths.iter().map(|p| &p.path.hops[0].3).collect();
+        assert_ne!(first_hops[0], first_hops[1]);
+        for channel_id in first_hops {
+            assert_eq!(simulator.payments_using_channel(channel_id), vec![7]);
+        }
+        assert!(simulator.payments_using_channel("unknown").is_empty());
+    }
+
+    #[test]
+    fn batch_progress_counts_every_payment() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let payments = (0..3)
+            .map(|id| Payment::new(id, "bob".to_string(), "alice".to_string(), 1000, None))
+            .collect::<Vec<Payment>>();
+        let progress = AtomicUsize::new(0);
+        let result = simulator.run_batch_with_progress(payments, &progress);
+        assert_eq!(progress.load(Ordering::Relaxed), 3);
+        assert_eq!(result.num_succesful + result.num_failed, 3);
+    }
+
+    // carol's channels hold just the amount, which is not enough to send it until alice pays her
+    #[test]
+    fn round_trip_returns_on_the_shifted_liquidity() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.capacity = 1000000;
+                e.balance = if e.source == "carol" { 5000 } else { 70000 };
+            }
+        }
+        let (alice, carol) = ("alice".to_string(), "carol".to_string());
+        let (channel, channel_back) = ("alice-carol".to_string(), "carol-alice".to_string());
+        assert_eq!(
+            simulator.send_round_trip("alice", "carol", 5000),
+            (true, true)
+        );
+        assert_eq!(simulator.graph.get_channel_balance(&alice, &channel), 70000);
+        assert_eq!(
+            simulator.graph.get_channel_balance(&carol, &channel_back),
+            5000
+        );
+        // the other way around carol cannot pay first
+        assert_eq!(
+            simulator.send_round_trip("carol", "alice", 5000),
+            (false, true)
+        );
+        assert_eq!(simulator.graph.get_channel_balance(&alice, &channel), 65000);
+        assert_eq!(
+            simulator.graph.get_channel_balance(&carol, &channel_back),
+            10000
+        );
+        assert_eq!(simulator.num_successful, 3);
+        assert_eq!(simulator.num_failed, 1);
+    }
+
+    // carol-alice is too low for the payment until it is topped up
+    #[test]
+    fn replayed_payment_succeeds_after_raising_the_bottleneck() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.capacity = 1000000;
+                e.balance = match e.channel_id.as_str() {
+                    "carol-alice" | "dave-alice" => 1000,
+                    _ => 70000,
+                };
+            }
+        }
+        let mut recorded = Payment::new(
+            simulator.next_payment_id(),
+            "bob".to_string(),
+            "alice".to_string(),
+            5000,
+            None,
+        );
+        simulator.add_invoice(Invoice::new(
+            recorded.payment_id,
+            recorded.amount_msat,
+            &recorded.source,
+            &recorded.dest,
+        ));
+        assert!(!simulator.send_payment(&mut recorded));
+        assert!(recorded.failure_reason.is_some());
+        simulator
+            .graph
+            .update_channel_balance(&"carol-alice".to_string(), 70000);
+        assert!(simulator.replay_payment(&recorded));
+        assert_eq!(simulator.num_successful, 1);
+        // carol forwarded it and kept the fee
+        assert_eq!(
+            simulator
+                .graph
+                .get_channel_balance(&"carol".to_string(), &"carol-alice".to_string()),
+            70010
+        );
+    }
+
+    #[test]
+    fn dry_run_copy_leaves_out_the_recorded_payments() {
+        let mut simulator = crate::attempt::tests::init_sim(None, None);
+        simulator.set_record_events(true);
+        let (source, dest) = ("alice".to_string(), "chan".to_string());
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), 1000, None);
+        simulator.add_invoice(Invoice::new(0, 1000, &source, &dest));
+        assert!(simulator.send_one_payment(&mut payment).0);
+        simulator.successful_payments.push(payment);
+        let copy = simulator.dry_run_copy();
+        assert!(copy.successful_payments.is_empty());
+        assert!(copy.processed_events.is_empty());
+        assert!(!copy.record_events);
+        assert_eq!(copy.graph.content_hash(), simulator.graph.content_hash());
+        assert_eq!(copy.current_payment_id, simulator.current_payment_id);
+        assert!(copy
+            .plan_mpp(&source, &dest, 1000)
+            .is_some_and(|planned| planned.succeeded));
+    }
+
+    // bob's channels hold 9000 msat in total which is enough for either payment but not both
+    #[test]
+    fn bundled_payments_share_source_liquidity() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+            }
+        }
+        for channel_id in ["bob-carol", "bob-eve", "bob-dave"] {
+            simulator
+                .graph
+                .update_channel_balance(&channel_id.to_string(), 3000);
+        }
+        let bundle = || {
+            (0..2)
+                .map(|id| Payment::new(id, "bob".to_string(), "alice".to_string(), 5000, None))
+                .collect::<Vec<Payment>>()
+        };
+        for payment in bundle() {
+            assert!(simulator
+                .plan_mpp(&payment.source, &payment.dest, payment.amount_msat)
+                .is_some());
+        }
+        assert_eq!(simulator.send_bundle(bundle()), vec![true, false]);
+        assert_eq!(simulator.num_successful, 1);
+        assert_eq!(simulator.num_failed, 1);
+        assert!(simulator.graph.in_flight.is_empty());
+    }
+
+    #[test]
+    fn repeated_route_lookup_hits_cache() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+            }
+        }
+        let (bob, alice) = ("bob".to_string(), "alice".to_string());
+        // the cache is disabled by default
+        assert!(simulator.find_route(&bob, &alice, 1000).is_found());
+        assert!(simulator.find_route(&bob, &alice, 1000).is_found());
+        assert_eq!(simulator.route_cache_hits(), 0);
+        simulator.set_cache_capacity(4);
+        let first = simulator.find_route(&bob, &alice, 1000);
+        assert_eq!(simulator.route_cache_hits(), 0);
+        let second = simulator.find_route(&bob, &alice, 1000);
+        assert_eq!(simulator.route_cache_hits(), 1);
+        assert_eq!(first, second);
+        assert_eq!(simulator.route_cache_hit_rate(), 0.5);
+        // a balance change along the path invalidates the cached path
+        let channel_id = first.path().unwrap().path.hops[0].3.clone();
+        simulator.graph.update_channel_balance(&channel_id, 60000);
+        assert!(simulator.find_route(&bob, &alice, 1000).is_found());
+        assert_eq!(simulator.route_cache_hits(), 1);
+    }
+
+    #[test]
+    fn path_is_no_longer_viable_once_a_channel_is_drained() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+            }
+        }
+        let (bob, alice) = ("bob".to_string(), "alice".to_string());
+        let path = simulator.find_route(&bob, &alice, 1000).path().unwrap();
+        let liquidity = simulator.graph.total_liquidity();
+        assert!(simulator.is_path_viable(&path));
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+        // carol forwards the amount to alice but cannot take more in a single HTLC
+        for e in simulator.graph.edges.values_mut().flatten() {
+            if e.channel_id == "carol-alice" {
+                e.htlc_maximum_msat = 999;
+            }
+        }
+        assert!(!simulator.is_path_viable(&path));
+        for e in simulator.graph.edges.values_mut().flatten() {
+            if e.channel_id == "carol-alice" {
+                e.htlc_maximum_msat = 235000;
+            }
+        }
+        assert!(simulator.is_path_viable(&path));
+        let channel_id = path.path.hops[1].3.clone();
+        simulator.graph.update_channel_balance(&channel_id, 0);
+        assert!(!simulator.is_path_viable(&path));
+    }
+
+    // the cheapest route from alice to bob leads via carol and eve while there are routes with
+    // two hops
+    #[test]
+    fn min_hops_policy_finds_shorter_path() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+            }
+        }
+        let (alice, bob) = ("alice".to_string(), "bob".to_string());
+        assert_eq!(simulator.routing_metric, RoutingPolicy::default().into());
+        let min_fee = simulator.find_route(&alice, &bob, 1000).path().unwrap();
+        simulator.set_routing_policy(RoutingPolicy::MinHops);
+        let min_hops = simulator.find_route(&alice, &bob, 1000).path().unwrap();
+        assert_eq!(min_fee.path.path_length(), 3);
+        assert_eq!(min_hops.path.path_length(), 2);
+        assert!(min_hops.path_fees() > min_fee.path_fees());
+    }
+
+    // bob's paths to alice via carol and via eve differ by 20 msat in fees
+    #[test]
+    fn fee_volatility_varies_the_chosen_path() {
+        let first_hops = |sigma: f64| {
+            (0..20)
+                .map(|seed| {
+                    let json_file = "../test_data/trivial_multipath.json";
+                    let mut simulator =
+                        crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+                    for edges in simulator.graph.edges.values_mut() {
+                        for e in edges {
+                            e.balance = 70000;
+                            e.capacity = 1000000;
+                        }
+                    }
+                    simulator.decisions.lock().unwrap().reseed(seed);
+                    simulator.enable_fee_volatility(sigma);
+                    let route =
+                        simulator.find_route(&"bob".to_string(), &"alice".to_string(), 1000);
+                    route.path().unwrap().path.hops[0].3.clone()
+                })
+                .collect::<HashSet<String>>()
+        };
+        assert_eq!(first_hops(0.0).len(), 1);
+        assert!(first_hops(2.0).len() > 1);
+        // the graph's fees are left alone
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        let fees = |simulator: &Simulation| {
+            simulator
+                .graph
+                .edges
+                .values()
+                .flatten()
+                .map(|e| (e.fee_base_msat, e.fee_proportional_millionths))
+                .collect::<Vec<(usize, usize)>>()
+        };
+        let before = fees(&simulator);
+        simulator.enable_fee_volatility(2.0);
+        let _ = simulator.find_route(&"bob".to_string(), &"alice".to_string(), 1000);
+        assert_eq!(fees(&simulator), before);
+    }
+
+    // carol-alice is larger than dave-alice but fails most of the time
+    #[test]
+    fn loaded_reliability_steers_the_simulations_routes() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = if e.channel_id == "dave-alice" {
+                    100000
+                } else {
+                    1000000
+                };
+            }
+        }
+        simulator.routing_metric = RoutingMetric::MaxProb;
+        let (bob, alice) = ("bob".to_string(), "alice".to_string());
+        let route = simulator.find_route(&bob, &alice, 5000).path().unwrap();
+        assert_eq!(
+            route.path.get_involved_nodes(),
+            vec!["bob", "carol", "alice"]
+        );
+        simulator
+            .load_reliability(Path::new("../test_data/channel_reliability.csv"))
+            .unwrap();
+        let route = simulator.find_route(&bob, &alice, 5000).path().unwrap();
+        assert_eq!(
+            route.path.get_involved_nodes(),
+            vec!["bob", "dave", "alice"]
+        );
+    }
+
+    #[test]
+    fn fee_volatility_charges_the_real_fees() {
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        for seed in 0..20 {
+            let json_file = "../test_data/trivial_multipath.json";
+            let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+            for edges in simulator.graph.edges.values_mut() {
+                for e in edges {
+                    e.balance = 70000;
+                    e.capacity = 1000000;
+                }
+            }
+            simulator.decisions.lock().unwrap().reseed(seed);
+            simulator.enable_fee_volatility(2.0);
+            let route = simulator.find_route(&source, &dest, 1000);
+            let perturbed = route.path().unwrap();
+            let mut path_finder = PathFinder::new(
+                source.clone(),
+                dest.clone(),
+                1000,
+                &simulator.graph,
+                simulator.routing_metric,
+                simulator.payment_parts,
+            );
+            let mut real = CandidatePath::new_with_path(perturbed.path.clone());
+            path_finder.get_aggregated_path_cost(&mut real, false);
+            assert_eq!(perturbed.amount, real.amount);
+            assert_eq!(perturbed.path.hops, real.path.hops);
+        }
+    }
+
+    // carol drops the first payment's HTLC at carol-alice, after which she is reliable again
+    #[test]
+    fn channel_that_failed_an_earlier_payment_is_avoided() {
+        let second_path = |persistent: bool| {
+            let json_file = "../test_data/trivial_multipath.json";
+            let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+            for edges in simulator.graph.edges.values_mut() {
+                for e in edges {
+                    e.balance = 70000;
+                    e.capacity = 1000000;
+                    if e.channel_id == "dave-alice" {
+                        e.fee_base_msat = 100;
+                        e.fee_proportional_millionths = 0;
+                    }
+                }
+            }
+            simulator.routing_metric = RoutingMetric::MaxProb;
+            simulator.set_persistent_penalties(persistent);
+            let (source, dest) = ("bob".to_string(), "alice".to_string());
+            let amount = 1000;
+            simulator.set_node_reliability("carol", 0.0);
+            simulator.add_invoice(Invoice::new(0, amount, &source, &dest));
+            let mut payment = Payment::new(0, source.clone(), dest.clone(), amount, None);
+            assert!(simulator.send_payment(&mut payment));
+            assert_eq!(payment.failed_channels, vec!["carol-alice".to_string()]);
+            simulator.set_node_reliability("carol", 1.0);
+            simulator.add_invoice(Invoice::new(1, amount, &source, &dest));
+            let mut payment = Payment::new(1, source, dest, amount, None);
+            assert!(simulator.send_payment(&mut payment));
+            assert!(payment.failed_paths.is_empty());
+            payment.used_paths[0].path.get_involved_nodes()
+        };
+        assert_eq!(second_path(true), vec!["bob", "dave", "alice"]);
+        assert_eq!(second_path(false), vec!["bob", "carol", "alice"]);
+    }
+
+    // both payments are split in two parts that take bob -> carol -> alice
+    #[test]
+    fn used_edges_sum_the_volume_of_all_payments() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 100000;
+                e.capacity = 10000000;
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let payments = [4000, 6000]
+            .into_iter()
+            .enumerate()
+            .map(|(id, amount)| {
+                let mut payment =
+                    Payment::new(id, "bob".to_string(), "alice".to_string(), amount, Some(10));
+                payment.target_parts = Some(2);
+                (Time::from_secs(10.0 * id as f32), payment)
+            })
+            .collect();
+        let result = simulator.run_scheduled(payments);
+        assert_eq!(result.num_succesful, 2);
+        assert!(result.successful_payments.iter().all(|p| p.num_parts == 2));
+        let used_edges = simulator.used_edges();
+        assert_eq!(used_edges.len(), 2);
+        assert_eq!(used_edges["carol-alice"], 4000 + 6000);
+        // carol charges 10 msat per part
+        assert_eq!(used_edges["bob-carol"], 4020 + 6020);
+    }
+
+    #[test]
+    fn splitting_needs_a_min_shard_amount() {
+        let mut simulator = crate::attempt::tests::init_sim(None, None);
+        assert_eq!(simulator.payment_parts(), PaymentParts::Single);
+        simulator.min_shard_amt = 0;
+        assert_eq!(
+            simulator.set_payment_parts(PaymentParts::Split),
+            Err(PaymentPartsError::ZeroMinShardAmount)
+        );
+        assert_eq!(simulator.payment_parts(), PaymentParts::Single);
+        // a single part has no min size
+        assert!(simulator.set_payment_parts(PaymentParts::Single).is_ok());
+        simulator.min_shard_amt = 10;
+        simulator.max_parts = 0;
+        assert_eq!(
+            simulator.set_payment_parts(PaymentParts::Amp),
+            Err(PaymentPartsError::ZeroMaxParts)
+        );
+        simulator.max_parts = 2;
+        assert!(simulator.set_payment_parts(PaymentParts::Split).is_ok());
+        assert_eq!(simulator.payment_parts(), PaymentParts::Split);
+    }
+
+    // each payment takes at least one attempt per part, so the budget runs out after the second
+    #[test]
+    fn payments_are_abandoned_once_the_attempt_budget_is_used_up() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 100000;
+                e.capacity = 10000000;
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        simulator.set_attempt_budget(5);
+        let payments = (0..4)
+            .map(|id| {
+                let mut payment =
+                    Payment::new(id, "bob".to_string(), "alice".to_string(), 4000, Some(10));
+                payment.target_parts = Some(2);
+                (Time::from_secs(10.0 * id as f32), payment)
+            })
+            .collect();
+        let result = simulator.run_scheduled(payments);
+        assert_eq!(result.num_succesful, 2);
+        assert_eq!(result.num_failed, 2);
+        let spent: usize = result
+            .successful_payments
+            .iter()
+            .map(|p| p.htlc_attempts)
+            .sum();
+        assert_eq!(simulator.htlc_attempts_spent, spent as u64);
+        assert!(spent >= 5);
+        let mut abandoned: Vec<&Payment> = simulator.failed_payments.iter().collect();
+        abandoned.sort_by_key(|p| p.payment_id);
+        assert_eq!(abandoned[0].payment_id, 2);
+        assert_eq!(abandoned[1].payment_id, 3);
+        for payment in abandoned {
+            assert_eq!(
+                payment.failure_reason,
+                Some(FailureReason::AttemptBudgetExhausted)
+            );
+            assert_eq!(payment.htlc_attempts, 0);
+        }
+    }
+
+    // carol forwards both parts of bob's payment and charges 10 msat for each
+    #[test]
+    fn node_fee_revenue_sums_the_fees_of_all_parts() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 100000;
+                e.capacity = 10000000;
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let mut payment = Payment::new(0, "bob".to_string(), "alice".to_string(), 6000, Some(10));
+        payment.target_parts = Some(2);
+        let result = simulator.run_scheduled(vec![(Time::from_secs(10.0), payment)]);
+        assert_eq!(result.num_succesful, 1);
+        let payment = &result.successful_payments[0];
+        assert_eq!(payment.num_parts, 2);
+        let charged: usize = payment
+            .used_paths
+            .iter()
+            .flat_map(|p| p.path.hops.iter())
+            .filter(|hop| hop.0 == "carol" && hop.3 == "carol-alice")
+            .map(|hop| hop.1)
+            .sum();
+        assert_eq!(charged, 20);
+        let revenue = simulator.node_fee_revenue();
+        assert_eq!(revenue, HashMap::from([("carol".to_string(), 20)]));
+        assert_eq!(
+            revenue.values().sum::<u64>(),
+            payment.total_fees_msat as u64
+        );
+    }
+
+    // bob's payment reaches alice in two shards, the second payment's events are not listed
+    #[test]
+    fn payment_events_list_the_shards_of_the_payment() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 100000;
+                e.capacity = 10000000;
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let mut unrecorded = simulator.clone();
+        simulator.set_record_events(true);
+        let mut payment = Payment::new(0, "bob".to_string(), "alice".to_string(), 6000, Some(10));
+        payment.target_parts = Some(2);
+        let other = Payment::new(1, "carol".to_string(), "alice".to_string(), 1000, Some(10));
+        let payments = vec![
+            (Time::from_secs(10.0), payment),
+            (Time::from_secs(20.0), other),
+        ];
+        assert_eq!(unrecorded.run_scheduled(payments.clone()).num_succesful, 2);
+        assert!(unrecorded.payment_events(0).is_empty());
+        let result = simulator.run_scheduled(payments);
+        assert_eq!(result.num_succesful, 2);
+        let events = simulator.payment_events(0);
+        assert!(events.iter().all(|e| e.payment_id() == Some(0)));
+        assert!(matches!(
+            events.first(),
+            Some(PaymentEvent::Scheduled { .. })
+        ));
+        assert!(matches!(
+            events.last(),
+            Some(PaymentEvent::UpdateSuccesful { .. })
+        ));
+        let shards = events
+            .iter()
+            .filter(|e| matches!(e, PaymentEvent::ShardSuccess { .. }))
+            .count();
+        assert_eq!(shards, 2);
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, PaymentEvent::ShardFailed { .. })));
+        assert!(!simulator.payment_events(1).is_empty());
+        assert!(simulator.payment_events(2).is_empty());
+    }
 }
@@ -0,0 +1,320 @@
+use crate::{
+    core_types::event::PaymentEvent, payment::Payment, sim::SimResult, Invoice, Simulation,
+};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::sync::atomic::AtomicUsize;
+
+#[cfg(not(test))]
+use log::info;
+#[cfg(test)]
+use std::println as info;
+
+/// Settings of the payments to try every combination of
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SweepGrid {
+    pub min_shard_amts: Vec<usize>,
+    pub max_parts: Vec<usize>,
+}
+
+/// One combination of the grid's settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SweepPoint {
+    pub min_shard_amt: usize,
+    pub max_parts: usize,
+}
+
+impl SweepGrid {
+    /// Every combination of the settings, ordered by min shard amount first
+    pub fn points(&self) -> Vec<SweepPoint> {
+        self.min_shard_amts
+            .iter()
+            .flat_map(|min_shard_amt| {
+                self.max_parts.iter().map(|max_parts| SweepPoint {
+                    min_shard_amt: *min_shard_amt,
+                    max_parts: *max_parts,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Simulation {
+    /// Sends the payments with the settings of each point of the grid, one after another, and
+    /// returns the stats per point in the grid's order. Every point starts from a copy of the
+    /// simulation as it is, so the balances are the same for all of them and this simulation's
+    /// are not touched. The points are simulated concurrently, each drawing from its own RNG
+    pub fn sweep(
+        &self,
+        base_payments: &[Payment],
+        grid: SweepGrid,
+    ) -> Vec<(SweepPoint, SimResult)> {
+        info!(
+            "Sweeping {} payments over {} grid points.",
+            base_payments.len(),
+            grid.points().len()
+        );
+        grid.points()
+            .into_par_iter()
+            .map(|point| {
+                let mut sim = self.trial_copy();
+                let payments = base_payments
+                    .iter()
+                    .cloned()
+                    .map(|mut payment| {
+                        payment.min_shard_amt = point.min_shard_amt;
+                        payment.max_parts = point.max_parts;
+                        payment
+                    })
+                    .collect();
+                let stats = sim.run_batch_with_progress(payments, &AtomicUsize::new(0));
+                (point, stats)
+            })
+            .collect()
+    }
+
+    /// Sends every payment on its own copy of the simulation as it is and returns them resolved,
+    /// in the order they were passed. The paths are searched concurrently. Balances moved by one
+    /// payment are not seen by the others and this simulation's are not touched, so the result
+    /// matches sending them one after another only for payments that do not share channels
+    /// Every copy draws the same random choices, so the result does not depend on the threads
+    pub fn run_batch_parallel_ordered(&self, payments: Vec<Payment>) -> Vec<Payment> {
+        info!(
+            "Sending {} payments on separate copies of the simulation.",
+            payments.len()
+        );
+        // the payments sent so far are left out of the copies
+        let mut base = self.trial_copy();
+        base.successful_payments.clear();
+        base.failed_payments.clear();
+        base.processed_events.clear();
+        payments
+            .into_par_iter()
+            .map(|payment| {
+                let mut sim = base.clone();
+                sim.add_invoice(Invoice::new(
+                    payment.payment_id,
+                    payment.amount_msat,
+                    &payment.source,
+                    &payment.dest,
+                ));
+                sim.event_queue
+                    .schedule(sim.sim_delay, PaymentEvent::Scheduled { payment });
+                sim.process_events();
+                sim.successful_payments
+                    .pop()
+                    .or_else(|| sim.failed_payments.pop())
+                    .expect("A payment resolves once it has been sent.")
+            })
+            .collect()
+    }
+
+    /// Scales the amounts of the payments up until the share of them that succeeds falls below
+    /// the threshold and returns the factor it fell at. The factor doubles until then and is
+    /// narrowed down between the last two factors after. Every trial starts from a copy of the
+    /// simulation as it is. 1 if the payments fall below the threshold as they are
+    pub fn find_saturation(&self, base_payments: &[Payment], threshold: f64) -> f64 {
+        // even payments that all fail do not fall below it
+        if threshold <= 0.0 {
+            return f64::INFINITY;
+        }
+        let success_rate = |factor: f64| {
+            let payments = base_payments
+                .iter()
+                .cloned()
+                .map(|mut payment| {
+                    payment.amount_msat = (payment.amount_msat as f64 * factor).round() as usize;
+                    payment
+                })
+                .collect();
+            self.trial_copy()
+                .run_batch_with_progress(payments, &AtomicUsize::new(0))
+                .success_rate()
+        };
+        if success_rate(1.0) < threshold {
+            return 1.0;
+        }
+        let (mut holds, mut breaks) = (1.0, 2.0);
+        while success_rate(breaks) >= threshold {
+            holds = breaks;
+            breaks *= 2.0;
+        }
+        for _ in 0..crate::SATURATION_BISECTIONS {
+            let factor = (holds + breaks) / 2.0;
+            if success_rate(factor) < threshold {
+                breaks = factor;
+            } else {
+                holds = factor;
+            }
+        }
+        info!(
+            "Success rate of {} payments falls below {} when scaled by {}.",
+            base_payments.len(),
+            threshold,
+            breaks
+        );
+        breaks
+    }
+
+    /// Copy of the simulation with its own decision log, whose RNG starts over from the run's
+    /// seed. Trials draw the same choices whichever thread runs them and whatever the
+    /// simulation drew before
+    fn trial_copy(&self) -> Simulation {
+        let sim = self.clone();
+        sim.decisions.lock().unwrap().reseed(self.run);
+        sim
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PaymentParts, TieBreak};
+
+    // 12000 msat need two parts as no channel into alice holds more than 10000
+    #[test]
+    fn sweep_returns_stats_per_grid_point() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 10000;
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let payments: Vec<Payment> = (0..2)
+            .map(|id| Payment::new(id, "bob".to_string(), "alice".to_string(), 12000, None))
+            .collect();
+        let liquidity = simulator.graph.total_liquidity();
+        let grid = SweepGrid {
+            min_shard_amts: vec![10, 10000],
+            max_parts: vec![1, 16],
+        };
+        let results = simulator.sweep(&payments, grid.clone());
+        assert_eq!(results.len(), 4);
+        for ((point, stats), expected) in results.iter().zip(grid.points()) {
+            assert_eq!(*point, expected);
+            assert_eq!(stats.total_num, 2);
+            assert_eq!(stats.num_succesful + stats.num_failed, 2);
+        }
+        // parts of at least 10000 msat never get the payment through, small ones do
+        let succeeded: Vec<usize> = results.iter().map(|(_, s)| s.num_succesful).collect();
+        assert!(succeeded[1] > 0);
+        assert_eq!(succeeded[2], 0);
+        assert_eq!(succeeded[3], 0);
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+        assert_eq!(simulator.total_num_payments, 0);
+    }
+
+    #[test]
+    fn sweep_points_draw_from_their_own_rng() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        simulator.set_tie_break(TieBreak::Random);
+        simulator.set_failure_probability(0.3);
+        let payments: Vec<Payment> = (0..6)
+            .map(|id| Payment::new(id, "bob".to_string(), "alice".to_string(), 30000, None))
+            .collect();
+        let grid = SweepGrid {
+            min_shard_amts: vec![10, 1000],
+            max_parts: vec![4, 16],
+        };
+        let paths = |results: Vec<(SweepPoint, SimResult)>| {
+            results
+                .into_iter()
+                .map(|(_, stats)| {
+                    stats
+                        .successful_payments
+                        .into_iter()
+                        .chain(stats.failed_payments)
+                        .map(|payment| (payment.payment_id, payment.used_paths))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        let first = paths(simulator.sweep(&payments, grid.clone()));
+        // draws of the simulation itself do not shift the points' choices
+        simulator.decisions.lock().unwrap().failure_roll();
+        let second = paths(simulator.sweep(&payments, grid));
+        assert_eq!(first, second);
+    }
+
+    // bob holds 210000 msat in total, far more than the two payments of 1000 msat
+    #[test]
+    fn saturation_of_a_graph_with_slack() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let payments: Vec<Payment> = (0..2)
+            .map(|id| Payment::new(id, "bob".to_string(), "alice".to_string(), 1000, None))
+            .collect();
+        let liquidity = simulator.graph.total_liquidity();
+        let factor = simulator.find_saturation(&payments, 0.5);
+        assert!(factor > 1.0);
+        // a single payment cannot be larger than what bob holds
+        assert!(factor < 1000.0);
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+        assert_eq!(simulator.find_saturation(&payments, 1.1), 1.0);
+    }
+
+    // the payments leave from different nodes over different channels. An earlier payment of
+    // bob's had the same id as the first one
+    #[test]
+    fn parallel_ordered_batch_matches_serial_run() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+            }
+        }
+        let payments: Vec<Payment> = [(0, "bob", "dave"), (1, "carol", "alice"), (2, "eve", "bob")]
+            .into_iter()
+            .map(|(id, source, dest)| {
+                Payment::new(id, source.to_string(), dest.to_string(), 1000, None)
+            })
+            .collect();
+        let earlier = Payment::new(0, "bob".to_string(), "dave".to_string(), 500, None);
+        simulator.run_batch_with_progress(vec![earlier], &AtomicUsize::new(0));
+        let liquidity = simulator.graph.total_liquidity();
+        let parallel = simulator.run_batch_parallel_ordered(payments.clone());
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+        assert_eq!(parallel[0].amount_msat, 1000);
+        assert_eq!(
+            simulator.run_batch_parallel_ordered(payments.clone()),
+            parallel
+        );
+        let mut serial_sim = simulator.clone();
+        let serial = serial_sim.run_batch_with_progress(payments.clone(), &AtomicUsize::new(0));
+        assert_eq!(serial.num_succesful, 4);
+        assert_eq!(parallel.len(), payments.len());
+        for (parallel, sent) in parallel.iter().zip(payments.iter()) {
+            assert_eq!(parallel.payment_id, sent.payment_id);
+            let serial = serial
+                .successful_payments
+                .iter()
+                .rfind(|p| p.payment_id == sent.payment_id)
+                .unwrap();
+            assert!(parallel.succeeded);
+            assert_eq!(parallel.used_paths, serial.used_paths);
+            assert_eq!(parallel.total_fees_msat, serial.total_fees_msat);
+            assert_eq!(parallel.htlc_attempts, serial.htlc_attempts);
+        }
+    }
+}
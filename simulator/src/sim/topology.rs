@@ -0,0 +1,222 @@
+use crate::{core_types::event::PaymentEvent, time::Time, Simulation, ID};
+
+#[cfg(not(test))]
+use log::{error, info};
+#[cfg(test)]
+use std::{println as info, println as error};
+
+impl Simulation {
+    /// Schedules a channel of capacity msat between node_a and node_b to open at the given time.
+    /// node_a holds the balance_split share of it, node_b the rest. Payments dispatched after
+    /// it opened may route through it
+    pub fn schedule_channel_open(
+        &mut self,
+        node_a: ID,
+        node_b: ID,
+        capacity: u64,
+        balance_split: f64,
+        at: Time,
+    ) {
+        let capacity = capacity as usize;
+        let balance_a = (capacity as f64 * balance_split.clamp(0.0, 1.0)).round() as usize;
+        self.event_queue.schedule(
+            at,
+            PaymentEvent::ChannelOpen {
+                node_a,
+                node_b,
+                capacity,
+                balance_a,
+            },
+        );
+    }
+
+    /// Schedules the channel to close at the given time. Payments dispatched after it closed
+    /// are routed around it. HTLCs in flight over the channel at that time resolve first
+    pub fn schedule_channel_close(&mut self, channel_id: String, at: Time) {
+        self.event_queue
+            .schedule(at, PaymentEvent::ChannelClose { channel_id });
+    }
+
+    pub(crate) fn open_channel(
+        &mut self,
+        node_a: &ID,
+        node_b: &ID,
+        capacity: usize,
+        balance_a: usize,
+    ) {
+        if !self.graph.node_is_in_graph(node_a) || !self.graph.node_is_in_graph(node_b) {
+            error!(
+                "Cannot open a channel between {} and {} as they are not in the graph.",
+                node_a, node_b
+            );
+            return;
+        }
+        let (channel_id, _) = self.graph.open_channel(
+            node_a,
+            node_b,
+            balance_a,
+            capacity.saturating_sub(balance_a),
+        );
+        // cached paths may no longer be the best ones
+        self.route_cache.clear();
+        info!(
+            "{} opened channel {} of {} msat to {}.",
+            node_a, channel_id, capacity, node_b
+        );
+    }
+
+    pub(crate) fn close_channel(&mut self, channel_id: &String) {
+        // the channel stays open until the HTLCs over it resolved
+        let sides = self.graph.channel_sides(channel_id);
+        if let Some(resolved_at) = self.event_queue.last_resolution(&sides) {
+            info!(
+                "Deferring the close of channel {} until its HTLCs resolved at {}.",
+                channel_id, resolved_at
+            );
+            let delay = resolved_at - self.event_queue.now();
+            self.event_queue.schedule(
+                delay,
+                PaymentEvent::ChannelClose {
+                    channel_id: channel_id.clone(),
+                },
+            );
+            return;
+        }
+        let closed = self.graph.close_channel(channel_id);
+        if closed.is_empty() {
+            error!(
+                "Cannot close channel {} as it is not in the graph.",
+                channel_id
+            );
+            return;
+        }
+        for channel_id in closed.iter() {
+            self.route_cache.invalidate_channel(channel_id);
+        }
+        info!("Channel {} closed.", channel_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{payment::Payment, PaymentId};
+
+    fn init_sim() -> Simulation {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+                if e.channel_id == "dave-alice" {
+                    e.fee_base_msat = 100;
+                    e.fee_proportional_millionths = 0;
+                }
+            }
+        }
+        simulator
+    }
+
+    // the second payment is dispatched once the first one resolved
+    fn payments() -> Vec<(Time, Payment)> {
+        (0..2)
+            .map(|id| {
+                let payment = Payment::new(id, "bob".to_string(), "alice".to_string(), 1000, None);
+                (Time::from_secs(200.0 * id as f32), payment)
+            })
+            .collect()
+    }
+
+    fn used_nodes(simulator: &Simulation, payment_id: PaymentId) -> Vec<ID> {
+        simulator
+            .successful_payments
+            .iter()
+            .find(|p| p.payment_id == payment_id)
+            .unwrap()
+            .used_paths[0]
+            .path
+            .get_involved_nodes()
+    }
+
+    #[test]
+    fn later_payment_uses_opened_channel() {
+        let mut simulator = init_sim();
+        simulator.schedule_channel_open(
+            "alice".to_string(),
+            "bob".to_string(),
+            100000,
+            0.5,
+            Time::from_secs(5.0),
+        );
+        let result = simulator.run_scheduled(payments());
+        assert_eq!(result.num_succesful, 2);
+        assert_eq!(used_nodes(&simulator, 0), vec!["bob", "carol", "alice"]);
+        assert_eq!(used_nodes(&simulator, 1), vec!["bob", "alice"]);
+        let bob_side = simulator
+            .graph
+            .get_edge(&"bob".to_string(), &"alice".to_string())
+            .unwrap();
+        assert_eq!(bob_side.channel_id, "bob-alice");
+        assert_eq!(bob_side.balance, 50000 - 1000);
+        assert!(!simulator.graph.private_channels.contains("bob-alice"));
+    }
+
+    #[test]
+    fn later_payment_is_routed_around_closed_channel() {
+        let mut simulator = init_sim();
+        simulator.schedule_channel_close("carol-alice".to_string(), Time::from_secs(5.0));
+        let result = simulator.run_scheduled(payments());
+        assert_eq!(result.num_succesful, 2);
+        assert_eq!(used_nodes(&simulator, 0), vec!["bob", "carol", "alice"]);
+        assert_eq!(used_nodes(&simulator, 1), vec!["bob", "dave", "alice"]);
+        for channel_id in ["carol-alice", "alice-carol"] {
+            assert!(simulator
+                .graph
+                .get_edges()
+                .values()
+                .flatten()
+                .all(|e| e.channel_id != channel_id));
+        }
+    }
+
+    #[test]
+    fn close_waits_for_the_htlcs_in_flight() {
+        let mut simulator = init_sim();
+        simulator.set_record_events(true);
+        simulator.schedule_channel_close("alice-carol".to_string(), Time::from_secs(5.0));
+        let result = simulator.run_scheduled(payments());
+        assert_eq!(result.num_succesful, 2);
+        assert_eq!(used_nodes(&simulator, 0), vec!["bob", "carol", "alice"]);
+        assert_eq!(used_nodes(&simulator, 1), vec!["bob", "dave", "alice"]);
+        assert!(simulator.graph.in_flight.is_empty());
+        let kinds: Vec<&str> = simulator
+            .processed_events
+            .iter()
+            .filter(|(_, event)| event.payment_id() != Some(1))
+            .map(|(_, event)| event.kind())
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "scheduled",
+                "channel_closed",
+                "payment_succeeded",
+                "channel_closed"
+            ]
+        );
+        let closed_at: Vec<Time> = simulator
+            .processed_events
+            .iter()
+            .filter(|(_, event)| matches!(event, PaymentEvent::ChannelClose { .. }))
+            .map(|(time, _)| *time)
+            .collect();
+        assert_eq!(
+            closed_at,
+            vec![
+                Time::from_secs(5.0),
+                Time::from_secs(crate::SIM_DELAY_IN_SECS)
+            ]
+        );
+    }
+}
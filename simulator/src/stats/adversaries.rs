@@ -116,7 +116,7 @@ impl Simulation {
         let mut adv_count_successful: HashMap<usize, usize> = HashMap::default();
         let mut contains_an_adversary = |payment: &Payment| {
             let mut all_paths = payment.used_paths.to_owned();
-            all_paths.extend(payment.failed_paths.to_owned());
+            all_paths.extend(payment.failed_paths.iter().map(|(path, ..)| path.clone()));
             for path in all_paths.iter() {
                 if !path.path.path_contains_adversary(adv).is_empty() {
                     hits += 1;
@@ -130,7 +130,7 @@ impl Simulation {
         for payment in payments {
             contains_an_adversary(payment);
             let mut used_paths = payment.used_paths.to_owned();
-            used_paths.extend(payment.failed_paths.to_owned());
+            used_paths.extend(payment.failed_paths.iter().map(|(path, ..)| path.clone()));
             let mut num_attacks = 0;
             for path in used_paths.iter() {
                 let num_adv = path.path.path_contains_adversary(adv);
@@ -232,7 +232,7 @@ impl Simulation {
         let mut correlated_successful = 0;
         for payment in payments {
             let mut all_paths = payment.used_paths.to_owned();
-            all_paths.extend(payment.failed_paths.to_owned());
+            all_paths.extend(payment.failed_paths.iter().map(|(path, ..)| path.clone()));
             let mut paths_containing_adversaries = 0;
             for path in all_paths.iter() {
                 // no need to exclude the src and dest and the called function takes that into account
@@ -287,7 +287,7 @@ mod tests {
 
     use super::*;
     use crate::{
-        payment::Payment,
+        payment::{FailureReason, Payment},
         traversal::pathfinding::{CandidatePath, Path},
         AdversarySelection,
     };
@@ -347,7 +347,7 @@ mod tests {
         let number_of_adversaries = 4;
         let simulator = crate::attempt::tests::init_sim(None, Some(vec![number_of_adversaries]));
         let adversaries = simulator.get_adversaries(number_of_adversaries);
-        assert!(adversaries.get(&AdversarySelection::Random).is_some());
+        assert!(adversaries.contains_key(&AdversarySelection::Random));
         let actual = adversaries.get(&AdversarySelection::Random).unwrap();
         assert_eq!(actual.len(), number_of_adversaries);
         for node in simulator.graph.get_node_ids() {
@@ -386,13 +386,7 @@ mod tests {
         let source = "alice".to_string();
         let payments = vec![
             Payment {
-                payment_id: 2,
-                source: source.clone(),
-                dest: "eric".to_string(),
-                amount_msat: 1000,
                 succeeded: true,
-                min_shard_amt: crate::MIN_SHARD_AMOUNT,
-                num_parts: 1,
                 htlc_attempts: 2,
                 used_paths: vec![CandidatePath {
                     path: Path {
@@ -409,32 +403,30 @@ mod tests {
                     weight: 100.0,
                     amount: 1100,
                     time: 40,
+                    shard_id: None,
                 }],
-                failed_amounts: Vec::default(),
-                successful_shards: Vec::default(),
-                failed_paths: vec![CandidatePath {
-                    path: Path {
-                        src: "alice".to_string(),
-                        dest: "eric".to_string(),
-                        hops: VecDeque::from([
-                            ("alice".to_string(), 1100, 40, "alice1".to_string()),
-                            ("bob".to_string(), 100, 40, "bob2".to_string()),
-                            ("eric".to_string(), 1000, 0, "eric1".to_string()),
-                        ]),
+                failed_paths: vec![(
+                    CandidatePath {
+                        path: Path {
+                            src: "alice".to_string(),
+                            dest: "eric".to_string(),
+                            hops: VecDeque::from([
+                                ("alice".to_string(), 1100, 40, "alice1".to_string()),
+                                ("bob".to_string(), 100, 40, "bob2".to_string()),
+                                ("eric".to_string(), 1000, 0, "eric1".to_string()),
+                            ]),
+                        },
+                        weight: 100.0,
+                        amount: 1100,
+                        time: 40,
+                        shard_id: None,
                     },
-                    weight: 100.0,
-                    amount: 1100,
-                    time: 40,
-                }],
+                    FailureReason::InsufficientLiquidity,
+                    None,
+                )],
+                ..Payment::new(2, source.clone(), "eric".to_string(), 1000, None)
             },
             Payment {
-                payment_id: 2,
-                source: source.clone(),
-                dest: "eric".to_string(),
-                amount_msat: 1000,
-                succeeded: false,
-                min_shard_amt: crate::MIN_SHARD_AMOUNT,
-                num_parts: 1,
                 htlc_attempts: 2,
                 used_paths: vec![CandidatePath {
                     path: Path {
@@ -451,31 +443,36 @@ mod tests {
                     weight: 100.0,
                     amount: 1100,
                     time: 40,
+                    shard_id: None,
                 }],
-                failed_amounts: Vec::default(),
-                successful_shards: Vec::default(),
-                failed_paths: vec![CandidatePath {
-                    path: Path {
-                        src: "alice".to_string(),
-                        dest: "eric".to_string(),
-                        hops: VecDeque::from([
-                            ("alice".to_string(), 1100, 40, "alice1".to_string()),
-                            ("bob".to_string(), 100, 40, "bob2".to_string()),
-                            ("eric".to_string(), 1000, 0, "eric1".to_string()),
-                        ]),
+                failed_paths: vec![(
+                    CandidatePath {
+                        path: Path {
+                            src: "alice".to_string(),
+                            dest: "eric".to_string(),
+                            hops: VecDeque::from([
+                                ("alice".to_string(), 1100, 40, "alice1".to_string()),
+                                ("bob".to_string(), 100, 40, "bob2".to_string()),
+                                ("eric".to_string(), 1000, 0, "eric1".to_string()),
+                            ]),
+                        },
+                        weight: 100.0,
+                        amount: 1100,
+                        time: 40,
+                        shard_id: None,
                     },
-                    weight: 100.0,
-                    amount: 1100,
-                    time: 40,
-                }],
+                    FailureReason::InsufficientLiquidity,
+                    None,
+                )],
+                ..Payment::new(2, source.clone(), "eric".to_string(), 1000, None)
             },
         ];
         let (correlation_count, correlation_count_successful) =
-            Simulation::colluding_adversaries(&payments, &adversaries);
+            Simulation::colluding_adversaries(&payments, adversaries);
         assert_eq!(correlation_count, 2); // bob sees the payment twice
         assert_eq!(correlation_count_successful, 1);
         let (prone_paths, prone_payments) =
-            Simulation::prone_paths_and_payments(&payments, &adversaries);
+            Simulation::prone_paths_and_payments(&payments, adversaries);
         // all paths are susceptible
         assert_abs_diff_eq!(prone_paths.0, 1.0, epsilon = 0.001f32);
         // all successful payemnts' paths are susceptible
@@ -42,7 +42,11 @@ impl Simulation {
                 let mut sd_anon_set = HashSet::new();
                 let mut rx_anon_set = HashSet::new();
                 // will only be one at most
-                if let Some(adv) = p.path.path_contains_adversary(&[adversary.clone()]).first() {
+                if let Some(adv) = p
+                    .path
+                    .path_contains_adversary(std::slice::from_ref(adversary))
+                    .first()
+                {
                     let adversary_id = adv.0.clone();
                     let (pred, succ, amount_to_succ, ttl_to_rx) =
                         Self::extract_tx_info(p, &adversary_id);
@@ -148,11 +152,7 @@ impl Simulation {
             });
         });
         if let Ok(arc) = Arc::try_unwrap(all_anonymits_sets) {
-            if let Ok(mutex) = arc.into_inner() {
-                mutex
-            } else {
-                vec![]
-            }
+            arc.into_inner().unwrap_or_default()
         } else {
             vec![]
         }
@@ -441,13 +441,14 @@ mod tests {
             weight: 0.0,
             amount: 0,
             time: 0,
+            shard_id: None,
         }];
         let actual = Simulation::get_all_reachable_paths(&graph, &next, amount, ttl);
         assert!(actual.is_some());
         assert_eq!(actual.unwrap(), expected);
         let next = "bob".to_string();
         let ttl = 40;
-        let expected = vec![
+        let expected = [
             CandidatePath {
                 path: Path {
                     src: "bob".to_owned(),
@@ -460,6 +461,7 @@ mod tests {
                 weight: 0.0,
                 amount: 0,
                 time: 0,
+                shard_id: None,
             },
             CandidatePath {
                 path: Path {
@@ -473,6 +475,7 @@ mod tests {
                 weight: 0.0,
                 amount: 0,
                 time: 0,
+                shard_id: None,
             },
         ];
         let actual = Simulation::get_all_reachable_paths(&graph, &next, amount, ttl);
@@ -495,6 +498,7 @@ mod tests {
             weight: 0.0,
             amount: 0,
             time: 0,
+            shard_id: None,
         }];
         let actual = Simulation::get_all_reachable_paths(&graph, &next, amount, ttl);
         assert!(actual.is_some());
@@ -535,6 +539,7 @@ mod tests {
             weight: 0.0,
             amount: 0,
             time: 0,
+            shard_id: None,
         };
         // alice's neighbours
         let mut shortest_paths = HashMap::from([
@@ -545,6 +550,7 @@ mod tests {
                     weight: 0.0,
                     amount: 0,
                     time: 0,
+                    shard_id: None,
                 },
             ),
             (
@@ -562,6 +568,7 @@ mod tests {
                     weight: 0.0,
                     amount: 0,
                     time: 0,
+                    shard_id: None,
                 },
             ),
         ]);
@@ -592,6 +599,7 @@ mod tests {
             weight: 0.0,
             amount: 0,
             time: 0,
+            shard_id: None,
         };
         let path_from_pre = CandidatePath {
             path: Path {
@@ -607,6 +615,7 @@ mod tests {
             weight: 0.0,
             amount: 0,
             time: 0,
+            shard_id: None,
         };
         let mut shortest_paths = HashMap::from([((pre.to_owned(), next), path_from_pre)]);
         assert!(simulator.is_pred_definitive_sender(&p_i_prime, &pre, amount, &mut shortest_paths));
@@ -631,6 +640,7 @@ mod tests {
             weight: 5175.0,
             amount: 5175,
             time: 90,
+            shard_id: None,
         };
         let path_from_adv = CandidatePath {
             path: Path {
@@ -644,6 +654,7 @@ mod tests {
             weight: 5175.0,
             amount: 5175,
             time: 90,
+            shard_id: None,
         };
         assert!(Simulation::is_potential_destination(
             &p_i_prime,
@@ -665,6 +676,7 @@ mod tests {
             weight: 5175.0,
             amount: 5175,
             time: 90,
+            shard_id: None,
         };
         assert!(!Simulation::is_potential_destination(
             &p_i_prime,
@@ -52,8 +52,9 @@ impl Simulation {
         for idx in 0..paths.len() {
             let mut div_min_path_i = f32::MAX;
             let mut alternate_paths = paths.to_vec();
-            // the base path should be the only item returned by drain
-            if let Some(base_path) = alternate_paths.drain(idx..idx + 1).last() {
+            // remove the base path so only the alternates remain
+            if idx < alternate_paths.len() {
+                let base_path = alternate_paths.remove(idx);
                 for path in alternate_paths {
                     let div = Self::calculate_path_diversity(&base_path, &path);
                     div_min_path_i = f32::min(div_min_path_i, div);
@@ -229,13 +230,6 @@ mod tests {
         let source = String::from("a");
         let dest = String::from("d");
         let successful_payments = vec![Payment {
-            payment_id: 0,
-            source: source.clone(),
-            dest: dest.clone(),
-            amount_msat: amount,
-            succeeded: false,
-            min_shard_amt: 10,
-            htlc_attempts: 0,
             num_parts: 2,
             used_paths: vec![
                 CandidatePath {
@@ -252,6 +246,7 @@ mod tests {
                     weight: 175.0, // fees (b->c, c->d)
                     amount: 5175,  // amount + fees
                     time: 55,
+                    shard_id: None,
                 },
                 CandidatePath {
                     path: Path {
@@ -265,11 +260,10 @@ mod tests {
                     weight: 15.0,
                     amount: 55,
                     time: 5,
+                    shard_id: None,
                 },
             ],
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-            failed_paths: vec![],
+            ..Payment::new(0, source.clone(), dest.clone(), amount, Some(10))
         }];
         simulator.successful_payments = successful_payments;
         simulator.eval_path_similarity();
@@ -1,6 +1,5 @@
 use crate::{
-    event::*, io::PaymentInfo, payment::Payment, stats::TargetedAttack, time::Time, Invoice,
-    PaymentParts, Simulation, ID,
+    event::*, io::PaymentInfo, payment::Payment, stats::TargetedAttack, Invoice, Simulation, ID,
 };
 
 use itertools::EitherOrBoth::{Both, Left, Right};
@@ -46,10 +45,17 @@ impl Simulation {
             let payment_id = self.next_payment_id();
             let invoice = Invoice::new(payment_id, self.amount, &src, &dest);
             self.add_invoice(invoice);
-            let payment = Payment::new(payment_id, src, dest, self.amount, min_shard_amt);
+            let mut payment = Payment::new(
+                payment_id,
+                src,
+                dest,
+                self.amount,
+                Some(min_shard_amt.unwrap_or(self.min_shard_amt)),
+            );
+            payment.max_parts = self.max_parts;
             let event = PaymentEvent::Scheduled { payment };
             self.event_queue.schedule(now, event);
-            now += Time::from_secs(crate::SIM_DELAY_IN_SECS);
+            now += self.sim_delay;
         }
         self.total_num_payments = self.event_queue.queue_length();
         debug!(
@@ -59,6 +65,7 @@ impl Simulation {
 
         info!("Starting simulation.");
         while let Some(event) = self.event_queue.next() {
+            self.record_event(&event);
             match event {
                 PaymentEvent::Scheduled { mut payment } => {
                     debug!(
@@ -66,19 +73,56 @@ impl Simulation {
                         payment.payment_id,
                         self.event_queue.now()
                     );
-                    match self.payment_parts {
-                        PaymentParts::Single => self.send_single_payment(&mut payment),
-                        PaymentParts::Split => self.send_mpp_payment(&mut payment),
-                    };
+                    self.send_payment(&mut payment);
                 }
-                PaymentEvent::UpdateFailed { payment } => {
+                PaymentEvent::UpdateFailed { payment }
+                | PaymentEvent::Abandoned { payment, .. } => {
                     self.num_failed += 1;
                     self.failed_payments.push(payment.to_owned());
                 }
                 PaymentEvent::UpdateSuccesful { payment } => {
+                    self.release_in_flight(&payment.used_paths);
                     self.num_successful += 1;
                     self.successful_payments.push(payment.to_owned());
                 }
+                PaymentEvent::HtlcTimeout { reservations, .. } => {
+                    self.release_held_htlcs(&reservations);
+                }
+                PaymentEvent::Rebalance {
+                    node,
+                    out_channel,
+                    in_channel,
+                    amount,
+                } => {
+                    let _ = self.rebalance(&node, &out_channel, &in_channel, amount);
+                }
+                PaymentEvent::ChannelOpen {
+                    node_a,
+                    node_b,
+                    capacity,
+                    balance_a,
+                } => {
+                    self.open_channel(&node_a, &node_b, capacity, balance_a);
+                }
+                PaymentEvent::ChannelClose { channel_id } => {
+                    self.close_channel(&channel_id);
+                }
+                PaymentEvent::ShardSuccess {
+                    shard_id, amount, ..
+                } => {
+                    debug!(
+                        "Shard {} of payment {} worth {} msat delivered.",
+                        shard_id.1, shard_id.0, amount
+                    );
+                }
+                PaymentEvent::ShardFailed {
+                    shard_id, amount, ..
+                } => {
+                    debug!(
+                        "Shard {} of payment {} worth {} msat failed.",
+                        shard_id.1, shard_id.0, amount
+                    );
+                }
             }
         }
         info!("Completed simulation of targeted attacks.");
@@ -105,9 +149,7 @@ impl Simulation {
         }
     }
 
-    fn reconstruct_payment_pairs(
-        &self,
-    ) -> ((impl Iterator<Item = (ID, ID)> + Clone), Option<usize>) {
+    fn reconstruct_payment_pairs(&self) -> (impl Iterator<Item = (ID, ID)> + Clone, Option<usize>) {
         let mut payment_pairs = vec![];
         let mut min_shard_amt = None;
         for payments_iter in self
@@ -145,6 +187,24 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn simulate_records_the_processed_events() {
+        let mut simulator = crate::attempt::tests::init_sim(None, None);
+        simulator.set_record_events(true);
+        let pairs = vec![
+            ("alice".to_string(), "chan".to_string()),
+            ("bob".to_string(), "dina".to_string()),
+        ];
+        simulator.simulate(pairs.into_iter(), None);
+        let scheduled = simulator
+            .processed_events
+            .iter()
+            .filter(|(_, event)| matches!(event, PaymentEvent::Scheduled { .. }))
+            .count();
+        assert_eq!(scheduled, 2);
+        assert!(!simulator.payment_events(1).is_empty());
+    }
+
     #[test]
     fn delete_targets() {
         let mut simulator = crate::attempt::tests::init_sim(None, None);
@@ -218,7 +278,7 @@ mod tests {
             total_num: 2,
             num_successful: 2,
             num_failed: 0,
-            payments: vec![
+            payments: [
                 Payment {
                     payment_id: 0,
                     source: "alice".to_string(),
@@ -0,0 +1,215 @@
+use crate::{
+    graph::Graph,
+    traversal::pathfinding::{CandidatePath, PathCost},
+    ID,
+};
+
+use std::collections::{HashMap, VecDeque};
+
+/// Paths are cached per (src, dest, amount bucket, context) where the bucket is the amount's power
+/// of two and the context fingerprints the settings of the search that change which path it finds
+type RouteCacheKey = (ID, ID, u32, u64);
+
+/// A path along with the (balance, in flight amount) of each of its hops' channels at the time it
+/// was found
+#[derive(Debug, Clone)]
+struct CachedRoute {
+    candidate_path: CandidatePath,
+    balances: Vec<Option<(usize, usize)>>,
+}
+
+/// Next node towards a dest along with the cost of the rest of the path, for every node that can
+/// reach the dest. Computed for one amount and valid as long as no channel's balance changes
+#[derive(Debug, Clone)]
+pub(crate) struct ShortestPathTree {
+    pub(crate) amount: usize,
+    pub(crate) next_hops: HashMap<ID, (ID, PathCost)>,
+    /// (balance, in flight amount) of every channel at the time the tree was computed
+    balances: HashMap<String, (usize, usize)>,
+}
+
+/// Least recently used cache of the paths found between two nodes for similar amounts
+/// A path is dropped once the balance of one of its channels has changed
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RouteCache {
+    /// Max number of cached paths. 0 disables the cache
+    pub(crate) capacity: usize,
+    /// The least recently used path first
+    entries: VecDeque<(RouteCacheKey, CachedRoute)>,
+    pub(crate) hits: usize,
+    pub(crate) misses: usize,
+    /// Precomputed shortest paths per dest
+    pub(crate) trees: HashMap<ID, ShortestPathTree>,
+}
+
+impl RouteCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    fn key(src: &ID, dest: &ID, amount: usize, context: u64) -> RouteCacheKey {
+        (
+            src.clone(),
+            dest.clone(),
+            usize::BITS - amount.leading_zeros(),
+            context,
+        )
+    }
+
+    /// None for channels that are no longer part of the graph
+    fn channel_balances(
+        graph: &Graph,
+        candidate_path: &CandidatePath,
+    ) -> Vec<Option<(usize, usize)>> {
+        candidate_path
+            .path
+            .hops
+            .iter()
+            .map(|(node, _, _, channel_id)| {
+                graph
+                    .get_outedges(node)
+                    .iter()
+                    .find(|e| e.channel_id == *channel_id)
+                    .map(|e| (e.balance, graph.get_in_flight(channel_id)))
+            })
+            .collect()
+    }
+
+    /// Returns the cached path if none of its channels' balances changed since it was found
+    /// Stale paths are removed
+    pub(crate) fn get(
+        &mut self,
+        graph: &Graph,
+        src: &ID,
+        dest: &ID,
+        amount: usize,
+        context: u64,
+    ) -> Option<CandidatePath> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let key = Self::key(src, dest, amount, context);
+        let cached = match self.entries.iter().position(|(k, _)| *k == key) {
+            Some(idx) => self.entries.remove(idx),
+            None => None,
+        };
+        match cached {
+            Some((key, route))
+                if Self::channel_balances(graph, &route.candidate_path) == route.balances =>
+            {
+                let candidate_path = route.candidate_path.clone();
+                self.entries.push_back((key, route));
+                self.hits += 1;
+                Some(candidate_path)
+            }
+            _ => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Caches the path, evicting the least recently used one if the cache is full
+    pub(crate) fn insert(
+        &mut self,
+        graph: &Graph,
+        src: &ID,
+        dest: &ID,
+        amount: usize,
+        context: u64,
+        candidate_path: &CandidatePath,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = Self::key(src, dest, amount, context);
+        self.entries.retain(|(k, _)| *k != key);
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        let route = CachedRoute {
+            candidate_path: candidate_path.clone(),
+            balances: Self::channel_balances(graph, candidate_path),
+        };
+        self.entries.push_back((key, route));
+    }
+
+    /// Drops the paths using the given channel
+    /// The trees are dropped as well as the channel's weight may have changed
+    pub(crate) fn invalidate_channel(&mut self, channel_id: &str) {
+        self.trees.clear();
+        self.entries.retain(|(_, route)| {
+            !route
+                .candidate_path
+                .path
+                .hops
+                .iter()
+                .any(|hop| hop.3 == channel_id)
+        });
+    }
+
+    /// Drops all paths and trees, e.g. once a channel opened that may offer better paths
+    pub(crate) fn clear(&mut self) {
+        self.trees.clear();
+        self.entries.clear();
+    }
+
+    /// Share of lookups that were answered from the cache. 0 if there were none
+    pub(crate) fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+impl ShortestPathTree {
+    pub(crate) fn new(
+        graph: &Graph,
+        amount: usize,
+        next_hops: HashMap<ID, (ID, PathCost)>,
+    ) -> Self {
+        Self {
+            amount,
+            next_hops,
+            balances: Self::channel_balances(graph),
+        }
+    }
+
+    fn channel_balances(graph: &Graph) -> HashMap<String, (usize, usize)> {
+        graph
+            .edges
+            .values()
+            .flatten()
+            .map(|e| {
+                (
+                    e.channel_id.clone(),
+                    (e.balance, graph.get_in_flight(&e.channel_id)),
+                )
+            })
+            .collect()
+    }
+
+    /// Whether the tree holds for the amount in the graph as it is now
+    pub(crate) fn is_valid(&self, graph: &Graph, amount: usize) -> bool {
+        self.amount == amount && Self::channel_balances(graph) == self.balances
+    }
+
+    /// The nodes from node to dest, including both. None if node cannot reach dest
+    pub(crate) fn path_from(&self, node: &ID, dest: &ID) -> Option<Vec<ID>> {
+        let mut path = vec![node.clone()];
+        while path.last() != Some(dest) {
+            let (next, _) = self.next_hops.get(path.last()?)?;
+            path.push(next.clone());
+            if path.len() > self.next_hops.len() + 1 {
+                return None;
+            }
+        }
+        Some(path)
+    }
+}
@@ -0,0 +1,238 @@
+use crate::{payment::Payment, RouteHint, Simulation, ID};
+
+#[cfg(not(test))]
+use log::{error, info};
+#[cfg(test)]
+use std::{println as info, println as error};
+
+impl Simulation {
+    /// Opens a channel of max_provide from the JIT LSP to the payment's dest so that dest can
+    /// receive the amount, given what it can receive without. The channel is hinted at in the payment's
+    /// invoice and the LSP's fee added to the payment's service fee, see Simulation::jit_fee_due.
+    /// Returns the LSP's side of the channel and the amount it holds, None if there is no LSP or
+    /// it cannot provide what dest lacks
+    pub(crate) fn open_jit_channel(
+        &mut self,
+        payment: &mut Payment,
+        receive_capacity: usize,
+    ) -> Option<(String, usize)> {
+        let jit = self.jit_lsp.clone()?;
+        if jit.lsp == payment.dest || !self.graph.node_is_in_graph(&jit.lsp) {
+            return None;
+        }
+        let missing = payment.amount_msat.saturating_sub(receive_capacity);
+        if missing > jit.max_provide as usize {
+            error!(
+                "{} cannot provide the {} msat of inbound liquidity {} lacks.",
+                jit.lsp, missing, payment.dest
+            );
+            return None;
+        }
+        let (channel_id, channel_back) = Self::jit_channel_ids(&jit.lsp, payment);
+        let hint = RouteHint::new(&jit.lsp, &channel_id, 0);
+        if !self.add_route_hint(&payment.dest, payment.payment_id, hint) {
+            return None;
+        }
+        // all of the channel's liquidity is the LSP's, dest's side starts empty. A forwarding
+        // side needs room for the amount, so the capacity is that of a balanced channel
+        let provided = jit.max_provide as usize;
+        self.graph.add_private_channel(
+            (&jit.lsp, &channel_id, provided),
+            (&payment.dest, &channel_back, provided),
+        );
+        self.graph.update_channel_balance(&channel_back, 0);
+        self.jit_liquidity_msat += provided;
+        payment.service_fee_msat += jit.fee_msat;
+        info!(
+            "{} opened a channel with {} msat to {} for a fee of {} msat.",
+            jit.lsp, provided, payment.dest, jit.fee_msat
+        );
+        Some((channel_id, provided))
+    }
+
+    /// Closes the channel the JIT LSP opened for a payment that did not go through and takes
+    /// back its fee
+    pub(crate) fn close_jit_channel(&mut self, payment: &mut Payment, channel_id: &String) {
+        let Some(jit) = self.jit_lsp.clone() else {
+            return;
+        };
+        let (_, channel_back) = Self::jit_channel_ids(&jit.lsp, payment);
+        self.graph.remove_channel(channel_id);
+        self.graph.remove_channel(&channel_back);
+        self.jit_liquidity_msat = self
+            .jit_liquidity_msat
+            .saturating_sub(jit.max_provide as usize);
+        payment.service_fee_msat = payment.service_fee_msat.saturating_sub(jit.fee_msat);
+    }
+
+    /// The LSP's side of the channel it opened for the payment and the fee it charged for it,
+    /// None if it opened none
+    pub(crate) fn jit_fee_due(&self, payment: &Payment) -> Option<(ID, String, usize)> {
+        let jit = self.jit_lsp.as_ref()?;
+        let (channel_id, _) = Self::jit_channel_ids(&jit.lsp, payment);
+        self.graph
+            .get_outedges(&jit.lsp)
+            .iter()
+            .any(|e| e.channel_id == channel_id)
+            .then(|| (jit.lsp.clone(), channel_id, jit.fee_msat as usize))
+    }
+
+    /// The LSP's and dest's side of the channel opened for the payment
+    fn jit_channel_ids(lsp: &ID, payment: &Payment) -> (String, String) {
+        (
+            format!("{}-{}-jit{}", lsp, payment.dest, payment.payment_id),
+            format!("{}-{}-jit{}", payment.dest, lsp, payment.payment_id),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Invoice, PaymentParts};
+
+    // alice's channels are full so carol opens one for the payment
+    #[test]
+    fn jit_channel_lets_dest_receive() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.capacity = 100000;
+                e.balance = if e.destination == "alice" {
+                    100000
+                } else {
+                    50000
+                };
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let amount_msat = 5000;
+        let payment = || Payment::new(0, source.clone(), dest.clone(), amount_msat, None);
+        let mut without_lsp = payment();
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        assert!(!simulator.send_mpp_payment(&mut without_lsp));
+        simulator.set_jit_lsp("carol".to_string(), 10000, 100);
+        let mut with_lsp = payment();
+        assert!(simulator.send_mpp_payment(&mut with_lsp));
+        // carol charges 100 msat for the channel and no routing fee over it
+        assert_eq!(with_lsp.service_fee_msat, 100);
+        assert_eq!(with_lsp.total_fees_msat, 100);
+        assert_eq!(
+            with_lsp.used_paths[0].path.hops.back().unwrap().3,
+            "alice-carol-jit0"
+        );
+        assert_eq!(
+            simulator
+                .graph
+                .get_channel_balance(&dest, &"alice-carol-jit0".to_string()),
+            amount_msat
+        );
+        // carol's side holds what she provided plus her fee
+        assert_eq!(
+            simulator
+                .graph
+                .get_channel_balance(&"carol".to_string(), &"carol-alice-jit0".to_string()),
+            10000 + 100
+        );
+        assert_eq!(simulator.jit_liquidity_msat, 10000);
+    }
+
+    // eve pays alice via bob and dave, who opens the channel: the fee goes to dave, not bob
+    #[test]
+    fn jit_fee_goes_to_the_lsp() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.capacity = 100000;
+                e.balance = if e.destination == "alice" {
+                    100000
+                } else {
+                    50000
+                };
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        simulator.set_jit_lsp("dave".to_string(), 10000, 100);
+        let (source, dest) = ("eve".to_string(), "alice".to_string());
+        let amount_msat = 5000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, None);
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        let liquidity = simulator.graph.total_liquidity();
+        assert!(simulator.send_mpp_payment(&mut payment));
+        let hops: Vec<ID> = payment.used_paths[0]
+            .path
+            .hops
+            .iter()
+            .map(|h| h.0.clone())
+            .collect();
+        assert_eq!(hops, vec!["eve", "bob", "dave", "alice"]);
+        // bob only earns his routing fee
+        assert_eq!(
+            simulator
+                .graph
+                .get_channel_balance(&"bob".to_string(), &"bob-dave".to_string()),
+            50000 + 50
+        );
+        assert_eq!(
+            simulator
+                .graph
+                .get_channel_balance(&"dave".to_string(), &"dave-alice-jit0".to_string()),
+            10000 + 100
+        );
+        assert_eq!(simulator.graph.total_liquidity(), liquidity + 10000);
+    }
+
+    #[test]
+    fn single_path_payment_uses_jit_channel() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.capacity = 100000;
+                e.balance = if e.destination == "alice" {
+                    100000
+                } else {
+                    50000
+                };
+            }
+        }
+        simulator.set_jit_lsp("carol".to_string(), 10000, 100);
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), 5000, None);
+        simulator.add_invoice(Invoice::new(0, 5000, &source, &dest));
+        assert!(simulator.send_single_payment(&mut payment));
+        assert_eq!(payment.service_fee_msat, 100);
+        assert_eq!(
+            payment.used_paths[0].path.hops.back().unwrap().3,
+            "alice-carol-jit0"
+        );
+    }
+
+    #[test]
+    fn no_jit_channel_beyond_the_cap() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.capacity = 100000;
+                e.balance = if e.destination == "alice" {
+                    100000
+                } else {
+                    50000
+                };
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        simulator.set_jit_lsp("carol".to_string(), 1000, 100);
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), 5000, None);
+        simulator.add_invoice(Invoice::new(0, 5000, &source, &dest));
+        let liquidity = simulator.graph.total_liquidity();
+        assert!(!simulator.send_mpp_payment(&mut payment));
+        assert_eq!(payment.service_fee_msat, 0);
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+    }
+}
@@ -0,0 +1,431 @@
+use crate::{
+    core_types::event::PaymentEvent,
+    payment::{FailureReason, Payment},
+    traversal::pathfinding::CandidatePath,
+    Edge, Simulation, ID,
+};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+#[cfg(not(test))]
+use log::{error, info, trace};
+#[cfg(test)]
+use std::{println as info, println as error, println as trace};
+
+impl Simulation {
+    /// Sends the payment over the paths of a min-cost flow that delivers the whole amount at
+    /// once, as a baseline for the splitter. The channels' available balances are the
+    /// capacities and their fees the costs, see FlowNetwork. Triggers an event either way
+    pub fn send_mpp_mincostflow(&mut self, payment: &mut Payment) -> bool {
+        payment.used_paths.clear();
+        payment.num_parts = 0;
+        payment.failure_reason = None;
+        let mut succeeded = false;
+        match self.mincostflow_paths(payment) {
+            None => {
+                error!(
+                    "Payment {} failing as no flow of {} msat from {} to {} exists.",
+                    payment.payment_id, payment.amount_msat, payment.source, payment.dest
+                );
+                payment.failure_reason = if self.graph.is_reachable(&payment.source, &payment.dest)
+                {
+                    Some(FailureReason::InsufficientLiquidity)
+                } else {
+                    Some(FailureReason::NoTopology)
+                };
+            }
+            Some(paths) => {
+                trace!(
+                    "Sending payment {} over a min-cost flow of {} paths.",
+                    payment.payment_id,
+                    paths.len()
+                );
+                let mut path_finder = self.shard_path_finder(payment);
+                let mut to_revert = vec![];
+                succeeded = true;
+                for path in paths.iter() {
+                    let mut shard = payment.to_shard(path.path_amount());
+                    let (delivered, transferred) =
+                        self.attempt_payment(&mut shard, path, &mut path_finder);
+                    to_revert.extend(transferred);
+                    payment.htlc_attempts = shard.htlc_attempts;
+                    payment.failed_channels = shard.failed_channels;
+                    if !delivered {
+                        payment.failed_paths.push((
+                            path.clone(),
+                            FailureReason::InsufficientLiquidity,
+                            shard.failing_channel.clone(),
+                        ));
+                        payment.failure_reason = Some(FailureReason::InsufficientLiquidity);
+                        succeeded = false;
+                        break;
+                    }
+                    payment.used_paths.push(shard.used_path);
+                    payment.num_parts += 1;
+                }
+                if succeeded && payment.exceeds_fee_budget() {
+                    payment.failure_reason = Some(FailureReason::FeeBudgetExceeded);
+                    succeeded = false;
+                } else if succeeded && !self.pay_service_fees(payment) {
+                    payment.failure_reason = Some(FailureReason::InsufficientLiquidity);
+                    succeeded = false;
+                }
+                if !succeeded {
+                    self.revert_payment(&to_revert);
+                    payment.used_paths.clear();
+                }
+                self.channel_penalties = path_finder.penalties;
+            }
+        }
+        payment.succeeded = succeeded;
+        let now = self.event_queue.now() + self.sim_delay;
+        let event = if succeeded {
+            payment.set_completion_time(self.event_queue.now());
+            payment.settle();
+            info!(
+                "Payment from {} to {} delivered over a min-cost flow in {} parts.",
+                payment.source, payment.dest, payment.num_parts
+            );
+            self.reserve_in_flight(&payment.used_paths);
+            self.record_channel_usage(&payment.used_paths);
+            PaymentEvent::UpdateSuccesful {
+                payment: payment.to_owned(),
+            }
+        } else {
+            PaymentEvent::UpdateFailed {
+                payment: payment.to_owned(),
+            }
+        };
+        self.event_queue.schedule(now, event);
+        succeeded
+    }
+
+    /// Paths of a min-cost flow of the payment's amount. The flow does not include the fees on
+    /// top, so a channel they would overdraw has its capacity reduced by the difference and the
+    /// flow is solved again. None if the amount cannot be delivered
+    fn mincostflow_paths(&self, payment: &Payment) -> Option<Vec<CandidatePath>> {
+        let mut capacities: HashMap<String, usize> = self
+            .graph
+            .edges
+            .values()
+            .flatten()
+            .map(|e| (e.channel_id.clone(), self.graph.get_available_balance(e)))
+            .collect();
+        // every round reduces the capacity of at least one channel
+        for _ in 0..=capacities.len() {
+            let mut network = FlowNetwork::new(self, payment, &capacities)?;
+            let paths = network
+                .paths(payment.amount_msat)?
+                .into_iter()
+                .map(|(channels, amount)| {
+                    CandidatePath::from_channels(
+                        &self.graph,
+                        &payment.source,
+                        &channels,
+                        amount as u64,
+                    )
+                    .ok()
+                })
+                .collect::<Option<Vec<CandidatePath>>>()?;
+            let mut sent: HashMap<String, usize> = HashMap::new();
+            for (channel_id, amount) in paths.iter().flat_map(|p| p.hop_amounts()) {
+                *sent.entry(channel_id).or_insert(0) += amount as usize;
+            }
+            let mut overdrawn = false;
+            for (channel_id, amount) in sent {
+                let balance = self
+                    .graph
+                    .edges
+                    .values()
+                    .flatten()
+                    .find(|e| e.channel_id == channel_id)
+                    .map(|e| self.graph.get_available_balance(e))
+                    .unwrap_or_default();
+                // the balances have to stay above what is sent
+                if amount >= balance {
+                    let capacity = capacities.entry(channel_id).or_default();
+                    *capacity = capacity.saturating_sub(amount + 1 - balance);
+                    overdrawn = true;
+                }
+            }
+            if !overdrawn {
+                return Some(paths);
+            }
+        }
+        None
+    }
+}
+
+/// Channel in the flow network. The arc at index i ^ 1 is its residual counterpart
+#[derive(Debug, Clone)]
+struct FlowArc {
+    to: usize,
+    capacity: i64,
+    cost: i64,
+    channel_id: String,
+}
+
+/// The channels between the nodes of the graph with their capacities and per-msat fees in
+/// millionths. Base fees are spread over the payment's amount as if the channel carried all of
+/// it, while the source's own channels are free
+struct FlowNetwork {
+    arcs: Vec<FlowArc>,
+    adjacency: Vec<Vec<usize>>,
+    src: usize,
+    dest: usize,
+}
+
+impl FlowNetwork {
+    fn new(
+        simulation: &Simulation,
+        payment: &Payment,
+        capacities: &HashMap<String, usize>,
+    ) -> Option<Self> {
+        let node_ids = simulation.graph.get_node_ids();
+        let index: HashMap<&ID, usize> = node_ids
+            .iter()
+            .enumerate()
+            .map(|(idx, id)| (id, idx))
+            .collect();
+        let (&src, &dest) = (index.get(&payment.source)?, index.get(&payment.dest)?);
+        if src == dest || payment.amount_msat == 0 {
+            return None;
+        }
+        let mut network = Self {
+            arcs: vec![],
+            adjacency: vec![vec![]; node_ids.len()],
+            src,
+            dest,
+        };
+        for edge in simulation.graph.edges.values().flatten() {
+            let (Some(&from), Some(&to)) = (index.get(&edge.source), index.get(&edge.destination))
+            else {
+                continue;
+            };
+            let capacity = capacities
+                .get(&edge.channel_id)
+                .copied()
+                .unwrap_or_default();
+            if to == src || from == dest || capacity == 0 {
+                continue;
+            }
+            let cost = if from == src {
+                0
+            } else {
+                Self::unit_cost(edge, payment.amount_msat)
+            };
+            network.add_arc(from, to, capacity as i64, cost, &edge.channel_id);
+        }
+        Some(network)
+    }
+
+    fn unit_cost(edge: &Edge, amount: usize) -> i64 {
+        let base = (edge.fee_base_msat as i64).saturating_mul(1_000_000) / amount as i64;
+        base.saturating_add(edge.fee_proportional_millionths as i64)
+    }
+
+    fn add_arc(&mut self, from: usize, to: usize, capacity: i64, cost: i64, channel_id: &str) {
+        self.adjacency[from].push(self.arcs.len());
+        self.arcs.push(FlowArc {
+            to,
+            capacity,
+            cost,
+            channel_id: channel_id.to_string(),
+        });
+        self.adjacency[to].push(self.arcs.len());
+        self.arcs.push(FlowArc {
+            to: from,
+            capacity: 0,
+            cost: -cost,
+            channel_id: channel_id.to_string(),
+        });
+    }
+
+    /// Sends the amount along successive cheapest augmenting paths and splits the flow into
+    /// paths (channel ids, amount). None if less than the amount can be sent
+    fn paths(&mut self, amount: usize) -> Option<Vec<(Vec<String>, usize)>> {
+        if self.augment(amount as i64) < amount as i64 {
+            return None;
+        }
+        Some(self.decompose())
+    }
+
+    /// Successive shortest paths with Dijkstra on the costs reduced by the node potentials,
+    /// which keeps them non-negative. Returns the amount sent
+    fn augment(&mut self, amount: i64) -> i64 {
+        let num_nodes = self.adjacency.len();
+        let mut potentials = vec![0; num_nodes];
+        let mut sent = 0;
+        while sent < amount {
+            let mut distances = vec![i64::MAX; num_nodes];
+            let mut prev_arc: Vec<Option<usize>> = vec![None; num_nodes];
+            let mut queue = BinaryHeap::from([Reverse((0, self.src))]);
+            distances[self.src] = 0;
+            while let Some(Reverse((distance, node))) = queue.pop() {
+                if distance > distances[node] {
+                    continue;
+                }
+                for &idx in self.adjacency[node].iter() {
+                    let arc = &self.arcs[idx];
+                    if arc.capacity <= 0 {
+                        continue;
+                    }
+                    let next = distance + arc.cost + potentials[node] - potentials[arc.to];
+                    if next < distances[arc.to] {
+                        distances[arc.to] = next;
+                        prev_arc[arc.to] = Some(idx);
+                        queue.push(Reverse((next, arc.to)));
+                    }
+                }
+            }
+            if distances[self.dest] == i64::MAX {
+                break;
+            }
+            for (potential, distance) in potentials.iter_mut().zip(distances) {
+                if distance < i64::MAX {
+                    *potential += distance;
+                }
+            }
+            let mut push = amount - sent;
+            let mut node = self.dest;
+            while let Some(idx) = prev_arc[node] {
+                push = push.min(self.arcs[idx].capacity);
+                node = self.arcs[idx ^ 1].to;
+            }
+            node = self.dest;
+            while let Some(idx) = prev_arc[node] {
+                self.arcs[idx].capacity -= push;
+                self.arcs[idx ^ 1].capacity += push;
+                node = self.arcs[idx ^ 1].to;
+            }
+            sent += push;
+        }
+        sent
+    }
+
+    /// Splits the flow into paths from src to dest. Cycles in the flow are cancelled as they
+    /// carry nothing to dest
+    fn decompose(&mut self) -> Vec<(Vec<String>, usize)> {
+        let mut paths = vec![];
+        'paths: loop {
+            let mut nodes = vec![self.src];
+            let mut path_arcs: Vec<usize> = vec![];
+            let mut node = self.src;
+            while node != self.dest {
+                // the flow over a channel is the capacity of its residual counterpart
+                let Some(idx) = self.adjacency[node]
+                    .iter()
+                    .copied()
+                    .find(|idx| idx % 2 == 0 && self.arcs[idx ^ 1].capacity > 0)
+                else {
+                    break 'paths;
+                };
+                let next = self.arcs[idx].to;
+                path_arcs.push(idx);
+                if let Some(pos) = nodes.iter().position(|n| *n == next) {
+                    self.cancel(&path_arcs[pos..]);
+                    continue 'paths;
+                }
+                nodes.push(next);
+                node = next;
+            }
+            let amount = self.cancel(&path_arcs);
+            let channels = path_arcs
+                .iter()
+                .map(|idx| self.arcs[*idx].channel_id.clone())
+                .collect();
+            paths.push((channels, amount as usize));
+        }
+        paths
+    }
+
+    /// Takes the largest flow all the arcs carry off them and returns it
+    fn cancel(&mut self, arcs: &[usize]) -> i64 {
+        let flow = arcs
+            .iter()
+            .map(|idx| self.arcs[idx ^ 1].capacity)
+            .min()
+            .unwrap_or_default();
+        for idx in arcs {
+            self.arcs[*idx].capacity += flow;
+            self.arcs[idx ^ 1].capacity -= flow;
+        }
+        flow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Invoice, PaymentParts};
+
+    // every channel charges a base fee of 100 msat and none of bob's channels can carry the
+    // whole payment
+    #[test]
+    fn mincostflow_pays_no_more_fees_than_the_splitter() {
+        let send = |mincostflow: bool| {
+            let json_file = "../test_data/trivial_multipath.json";
+            let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+            for edges in simulator.graph.edges.values_mut() {
+                for e in edges {
+                    e.balance = 100000;
+                    e.capacity = 10000000;
+                    e.fee_base_msat = 100;
+                    e.fee_proportional_millionths = 0;
+                }
+            }
+            for (channel_id, balance) in
+                [("bob-carol", 7000), ("bob-eve", 3500), ("bob-dave", 3500)]
+            {
+                simulator
+                    .graph
+                    .update_channel_balance(&channel_id.to_string(), balance);
+            }
+            simulator.set_payment_parts(PaymentParts::Split).unwrap();
+            let (source, dest) = ("bob".to_string(), "alice".to_string());
+            let amount_msat = 9000;
+            let liquidity = simulator.graph.total_liquidity();
+            let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+            simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+            let succeeded = if mincostflow {
+                simulator.send_mpp_mincostflow(&mut payment)
+            } else {
+                simulator.send_mpp_payment(&mut payment)
+            };
+            assert!(succeeded);
+            assert_eq!(simulator.graph.total_liquidity(), liquidity);
+            assert_eq!(payment.delivered_msat, amount_msat);
+            payment.total_fees_msat
+        };
+        let splitter_fees = send(false);
+        let mincostflow_fees = send(true);
+        assert!(mincostflow_fees <= splitter_fees);
+        // one intermediary on each of the two paths
+        assert_eq!(mincostflow_fees, 200);
+    }
+
+    // only 2000 msat can leave bob
+    #[test]
+    fn mincostflow_fails_without_enough_capacity() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = if e.source == "bob" { 1000 } else { 100000 };
+            }
+        }
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let liquidity = simulator.graph.total_liquidity();
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), 5000, None);
+        simulator.add_invoice(Invoice::new(0, 5000, &source, &dest));
+        assert!(!simulator.send_mpp_mincostflow(&mut payment));
+        assert_eq!(
+            payment.failure_reason,
+            Some(FailureReason::InsufficientLiquidity)
+        );
+        assert!(payment.used_paths.is_empty());
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+    }
+}
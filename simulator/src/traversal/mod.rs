@@ -1,3 +1,9 @@
+pub(crate) mod cache;
+mod jit;
+mod mincostflow;
 mod mpp;
 pub mod pathfinding;
+mod probe;
+mod rebalance;
+mod redundant;
 mod single;
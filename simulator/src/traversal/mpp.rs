@@ -1,8 +1,13 @@
 use crate::{
     core_types::{event::PaymentEvent, time::Time},
-    payment::Payment,
-    traversal::pathfinding::{CandidatePath, PathFinder},
-    Simulation,
+    payment::{FailureReason, Payment, ShardRef},
+    traversal::pathfinding::{CandidatePath, ChannelPenalty, PathFinder},
+    DustPolicy, Invoice, PaymentParts, ShardId, ShardOrder, Simulation, TieBreak, ID,
+};
+use rayon::prelude::*;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::Instant,
 };
 
 #[cfg(not(test))]
@@ -15,54 +20,165 @@ impl Simulation {
     /// Triggers an event either way
     /// Includes pathfinding and ultimate routing
     pub(crate) fn send_mpp_payment(&mut self, payment: &mut Payment) -> bool {
+        self.send_mpp_payment_with_override(payment, None)
+    }
+
+    /// Like send_mpp_payment but the sender's total balance precheck uses the given balance
+    /// instead of the graph's. Forwarding still uses the actual balances
+    pub fn send_mpp_payment_with_override(
+        &mut self,
+        payment: &mut Payment,
+        source_balance_override: Option<u64>,
+    ) -> bool {
+        self.send_mpp(payment, source_balance_override, 1)
+    }
+
+    /// Sends the MPP, each of its shards over as many disjoint paths as the redundancy asks for,
+    /// see send_mpp_redundant
+    pub(crate) fn send_mpp(
+        &mut self,
+        payment: &mut Payment,
+        source_balance_override: Option<u64>,
+        redundancy: usize,
+    ) -> bool {
         let mut succeeded = false;
         let mut failed = false;
+        // how long the last part waited for earlier ones to resolve
+        let mut launched_at = Time::default();
         let graph = Box::new(self.graph.clone());
+        if payment.amount_msat == 0 && !payment.is_probe {
+            error!(
+                "Rejecting payment {} from {} to {} as its amount is 0 msat.",
+                payment.payment_id, payment.source, payment.dest
+            );
+            failed = true;
+            payment.failure_reason = Some(FailureReason::ZeroAmount);
+        }
+        if !failed && payment.exceeds_service_fee_budget() {
+            error!(
+                "Rejecting payment {} as its service fee alone exceeds the max fee.",
+                payment.payment_id
+            );
+            failed = true;
+            payment.failure_reason = Some(FailureReason::FeeBudgetExceeded);
+        }
+        // no need to look at any balances if there is no path at all
+        if !failed && !graph.is_reachable(&payment.source, &payment.dest) {
+            error!(
+                "Payment failing. {} is unreachable from {}.",
+                payment.dest, payment.source
+            );
+            failed = true;
+            payment.failure_reason = Some(FailureReason::NoTopology);
+        }
         // fail immediately if sender's total balance < amount
-        let total_out_balance = graph.get_total_node_balance(&payment.source);
-        if total_out_balance < payment.amount_msat {
+        let total_out_balance = match source_balance_override {
+            Some(balance) => balance as usize,
+            None => graph.get_total_node_balance(&payment.source),
+        };
+        if !failed && total_out_balance < payment.amount_msat {
             error!("Payment failing. {} total balance insufficient for payment. Amount {}, max balance {}", payment.source, payment.amount_msat, total_out_balance);
             payment.htlc_attempts += 1;
             failed = true;
+            payment.failure_reason = Some(FailureReason::InsufficientLiquidity);
         }
+        let mut jit_channel = None;
         if !failed {
             // we would otherwise miscount failed htlc_attempts
-            let max_receive_balance = graph.get_max_receive_amount(&payment.dest);
+            let mut max_receive_balance =
+                graph.mpp_receive_capacity(&payment.dest, payment.max_parts) as usize;
+            if max_receive_balance < payment.amount_msat {
+                if let Some((channel_id, provided)) =
+                    self.open_jit_channel(payment, max_receive_balance)
+                {
+                    max_receive_balance += provided;
+                    jit_channel = Some(channel_id);
+                }
+            }
             if max_receive_balance < payment.amount_msat {
                 error!("Payment failing due to insufficient receive capacity. Payment amount {}, max receive {}", payment.amount_msat, max_receive_balance);
                 payment.htlc_attempts += 1;
                 failed = true;
+                payment.failure_reason = Some(FailureReason::InsufficientLiquidity);
             }
         }
-
         if !succeeded && !failed {
             payment.used_paths.clear();
+            payment.failure_reason = None;
             payment.num_parts = 0;
-            succeeded = self.send_mpp_shards(payment);
+            payment.held_htlcs = 0;
+            payment.cancelled_htlcs = 0;
+            payment.delivered_msat = 0;
+            payment.total_fees_msat = 0;
+            payment.excluded_channels.clear();
+            payment.shard_amounts.clear();
+            (succeeded, launched_at) = self.send_mpp_shards(payment, redundancy);
+        }
+        // a payment that delivered nothing never needed the channel
+        if let Some(channel_id) = jit_channel.filter(|_| !succeeded && payment.delivered_msat == 0)
+        {
+            self.close_jit_channel(payment, &channel_id);
         }
-        let now = self.event_queue.now() + Time::from_secs(crate::SIM_DELAY_IN_SECS);
+        let event_start = Instant::now();
+        // the payment resolves with its last shard
+        let sent_at = self.event_queue.now() + launched_at + self.held_htlc_delay(payment);
+        let now = sent_at + self.sim_delay;
+        let succeeded = succeeded
+            && self.check_invariant(
+                payment.succeeded,
+                "Delivered payment is not marked as such.",
+            );
         let event = if succeeded {
-            assert!(payment.succeeded);
             info!(
                 "Payment from {} to {} delivered in {} parts.",
                 payment.source, payment.dest, payment.num_parts
             );
+            self.reserve_in_flight(&payment.used_paths);
+            self.record_channel_usage(&payment.used_paths);
             PaymentEvent::UpdateSuccesful {
                 payment: payment.to_owned(),
             }
         } else {
-            assert!(!payment.succeeded);
-            assert!(payment.used_paths.is_empty());
-            PaymentEvent::UpdateFailed {
-                payment: payment.to_owned(),
+            if !self.check_invariant(!payment.succeeded, "Failed payment is marked as delivered.") {
+                payment.succeeded = false;
+            }
+            // only AMP keeps the paths of the parts that arrived
+            if !self.check_invariant(
+                payment.used_paths.is_empty() || payment.delivered_msat > 0,
+                "Failed payment has used paths but delivered nothing.",
+            ) {
+                payment.used_paths.clear();
+            }
+            match payment.failure_reason {
+                Some(reason) if reason.is_abandonment() => PaymentEvent::Abandoned {
+                    payment: payment.to_owned(),
+                    reason,
+                    parts_delivered: payment.used_paths.len(),
+                },
+                _ => PaymentEvent::UpdateFailed {
+                    payment: payment.to_owned(),
+                },
             }
         };
         self.event_queue.schedule(now, event);
+        self.timings.event_processing += event_start.elapsed().as_nanos();
+        if self.summary_logging {
+            let resolved_at = if succeeded {
+                payment.completion_time
+            } else {
+                sent_at
+            };
+            // through the log crate in tests too, so they can check the line
+            log::info!("{}", Self::summary_line(payment, resolved_at));
+        }
         succeeded
     }
 
     /// Splits a payment into a list of shards belonging to one payment and tries to send them atomically
-    fn send_mpp_shards(&mut self, root: &mut Payment) -> bool {
+    /// Also returns when the last shard was launched, relative to now. Shards wait for a slot
+    /// if the payment limits the number of shards in flight. With a redundancy above 1 each
+    /// shard races copies over disjoint paths
+    fn send_mpp_shards(&mut self, root: &mut Payment, redundancy: usize) -> (bool, Time) {
         trace!(
             "Attempting MPP payment {} worth {} msat.",
             root.payment_id,
@@ -71,36 +187,218 @@ impl Simulation {
         let mut succeeded = false;
         let mut failed = false;
         let mut stack = vec![];
-        stack.push(root.clone());
+        // splitting a failed shard would have left a part below min_shard_amt
+        let mut dust_split = false;
+        let parts = match root.target_parts {
+            // biased towards the requested number of parts, failing parts are split further as usual
+            Some(target) if target > 1 => {
+                trace!(
+                    "Splitting payment {} into {} parts as requested by the sender.",
+                    root.payment_id,
+                    target
+                );
+                let parts = Payment::split_payment_into(root, target);
+                // a split leaving dust is not made, the payment is attempted as a whole instead
+                self.settle_dust(Payment::round_to_quantum(
+                    parts,
+                    self.shard_quantum_msat as usize,
+                ))
+                .unwrap_or_else(|| vec![root.clone()])
+            }
+            _ => {
+                // no single path is wide enough for the whole amount so it is split right away
+                let widest = self
+                    .graph
+                    .largest_single_path_capacity(&root.source, &root.dest);
+                let parts = if widest > 0 && root.amount_msat > widest {
+                    self.settle_dust(Payment::round_to_quantum(
+                        Payment::split_payment_into(root, root.amount_msat.div_ceil(widest)),
+                        self.shard_quantum_msat as usize,
+                    ))
+                    .unwrap_or_default()
+                } else {
+                    vec![]
+                };
+                if parts.len() > 1 {
+                    trace!(
+                        "Splitting payment {} into {} parts as no path can carry {} msat.",
+                        root.payment_id,
+                        parts.len(),
+                        root.amount_msat
+                    );
+                    parts
+                } else {
+                    vec![root.clone()]
+                }
+            }
+        };
+        let pathfinding_start = Instant::now();
+        let found_paths = if redundancy > 1 {
+            // the copies are searched for when their shard is sent
+            vec![None; parts.len()]
+        } else {
+            self.find_initial_paths(&parts)
+        };
+        let penalties_at_search = self.channel_penalties.clone();
+        self.timings.pathfinding += pathfinding_start.elapsed().as_nanos();
+        stack.extend(
+            parts
+                .into_iter()
+                .zip(found_paths)
+                .rev()
+                .map(|(p, path)| (p, (None, path))),
+        );
         let mut num_parts = 0;
-        while let Some(mut current_shard) = stack.pop() {
+        // any shard finding a path or running out of liquidity means the topology was not the problem
+        let mut topology_path_exists = false;
+        let mut persistent_bottleneck = false;
+        // (amount, channel) of the failed shards. Failing the same way twice means splitting
+        // does not get the payment anywhere
+        let mut seen_failures: HashSet<(usize, String)> = HashSet::new();
+        let mut split_cycle = false;
+        let mut deadline_exceeded = false;
+        let mut fee_budget_exceeded = false;
+        let mut amount_overflow = false;
+        let mut fees_exceed_amount = false;
+        // resolution times of the shards in flight, relative to now
+        let mut in_flight: Vec<Time> = vec![];
+        let mut launched_at = Time::default();
+        // shards are stacked along with the channel their parent failed at and the path found
+        // for them in advance
+        while let Some((mut current_shard, (parent_failing_channel, found_path))) =
+            self.next_shard(&mut stack)
+        {
             if !succeeded && !failed {
+                let shard_id = (root.payment_id, num_parts);
                 num_parts += 1;
-                let (success, mut to_reverse) = self.send_one_payment(&mut current_shard);
+                root.shard_amounts.push(current_shard.amount_msat);
+                // channels where earlier shards got stuck are avoided
+                root.excluded_channels
+                    .clone_into(&mut current_shard.excluded_channels);
+                if self.disjoint_paths {
+                    current_shard.excluded_channels.extend(
+                        root.used_paths
+                            .iter()
+                            .flat_map(|p| p.path.hops.iter().map(|h| h.3.clone())),
+                    );
+                }
+                let num_excluded = current_shard.excluded_channels.len();
+                current_shard.last_hops = root
+                    .used_paths
+                    .iter()
+                    .filter_map(|p| p.last_hop())
+                    .collect();
+                // earlier shards may have taken the liquidity the path was found with or made
+                // it more expensive than another
+                let found_path = found_path.filter(|path| {
+                    self.path_has_liquidity(path, &current_shard.excluded_channels)
+                        && self.path_is_still_cheapest(
+                            path,
+                            &current_shard.last_hops,
+                            &penalties_at_search,
+                        )
+                });
+                if root.max_concurrent_shards > 0 {
+                    // the shard is launched once enough of those in flight resolved
+                    in_flight.sort();
+                    while in_flight.len() >= root.max_concurrent_shards {
+                        launched_at = launched_at.max(in_flight.remove(0));
+                    }
+                    in_flight.retain(|resolved_at| *resolved_at > launched_at);
+                }
+                let (success, mut to_reverse) = if redundancy > 1 {
+                    self.send_redundant_shard(&mut current_shard, redundancy)
+                } else {
+                    self.send_one_payment_with_path(&mut current_shard, found_path)
+                };
+                for path in current_shard
+                    .used_paths
+                    .iter_mut()
+                    .chain(current_shard.failed_paths.iter_mut().map(|(path, ..)| path))
+                {
+                    path.shard_id = Some(shard_id);
+                }
+                let resolved_at =
+                    self.schedule_shard_event(&current_shard, shard_id, success, launched_at);
+                in_flight.push(resolved_at);
                 root.htlc_attempts += current_shard.htlc_attempts;
+                root.held_htlcs += current_shard.held_htlcs;
+                root.cancelled_htlcs += current_shard.cancelled_htlcs;
+                // only the channels the shard got stuck at are passed on to the following shards
+                root.excluded_channels.extend(
+                    current_shard
+                        .excluded_channels
+                        .iter()
+                        .skip(num_excluded)
+                        .cloned(),
+                );
                 root.failed_paths.append(&mut current_shard.failed_paths);
+                let shard_failed_channels = std::mem::take(&mut current_shard.failed_channels);
+                root.failed_channels
+                    .extend(shard_failed_channels.iter().cloned());
+                if current_shard.failure_reason != Some(FailureReason::NoTopology) {
+                    topology_path_exists = true;
+                }
+                if current_shard.failure_reason == Some(FailureReason::AmountOverflow) {
+                    // smaller parts would only hide that the whole payment cannot be represented
+                    amount_overflow = true;
+                    failed = true;
+                }
+                if current_shard.failure_reason == Some(FailureReason::FeesExceedAmount) {
+                    fees_exceed_amount = true;
+                }
                 if !success && !failed {
                     root.failed_amounts.push(current_shard.amount_msat);
-                    trace!(
-                        "Splitting payment {} worth {} msat into {} parts.",
-                        root.payment_id,
-                        root.amount_msat,
-                        2
-                    );
-                    if num_parts > crate::MAX_PARTS {
+                    if self.stop_on_bottleneck
+                        && current_shard.failing_channel.is_some()
+                        && current_shard.failing_channel == parent_failing_channel
+                    {
+                        // splitting further will most likely fail at the same channel too
+                        error!(
+                            "Aborting splitting as consecutive splits failed at channel {:?}.",
+                            current_shard.failing_channel
+                        );
+                        persistent_bottleneck = true;
+                        failed = true;
+                    } else if current_shard
+                        .failing_channel
+                        .as_ref()
+                        .is_some_and(|channel| {
+                            !seen_failures.insert((current_shard.amount_msat, channel.clone()))
+                        })
+                    {
+                        error!(
+                            "Aborting splitting as a shard of {} msat failed at channel {} before.",
+                            current_shard.amount_msat,
+                            current_shard.failing_channel.as_deref().unwrap_or_default()
+                        );
+                        split_cycle = true;
+                        failed = true;
+                    } else if num_parts > root.max_parts {
                         error!(
                             "Aborting splitting as max parts of {} has been reached.",
-                            crate::MAX_PARTS
+                            root.max_parts
                         );
                         failed = true;
-                    } else if let Some(shards) = Payment::split_payment(&current_shard) {
+                    } else if let Some(shards) = self.split_shard(&current_shard) {
                         let (mut shard1, mut shard2) = (shards.0, shards.1);
+                        // through the log crate in tests too, so they can check the line
+                        log::trace!(
+                            "{}",
+                            Self::describe_split(
+                                &current_shard,
+                                shard_id,
+                                &shard_failed_channels,
+                                (&shard1, &shard2)
+                            )
+                        );
                         root.failed_amounts.clone_into(&mut shard1.failed_amounts);
                         root.failed_amounts.clone_into(&mut shard2.failed_amounts);
-                        stack.push(shard1);
-                        stack.push(shard2);
+                        stack.push((shard1, (current_shard.failing_channel.clone(), None)));
+                        stack.push((shard2, (current_shard.failing_channel.clone(), None)));
                     } else {
                         // Splitting failed so we know at least some part wont succeed
+                        dust_split = self.split_leaves_dust(&current_shard);
                         failed = true;
                     }
                 } else if success {
@@ -108,17 +406,48 @@ impl Simulation {
                     root.used_paths
                         .append(&mut current_shard.used_paths.clone());
                     root.successful_shards.append(&mut to_reverse);
+                    root.sort_shards();
+                    if self.exceeds_deadline(root, &current_shard.used_paths) {
+                        error!(
+                            "Abandoning payment {} as a part would resolve after the deadline.",
+                            root.payment_id
+                        );
+                        deadline_exceeded = true;
+                        failed = true;
+                    }
+                    if root.exceeds_fee_budget() {
+                        error!(
+                            "Abandoning payment {} as its fees exceed the max fee.",
+                            root.payment_id
+                        );
+                        fee_budget_exceeded = true;
+                        failed = true;
+                    }
+                    if !failed && !self.remainder_is_routable(root) {
+                        failed = true;
+                    }
                 }
             }
             // the value of successful parts tells us if the entire payment succeeded
-            let mut amount_received = 0;
-            for s in root.successful_shards.iter() {
-                if s.0 == root.dest {
-                    amount_received += s.2;
-                }
+            let amount_delivered = Self::delivered_amount(root);
+            if amount_delivered.is_none() && !failed {
+                error!(
+                    "Abandoning payment {} as the sum of its parts overflows.",
+                    root.payment_id
+                );
+                amount_overflow = true;
+                failed = true;
+            }
+            if amount_delivered == Some(root.amount_msat) && !failed && !self.pay_service_fees(root)
+            {
+                failed = true;
             }
-            if amount_received == root.amount_msat {
+            if amount_delivered == Some(root.amount_msat) && !failed {
                 root.succeeded = true;
+                root.settle();
+                // the payment resolves with its slowest shard
+                let sent_at = self.event_queue.now() + launched_at + self.held_htlc_delay(root);
+                root.set_completion_time(sent_at);
                 succeeded = true;
                 // no longer needed - used to revert payments
                 root.successful_shards.clear();
@@ -127,11 +456,735 @@ impl Simulation {
         // total failure so revert succesful payments
         // some payment failed so all must now be reversed
         if !succeeded {
-            self.revert_payment(&root.successful_shards);
-            // remove any successful paths we may have stored after shards' success
-            root.used_paths.clear();
+            root.failure_reason = if amount_overflow {
+                Some(FailureReason::AmountOverflow)
+            } else if deadline_exceeded {
+                Some(FailureReason::DeadlineExceeded)
+            } else if fee_budget_exceeded {
+                Some(FailureReason::FeeBudgetExceeded)
+            } else if persistent_bottleneck {
+                Some(FailureReason::PersistentBottleneck)
+            } else if split_cycle {
+                Some(FailureReason::SplitCycle)
+            } else if dust_split {
+                Some(FailureReason::DustPart)
+            } else if fees_exceed_amount {
+                Some(FailureReason::FeesExceedAmount)
+            } else if topology_path_exists {
+                Some(FailureReason::InsufficientLiquidity)
+            } else {
+                Some(FailureReason::NoTopology)
+            };
+            if root.payment_parts.unwrap_or(self.payment_parts) == PaymentParts::Amp {
+                // the parts that arrived in time are settled, those resolving after the deadline
+                // fail and are reverted
+                let late: Vec<ShardRef> = root
+                    .used_paths
+                    .iter()
+                    .filter(|p| self.exceeds_deadline(root, std::slice::from_ref(*p)))
+                    .map(ShardRef::from)
+                    .collect();
+                self.revert_shards(&late);
+                for shard in late.iter() {
+                    if let Some(shard_id) = shard.shard_id {
+                        self.event_queue.cancel_shard_success(shard_id);
+                    }
+                }
+                root.used_paths
+                    .retain(|p| !late.iter().any(|shard| shard.shard_id == p.shard_id));
+                root.num_parts -= late.len();
+                root.settle();
+                info!(
+                    "AMP payment {} delivered {} of {} msat.",
+                    root.payment_id, root.delivered_msat, root.amount_msat
+                );
+            } else {
+                let balance_start = Instant::now();
+                for transfers in root.shard_transfers() {
+                    self.revert_payment(transfers);
+                }
+                self.timings.balance_updates += balance_start.elapsed().as_nanos();
+                // the parts that arrived no longer resolve as the payment failed
+                let cancelled = self.event_queue.cancel_shard_successes(root.payment_id);
+                trace!(
+                    "Cancelled {} shard events of payment {}.",
+                    cancelled,
+                    root.payment_id
+                );
+                // remove any successful paths we may have stored after shards' success
+                root.used_paths.clear();
+            }
+            root.successful_shards.clear();
+        }
+        (succeeded, launched_at)
+    }
+
+    /// Paths for the parts, searched concurrently on the current graph without moving any
+    /// balances. None for every part unless parallel pathfinding is enabled and there are
+    /// several parts. Random tie breaks and fee volatility would draw from the RNG in an
+    /// arbitrary order
+    fn find_initial_paths(&self, parts: &[Payment]) -> Vec<Option<CandidatePath>> {
+        if !self.parallel_pathfinding
+            || parts.len() < 2
+            || self.tie_break == TieBreak::Random
+            || self.fee_volatility > 0.0
+        {
+            return vec![None; parts.len()];
+        }
+        parts
+            .par_iter()
+            .map(|part| {
+                let mut path_finder = self.shard_path_finder(part);
+                path_finder
+                    .find_direct_path()
+                    .or_else(|| path_finder.find_path().path())
+            })
+            .collect()
+    }
+
+    /// Whether the channels along the path can still forward what the path needs and none of
+    /// them is excluded
+    fn path_has_liquidity(&self, path: &CandidatePath, excluded_channels: &[String]) -> bool {
+        let Some(last_hop) = path.path.hops.back() else {
+            return false;
+        };
+        let forwards =
+            path.path
+                .hops
+                .iter()
+                .zip(path.hop_amounts())
+                .all(|(hop, (channel_id, amount))| {
+                    !excluded_channels.contains(&channel_id)
+                        && self.graph.get_channel_balance(&hop.0, &channel_id) > amount as usize
+                });
+        forwards
+            && !excluded_channels.contains(&last_hop.3)
+            && self
+                .graph
+                .channel_can_receive_amount(&last_hop.3, path.path_amount())
+    }
+
+    /// Whether the search would still find the path now that earlier shards went out
+    /// Failures recorded since the search only penalise their own channels and the last hop
+    /// penalty only the last hops used since, so a path avoiding both is still the cheapest
+    fn path_is_still_cheapest(
+        &self,
+        path: &CandidatePath,
+        last_hops: &[String],
+        penalties_at_search: &HashMap<String, ChannelPenalty>,
+    ) -> bool {
+        let penalised = path
+            .path
+            .hops
+            .iter()
+            .any(|hop| self.channel_penalties.get(&hop.3) != penalties_at_search.get(&hop.3));
+        let last_hop_used = self.last_hop_penalty > 0.0
+            && path
+                .last_hop()
+                .is_some_and(|last_hop| last_hops.contains(&last_hop));
+        !penalised && !last_hop_used
+    }
+
+    /// Value of the parts that arrived so far. None if it overflows
+    /// If the fees come out of the amount, the amount is what the sender sends instead
+    /// Each part counts once with what its last hop delivers, even if its path passes through
+    /// dest or source on the way
+    fn delivered_amount(root: &Payment) -> Option<usize> {
+        let (mut amount_received, mut amount_sent) = (Some(0usize), Some(0usize));
+        let mut counted = HashSet::new();
+        for path in root.used_paths.iter() {
+            if path
+                .shard_id
+                .is_some_and(|shard_id| !counted.insert(shard_id))
+            {
+                continue;
+            }
+            let transfers = path.transfers();
+            let (Some(sent), Some(received)) = (transfers.first(), transfers.last()) else {
+                continue;
+            };
+            amount_received = amount_received.and_then(|a| a.checked_add(received.2));
+            amount_sent = amount_sent.and_then(|a| a.checked_add(sent.2));
+        }
+        match (amount_received, amount_sent) {
+            (Some(received), Some(_)) if root.fees_on_top => Some(received),
+            (Some(_), Some(sent)) => Some(sent),
+            _ => None,
+        }
+    }
+
+    /// False if what is left of the payment after the parts delivered so far exceeds the
+    /// source's liquidity that can still reach dest. Checked after each delivered part as only
+    /// those move the source's balances
+    fn remainder_is_routable(&self, root: &Payment) -> bool {
+        let Some(delivered) = Self::delivered_amount(root) else {
+            return true;
+        };
+        let remainder = root.amount_msat.saturating_sub(delivered);
+        if remainder == 0 {
+            return true;
+        }
+        let liquidity = self.graph.reachable_liquidity(&root.source, &root.dest);
+        if liquidity < remainder {
+            error!(
+                "Aborting payment {} as the remaining {} msat exceed the {} msat that can reach {}.",
+                root.payment_id, remainder, liquidity, root.dest
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Takes the shard to attempt next off the stack according to the shard order
+    /// Among parts of the same amount, the one pushed last is taken
+    fn next_shard<T>(&self, stack: &mut Vec<(Payment, T)>) -> Option<(Payment, T)> {
+        let amounts = stack.iter().map(|(shard, _)| shard.amount_msat).enumerate();
+        let idx = match self.shard_order {
+            ShardOrder::Lifo => stack.len().checked_sub(1),
+            ShardOrder::LargestFirst => amounts.max_by_key(|(_, amount)| *amount).map(|(i, _)| i),
+            ShardOrder::SmallestFirst => amounts
+                .rev()
+                .min_by_key(|(_, amount)| *amount)
+                .map(|(i, _)| i),
+        };
+        idx.map(|idx| stack.remove(idx))
+    }
+
+    /// Splits a failed shard in two. Usually into halves, but when optimising for fees the split
+    /// adding the lower fees is taken: halving, or splitting off the largest part a single path
+    /// can carry, which keeps the number of parts paying base fees low. The fees of both are
+    /// estimated with a single path finder that leaves out channels lacking the balance
+    fn split_shard(&self, shard: &Payment) -> Option<(Payment, Payment)> {
+        let (shard1, shard2) = Payment::split_payment(shard)?;
+        let mut halves = self.settle_dust(Payment::round_to_quantum(
+            vec![shard1, shard2],
+            self.shard_quantum_msat as usize,
+        ))?;
+        if halves.len() < 2 {
+            // folding the dust left a single part, which is the shard that just failed
+            return None;
+        }
+        let halves = (halves.remove(0), halves.remove(0));
+        if shard.payment_parts.unwrap_or(self.payment_parts) != PaymentParts::FeeOptimalSplit {
+            return Some(halves);
+        }
+        let mut path_finder = self.path_finder(&shard.source, &shard.dest, shard.amount_msat);
+        path_finder.skip_illiquid_edges = true;
+        let largest = self.largest_single_part(&mut path_finder, shard);
+        if largest < shard.min_shard_amt || shard.amount_msat - largest < shard.min_shard_amt {
+            return Some(halves);
+        }
+        // the largest part is stacked last so that it is attempted first
+        let (largest_part, rest) = Payment::split_payment_at(shard, largest);
+        let greedy = (rest, largest_part);
+        let mut fees = |(shard1, shard2): &(Payment, Payment)| -> Option<usize> {
+            Some(
+                self.marginal_fee(&mut path_finder, shard1)?
+                    .saturating_add(self.marginal_fee(&mut path_finder, shard2)?),
+            )
+        };
+        let (greedy_fees, halves_fees) = (fees(&greedy), fees(&halves));
+        match (greedy_fees, halves_fees) {
+            (greedy_fees, Some(halves_fees)) if greedy_fees.is_none_or(|f| halves_fees < f) => {
+                trace!(
+                    "Halving shard of {} msat as it adds {} msat in fees, {:?} msat when splitting off {} msat.",
+                    shard.amount_msat,
+                    halves_fees,
+                    greedy_fees,
+                    largest
+                );
+                Some(halves)
+            }
+            _ => Some(greedy),
+        }
+    }
+
+    /// The parts unless one of them is below min_shard_amt, in which case the dust policy either
+    /// rejects the split or folds the dust into a sibling part
+    fn settle_dust(&self, parts: Vec<Payment>) -> Option<Vec<Payment>> {
+        if !Payment::has_dust(&parts) {
+            return Some(parts);
+        }
+        match self.dust_policy {
+            DustPolicy::Fail => {
+                error!(
+                    "Not splitting as a part would be below the min shard amount. Amounts {:?}",
+                    parts.iter().map(|p| p.amount_msat).collect::<Vec<usize>>()
+                );
+                None
+            }
+            DustPolicy::FoldIntoSibling => Some(Payment::fold_dust(parts)),
+        }
+    }
+
+    /// Whether the shard was not split as the dust policy rejects the parts it would be split into
+    fn split_leaves_dust(&self, shard: &Payment) -> bool {
+        self.dust_policy == DustPolicy::Fail
+            && Payment::split_payment(shard).is_some_and(|(shard1, shard2)| {
+                Payment::has_dust(&Payment::round_to_quantum(
+                    vec![shard1, shard2],
+                    self.shard_quantum_msat as usize,
+                ))
+            })
+    }
+
+    /// Largest amount below the shard's that a single path can currently deliver
+    fn largest_single_part(&self, path_finder: &mut PathFinder, shard: &Payment) -> usize {
+        let (mut low, mut high) = (0, shard.amount_msat - 1);
+        while low < high {
+            let amount = low + (high - low).div_ceil(2);
+            if self.single_part_fee(path_finder, amount).is_some() {
+                low = amount;
+            } else {
+                high = amount - 1;
+            }
+        }
+        low
+    }
+
+    /// Fees a part adds to the payment. Those of the cheapest single path able to deliver it
+    /// right now or, if there is none, those of the cheapest path regardless of balances, which
+    /// its parts pay at least about once it is split further. None if dest cannot be reached
+    fn marginal_fee(&self, path_finder: &mut PathFinder, part: &Payment) -> Option<usize> {
+        self.single_part_fee(path_finder, part.amount_msat)
+            .or_else(|| {
+                path_finder.skip_illiquid_edges = false;
+                path_finder.amount = part.amount_msat;
+                let fees = path_finder
+                    .find_k_shortest_paths(1)
+                    .pop()
+                    .map(|candidate_path| candidate_path.path_fees());
+                path_finder.skip_illiquid_edges = true;
+                fees
+            })
+    }
+
+    /// Fees of the cheapest single path able to deliver the amount right now. None if there is
+    /// none
+    fn single_part_fee(&self, path_finder: &mut PathFinder, amount: usize) -> Option<usize> {
+        path_finder.amount = amount;
+        let candidate_path = path_finder.find_k_shortest_paths(1).pop()?;
+        let (sender, out_channel) = (
+            &candidate_path.path.hops[0].0,
+            &candidate_path.path.hops[0].3,
+        );
+        // the sender has to cover the fees as well
+        (self.graph.get_channel_balance(sender, out_channel) > candidate_path.amount)
+            .then(|| candidate_path.path_fees())
+    }
+
+    /// Log line of a payment that resolved at the given time. The fields are space separated
+    /// key=value pairs in a fixed order, behind the "payment_summary" prefix
+    pub(crate) fn summary_line(payment: &Payment, resolved_at: Time) -> String {
+        format!(
+            "payment_summary id={} amount_msat={} succeeded={} parts={} attempts={} fee_msat={} completion_time_secs={}",
+            payment.payment_id,
+            payment.amount_msat,
+            payment.succeeded,
+            payment.num_parts,
+            payment.htlc_attempts,
+            payment.total_fees_msat,
+            resolved_at.as_secs()
+        )
+    }
+
+    /// Log line of a shard being split after failing at the given channels
+    fn describe_split(
+        shard: &Payment,
+        shard_id: ShardId,
+        failed_channels: &[String],
+        children: (&Payment, &Payment),
+    ) -> String {
+        format!(
+            "Splitting shard {} of payment {} worth {} msat that failed at channels {:?} into shards of {} and {} msat.",
+            shard_id.1,
+            shard_id.0,
+            shard.amount_msat,
+            failed_channels,
+            children.0.amount_msat,
+            children.1.amount_msat
+        )
+    }
+
+    /// Notes the resolution of a single shard launched at the given time relative to now. Held
+    /// shards resolve once they time out. Returns when the shard resolves relative to now
+    fn schedule_shard_event(
+        &mut self,
+        shard: &Payment,
+        shard_id: ShardId,
+        succeeded: bool,
+        launched_at: Time,
+    ) -> Time {
+        let channels_of = |path: &CandidatePath| -> Vec<String> {
+            path.path.hops.iter().map(|h| h.3.clone()).collect()
+        };
+        let event = if succeeded {
+            PaymentEvent::ShardSuccess {
+                shard_id,
+                amount: shard.amount_msat,
+                channels: shard.used_paths.iter().flat_map(channels_of).collect(),
+            }
+        } else {
+            PaymentEvent::ShardFailed {
+                shard_id,
+                amount: shard.amount_msat,
+                channels: shard
+                    .failed_paths
+                    .last()
+                    .map(|(path, ..)| channels_of(path))
+                    .unwrap_or_default(),
+            }
+        };
+        let resolved_at = launched_at + self.sim_delay + self.held_htlc_delay(shard);
+        self.event_queue
+            .schedule(self.event_queue.now() + resolved_at, event);
+        resolved_at
+    }
+
+    /// Dry run of an MPP on a copy of the simulation, see dry_run_copy. Returns the payment if
+    /// it would succeed without touching the balances
+    pub fn plan_mpp(&self, source: &ID, dest: &ID, amount_msat: usize) -> Option<Payment> {
+        let mut sim = self.dry_run_copy();
+        let payment_id = sim.next_payment_id();
+        sim.add_invoice(Invoice::new(payment_id, amount_msat, source, dest));
+        let mut payment = Payment::new(payment_id, source.clone(), dest.clone(), amount_msat, None);
+        if sim.send_mpp_payment(&mut payment) {
+            Some(payment)
+        } else {
+            None
+        }
+    }
+
+    /// Total fees in msat a payment from src to dest would pay, summed over its parts if it has to
+    /// be split. Dry run, None if the payment would fail
+    pub fn estimate_fee(&self, src: &str, dest: &str, amount: usize) -> Option<u64> {
+        let payment = self.plan_mpp(&src.to_string(), &dest.to_string(), amount)?;
+        let fees: usize = payment.used_paths.iter().map(|p| p.path_fees()).sum();
+        trace!(
+            "Estimated {} msat of fees to send {} msat from {} to {} in {} parts.",
+            fees,
+            amount,
+            src,
+            dest,
+            payment.num_parts
+        );
+        Some(fees as u64)
+    }
+
+    /// Fees in msat the payment is expected to pay when a part whose attempt fails is retried
+    /// along the next cheapest path. Each attempt's fees are weighted by the probability of
+    /// reaching it, i.e. of all attempts of the part before it failing
+    /// The parts are those of a dry run without random failures. The chance of a path failing
+    /// comes from the probability scorer, the failure probability and the intermediaries'
+    /// reliability. NaN if the dry run fails
+    pub fn expected_fee(&mut self, payment: &Payment) -> f64 {
+        let mut sim = self.dry_run_copy();
+        sim.failure_probability = 0.0;
+        sim.node_reliability.clear();
+        let mut planned = payment.unsent_copy(sim.next_payment_id());
+        sim.add_invoice(Invoice::new(
+            planned.payment_id,
+            planned.amount_msat,
+            &planned.source,
+            &planned.dest,
+        ));
+        if !sim.send_mpp_payment(&mut planned) {
+            return f64::NAN;
+        }
+        let mut expected = 0.0;
+        for used_path in planned.used_paths.iter() {
+            let part = Payment {
+                amount_msat: used_path.path_amount(),
+                ..payment.unsent_copy(planned.payment_id)
+            };
+            let channels = |p: &CandidatePath| -> Vec<String> {
+                p.path.hops.iter().map(|h| h.3.clone()).collect()
+            };
+            let alternatives = self
+                .shard_path_finder(&part)
+                .find_k_shortest_paths(crate::MAX_FEE_ATTEMPTS)
+                .into_iter()
+                .filter(|p| channels(p) != channels(used_path));
+            let mut reach_probability = 1.0;
+            for attempt in std::iter::once(used_path.clone())
+                .chain(alternatives)
+                .take(crate::MAX_FEE_ATTEMPTS)
+            {
+                expected += reach_probability * attempt.path_fees() as f64;
+                reach_probability *= 1.0 - self.path_success_probability(&attempt);
+            }
+        }
+        trace!(
+            "Expecting {:.2} msat of fees for payment {} of {} msat in {} parts.",
+            expected,
+            payment.payment_id,
+            payment.amount_msat,
+            planned.num_parts
+        );
+        expected
+    }
+
+    /// Chance that every intermediary of the path forwards the HTLC, according to the
+    /// probability scorer and the chance of random failures
+    fn path_success_probability(&self, path: &CandidatePath) -> f64 {
+        let mut path_finder = PathFinder::new(
+            path.path.src.clone(),
+            path.path.dest.clone(),
+            path.path_amount(),
+            &self.graph,
+            self.routing_metric,
+            self.payment_parts,
+        );
+        path_finder.reliability = self.channel_reliability.clone();
+        // the sender knows the balance of its own channel
+        path.path
+            .hops
+            .iter()
+            .zip(path.hop_amounts())
+            .skip(1)
+            .map(|(hop, (channel_id, amount))| {
+                let scored = self
+                    .graph
+                    .get_outedges(&hop.0)
+                    .into_iter()
+                    .find(|e| e.channel_id == channel_id)
+                    .map_or(0.0, |edge| {
+                        path_finder.get_edge_success_probability(&edge, amount as usize)
+                    });
+                let reliability = self.node_reliability.get(&hop.0).copied().unwrap_or(1.0);
+                scored as f64 * (1.0 - self.failure_probability as f64) * reliability as f64
+            })
+            .product()
+    }
+
+    /// Largest amount in msat that can be delivered from source to dest as an MPP
+    /// Binary search over dry runs, bounded by the sender's total balance and the receiver's
+    /// receive capacity
+    pub fn max_sendable(&mut self, source: &str, dest: &str) -> u64 {
+        let (source, dest) = (source.to_string(), dest.to_string());
+        let upper_bound = self
+            .graph
+            .get_total_node_balance(&source)
+            .min(self.graph.get_max_receive_amount(&dest));
+        let (mut low, mut high) = (0, upper_bound);
+        while low < high {
+            let amount = low + (high - low).div_ceil(2);
+            if self.plan_mpp(&source, &dest, amount).is_some() {
+                low = amount;
+            } else {
+                high = amount - 1;
+            }
+        }
+        info!(
+            "At most {} msat can be sent from {} to {}.",
+            low, source, dest
+        );
+        low as u64
+    }
+
+    /// Share of the max-flow between the payment's source and dest, capped at the payment's
+    /// amount, that the splitter manages to route. 0 if no flow is possible
+    pub fn mpp_optimality(&mut self, payment: &Payment) -> f64 {
+        let max_flow = self.graph.max_flow(&payment.source, &payment.dest) as usize;
+        let target = payment.amount_msat.min(max_flow);
+        if target == 0 {
+            return 0.0;
+        }
+        let routed = if self
+            .plan_mpp(&payment.source, &payment.dest, target)
+            .is_some()
+        {
+            target
+        } else {
+            self.max_sendable(&payment.source, &payment.dest) as usize
+        };
+        let optimality = routed as f64 / target as f64;
+        info!(
+            "Splitter routes {} of {} msat from {} to {}, optimality {:.4}.",
+            routed, target, payment.source, payment.dest, optimality
+        );
+        optimality
+    }
+
+    /// The channel that would have let the payment succeed if it had more liquidity
+    /// Tries the channels of the min-cut between the payment's source and dest, in the order of
+    /// their ids, by topping each up with the payment's amount in a dry run. None if none of them
+    /// helps on its own or if the payment would succeed as it is
+    pub fn find_bottleneck(&mut self, payment: &Payment) -> Option<String> {
+        if self
+            .plan_mpp(&payment.source, &payment.dest, payment.amount_msat)
+            .is_some()
+        {
+            return None;
+        }
+        let mut candidates: Vec<String> = self
+            .graph
+            .min_cut(&payment.source, &payment.dest)
+            .iter()
+            .flat_map(|(from, to)| self.graph.get_all_src_dest_edges(from, to))
+            .map(|e| e.channel_id)
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        let bottleneck = candidates.into_iter().find(|channel_id| {
+            let mut sim = self.dry_run_copy();
+            for edge in sim.graph.edges.values_mut().flatten() {
+                if edge.channel_id == *channel_id {
+                    edge.balance = edge.balance.saturating_add(payment.amount_msat);
+                    edge.capacity = edge.capacity.saturating_add(payment.amount_msat);
+                }
+            }
+            sim.plan_mpp(&payment.source, &payment.dest, payment.amount_msat)
+                .is_some()
+        });
+        info!(
+            "Bottleneck of payment {} from {} to {}: {:?}.",
+            payment.payment_id, payment.source, payment.dest, bottleneck
+        );
+        bottleneck
+    }
+
+    /// Up to k distinct ways to split the payment over paths, cheapest in total fees first
+    /// Dry runs on copies of the simulation: starting from the split the splitter picks, each
+    /// channel a found split uses is excluded in turn to find the next ones, breadth-first
+    /// Balances are not touched
+    pub fn enumerate_mpp_splits(&mut self, payment: &Payment, k: usize) -> Vec<Vec<CandidatePath>> {
+        let mut splits: Vec<Vec<CandidatePath>> = vec![];
+        let mut seen = HashSet::new();
+        let mut tried = HashSet::from([vec![]]);
+        let mut queue = VecDeque::from([vec![]]);
+        while let Some(excluded) = queue.pop_front() {
+            if splits.len() >= k {
+                break;
+            }
+            let mut sim = self.dry_run_copy();
+            // without liquidity the pathfinding leaves the channel out
+            for edge in sim.graph.edges.values_mut().flatten() {
+                if excluded.contains(&edge.channel_id) {
+                    edge.balance = 0;
+                }
+            }
+            let Some(planned) = sim.plan_mpp(&payment.source, &payment.dest, payment.amount_msat)
+            else {
+                continue;
+            };
+            let mut channels: Vec<String> = planned
+                .used_paths
+                .iter()
+                .flat_map(|p| p.path.hops.iter().map(|hop| hop.3.clone()))
+                .collect();
+            channels.sort();
+            channels.dedup();
+            for channel_id in channels {
+                let mut next = excluded.clone();
+                next.push(channel_id);
+                next.sort();
+                if tried.insert(next.clone()) {
+                    queue.push_back(next);
+                }
+            }
+            let mut key: Vec<_> = planned
+                .used_paths
+                .iter()
+                .map(|p| p.path.hops.clone())
+                .collect();
+            key.sort();
+            if seen.insert(key) {
+                splits.push(planned.used_paths);
+            }
+        }
+        splits.sort_by_key(|split| split.iter().map(|p| p.path_fees()).sum::<usize>());
+        trace!(
+            "Found {} splits to send {} msat from {} to {}.",
+            splits.len(),
+            payment.amount_msat,
+            payment.source,
+            payment.dest
+        );
+        splits
+    }
+
+    /// Fees in msat that sending the payment in n + 1 parts costs on top of sending it in n
+    /// parts, 0 if the extra part makes it cheaper. Dry runs targeting each number of parts.
+    /// None if either run fails or ends up with a different number of parts
+    pub fn marginal_split_cost(&mut self, payment: &Payment, n: usize) -> Option<u64> {
+        let fees = self.split_fees(payment, n)?;
+        let more_fees = self.split_fees(payment, n + 1)?;
+        trace!(
+            "Going from {} to {} parts costs {} msat instead of {} msat in fees.",
+            n,
+            n + 1,
+            more_fees,
+            fees
+        );
+        Some(more_fees.saturating_sub(fees))
+    }
+
+    /// Smallest number of parts the payment can be delivered in given the current liquidity
+    /// Dry runs with an increasing number of parts, each allowed to split no further. None if
+    /// it fails up to the payment's max parts or parts would fall below min_shard_amt
+    pub fn min_parts_required(&mut self, payment: &Payment) -> Option<usize> {
+        let max_parts = payment
+            .max_parts
+            .min(payment.amount_msat / payment.min_shard_amt.max(1))
+            .max(1);
+        let parts = (1..=max_parts).find(|parts| {
+            self.dry_run_split(payment, *parts)
+                .is_some_and(|planned| planned.num_parts <= *parts)
+        });
+        info!(
+            "Payment {} of {} msat from {} to {} needs at least {:?} parts.",
+            payment.payment_id, payment.amount_msat, payment.source, payment.dest, parts
+        );
+        parts
+    }
+
+    /// Total fees of a dry run of the payment split into the given number of parts
+    fn split_fees(&self, payment: &Payment, parts: usize) -> Option<u64> {
+        let planned = self
+            .dry_run_split(payment, parts)
+            .filter(|planned| planned.num_parts == parts)?;
+        Some(
+            planned
+                .used_paths
+                .iter()
+                .map(|p| p.path_fees())
+                .sum::<usize>() as u64,
+        )
+    }
+
+    /// The payment as sent in a dry run targeting the given number of parts and split no
+    /// further. None if the run fails
+    fn dry_run_split(&self, payment: &Payment, parts: usize) -> Option<Payment> {
+        let mut sim = self.dry_run_copy();
+        let payment_id = sim.next_payment_id();
+        sim.add_invoice(Invoice::new(
+            payment_id,
+            payment.amount_msat,
+            &payment.source,
+            &payment.dest,
+        ));
+        let mut planned = Payment::new(
+            payment_id,
+            payment.source.clone(),
+            payment.dest.clone(),
+            payment.amount_msat,
+            Some(payment.min_shard_amt),
+        );
+        planned.target_parts = Some(parts);
+        planned.max_parts = parts;
+        sim.send_mpp_payment(&mut planned).then_some(planned)
+    }
+
+    /// True if any of the paths would resolve after the payment's deadline
+    fn exceeds_deadline(&self, payment: &Payment, paths: &[CandidatePath]) -> bool {
+        let sent_at = self.event_queue.now() + self.held_htlc_delay(payment);
+        match payment.deadline {
+            Some(deadline) => paths
+                .iter()
+                .any(|p| sent_at + Time::from_secs(p.time as f32) > deadline),
+            None => false,
         }
-        succeeded
     }
 }
 
@@ -143,10 +1196,19 @@ impl PathFinder {
 
 #[cfg(test)]
 mod tests {
-    use std::collections::VecDeque;
-
     use super::*;
-    use crate::{traversal::pathfinding::Path, Invoice, PaymentParts};
+    use crate::{traversal::pathfinding::Path, Invoice};
+    use approx::assert_abs_diff_eq;
+
+    /// The next event that is not about a single shard
+    fn next_payment_event(simulator: &mut Simulation) -> Option<PaymentEvent> {
+        loop {
+            match simulator.event_queue.next() {
+                Some(PaymentEvent::ShardSuccess { .. } | PaymentEvent::ShardFailed { .. }) => {}
+                event => return event,
+            }
+        }
+    }
 
     #[test]
     fn send_multipath_payment() {
@@ -155,20 +1217,7 @@ mod tests {
         let json_file = "../test_data/trivial_multipath.json";
         let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
         let amount_msat = 300000;
-        let payment = &mut Payment {
-            payment_id: 0,
-            source: source.clone(),
-            dest: dest.clone(),
-            amount_msat,
-            succeeded: false,
-            min_shard_amt: 10,
-            htlc_attempts: 0,
-            num_parts: 1,
-            used_paths: Vec::default(),
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-            failed_paths: vec![],
-        };
+        let payment = &mut Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
         simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
         assert!(!simulator.send_single_payment(payment));
         simulator
@@ -178,7 +1227,7 @@ mod tests {
             .graph
             .update_channel_balance(&String::from("alice-dave"), 250000);
 
-        simulator.payment_parts = PaymentParts::Split;
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
         simulator.send_mpp_payment(payment);
         assert!(payment.num_parts > 1);
     }
@@ -199,24 +1248,11 @@ mod tests {
             }
         }
         let amount_msat = 12000;
-        let payment = &mut Payment {
-            payment_id: 0,
-            source: source.clone(),
-            dest: dest.clone(),
-            amount_msat,
-            succeeded: false,
-            min_shard_amt: 10,
-            htlc_attempts: 0,
-            num_parts: 1,
-            used_paths: Vec::default(),
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-            failed_paths: vec![],
-        };
+        let payment = &mut Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
         simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
-        simulator.payment_parts = PaymentParts::Single;
+        simulator.set_payment_parts(PaymentParts::Single).unwrap();
         assert!(!simulator.send_single_payment(payment));
-        simulator.payment_parts = PaymentParts::Split;
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
         assert!(simulator.send_mpp_payment(payment));
         assert!(payment.succeeded);
         assert!(payment.num_parts > 1);
@@ -251,25 +1287,16 @@ mod tests {
             .graph
             .update_channel_balance(&bob_dave_channel, bob_total_balance / 3);
         let amount_msat = 12000;
-        let payment = &mut Payment {
-            payment_id: 0,
-            source: source.clone(),
-            dest: dest.clone(),
-            amount_msat,
-            succeeded: false,
-            min_shard_amt: 10,
-            htlc_attempts: 0,
-            num_parts: 1,
-            used_paths: Vec::default(),
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-            failed_paths: vec![],
-        };
+        let payment = &mut Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
         simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
-        simulator.payment_parts = PaymentParts::Single;
+        simulator.set_payment_parts(PaymentParts::Single).unwrap();
         assert!(!simulator.send_single_payment(payment));
-        simulator.payment_parts = PaymentParts::Split;
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
         assert!(!simulator.send_mpp_payment(payment));
+        assert_eq!(
+            payment.failure_reason,
+            Some(FailureReason::InsufficientLiquidity)
+        );
     }
 
     #[test]
@@ -285,23 +1312,10 @@ mod tests {
             }
         }
         let amount_msat = 12000;
-        let payment = &mut Payment {
-            payment_id: 0,
-            source: source.clone(),
-            dest: dest.clone(),
-            amount_msat,
-            succeeded: false,
-            min_shard_amt: 10,
-            htlc_attempts: 0,
-            num_parts: 1,
-            used_paths: Vec::default(),
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-            failed_paths: vec![],
-        };
+        let payment = &mut Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
         simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
         assert!(!simulator.send_single_payment(payment));
-        simulator.payment_parts = PaymentParts::Split;
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
         assert!(simulator.send_mpp_payment(payment));
         let expected_used_path = vec![
             CandidatePath {
@@ -317,6 +1331,7 @@ mod tests {
                 weight: 10.0,
                 amount: 6010,
                 time: 5,
+                shard_id: Some((0, 1)),
             },
             CandidatePath {
                 path: Path {
@@ -332,6 +1347,7 @@ mod tests {
                 weight: 30.0,
                 amount: 6030,
                 time: 10,
+                shard_id: Some((0, 2)),
             },
         ];
         assert_eq!(payment.htlc_attempts, 5);
@@ -341,4 +1357,1795 @@ mod tests {
         assert!(payment.failed_paths.is_empty()); // since the single payment fails immediately
         assert_eq!(expected_used_path, payment.used_paths);
     }
+
+    #[test]
+    fn shards_set_the_completion_time_of_the_slowest_path() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 10000;
+            }
+        }
+        let amount_msat = 12000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        assert!(simulator.send_mpp_shards(&mut payment, 1).0);
+        assert_eq!(payment.used_paths.len(), 2);
+        let path_times: Vec<usize> = payment
+            .used_paths
+            .iter()
+            .map(|p| p.path.hops.iter().skip(1).map(|h| h.2).sum())
+            .collect();
+        // via carol and via eve and carol
+        assert_eq!(path_times.iter().min(), Some(&5));
+        let slowest = *path_times.iter().max().unwrap();
+        assert_eq!(slowest, 10);
+        assert_eq!(payment.completion_time, Time::from_secs(slowest as f32));
+    }
+
+    // 6000 msat each via carol for 10 msat and via eve and carol for 30 msat
+    fn send_two_path_mpp() -> Payment {
+        let json_file = "../test_data/trivial_multipath.json";
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 10000;
+            }
+        }
+        let amount_msat = 12000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        assert!(simulator.send_mpp_payment(&mut payment));
+        assert_eq!(payment.used_paths.len(), 2);
+        payment
+    }
+
+    #[test]
+    fn summary_line_names_the_payment_and_its_parts() {
+        let payment = send_two_path_mpp();
+        let line = Simulation::summary_line(&payment, payment.completion_time);
+        assert!(line.starts_with("payment_summary id=0 amount_msat=12000 succeeded=true parts=2 "));
+        assert!(line.contains(&format!("fee_msat={} ", payment.total_fees_msat)));
+        let failed = Payment::new(7, "bob".to_string(), "alice".to_string(), 1000, None);
+        let line = Simulation::summary_line(&failed, Time::from_secs(1.5));
+        assert_eq!(
+            line,
+            "payment_summary id=7 amount_msat=1000 succeeded=false parts=1 attempts=0 fee_msat=0 completion_time_secs=1.5"
+        );
+    }
+
+    #[test]
+    fn summary_logging_emits_an_info_line_per_payment() {
+        crate::attempt::tests::capture_logs();
+        let send = |payment_id: usize, summary_logging: bool| {
+            let mut simulator = crate::attempt::tests::init_sim(None, None);
+            simulator.set_summary_logging(summary_logging);
+            let (source, dest) = ("alice".to_string(), "chan".to_string());
+            let mut payment = Payment::new(payment_id, source.clone(), dest.clone(), 1000, None);
+            simulator.add_invoice(Invoice::new(payment_id, 1000, &source, &dest));
+            assert!(simulator.send_mpp_payment(&mut payment));
+            payment
+        };
+        send(4380, false);
+        assert!(crate::attempt::tests::captured_logs("payment_summary id=4380 ").is_empty());
+        let payment = send(4381, true);
+        assert_eq!(
+            crate::attempt::tests::captured_logs("payment_summary id=4381 "),
+            vec![(
+                log::Level::Info,
+                Simulation::summary_line(&payment, payment.completion_time)
+            )]
+        );
+    }
+
+    // bob can reach alice via carol, eve and dave, so there is more than one way to split
+    #[test]
+    fn enumerate_mpp_splits_returns_distinct_splits() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 10000;
+                if e.channel_id == "dave-alice" {
+                    e.fee_base_msat = 100;
+                    e.fee_proportional_millionths = 0;
+                }
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let payment = Payment::new(0, "bob".to_string(), "alice".to_string(), 12000, Some(10));
+        let liquidity = simulator.graph.total_liquidity();
+        let splits = simulator.enumerate_mpp_splits(&payment, 3);
+        assert!(splits.len() >= 2);
+        assert!(splits.len() <= 3);
+        assert_ne!(splits[0], splits[1]);
+        let fees: Vec<usize> = splits
+            .iter()
+            .map(|split| split.iter().map(|p| p.path_fees()).sum())
+            .collect();
+        assert!(fees.windows(2).all(|pair| pair[0] <= pair[1]));
+        for split in splits.iter() {
+            let delivered: usize = split.iter().map(|p| p.path.hops.back().unwrap().1).sum();
+            assert_eq!(delivered, 12000);
+        }
+        // the cheapest split is the one the splitter picks
+        assert_eq!(fees[0], 40);
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+    }
+
+    // every channel charges a base fee of 100 msat and can carry the whole payment, so a
+    // single part is the cheapest and each additional one adds another 100 msat
+    #[test]
+    fn marginal_split_cost_is_positive_beyond_the_fee_optimal_split() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 100000;
+                e.capacity = 10000000;
+                e.fee_base_msat = 100;
+                e.fee_proportional_millionths = 0;
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let payment = Payment::new(0, "bob".to_string(), "alice".to_string(), 4000, Some(10));
+        let liquidity = simulator.graph.total_liquidity();
+        assert_eq!(simulator.marginal_split_cost(&payment, 1), Some(100));
+        assert_eq!(simulator.marginal_split_cost(&payment, 2), Some(100));
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+    }
+
+    // none of bob's channels holds more than 7000 msat so 12000 msat need two of them
+    #[test]
+    fn min_parts_required_for_a_payment_larger_than_each_channel() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for (src, edges) in simulator.graph.edges.iter_mut() {
+            for e in edges {
+                e.capacity = 1000000;
+                e.balance = if src == "bob" { 7000 } else { 100000 };
+                if e.channel_id == "dave-alice" {
+                    e.fee_base_msat = 100;
+                    e.fee_proportional_millionths = 0;
+                }
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let payment = Payment::new(0, "bob".to_string(), "alice".to_string(), 12000, Some(10));
+        let liquidity = simulator.graph.total_liquidity();
+        assert_eq!(simulator.min_parts_required(&payment), Some(2));
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+        // more than bob holds in total cannot be sent in any number of parts
+        let payment = Payment::new(1, "bob".to_string(), "alice".to_string(), 30000, Some(10));
+        assert_eq!(simulator.min_parts_required(&payment), None);
+    }
+
+    #[test]
+    fn effective_ppm_of_the_used_paths() {
+        let payment = send_two_path_mpp();
+        let ppm: Vec<f64> = payment
+            .used_paths
+            .iter()
+            .map(|p| p.effective_ppm())
+            .collect();
+        assert_abs_diff_eq!(ppm[0], 10.0 / 6000.0 * 1_000_000.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(ppm[1], 30.0 / 6000.0 * 1_000_000.0, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn weighted_path_length_of_the_used_paths() {
+        let payment = send_two_path_mpp();
+        // a 3 and a 4 hop path of equal amounts
+        assert_abs_diff_eq!(payment.weighted_path_length(), 3.5, epsilon = 1e-9);
+    }
+
+    // the first part passes through alice on its way to carol and back, the second arrives
+    // directly
+    #[test]
+    fn delivered_amount_counts_the_last_hop_of_each_part() {
+        let part = |shard: usize, hops: Vec<(&str, usize, &str)>| {
+            let hops: VecDeque<(ID, usize, usize, String)> = hops
+                .into_iter()
+                .map(|(node, amount, channel)| (node.to_string(), amount, 5, channel.to_string()))
+                .collect();
+            CandidatePath {
+                path: Path {
+                    src: "bob".to_string(),
+                    dest: "alice".to_string(),
+                    hops,
+                },
+                weight: 0.0,
+                amount: 0,
+                time: 5,
+                shard_id: Some((0, shard)),
+            }
+        };
+        let mut passing = part(
+            1,
+            vec![
+                ("bob", 6020, "bob-dave"),
+                ("dave", 10, "dave-alice"),
+                ("alice", 10, "alice-carol"),
+                ("carol", 0, "carol-alice"),
+                ("alice", 6000, "alice-carol"),
+            ],
+        );
+        passing.amount = 6020;
+        let mut direct = part(
+            2,
+            vec![
+                ("bob", 4010, "bob-carol"),
+                ("carol", 10, "carol-alice"),
+                ("alice", 4000, "alice-carol"),
+            ],
+        );
+        direct.amount = 4010;
+        let mut root = Payment::new(0, "bob".to_string(), "alice".to_string(), 10000, None);
+        for path in [&passing, &direct] {
+            root.used_paths.push(path.clone());
+            root.successful_shards.extend(path.transfers());
+        }
+        assert_eq!(Simulation::delivered_amount(&root), Some(10000));
+        // a part is counted once even if its path was recorded twice
+        root.used_paths.push(passing.clone());
+        assert_eq!(Simulation::delivered_amount(&root), Some(10000));
+        root.fees_on_top = false;
+        assert_eq!(Simulation::delivered_amount(&root), Some(6020 + 4010));
+    }
+
+    #[test]
+    fn path_overlap_of_the_two_path_success() {
+        let mut payment = send_two_path_mpp();
+        // carol-alice and alice-carol out of five channels are used by both paths
+        assert_eq!(payment.path_overlap(), 2.0 / 5.0);
+        payment.used_paths.truncate(1);
+        assert_eq!(payment.path_overlap(), 0.0);
+    }
+
+    #[test]
+    fn shard_correlation_of_shared_and_disjoint_paths() {
+        // both paths go through carol and over carol-alice
+        let payment = send_two_path_mpp();
+        assert_abs_diff_eq!(
+            payment.shard_correlation(),
+            (2.0 / 5.0 + 1.0 / 2.0) / 2.0,
+            epsilon = 1e-9
+        );
+        // bob's channels hold 7000 msat each and the second part has to avoid carol-alice
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for (src, edges) in simulator.graph.edges.iter_mut() {
+            for e in edges {
+                e.capacity = 1000000;
+                e.balance = if src == "bob" { 7000 } else { 100000 };
+                if e.channel_id == "dave-alice" {
+                    e.fee_base_msat = 100;
+                    e.fee_proportional_millionths = 0;
+                }
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        simulator.set_disjoint_paths(true);
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), 12000, Some(10));
+        simulator.add_invoice(Invoice::new(0, 12000, &source, &dest));
+        assert!(simulator.send_mpp_payment(&mut payment));
+        assert_eq!(payment.used_paths.len(), 2);
+        assert_abs_diff_eq!(payment.shard_correlation(), 0.0, epsilon = 1e-9);
+        payment.used_paths.truncate(1);
+        assert_eq!(payment.shard_correlation(), 0.0);
+    }
+
+    #[test]
+    fn mpp_targets_requested_parts() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let source = "bob".to_string();
+        let dest = "alice".to_string();
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 100000;
+                e.capacity = 10000000;
+            }
+        }
+        let amount_msat = 12000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+        payment.target_parts = Some(4);
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        assert!(simulator.send_mpp_payment(&mut payment));
+        assert_eq!(payment.num_parts, 4);
+        assert_eq!(payment.used_paths.len(), 4);
+    }
+
+    // both parts take bob -> carol -> alice where carol charges 10 msat
+    #[test]
+    fn channel_usage_counts_delivered_shards() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let source = "bob".to_string();
+        let dest = "alice".to_string();
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 100000;
+                e.capacity = 10000000;
+            }
+        }
+        let amount_msat = 4000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+        payment.target_parts = Some(2);
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        assert!(simulator.send_mpp_payment(&mut payment));
+        assert_eq!(payment.num_parts, 2);
+        let usage = simulator.graph.channel_usage();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage["bob-carol"], (2, 4020));
+        assert_eq!(usage["carol-alice"], (2, 4000));
+        // the shards of a failed payment are reverted and not counted
+        simulator
+            .graph
+            .update_channel_balance(&"carol-alice".to_string(), 1000);
+        simulator
+            .graph
+            .update_channel_balance(&"dave-alice".to_string(), 1000);
+        let mut payment = Payment::new(1, source.clone(), dest.clone(), amount_msat, Some(10));
+        payment.target_parts = Some(2);
+        simulator.add_invoice(Invoice::new(1, amount_msat, &source, &dest));
+        assert!(!simulator.send_mpp_payment(&mut payment));
+        assert_eq!(simulator.graph.channel_usage(), usage);
+    }
+
+    // every channel charges a base fee of 100 msat and none of bob's channels can carry the
+    // whole payment
+    #[test]
+    fn fee_optimal_split_uses_fewer_larger_shards() {
+        let send = |payment_parts: PaymentParts| {
+            let json_file = "../test_data/trivial_multipath.json";
+            let source = "bob".to_string();
+            let dest = "alice".to_string();
+            let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+            for edges in simulator.graph.edges.values_mut() {
+                for e in edges {
+                    e.balance = 100000;
+                    e.capacity = 10000000;
+                    e.fee_base_msat = 100;
+                    e.fee_proportional_millionths = 0;
+                }
+            }
+            for (channel_id, balance) in
+                [("bob-carol", 7000), ("bob-eve", 3500), ("bob-dave", 3500)]
+            {
+                simulator
+                    .graph
+                    .update_channel_balance(&channel_id.to_string(), balance);
+            }
+            let amount_msat = 9000;
+            let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+            simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+            simulator.set_payment_parts(payment_parts).unwrap();
+            assert!(simulator.send_mpp_payment(&mut payment));
+            let fees: usize = payment.used_paths.iter().map(|p| p.path_fees()).sum();
+            let mut amounts: Vec<usize> =
+                payment.used_paths.iter().map(|p| p.path_amount()).collect();
+            amounts.sort();
+            (amounts, fees)
+        };
+        let (adaptive, adaptive_fees) = send(PaymentParts::Split);
+        let (fee_optimal, fee_optimal_fees) = send(PaymentParts::FeeOptimalSplit);
+        assert_eq!(adaptive, vec![2250, 2250, 4500]);
+        assert_eq!(adaptive_fees, 300);
+        // the largest part bob-carol can carry along with its fee, the rest goes via dave
+        assert_eq!(fee_optimal, vec![2101, 6899]);
+        assert_eq!(fee_optimal_fees, 200);
+    }
+
+    // bob-carol can carry the largest part but carol charges for it, the free route via dave
+    // is only short of the halves
+    #[test]
+    fn fee_optimal_split_compares_marginal_fees() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let source = "bob".to_string();
+        let dest = "alice".to_string();
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 100000;
+                e.capacity = 10000000;
+                e.fee_base_msat = if e.channel_id == "dave-alice" { 0 } else { 100 };
+                e.fee_proportional_millionths = 0;
+            }
+        }
+        for (channel_id, balance) in [("bob-carol", 4400), ("bob-eve", 0), ("bob-dave", 4000)] {
+            simulator
+                .graph
+                .update_channel_balance(&channel_id.to_string(), balance);
+        }
+        simulator
+            .set_payment_parts(PaymentParts::FeeOptimalSplit)
+            .unwrap();
+        let shard = Payment::new(0, source, dest, 9000, Some(10));
+        let (shard1, shard2) = simulator.split_shard(&shard).unwrap();
+        // splitting off the largest part carol can forward pays her 100 msat, while the halves are
+        // estimated to go via dave for free once split further
+        assert_eq!((shard1.amount_msat, shard2.amount_msat), (4500, 4500));
+    }
+
+    // alice receives via carol-alice or the slightly more expensive dave-alice
+    #[test]
+    fn last_hop_penalty_spreads_shards_across_inbound_channels() {
+        let last_hops = |penalty: Option<f32>, parallel: bool| {
+            let json_file = "../test_data/trivial_multipath.json";
+            let source = "bob".to_string();
+            let dest = "alice".to_string();
+            let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+            for edges in simulator.graph.edges.values_mut() {
+                for e in edges {
+                    e.balance = 100000;
+                    e.capacity = 10000000;
+                    if e.channel_id == "dave-alice" {
+                        e.fee_base_msat = 20;
+                        e.fee_proportional_millionths = 0;
+                    }
+                }
+            }
+            if let Some(penalty) = penalty {
+                simulator.set_last_hop_penalty(penalty);
+            }
+            simulator.set_parallel_pathfinding(parallel);
+            let amount_msat = 8000;
+            let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+            payment.target_parts = Some(2);
+            simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+            simulator.set_payment_parts(PaymentParts::Split).unwrap();
+            assert!(simulator.send_mpp_payment(&mut payment));
+            assert_eq!(payment.num_parts, 2);
+            let mut last_hops: Vec<String> = payment
+                .used_paths
+                .iter()
+                .filter_map(|p| p.last_hop())
+                .collect();
+            last_hops.sort();
+            last_hops
+        };
+        assert_eq!(
+            last_hops(Some(0.0), false),
+            vec!["carol-alice", "carol-alice"]
+        );
+        assert_eq!(
+            last_hops(Some(100.0), false),
+            vec!["carol-alice", "dave-alice"]
+        );
+        // the path found in advance for the second part reuses carol-alice
+        assert_eq!(
+            last_hops(Some(100.0), true),
+            vec!["carol-alice", "dave-alice"]
+        );
+        // spread by default
+        assert_eq!(last_hops(None, false), vec!["carol-alice", "dave-alice"]);
+    }
+
+    #[test]
+    fn shard_order_changes_the_attempt_sequence() {
+        let send = |shard_order: ShardOrder| {
+            let json_file = "../test_data/trivial_multipath.json";
+            let source = "bob".to_string();
+            let dest = "carol".to_string();
+            let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+            for edges in simulator.graph.edges.values_mut() {
+                for e in edges {
+                    e.balance = 100000;
+                    e.capacity = 10000000;
+                    if e.channel_id == "dave-alice" {
+                        e.fee_base_msat = 20;
+                        e.fee_proportional_millionths = 0;
+                    }
+                }
+            }
+            for (channel_id, balance) in [
+                ("bob-carol", 4000),
+                ("eve-carol", 4000),
+                ("alice-carol", 4000),
+            ] {
+                simulator
+                    .graph
+                    .update_channel_balance(&channel_id.to_string(), balance);
+            }
+            simulator.set_shard_order(shard_order);
+            simulator.set_shard_quantum(1000);
+            let amount_msat = 9000;
+            let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+            simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+            simulator.set_payment_parts(PaymentParts::Split).unwrap();
+            assert!(simulator.send_mpp_payment(&mut payment));
+            payment.shard_amounts
+        };
+        // each of carol's channels can only receive 4000 msat. Rounded to the quantum, the
+        // halves are 4000 and 5000 msat with the larger one pushed last
+        assert_eq!(send(ShardOrder::Lifo), vec![9000, 5000, 3000, 2000, 4000]);
+        assert_eq!(
+            send(ShardOrder::LargestFirst),
+            vec![9000, 5000, 4000, 3000, 2000, 2000, 2000]
+        );
+        assert_eq!(
+            send(ShardOrder::SmallestFirst),
+            vec![9000, 4000, 2000, 2000, 5000, 2000, 3000]
+        );
+    }
+
+    #[test]
+    fn mpp_timings_are_recorded() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let source = "bob".to_string();
+        let dest = "alice".to_string();
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 10000;
+            }
+        }
+        let amount_msat = 12000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        assert!(simulator.send_mpp_payment(&mut payment));
+        let timings = simulator.timings();
+        assert!(timings.pathfinding > 0);
+        assert!(timings.balance_updates > 0);
+        assert!(timings.event_processing > 0);
+    }
+
+    // alice -> bob -> chan -> dina is the only path and bob can only forward small amounts to chan.
+    // Without the stopping condition the payment is split until MAX_PARTS is exceeded.
+    #[test]
+    fn mpp_aborts_on_persistent_bottleneck() {
+        let send = |stop_on_bottleneck: bool| {
+            let source = "alice".to_string();
+            let dest = "dina".to_string();
+            let mut simulator = crate::attempt::tests::init_sim(None, None);
+            for edges in simulator.graph.edges.values_mut() {
+                for e in edges {
+                    e.capacity = 10000000;
+                    if e.channel_id == "bob2" {
+                        e.capacity = e.balance + 100;
+                    }
+                }
+            }
+            let amount_msat = 4000;
+            let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+            simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+            simulator.set_payment_parts(PaymentParts::Split).unwrap();
+            simulator.set_stop_on_bottleneck(stop_on_bottleneck);
+            assert!(!simulator.send_mpp_payment(&mut payment));
+            payment
+        };
+        let payment = send(true);
+        assert_eq!(
+            payment.failure_reason,
+            Some(FailureReason::PersistentBottleneck)
+        );
+        // whole amount and the first half only, each failing at bob
+        assert_eq!(payment.htlc_attempts, 4);
+        assert_eq!(payment.failed_amounts, vec![4000, 2000]);
+        // without it the first half is split until the parts would fall below min_shard_amt
+        let payment = send(false);
+        assert_eq!(
+            payment.failure_reason,
+            Some(FailureReason::InsufficientLiquidity)
+        );
+        assert_eq!(payment.htlc_attempts, 18);
+        assert_eq!(
+            payment.failed_amounts,
+            vec![4000, 2000, 1000, 500, 250, 125, 62, 31, 15]
+        );
+    }
+
+    // same bottleneck as above
+    #[test]
+    fn split_log_names_failed_channels_and_child_amounts() {
+        let source = "alice".to_string();
+        let dest = "dina".to_string();
+        let mut simulator = crate::attempt::tests::init_sim(None, None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.capacity = 10000000;
+                if e.channel_id == "bob2" {
+                    e.capacity = e.balance + 100;
+                }
+            }
+        }
+        let amount_msat = 4000;
+        let mut payment = Payment::new(3570, source.clone(), dest.clone(), amount_msat, Some(10));
+        simulator.add_invoice(Invoice::new(3570, amount_msat, &source, &dest));
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        simulator.set_stop_on_bottleneck(true);
+        crate::attempt::tests::capture_logs();
+        assert!(!simulator.send_mpp_payment(&mut payment));
+        // the whole amount and its first half each failed at bob2, the latter stopping the payment
+        assert_eq!(payment.failed_channels, vec!["bob2", "bob2"]);
+        assert_eq!(
+            crate::attempt::tests::captured_logs("of payment 3570 "),
+            vec![(
+                log::Level::Trace,
+                "Splitting shard 0 of payment 3570 worth 4000 msat that failed at channels [\"bob2\"] into shards of 2000 and 2000 msat.".to_string()
+            )]
+        );
+    }
+
+    // chan can forward just 2000 msat to dina, which pathfinding allows for but which leaves nothing
+    // for chan's fee. Both halves fail at chan2, so splitting further cannot help
+    #[test]
+    fn mpp_aborts_on_split_cycle() {
+        let source = "alice".to_string();
+        let dest = "dina".to_string();
+        let mut simulator = crate::attempt::tests::init_sim(None, None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.capacity = 10000000;
+                if e.channel_id == "chan2" {
+                    e.balance = 2000;
+                }
+            }
+        }
+        let amount_msat = 4000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        simulator.set_shard_order(ShardOrder::LargestFirst);
+        assert!(!simulator.send_mpp_payment(&mut payment));
+        assert_eq!(payment.failure_reason, Some(FailureReason::SplitCycle));
+        assert_eq!(payment.failed_channels, vec!["chan2", "chan2"]);
+        assert_eq!(payment.shard_amounts, vec![4000, 2000, 2000]);
+        assert!(payment.shard_amounts.len() <= crate::MAX_PARTS);
+    }
+
+    // bob's channels hold no more than 100000 msat, so 120000 msat can only be sent in parts
+    #[test]
+    fn payment_wider_than_any_path_is_split_right_away() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for (src, edges) in simulator.graph.edges.iter_mut() {
+            for e in edges {
+                if src == "bob" {
+                    e.capacity = 100000;
+                    e.balance = 70000;
+                } else {
+                    e.capacity = 1000000;
+                    e.balance = 100000;
+                }
+            }
+        }
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let amount_msat = 120000;
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let mut payment = Payment::new(0, source, dest, amount_msat, None);
+        assert!(simulator.send_mpp_payment(&mut payment));
+        assert!(!payment.shard_amounts.contains(&amount_msat));
+        assert!(!payment.failed_amounts.contains(&amount_msat));
+        assert_eq!(payment.shard_amounts, vec![60000, 60000]);
+        assert_eq!(payment.num_parts, 2);
+        // an htlc at every hop before dest of the two parts, none for the whole amount
+        assert_eq!(payment.htlc_attempts, 5);
+    }
+
+    // every intermediary fails to forward so the attempt is made but fails
+    #[test]
+    fn split_of_an_attempted_shard_keeps_its_settings() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+            }
+        }
+        simulator.set_failure_probability(1.0);
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let amount_msat = 20000;
+        let mut shard = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(777));
+        shard.max_parts = 5;
+        shard.max_hops = 4;
+        shard.deadline = Some(Time::from_secs(60.0));
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        assert!(!simulator.send_one_payment(&mut shard).0);
+        assert!(shard.htlc_attempts > 0);
+        let (shard1, shard2) = simulator.split_shard(&shard).unwrap();
+        for child in [shard1, shard2] {
+            assert_eq!(child.min_shard_amt, 777);
+            assert_eq!(child.max_parts, 5);
+            assert_eq!(child.max_hops, 4);
+            assert_eq!(child.deadline, Some(Time::from_secs(60.0)));
+        }
+    }
+
+    #[test]
+    fn shard_amounts_are_rounded_to_the_quantum() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for (src, edges) in simulator.graph.edges.iter_mut() {
+            for e in edges {
+                if src == "bob" {
+                    e.capacity = 100000;
+                    e.balance = 70000;
+                } else {
+                    e.capacity = 1000000;
+                    e.balance = 100000;
+                }
+            }
+        }
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let amount_msat = 121000;
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        simulator.set_shard_quantum(1000);
+        let mut payment = Payment::new(0, source, dest, amount_msat, None);
+        assert!(simulator.send_mpp_payment(&mut payment));
+        // halving would have sent 60500 msat twice
+        assert_eq!(payment.shard_amounts, vec![60000, 61000]);
+        assert!(payment
+            .shard_amounts
+            .iter()
+            .all(|amount| amount % 1000 == 0));
+        assert_eq!(payment.shard_amounts.iter().sum::<usize>(), amount_msat);
+    }
+
+    // a third of 30000 msat is rounded to 9000 msat, so the three parts are 9000, 9000 and
+    // 12000 msat, folded into 18000 and 12000 msat. carol and dave can each forward up to 20000
+    // msat to alice so the payment as a whole fails and its halves of 15000 msat are rounded to
+    // 9000 and 21000 msat
+    #[test]
+    fn dust_part_is_folded_into_its_sibling() {
+        let send = |dust_policy: DustPolicy| {
+            let json_file = "../test_data/trivial_multipath.json";
+            let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+            for edges in simulator.graph.edges.values_mut() {
+                for e in edges {
+                    e.capacity = 1000000;
+                    e.balance = if e.destination == "alice" {
+                        20000
+                    } else {
+                        100000
+                    };
+                    if e.source == "dave" {
+                        e.fee_base_msat = 10;
+                        e.fee_proportional_millionths = 0;
+                    }
+                }
+            }
+            let (source, dest) = ("bob".to_string(), "alice".to_string());
+            let amount_msat = 30000;
+            simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+            simulator.set_payment_parts(PaymentParts::Split).unwrap();
+            simulator.set_shard_quantum(9000);
+            simulator.set_dust_policy(dust_policy);
+            let mut payment = Payment::new(0, source, dest, amount_msat, Some(10000));
+            payment.target_parts = Some(3);
+            let succeeded = simulator.send_mpp_payment(&mut payment);
+            (succeeded, payment)
+        };
+        let (succeeded, payment) = send(DustPolicy::Fail);
+        assert!(!succeeded);
+        assert_eq!(payment.failure_reason, Some(FailureReason::DustPart));
+        let (succeeded, payment) = send(DustPolicy::FoldIntoSibling);
+        assert!(succeeded);
+        assert_eq!(payment.shard_amounts, vec![18000, 12000]);
+        assert_eq!(payment.num_parts, 2);
+    }
+
+    // bob -> carol has room for one of the parts only, so the other has to go via dave
+    #[test]
+    fn parallel_pathfinding_matches_serial_routes() {
+        let send = |parallel: bool| {
+            let json_file = "../test_data/trivial_multipath.json";
+            let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+            for edges in simulator.graph.edges.values_mut() {
+                for e in edges {
+                    e.capacity = 1000000;
+                    e.balance = if e.channel_id == "bob-carol" {
+                        40000
+                    } else {
+                        70000
+                    };
+                }
+            }
+            let (source, dest) = ("bob".to_string(), "alice".to_string());
+            let amount_msat = 60000;
+            simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+            simulator.set_payment_parts(PaymentParts::Split).unwrap();
+            simulator.set_parallel_pathfinding(parallel);
+            let mut payment = Payment::new(0, source, dest, amount_msat, None);
+            payment.target_parts = Some(2);
+            assert!(simulator.send_mpp_payment(&mut payment));
+            (payment.used_paths, simulator.graph.total_liquidity())
+        };
+        let (serial_paths, serial_liquidity) = send(false);
+        let (parallel_paths, parallel_liquidity) = send(true);
+        assert_eq!(serial_paths.len(), 2);
+        assert_ne!(
+            serial_paths[0].path.hops[0].3,
+            serial_paths[1].path.hops[0].3
+        );
+        assert_eq!(serial_paths, parallel_paths);
+        assert_eq!(serial_liquidity, parallel_liquidity);
+    }
+
+    // dave cannot forward to alice and alice can only receive 40000 msat from carol
+    #[test]
+    fn amp_keeps_the_parts_that_arrived() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.capacity = 1000000;
+                e.balance = 70000;
+                match e.channel_id.as_str() {
+                    "alice-carol" => e.capacity = 110000,
+                    "dave-alice" => e.balance = 0,
+                    _ => {}
+                }
+            }
+        }
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let amount_msat = 60000;
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        simulator.set_payment_parts(PaymentParts::Amp).unwrap();
+        let mut payment = Payment::new(0, source, dest.clone(), amount_msat, Some(10000));
+        assert!(!simulator.send_mpp_payment(&mut payment));
+        assert!(!payment.succeeded);
+        assert!(payment.delivered_msat > 0);
+        assert!(payment.delivered_msat < amount_msat);
+        // the delivered parts have not been reverted
+        let received = simulator
+            .graph
+            .get_channel_balance(&dest, &"alice-carol".to_string())
+            - 70000;
+        assert_eq!(payment.delivered_msat, received);
+        assert_eq!(payment.used_paths.len(), payment.num_parts);
+    }
+
+    // the first part goes via carol, which then lacks the balance for the second. The second
+    // part's path via dave locks for longer than the deadline allows
+    #[test]
+    fn amp_reverts_the_late_part_only() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.capacity = 1000000;
+                e.balance = 100000;
+                e.cltv_expiry_delta = 40;
+                match e.channel_id.as_str() {
+                    "bob-carol" => e.balance = 40000,
+                    "bob-eve" => e.balance = 0,
+                    "dave-alice" => {
+                        e.fee_base_msat = 100;
+                        e.fee_proportional_millionths = 0;
+                        e.cltv_expiry_delta = 1000;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let (bob, alice) = ("bob".to_string(), "alice".to_string());
+        let amount_msat = 60000;
+        simulator.add_invoice(Invoice::new(0, amount_msat, &bob, &alice));
+        simulator.set_payment_parts(PaymentParts::Amp).unwrap();
+        let mut payment = Payment::new(0, bob.clone(), alice.clone(), amount_msat, Some(10000));
+        payment.target_parts = Some(2);
+        payment.deadline = Some(Time::from_secs(500.0));
+        assert!(!simulator.send_mpp_payment(&mut payment));
+        assert_eq!(
+            payment.failure_reason,
+            Some(FailureReason::DeadlineExceeded)
+        );
+        assert_eq!(payment.shard_amounts, vec![30000, 30000]);
+        // the part via carol stays delivered
+        assert_eq!(payment.delivered_msat, 30000);
+        assert_eq!(payment.num_parts, 1);
+        let delivered = payment.delivered_shards();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].shard_id, Some((0, 0)));
+        let balance = |node: &String, channel_id: &str| {
+            simulator
+                .graph
+                .get_channel_balance(node, &channel_id.to_string())
+        };
+        assert_eq!(balance(&bob, "bob-carol"), 40000 - 30010);
+        assert_eq!(balance(&alice, "alice-carol"), 100000 + 30000);
+        // the part via dave is undone
+        assert_eq!(balance(&bob, "bob-dave"), 100000);
+        assert_eq!(balance(&"dave".to_string(), "dave-alice"), 100000);
+        assert_eq!(balance(&alice, "alice-dave"), 100000);
+        // and only the part via carol still resolves
+        let mut resolved = vec![];
+        while let Some(event) = simulator.event_queue.next() {
+            if let PaymentEvent::ShardSuccess { shard_id, .. } = event {
+                resolved.push(shard_id);
+            }
+        }
+        assert_eq!(resolved, vec![(0, 0)]);
+    }
+
+    #[test]
+    fn source_balance_override_skips_precheck() {
+        let source = "alice".to_string();
+        let dest = "chan".to_string();
+        let mut simulator = crate::attempt::tests::init_sim(None, None);
+        simulator
+            .graph
+            .update_channel_balance(&String::from("alice1"), 500);
+        let amount_msat = 1000;
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+        assert!(!simulator.send_mpp_payment(&mut payment));
+        assert!(payment.failed_amounts.is_empty());
+        let mut payment = Payment::new(0, source, dest, amount_msat, Some(10));
+        assert!(!simulator.send_mpp_payment_with_override(&mut payment, Some(10000)));
+        // shards were attempted
+        assert!(!payment.failed_amounts.is_empty());
+        assert_eq!(simulator.graph.get_total_node_balance(&payment.source), 500);
+    }
+
+    // every cheap route to alice leads through carol who holds the shards. The sender learns
+    // about it only after the timeout and has to split around her
+    #[test]
+    fn griefing_node_delays_resolution_and_forces_split() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 1000000;
+                e.capacity = 10000000;
+                if e.channel_id == "dave-alice" {
+                    e.fee_base_msat = 100;
+                }
+            }
+        }
+        let timeout = 300.0;
+        simulator.set_node_griefing("carol".to_string());
+        simulator.set_griefing_timeout(timeout);
+        let amount_msat = 12000;
+        let mut payment =
+            Payment::new(0, "bob".to_string(), "alice".to_string(), amount_msat, None);
+        simulator.add_invoice(Invoice::new(0, amount_msat, &payment.source, &payment.dest));
+        assert!(simulator.send_mpp_payment(&mut payment));
+        assert_eq!(payment.held_htlcs, 2);
+        assert!(payment.num_parts > 1);
+        assert!(payment
+            .used_paths
+            .iter()
+            .all(|p| p.path.hops.iter().all(|h| h.0 != "carol")));
+        // the liquidity is locked until the timeout
+        assert_eq!(
+            simulator.graph.get_in_flight(&String::from("bob-carol")),
+            amount_msat + 10
+        );
+        assert!(payment.completion_time > Time::from_secs(timeout));
+        // held HTLCs time out before the payment resolves
+        for _ in 0..2 {
+            let event = next_payment_event(&mut simulator);
+            assert!(matches!(event, Some(PaymentEvent::HtlcTimeout { .. })));
+            assert_eq!(simulator.event_queue.now(), Time::from_secs(timeout));
+            if let Some(PaymentEvent::HtlcTimeout { reservations, .. }) = event {
+                simulator.release_held_htlcs(&reservations);
+            }
+        }
+        assert!(matches!(
+            next_payment_event(&mut simulator),
+            Some(PaymentEvent::UpdateSuccesful { .. })
+        ));
+        assert_eq!(
+            simulator.event_queue.now(),
+            Time::from_secs(timeout + crate::SIM_DELAY_IN_SECS)
+        );
+        assert_eq!(simulator.graph.get_in_flight(&String::from("bob-carol")), 0);
+    }
+
+    // like above but bob gives up on the shards carol holds after 60 secs and sends them via
+    // dave instead, the liquidity stays locked until carol's timeout though
+    #[test]
+    fn slow_shard_is_cancelled_and_rerouted() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 1000000;
+                e.capacity = 10000000;
+                if e.channel_id == "dave-alice" {
+                    e.fee_base_msat = 100;
+                }
+            }
+        }
+        let (timeout, threshold) = (300.0, 60.0);
+        simulator.set_node_griefing("carol".to_string());
+        simulator.set_griefing_timeout(timeout);
+        simulator.set_slow_threshold(threshold);
+        let amount_msat = 12000;
+        let mut payment =
+            Payment::new(0, "bob".to_string(), "alice".to_string(), amount_msat, None);
+        simulator.add_invoice(Invoice::new(0, amount_msat, &payment.source, &payment.dest));
+        assert!(simulator.send_mpp_payment(&mut payment));
+        // rerouted without splitting
+        assert_eq!(payment.num_parts, 1);
+        assert_eq!(payment.held_htlcs, 1);
+        assert_eq!(
+            payment.used_paths[0].path.get_involved_nodes(),
+            vec!["bob", "dave", "alice"]
+        );
+        assert_eq!(
+            simulator.graph.get_in_flight(&String::from("bob-carol")),
+            amount_msat + 10
+        );
+        assert!(payment.completion_time > Time::from_secs(threshold));
+        assert!(payment.completion_time < Time::from_secs(timeout));
+        assert!(matches!(
+            next_payment_event(&mut simulator),
+            Some(PaymentEvent::UpdateSuccesful { .. })
+        ));
+        assert_eq!(
+            simulator.event_queue.now(),
+            Time::from_secs(threshold + crate::SIM_DELAY_IN_SECS)
+        );
+    }
+
+    // nobody griefs but carol-alice takes 100 secs to resolve, so a payment that gives up after
+    // 60 secs goes via dave instead while its HTLC via carol stays locked
+    #[test]
+    fn slow_path_is_cancelled_at_the_payment_threshold() {
+        let send = |slow_threshold: Option<f32>| {
+            let json_file = "../test_data/trivial_multipath.json";
+            let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+            for edges in simulator.graph.edges.values_mut() {
+                for e in edges {
+                    e.balance = 1000000;
+                    e.capacity = 10000000;
+                    match e.channel_id.as_str() {
+                        "dave-alice" => e.fee_base_msat = 100,
+                        "carol-alice" => e.cltv_expiry_delta = 100,
+                        _ => {}
+                    }
+                }
+            }
+            let amount_msat = 12000;
+            let mut builder = crate::payment::PaymentBuilder::new(
+                0,
+                "bob".to_string(),
+                "alice".to_string(),
+                amount_msat,
+            );
+            if let Some(threshold) = slow_threshold {
+                builder = builder.slow_threshold(threshold);
+            }
+            let mut payment = builder.build();
+            simulator.add_invoice(Invoice::new(0, amount_msat, &payment.source, &payment.dest));
+            assert!(simulator.send_mpp_payment(&mut payment));
+            (simulator, payment)
+        };
+        let (_, patient) = send(None);
+        assert_eq!(patient.cancelled_htlcs, 0);
+        assert_eq!(
+            patient.used_paths[0].path.get_involved_nodes(),
+            vec!["bob", "carol", "alice"]
+        );
+        let (mut simulator, payment) = send(Some(60.0));
+        assert_eq!(payment.num_parts, 1);
+        assert_eq!(payment.held_htlcs, 0);
+        assert_eq!(payment.cancelled_htlcs, 1);
+        assert_eq!(payment.failed_channels, vec!["carol-alice"]);
+        assert_eq!(
+            payment.used_paths[0].path.get_involved_nodes(),
+            vec!["bob", "dave", "alice"]
+        );
+        assert_eq!(
+            simulator.graph.get_in_flight(&String::from("bob-carol")),
+            12000 + 10
+        );
+        assert_eq!(
+            simulator.graph.get_in_flight(&String::from("carol-alice")),
+            12000
+        );
+        // the cancelled HTLC resolves after its 100 secs while the payment resolved for the
+        // sender once it gave up on the HTLC
+        assert!(matches!(
+            next_payment_event(&mut simulator),
+            Some(PaymentEvent::HtlcTimeout { .. })
+        ));
+        assert_eq!(simulator.event_queue.now(), Time::from_secs(100.0));
+        assert!(matches!(
+            next_payment_event(&mut simulator),
+            Some(PaymentEvent::UpdateSuccesful { .. })
+        ));
+        assert_eq!(
+            simulator.event_queue.now(),
+            Time::from_secs(60.0 + crate::SIM_DELAY_IN_SECS)
+        );
+    }
+
+    // alice -> bob -> chan locks for 40 secs whereas the direct channel to bob resolves at once
+    #[test]
+    fn mpp_fails_when_a_part_resolves_after_the_deadline() {
+        let mut simulator = crate::attempt::tests::init_sim(None, None);
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let amount_msat = 1000;
+        let deadline = Some(Time::from_secs(30.0));
+        let mut long = Payment::new(
+            0,
+            "alice".to_string(),
+            "chan".to_string(),
+            amount_msat,
+            None,
+        );
+        long.deadline = deadline;
+        let mut short = Payment::new(1, "alice".to_string(), "bob".to_string(), amount_msat, None);
+        short.deadline = deadline;
+        simulator.add_invoice(Invoice::new(0, amount_msat, &long.source, &long.dest));
+        simulator.add_invoice(Invoice::new(1, amount_msat, &short.source, &short.dest));
+        let balance_before = simulator.graph.get_total_node_balance(&long.source);
+        assert!(!simulator.send_mpp_payment(&mut long));
+        assert_eq!(long.failure_reason, Some(FailureReason::DeadlineExceeded));
+        assert!(long.used_paths.is_empty());
+        assert_eq!(
+            simulator.graph.get_total_node_balance(&long.source),
+            balance_before
+        );
+        assert!(simulator.send_mpp_payment(&mut short));
+        assert!(short.completion_time <= Time::from_secs(30.0));
+        // without a deadline the long path is fine
+        long.deadline = None;
+        assert!(simulator.send_mpp_payment(&mut long));
+    }
+
+    #[test]
+    fn tight_deadline_abandons_the_payment() {
+        let mut simulator = crate::attempt::tests::init_sim(None, None);
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let amount_msat = 1000;
+        let mut payment = Payment::new(
+            0,
+            "alice".to_string(),
+            "chan".to_string(),
+            amount_msat,
+            None,
+        );
+        payment.deadline = Some(Time::from_secs(30.0));
+        simulator.add_invoice(Invoice::new(0, amount_msat, &payment.source, &payment.dest));
+        assert!(!simulator.send_mpp_payment(&mut payment));
+        let mut events = vec![];
+        while let Some(event) = simulator.event_queue.next() {
+            events.push(event);
+        }
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, PaymentEvent::UpdateFailed { .. })));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            PaymentEvent::Abandoned {
+                reason: FailureReason::DeadlineExceeded,
+                parts_delivered: 0,
+                ..
+            }
+        )));
+    }
+
+    // the part via bob arrives but would resolve after the deadline
+    #[test]
+    fn failed_payment_cancels_its_shard_successes() {
+        let mut simulator = crate::attempt::tests::init_sim(None, None);
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let amount_msat = 1000;
+        let mut payment = Payment::new(
+            0,
+            "alice".to_string(),
+            "chan".to_string(),
+            amount_msat,
+            None,
+        );
+        payment.deadline = Some(Time::from_secs(30.0));
+        simulator.add_invoice(Invoice::new(0, amount_msat, &payment.source, &payment.dest));
+        assert!(!simulator.send_mpp_payment(&mut payment));
+        assert_eq!(payment.num_parts, 1);
+        let mut events = vec![];
+        while let Some(event) = simulator.event_queue.next() {
+            events.push(event);
+        }
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, PaymentEvent::ShardSuccess { .. })));
+        assert!(matches!(events[..], [PaymentEvent::Abandoned { .. }]));
+    }
+
+    /// The payment split into four parts along with the times its shards and the payment resolve
+    fn send_four_part_mpp(max_concurrent_shards: usize) -> (Payment, Vec<Time>, Time) {
+        let json_file = "../test_data/trivial_multipath.json";
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let amount_msat = 12000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+        payment.target_parts = Some(4);
+        payment.max_concurrent_shards = max_concurrent_shards;
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        assert!(simulator.send_mpp_payment(&mut payment));
+        let (mut shards_resolved, mut payment_resolved) = (vec![], Time::default());
+        while let Some(event) = simulator.event_queue.next() {
+            match event {
+                PaymentEvent::ShardSuccess { .. } | PaymentEvent::ShardFailed { .. } => {
+                    shards_resolved.push(simulator.event_queue.now())
+                }
+                _ => payment_resolved = simulator.event_queue.now(),
+            }
+        }
+        (payment, shards_resolved, payment_resolved)
+    }
+
+    #[test]
+    fn concurrent_shards_are_capped() {
+        let (uncapped, resolved, uncapped_resolved) = send_four_part_mpp(0);
+        assert_eq!(resolved.len(), 4);
+        let cap = 2;
+        let (capped, resolved, capped_resolved) = send_four_part_mpp(cap);
+        assert_eq!(resolved.len(), 4);
+        // each shard is in flight from its launch until it resolves one sim delay later
+        let in_flight: Vec<(Time, Time)> = resolved
+            .iter()
+            .map(|resolved_at| {
+                let launched_at = *resolved_at - Time::from_secs(crate::SIM_DELAY_IN_SECS);
+                (launched_at, *resolved_at)
+            })
+            .collect();
+        for (launched_at, _) in in_flight.iter() {
+            let concurrent = in_flight
+                .iter()
+                .filter(|(start, end)| start <= launched_at && launched_at < end)
+                .count();
+            assert!(concurrent <= cap);
+        }
+        // the outcome is the same, only later
+        assert_eq!(capped.used_paths, uncapped.used_paths);
+        assert!(capped_resolved > uncapped_resolved);
+        assert!(capped.completion_time > uncapped.completion_time);
+    }
+
+    // bob can spend 4011 msat via carol and 4031 msat via eve. Both paths' fees deducted that
+    // leaves 8000 msat (+ 1 msat each as the balance must exceed the amount)
+    #[test]
+    fn max_sendable_matches_routable_capacity() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 1000000;
+                e.capacity = 10000000;
+            }
+        }
+        simulator
+            .graph
+            .update_channel_balance(&String::from("bob-carol"), 4011);
+        simulator
+            .graph
+            .update_channel_balance(&String::from("bob-eve"), 4031);
+        simulator
+            .graph
+            .update_channel_balance(&String::from("bob-dave"), 0);
+        let balances_before = simulator.graph.get_total_node_balance(&String::from("bob"));
+        assert_eq!(simulator.max_sendable("bob", "alice"), 8000);
+        assert_eq!(
+            simulator.graph.get_total_node_balance(&String::from("bob")),
+            balances_before
+        );
+        assert!(simulator.graph.in_flight.is_empty());
+        assert!(simulator
+            .plan_mpp(&"bob".to_string(), &"alice".to_string(), 8001)
+            .is_none());
+    }
+
+    #[test]
+    fn fee_estimate_matches_paid_fee() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+            }
+        }
+        let amount = 1000;
+        let estimate = simulator.estimate_fee("bob", "alice", amount);
+        assert_eq!(estimate, Some(10));
+        assert!(simulator.graph.in_flight.is_empty());
+        let payment = Payment::new(0, "bob".to_string(), "alice".to_string(), amount, None);
+        let result = simulator.run_scheduled(vec![(Time::from_secs(0.0), payment)]);
+        assert_eq!(result.num_succesful, 1);
+        let paid: usize = result.successful_payments[0]
+            .used_paths
+            .iter()
+            .map(|p| p.path_fees())
+            .sum();
+        assert_eq!(estimate, Some(paid as u64));
+        assert_eq!(simulator.estimate_fee("bob", "alice", 10000000), None);
+    }
+
+    // the scorer is nearly certain of every channel, only random failures make bob fall back
+    // from carol to dave's more expensive route
+    #[test]
+    fn expected_fee_accounts_for_retries() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 500000000;
+                e.capacity = 1000000000;
+                if e.channel_id == "dave-alice" {
+                    e.fee_base_msat = 100;
+                    e.fee_proportional_millionths = 0;
+                }
+            }
+        }
+        let amount = 1000;
+        let payment = Payment::new(0, "bob".to_string(), "alice".to_string(), amount, None);
+        let single_attempt = simulator.estimate_fee("bob", "alice", amount).unwrap() as f64;
+        assert_eq!(single_attempt, 10.0);
+        assert_abs_diff_eq!(
+            simulator.expected_fee(&payment),
+            single_attempt,
+            epsilon = 0.01
+        );
+        simulator.set_failure_probability(0.5);
+        let expected = simulator.expected_fee(&payment);
+        // carol fails half of the time and bob pays dave's 100 msat instead
+        assert!(expected > single_attempt + 0.5 * 100.0);
+        assert!(simulator.graph.in_flight.is_empty());
+        let liquidity = simulator.graph.total_liquidity();
+        simulator.expected_fee(&payment);
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+    }
+
+    // bob can only pay out via bob-carol as the routes via dave and eve are drained
+    #[test]
+    fn bottleneck_is_the_limiting_channel() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 100000;
+                e.capacity = 1000000;
+            }
+        }
+        for channel_id in ["bob-dave", "bob-eve", "dave-alice", "eve-carol"] {
+            simulator
+                .graph
+                .update_channel_balance(&channel_id.to_string(), 0);
+        }
+        simulator
+            .graph
+            .update_channel_balance(&"bob-carol".to_string(), 3000);
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), 5000, None);
+        simulator.add_invoice(Invoice::new(0, 5000, &source, &dest));
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        assert!(!simulator.send_mpp_payment(&mut payment));
+        assert_eq!(
+            simulator.find_bottleneck(&payment),
+            Some("bob-carol".to_string())
+        );
+        // nothing changed in the simulation itself
+        assert_eq!(
+            simulator
+                .graph
+                .get_channel_balance(&source, &"bob-carol".to_string()),
+            3000
+        );
+        let payment = Payment::new(1, source, dest, 2000, None);
+        assert_eq!(simulator.find_bottleneck(&payment), None);
+    }
+
+    // bob's channels hold 8042 msat but fees and the reserve of 1 msat per channel leave 8000
+    #[test]
+    fn mpp_optimality_against_max_flow() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 1000000;
+                e.capacity = 10000000;
+            }
+        }
+        let payment = Payment::new(0, "bob".to_string(), "alice".to_string(), 5000, None);
+        assert_eq!(simulator.mpp_optimality(&payment), 1.0);
+        simulator
+            .graph
+            .update_channel_balance(&String::from("bob-carol"), 4011);
+        simulator
+            .graph
+            .update_channel_balance(&String::from("bob-eve"), 4031);
+        simulator
+            .graph
+            .update_channel_balance(&String::from("bob-dave"), 0);
+        assert_eq!(simulator.graph.max_flow("bob", "alice"), 8042);
+        let payment = Payment::new(1, "bob".to_string(), "alice".to_string(), 10000, None);
+        assert_abs_diff_eq!(
+            simulator.mpp_optimality(&payment),
+            8000.0 / 8042.0,
+            epsilon = 1e-9
+        );
+        assert!(simulator.graph.in_flight.is_empty());
+    }
+
+    // the only usable path is bob -> carol -> alice where carol charges 10 msat
+    #[test]
+    fn fees_on_top_or_out_of_the_amount() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let (bob_carol, alice_carol) = ("bob-carol".to_string(), "alice-carol".to_string());
+        let amount_msat = 5000;
+        for fees_on_top in [true, false] {
+            let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+            for edges in simulator.graph.edges.values_mut() {
+                for e in edges {
+                    e.balance = 10000;
+                    e.capacity = 100000;
+                }
+            }
+            simulator
+                .graph
+                .update_channel_balance(&String::from("bob-eve"), 0);
+            simulator
+                .graph
+                .update_channel_balance(&String::from("bob-dave"), 0);
+            let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+            payment.fees_on_top = fees_on_top;
+            simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+            assert!(simulator.send_mpp_payment(&mut payment));
+            let sent = 10000 - simulator.graph.get_channel_balance(&source, &bob_carol);
+            let received = simulator.graph.get_channel_balance(&dest, &alice_carol) - 10000;
+            let used_path = &payment.used_paths[0];
+            assert_eq!(used_path.path_fees(), 10);
+            if fees_on_top {
+                assert_eq!((sent, received), (amount_msat + 10, amount_msat));
+            } else {
+                assert_eq!((sent, received), (amount_msat, amount_msat - 10));
+            }
+            assert_eq!(used_path.amount, sent);
+            assert_eq!(used_path.path_amount(), received);
+        }
+    }
+
+    // carol's 10 msat leave nothing of a 10 msat payment whose fees come out of the amount
+    #[test]
+    fn fees_consuming_the_amount_fail_the_payment() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 10000;
+                e.capacity = 100000;
+            }
+        }
+        for parts in [PaymentParts::Single, PaymentParts::Split] {
+            let payment_id = simulator.next_payment_id();
+            let mut payment = Payment::new(payment_id, source.clone(), dest.clone(), 10, Some(10));
+            payment.fees_on_top = false;
+            payment.payment_parts = Some(parts);
+            simulator.add_invoice(Invoice::new(payment_id, 10, &source, &dest));
+            assert!(!simulator.send_mpp_payment(&mut payment));
+            assert_eq!(
+                payment.failure_reason,
+                Some(FailureReason::FeesExceedAmount)
+            );
+        }
+        assert!(simulator.graph.in_flight.is_empty());
+    }
+
+    // both parts go via carol who charges 10 msat each
+    #[test]
+    fn dest_receives_the_amount_and_the_source_pays_the_fees() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 100000;
+                e.capacity = 10000000;
+            }
+        }
+        let outbound = simulator.graph.get_total_node_balance(&source);
+        let inbound = simulator.graph.get_total_node_balance(&dest);
+        let amount_msat = 12000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+        payment.target_parts = Some(2);
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        assert!(simulator.send_mpp_payment(&mut payment));
+        assert_eq!(payment.num_parts, 2);
+        assert_eq!(payment.delivered_msat, amount_msat);
+        assert_eq!(payment.total_fees_msat, 20);
+        assert_eq!(
+            simulator.graph.get_total_node_balance(&dest) - inbound,
+            amount_msat
+        );
+        assert_eq!(
+            outbound - simulator.graph.get_total_node_balance(&source),
+            amount_msat + payment.total_fees_msat
+        );
+    }
+
+    #[test]
+    fn shards_are_identified_by_payment_and_index() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 100000;
+                e.capacity = 10000000;
+            }
+        }
+        let amount_msat = 12000;
+        let mut payment = Payment::new(7, source.clone(), dest.clone(), amount_msat, Some(10));
+        payment.target_parts = Some(2);
+        simulator.add_invoice(Invoice::new(7, amount_msat, &source, &dest));
+        assert!(simulator.send_mpp_payment(&mut payment));
+        let shard_ids: Vec<_> = payment.used_paths.iter().map(|p| p.shard_id).collect();
+        assert_eq!(shard_ids, vec![Some((7, 0)), Some((7, 1))]);
+        let mut event_ids = vec![];
+        while let Some(event) = simulator.event_queue.next() {
+            if let PaymentEvent::ShardSuccess { shard_id, .. } = event {
+                event_ids.push(Some(shard_id));
+            }
+        }
+        assert_eq!(event_ids, shard_ids);
+    }
+
+    #[test]
+    fn mpp_emits_an_event_per_shard() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 100000;
+                e.capacity = 10000000;
+            }
+        }
+        let amount_msat = 12000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+        payment.target_parts = Some(2);
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        assert!(simulator.send_mpp_payment(&mut payment));
+        assert_eq!(payment.num_parts, 2);
+        let mut events = vec![];
+        while let Some(event) = simulator.event_queue.next() {
+            events.push(event);
+        }
+        assert_eq!(events.len(), 3);
+        for event in events.iter().take(2) {
+            match event {
+                PaymentEvent::ShardSuccess {
+                    shard_id,
+                    amount,
+                    channels,
+                } => {
+                    assert_eq!(shard_id.0, 0);
+                    assert_eq!(*amount, amount_msat / 2);
+                    assert_eq!(channels.first(), Some(&String::from("bob-carol")));
+                }
+                other => panic!("Expected a shard success, got {:?}", other),
+            }
+        }
+        assert!(matches!(events[2], PaymentEvent::UpdateSuccesful { .. }));
+    }
+
+    // the fees carol charges on top of an amount close to the max do not fit in a u64
+    #[test]
+    fn amount_overflow_fails_cleanly() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = u64::MAX as usize - 1;
+                e.capacity = u64::MAX as usize;
+            }
+        }
+        let amount_msat = u64::MAX as usize - 5;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        // alice cannot receive that much
+        assert!(!simulator.send_mpp_payment_with_override(&mut payment, Some(u64::MAX)));
+        assert_eq!(
+            payment.failure_reason,
+            Some(FailureReason::InsufficientLiquidity)
+        );
+        // the path is found but the amount due cannot be represented
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+        assert!(!simulator.send_mpp_shards(&mut payment, 1).0);
+        assert!(!payment.succeeded);
+        assert_eq!(payment.failure_reason, Some(FailureReason::AmountOverflow));
+        assert!(payment.used_paths.is_empty());
+        assert_eq!(payment.failed_paths.len(), 1);
+        assert_eq!(
+            simulator
+                .graph
+                .get_channel_balance(&source, &String::from("bob-carol")),
+            u64::MAX as usize - 1
+        );
+    }
+
+    #[test]
+    fn unreachable_dest_fails_before_liquidity_checks() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        let (source, dest) = ("bob".to_string(), "zoe".to_string());
+        simulator.graph.nodes.push(crate::Node {
+            id: dest.clone(),
+            ..Default::default()
+        });
+        assert!(simulator.graph.is_reachable(&source, "alice"));
+        assert!(!simulator.graph.is_reachable(&source, &dest));
+        let amount_msat = 1000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, None);
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        assert!(!simulator.send_mpp_payment(&mut payment));
+        assert_eq!(payment.failure_reason, Some(FailureReason::NoTopology));
+        assert_eq!(payment.htlc_attempts, 0);
+        assert!(payment.failed_paths.is_empty());
+    }
+
+    // carol-alice is nearly at capacity so bob's cheapest path fails there
+    #[test]
+    fn failed_path_records_reason_and_bottleneck() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = if e.channel_id == "carol-alice" {
+                    70100
+                } else {
+                    1000000
+                };
+                if e.channel_id == "dave-alice" {
+                    e.fee_base_msat = 100;
+                    e.fee_proportional_millionths = 0;
+                }
+            }
+        }
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let amount_msat = 1000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, None);
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        assert!(simulator.send_mpp_payment(&mut payment));
+        let (failed_path, reason, failing_channel) = &payment.failed_paths[0];
+        assert_eq!(
+            failed_path.path.get_involved_nodes(),
+            vec!["bob".to_string(), "carol".to_string(), "alice".to_string()]
+        );
+        assert_eq!(*reason, FailureReason::InsufficientLiquidity);
+        assert_eq!(failing_channel.as_deref(), Some("carol-alice"));
+    }
+
+    #[test]
+    fn zero_amount_payment_is_rejected() {
+        let mut simulator = crate::attempt::tests::init_sim(None, None);
+        let mut payment = Payment::new(0, "alice".to_string(), "chan".to_string(), 0, None);
+        simulator.add_invoice(Invoice::new(0, 0, &payment.source, &payment.dest));
+        assert!(!simulator.send_mpp_payment(&mut payment));
+        assert_eq!(payment.failure_reason, Some(FailureReason::ZeroAmount));
+        assert_eq!(payment.htlc_attempts, 0);
+        assert!(payment.failed_paths.is_empty());
+        assert!(matches!(
+            simulator.event_queue.next(),
+            Some(PaymentEvent::UpdateFailed { .. })
+        ));
+    }
+
+    // bob pays alice via carol, who charges 10 msat, and carol also takes the service fee
+    #[test]
+    fn service_fee_counts_towards_the_fee_budget() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+                if e.channel_id == "dave-alice" {
+                    e.fee_base_msat = 100;
+                    e.fee_proportional_millionths = 0;
+                }
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let (bob, alice) = ("bob".to_string(), "alice".to_string());
+        let amount_msat = 1000;
+        let liquidity = simulator.graph.total_liquidity();
+        let send = |simulator: &mut Simulation, id: usize, service_fee_msat: u64| {
+            simulator.add_invoice(Invoice::new(id, amount_msat, &bob, &alice));
+            let mut payment =
+                crate::payment::PaymentBuilder::new(id, bob.clone(), alice.clone(), amount_msat)
+                    .service_fee_msat(service_fee_msat)
+                    .max_fee_msat(50)
+                    .build();
+            simulator.send_mpp_payment(&mut payment);
+            payment
+        };
+        let payment = send(&mut simulator, 0, 30);
+        assert!(payment.succeeded);
+        assert_eq!(payment.total_fees_msat, 40);
+        assert_eq!(
+            simulator.graph.get_total_node_balance(&bob),
+            3 * 70000 - amount_msat - 40
+        );
+        assert_eq!(
+            simulator
+                .graph
+                .get_channel_balance(&"carol".to_string(), &"carol-alice".to_string()),
+            70000 + 40
+        );
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+        // the same route with a higher service fee goes over the budget
+        let balance = simulator.graph.get_total_node_balance(&bob);
+        let payment = send(&mut simulator, 1, 45);
+        assert!(!payment.succeeded);
+        assert_eq!(
+            payment.failure_reason,
+            Some(FailureReason::FeeBudgetExceeded)
+        );
+        assert_eq!(simulator.graph.get_total_node_balance(&bob), balance);
+        // the service fee alone is over the budget, so nothing is sent
+        let payment = send(&mut simulator, 2, 60);
+        assert_eq!(
+            payment.failure_reason,
+            Some(FailureReason::FeeBudgetExceeded)
+        );
+        assert_eq!(payment.htlc_attempts, 0);
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+    }
+
+    // bob has enough balance in total but only bob-carol leads on to alice, so once the first
+    // part took most of it the second one cannot get through
+    #[test]
+    fn unroutable_remainder_aborts_the_payment() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = match e.channel_id.as_str() {
+                    "bob-carol" | "bob-eve" | "bob-dave" => 5000,
+                    "eve-carol" | "dave-alice" => 0,
+                    _ => 100000,
+                };
+                e.capacity = 1000000;
+            }
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let amount_msat = 9000;
+        let liquidity = simulator.graph.total_liquidity();
+        let balance = simulator.graph.get_total_node_balance(&source);
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10));
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        assert!(!simulator.send_mpp_payment(&mut payment));
+        assert_eq!(
+            payment.failure_reason,
+            Some(FailureReason::InsufficientLiquidity)
+        );
+        // the second half is never attempted
+        assert_eq!(payment.shard_amounts, vec![9000, 4500]);
+        assert_eq!(payment.htlc_attempts, 2);
+        assert!(payment.used_paths.is_empty());
+        assert_eq!(simulator.graph.get_total_node_balance(&source), balance);
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+    }
 }
@@ -1,8 +1,24 @@
-use crate::{graph::Graph, Edge, EdgeWeight, PaymentParts, RoutingMetric, ID};
+use crate::{
+    graph::Graph,
+    time::Time,
+    traversal::cache::{RouteCache, ShortestPathTree},
+    DecisionLog, Edge, EdgeWeight, FeeRounding, PaymentParts, RouteHint, RoutingMetric, ShardId,
+    TieBreak, ID,
+};
 
 use log::{debug, trace};
+use pathfinding::num_traits::Zero;
 use serde::Serialize;
-use std::collections::{HashMap, VecDeque};
+use std::{
+    cell::Cell,
+    cmp::Reverse,
+    collections::{hash_map::DefaultHasher, BinaryHeap, HashMap, HashSet, VecDeque},
+    error::Error,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Add,
+    sync::{Arc, Mutex},
+};
 
 /// Describes a path between two nodes
 #[derive(Debug, Clone, PartialEq, Default, Serialize)]
@@ -13,9 +29,56 @@ pub struct Path {
     /// the edge ID
     /// The dest's hop describes the channel whose balance will increase and used for reverting.
     /// Format: (hop, fees, timelock, channel_id)
+    /// The amount of the src's hop is what it sends in total and that of dest's hop what dest
+    /// receives. Intermediaries' hops hold the fee they keep, see CandidatePath::hop_amounts
+    /// for the amounts forwarded
     pub hops: VecDeque<(ID, usize, usize, String)>,
 }
 
+/// Why a path could not be built from a list of channels
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathError {
+    /// No channels were given
+    Empty,
+    /// The channel is not part of the graph
+    UnknownChannel(String),
+    /// The channel does not start at the node the path has reached
+    Disconnected { node: ID, channel_id: String },
+    /// The path visits the node more than once
+    RepeatedNode(ID),
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Empty => write!(f, "a path needs at least one channel"),
+            PathError::UnknownChannel(channel_id) => write!(f, "unknown channel {channel_id}"),
+            PathError::Disconnected { node, channel_id } => {
+                write!(f, "channel {channel_id} does not start at {node}")
+            }
+            PathError::RepeatedNode(node) => write!(f, "path visits {node} more than once"),
+        }
+    }
+}
+
+impl Error for PathError {}
+
+/// Decides whether a candidate path may be used, see PathFinder::set_path_filter
+#[derive(Clone)]
+pub(crate) struct PathFilter(Arc<dyn Fn(&CandidatePath) -> bool + Send + Sync>);
+
+impl PathFilter {
+    pub(crate) fn new(f: Box<dyn Fn(&CandidatePath) -> bool + Send + Sync>) -> Self {
+        Self(Arc::from(f))
+    }
+}
+
+impl fmt::Debug for PathFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PathFilter")
+    }
+}
+
 /// Pathfinding object
 #[derive(Debug, Clone)]
 pub struct PathFinder {
@@ -29,6 +92,134 @@ pub struct PathFinder {
     pub(super) amount: usize,
     pub(super) routing_metric: RoutingMetric,
     pub(super) payment_parts: PaymentParts,
+    /// Whether dest can be reached from src in the graph the PathFinder was created with,
+    /// i.e. before edges with insufficient funds were removed
+    pub(super) topology_path_exists: bool,
+    /// Failure history per channel penalising its success probability
+    pub(crate) penalties: HashMap<String, ChannelPenalty>,
+    /// Simulation time the penalties recover against
+    pub(crate) now: Time,
+    /// Time after which half of a channel's penalty is gone
+    pub(crate) half_life: Time,
+    /// Paths found for earlier, similar payments
+    pub(crate) route_cache: RouteCache,
+    /// Which path to take among those of equal weight
+    pub(crate) tie_break: TieBreak,
+    /// How the intermediaries round their fees
+    pub(crate) fee_rounding: FeeRounding,
+    /// Weight added to channels into dest that earlier parts of the same payment used
+    pub(crate) last_hop_penalty: f32,
+    /// Channels into dest that earlier parts of the same payment used
+    pub(crate) used_last_hops: Vec<String>,
+    /// Paths with more intermediaries are discarded
+    pub(crate) max_hops: usize,
+    /// Paths with more hops than fit into the onion cannot be used, regardless of max_hops
+    pub(crate) onion_hop_limit: usize,
+    /// Time-lock delta dest requires on top of the hops' deltas
+    pub(crate) final_cltv_expiry: usize,
+    /// Random choices, shared with the simulation to record or replay them
+    pub(crate) decisions: Arc<Mutex<DecisionLog>>,
+    /// Candidate paths it rejects are not used
+    pub(crate) path_filter: Option<PathFilter>,
+    /// Historical success rate per channel, used instead of the capacity-based probability
+    pub(crate) reliability: HashMap<String, f32>,
+    /// Factor the fee of each (source, channel) is scaled by when weighing paths by their fees,
+    /// see perturb_fees
+    pub(crate) fee_factors: HashMap<(ID, String), f64>,
+    /// Nodes removed from the graph, see exclude_nodes
+    pub(crate) excluded_nodes: Vec<ID>,
+    /// The only channel of src paths may start with, see restrict_first_hop
+    pub(crate) first_hop: Option<String>,
+    /// Whether channels lacking the balance to forward the amount are left out of the search.
+    /// Unlike removing them from the graph it follows the amount between searches
+    pub(crate) skip_illiquid_edges: bool,
+    /// Number of edges the searches looked at, to compare the effort of searches
+    pub(crate) edges_touched: Cell<usize>,
+}
+
+/// A node the search reached along with the inbound fee it charges for the channel it was reached
+/// by
+type SearchState = (ID, isize);
+
+/// Cost of a path while searching for the shortest ones. Paths of equal weight are ordered by
+/// the tie break
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct PathCost {
+    weight: EdgeWeight,
+    tie_break: usize,
+}
+
+impl Add for PathCost {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            weight: self.weight + other.weight,
+            tie_break: self.tie_break.saturating_add(other.tie_break),
+        }
+    }
+}
+
+impl Zero for PathCost {
+    fn zero() -> Self {
+        Self::default()
+    }
+
+    fn is_zero(&self) -> bool {
+        self.weight.is_zero() && self.tie_break == 0
+    }
+}
+
+/// Failures of a channel which each halve its estimated success probability. The penalty
+/// decays exponentially with the PathFinder's half life
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChannelPenalty {
+    pub(crate) failures: f32,
+    pub(crate) last_failure: Time,
+}
+
+impl ChannelPenalty {
+    /// The failures still counted at the given time
+    fn failures_at(&self, now: Time, half_life: Time) -> f32 {
+        let elapsed = (now.as_secs() - self.last_failure.as_secs()).max(0.0);
+        if half_life.as_secs() <= 0.0 {
+            return 0.0;
+        }
+        self.failures * 0.5_f32.powf(elapsed / half_life.as_secs())
+    }
+}
+
+/// Scores channels by the share of their balance a payment would use up, so that routes spread
+/// the load instead of draining channels with little liquidity left
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BalancedScorer;
+
+impl BalancedScorer {
+    /// Fraction of the channel's balance forwarding the amount consumes. Above 1 if the balance
+    /// does not suffice
+    pub fn channel_weight(edge: &Edge, amount: usize) -> EdgeWeight {
+        ordered_float::OrderedFloat(amount as f32 / edge.balance.max(1) as f32)
+    }
+}
+
+/// The outcome of looking for a route
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouteResult {
+    Found(CandidatePath),
+    /// No path exists between src and dest irrespective of the balances
+    NoTopology,
+    /// Paths exist but none can carry the amount
+    InsufficientLiquidity,
+}
+
+/// The outcome of probing a route
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeResult {
+    /// The first channel along the probed path that lacks the liquidity, None if the amount
+    /// would have arrived
+    pub failing_channel: Option<String>,
+    /// The largest amount the probed path would have delivered, 0 if no path was found
+    pub max_amount: u64,
 }
 
 /// A path that we may use to route from src to dest
@@ -41,6 +232,8 @@ pub struct CandidatePath {
     pub(crate) amount: usize,
     /// The aggregated timelock
     pub(crate) time: usize,
+    /// The part of the payment that took the path, if it was split
+    pub shard_id: Option<ShardId>,
 }
 
 impl Path {
@@ -153,6 +346,59 @@ impl Path {
         is_subpath
     }
 
+    /// The same route from dest back to src, delivering the same amount. Each node sends over
+    /// its own side of the channels, so fees and time-locks are those of the reverse direction
+    /// None if a channel lacks a reverse direction or its liquidity
+    pub fn reverse(&self, graph: &Graph) -> Option<Path> {
+        if self.hops.len() < 2 {
+            return None;
+        }
+        let amount = self.hops[self.hops.len() - 1].1;
+        let nodes: Vec<ID> = self.get_involved_nodes().into_iter().rev().collect();
+        // dest's side of the last channel is already part of the path
+        let mut edges = vec![graph.get_outedges(&nodes[0]).into_iter().find(|e| {
+            e.channel_id == self.hops[self.hops.len() - 1].3 && e.destination == nodes[1]
+        })?];
+        for pair in nodes[1..].windows(2) {
+            edges.push(graph.get_edge(&pair[0], &pair[1])?);
+        }
+        let mut reversed = Path::new(self.dest.clone(), self.src.clone());
+        // fees and time-locks accumulate from the new dest backwards
+        let (mut forwarded, mut time) = (amount, 0);
+        reversed
+            .hops
+            .push_front((self.src.clone(), amount, time, self.hops[0].3.clone()));
+        if !graph.channel_can_receive_amount(&self.hops[0].3, amount) {
+            return None;
+        }
+        for (idx, (node, edge)) in nodes.iter().zip(edges.iter()).enumerate().skip(1).rev() {
+            if edge.balance <= forwarded {
+                return None;
+            }
+            // the node's inbound fee is for the channel the HTLC arrives on
+            let fee = (PathFinder::get_edge_fee(edge, forwarded).into_inner() as usize)
+                .saturating_add_signed(edges[idx - 1].inbound_fee);
+            forwarded = forwarded.checked_add(fee)?;
+            time += edge.cltv_expiry_delta;
+            reversed.hops.push_front((
+                node.clone(),
+                fee,
+                edge.cltv_expiry_delta,
+                edge.channel_id.clone(),
+            ));
+        }
+        if edges[0].balance <= forwarded {
+            return None;
+        }
+        reversed.hops.push_front((
+            self.dest.clone(),
+            forwarded,
+            time,
+            edges[0].channel_id.clone(),
+        ));
+        Some(reversed)
+    }
+
     pub(crate) fn is_equal(
         lhs: &VecDeque<(ID, usize, usize, String)>,
         rhs: &VecDeque<(ID, usize, usize, String)>,
@@ -204,7 +450,73 @@ impl CandidatePath {
             weight: f32::default(),
             amount: usize::default(),
             time: usize::default(),
+            shard_id: None,
+        }
+    }
+
+    /// Builds the path taking the given channels, in order, from src and computes its weight
+    /// (min fee), amount and time for delivering amount msat
+    /// Fails if the channels do not form a connected sequence starting at src
+    pub fn from_channels(
+        graph: &Graph,
+        src: &str,
+        channels: &[String],
+        amount: u64,
+    ) -> Result<CandidatePath, PathError> {
+        if channels.is_empty() {
+            return Err(PathError::Empty);
+        }
+        let mut nodes = vec![src.to_string()];
+        let mut path_edges: HashMap<ID, Vec<Edge>> = HashMap::new();
+        for channel_id in channels {
+            let current = nodes.last().cloned().unwrap_or_default();
+            let edge = graph
+                .get_outedges(&current)
+                .into_iter()
+                .find(|e| e.channel_id == *channel_id);
+            let edge = match edge {
+                Some(edge) => edge,
+                None if graph
+                    .edges
+                    .values()
+                    .flatten()
+                    .any(|e| e.channel_id == *channel_id) =>
+                {
+                    return Err(PathError::Disconnected {
+                        node: current,
+                        channel_id: channel_id.clone(),
+                    })
+                }
+                None => return Err(PathError::UnknownChannel(channel_id.clone())),
+            };
+            if nodes.contains(&edge.destination) {
+                return Err(PathError::RepeatedNode(edge.destination));
+            }
+            nodes.push(edge.destination.clone());
+            path_edges
+                .entry(edge.source.clone())
+                .or_default()
+                .push(edge);
         }
+        // the destination's side of the final channel is the one whose balance increases
+        let (dest, last_hop) = (&nodes[nodes.len() - 1], &nodes[nodes.len() - 2]);
+        path_edges.insert(dest.clone(), graph.get_all_src_dest_edges(dest, last_hop));
+        let mut path_finder = PathFinder::new(
+            src.to_string(),
+            dest.clone(),
+            amount as usize,
+            graph,
+            RoutingMetric::MinFee,
+            PaymentParts::Single,
+        );
+        path_finder.graph.set_edges(path_edges);
+        Ok(path_finder.candidate_path_from_nodes(nodes))
+    }
+
+    /// The channel the path arrives at dest through. None for empty paths
+    pub(crate) fn last_hop(&self) -> Option<String> {
+        let len = self.path.hops.len();
+        (len >= 2).then(|| self.path.hops[len - 2].3.clone())
     }
 
     /// Returns the fees paid. We consider the separate parts' amounts for MPP payments.
@@ -216,6 +528,78 @@ impl CandidatePath {
             0
         }
     }
+
+    /// Fees paid in parts per million of the amount transferred. 0 for empty paths
+    pub fn effective_ppm(&self) -> f64 {
+        match self.path_amount() {
+            0 => 0.0,
+            amount => self.path_fees() as f64 / amount as f64 * 1_000_000.0,
+        }
+    }
+
+    /// Returns the amount each intermediary forwards (channel_id, amount).
+    /// The sender's channel is excluded as its balance is deducted immediately.
+    pub(crate) fn forwarded_amounts(&self) -> Vec<(String, usize)> {
+        let mut forwarded = self.amount;
+        let mut amounts = vec![];
+        if self.path.hops.len() > 2 {
+            for hop in self.path.hops.range(1..self.path.hops.len() - 1) {
+                forwarded = forwarded.saturating_sub(hop.1);
+                amounts.push((hop.3.clone(), forwarded));
+            }
+        }
+        amounts
+    }
+
+    /// The amount sent over each channel of the path from src to dest, (channel_id, amount)
+    /// Every intermediary forwards what it received minus its fee, so the last entry is the
+    /// amount dest receives
+    pub fn hop_amounts(&self) -> Vec<(String, u64)> {
+        let len = self.path.hops.len();
+        if len < 2 {
+            return vec![];
+        }
+        let mut amount = self.path.hops[0].1;
+        let mut amounts = vec![(self.path.hops[0].3.clone(), amount as u64)];
+        // dest's hop is the receiving end of the last channel
+        for hop in self.path.hops.range(1..len - 1) {
+            amount = amount.saturating_sub(hop.1);
+            amounts.push((hop.3.clone(), amount as u64));
+        }
+        amounts
+    }
+
+    /// The amount transferred plus the fees of all intermediaries. None if the sum overflows
+    pub(crate) fn checked_total_amount(&self) -> Option<usize> {
+        if self.path.hops.len() < 2 {
+            return Some(self.path_amount());
+        }
+        self.path
+            .hops
+            .range(1..self.path.hops.len() - 1)
+            .try_fold(self.path_amount(), |total, hop| total.checked_add(hop.1))
+    }
+
+    /// The balance changes delivering the path makes as (node, channel_id, amount): the source's
+    /// channel is debited the amount plus fees, each intermediary's channel gains its fee and
+    /// dest's channel what arrives
+    pub(crate) fn transfers(&self) -> Vec<(ID, String, usize)> {
+        let len = self.path.hops.len();
+        if len < 2 {
+            return vec![];
+        }
+        let source = &self.path.hops[0];
+        let mut remaining = self.amount;
+        let mut transfers = vec![(source.0.clone(), source.3.clone(), remaining)];
+        for hop in self.path.hops.range(1..len - 1) {
+            remaining = remaining.saturating_sub(hop.1);
+            transfers.push((hop.0.clone(), hop.3.clone(), hop.1));
+        }
+        let dest = &self.path.hops[len - 1];
+        transfers.push((dest.0.clone(), dest.3.clone(), remaining));
+        transfers
+    }
+
     /// Returns the amount that was trasferred by this path.
     pub(crate) fn path_amount(&self) -> usize {
         if !self.path.hops.is_empty() {
@@ -226,6 +610,20 @@ impl CandidatePath {
     }
 }
 
+impl RouteResult {
+    /// Returns the path if one was found
+    pub fn path(self) -> Option<CandidatePath> {
+        match self {
+            RouteResult::Found(candidate_path) => Some(candidate_path),
+            _ => None,
+        }
+    }
+
+    pub fn is_found(&self) -> bool {
+        matches!(self, RouteResult::Found(_))
+    }
+}
+
 impl PathFinder {
     /// New PathFinder for payment from src to dest transferring amount of msats
     pub fn new(
@@ -236,40 +634,387 @@ impl PathFinder {
         routing_metric: RoutingMetric,
         payment_parts: PaymentParts,
     ) -> Self {
+        let mut graph = Box::new(graph.clone());
+        // private channels are only known from route hints
+        graph.hide_private_channels(&[]);
+        let topology_path_exists = graph.is_reachable(&src, &dest);
         Self {
-            graph: Box::new(graph.clone()),
+            graph,
             src,
             dest,
             amount,
             routing_metric,
             payment_parts,
+            topology_path_exists,
+            penalties: HashMap::default(),
+            now: Time::default(),
+            half_life: Time::from_secs(crate::PENALTY_HALF_LIFE_IN_SECS),
+            route_cache: RouteCache::default(),
+            tie_break: TieBreak::default(),
+            fee_rounding: FeeRounding::default(),
+            last_hop_penalty: crate::LAST_HOP_PENALTY,
+            used_last_hops: vec![],
+            max_hops: crate::MAX_HOPS,
+            onion_hop_limit: crate::ONION_HOP_LIMIT,
+            final_cltv_expiry: 0,
+            decisions: Default::default(),
+            path_filter: None,
+            reliability: HashMap::default(),
+            fee_factors: HashMap::default(),
+            excluded_nodes: vec![],
+            first_hop: None,
+            skip_illiquid_edges: false,
+            edges_touched: Cell::default(),
+        }
+    }
+
+    /// Makes the private channels the invoice hints at usable. They charge the hinted fee
+    /// As dest only receives over them, those dropped for dest's side lacking the amount are
+    /// taken back from it. Channels the network's graph does not know are added as the hint
+    /// describes them, assuming they can carry the amount
+    pub(crate) fn apply_route_hints(&mut self, hints: &[RouteHint], network: &Graph) {
+        for hint in hints {
+            let (hinted, back) = match network
+                .get_outedges(&hint.node)
+                .into_iter()
+                .find(|e| e.channel_id == hint.channel_id && e.destination == self.dest)
+            {
+                Some(hinted) if network.get_available_balance(&hinted) >= self.amount => {
+                    let back = network.counterpart(&hinted);
+                    (hinted, back)
+                }
+                Some(_) => continue,
+                None => self.hinted_edges(hint),
+            };
+            for edge in std::iter::once(hinted).chain(back) {
+                let node_edges = self.graph.edges.entry(edge.source.clone()).or_default();
+                if !node_edges.iter().any(|e| e.channel_id == edge.channel_id) {
+                    node_edges.push(edge);
+                }
+            }
+        }
+        // the destination's direction of the hinted channels is needed to receive
+        let mut hinted: Vec<String> = hints.iter().map(|h| h.channel_id.clone()).collect();
+        for hint in hints {
+            hinted.extend(
+                self.graph
+                    .get_all_src_dest_edges(&self.dest, &hint.node)
+                    .into_iter()
+                    .map(|e| e.channel_id),
+            );
+        }
+        self.graph.hide_private_channels(&hinted);
+        for hint in hints {
+            if let Some(edges) = self.graph.edges.get_mut(&hint.node) {
+                for edge in edges
+                    .iter_mut()
+                    .filter(|e| e.channel_id == hint.channel_id && e.destination == self.dest)
+                {
+                    edge.fee_base_msat = hint.fee_msat;
+                    edge.fee_proportional_millionths = 0;
+                }
+            }
+        }
+    }
+
+    /// The channel of a hint the network's graph does not know, from the hinted node to dest and
+    /// back. Nothing but the fee is known about it, so it is taken to hold the amount and to
+    /// forward it for sure
+    fn hinted_edges(&mut self, hint: &RouteHint) -> (Edge, Option<Edge>) {
+        let hinted = Edge {
+            channel_id: hint.channel_id.clone(),
+            source: hint.node.clone(),
+            destination: self.dest.clone(),
+            fee_base_msat: hint.fee_msat,
+            balance: self.amount,
+            liquidity: self.amount,
+            capacity: self.amount,
+            ..Default::default()
+        };
+        self.reliability.insert(hint.channel_id.clone(), 1.0);
+        let back = Edge {
+            channel_id: Graph::counterpart_id(&hinted)
+                .unwrap_or_else(|| format!("{}-{}-{}", self.dest, hint.node, hint.channel_id)),
+            source: self.dest.clone(),
+            destination: hint.node.clone(),
+            balance: 0,
+            liquidity: 0,
+            ..hinted.clone()
+        };
+        (hinted, Some(back))
+    }
+
+    /// Called on every candidate path before it is used. Paths it returns false for are
+    /// excluded and the search continues with the next shortest ones
+    pub fn set_path_filter(&mut self, f: Box<dyn Fn(&CandidatePath) -> bool + Send + Sync>) {
+        self.path_filter = Some(PathFilter::new(f));
+    }
+
+    /// Whether the path filter, if any, lets the path be used
+    pub(crate) fn accepts(&self, candidate_path: &CandidatePath) -> bool {
+        self.path_filter
+            .as_ref()
+            .is_none_or(|filter| (filter.0)(candidate_path))
+    }
+
+    /// Notes that the channel failed at the current time
+    pub(crate) fn record_failure(&mut self, channel_id: &str) {
+        let (now, half_life) = (self.now, self.half_life);
+        let penalty = self.penalties.entry(channel_id.to_string()).or_default();
+        penalty.failures = penalty.failures_at(now, half_life) + 1.0;
+        penalty.last_failure = now;
+        self.route_cache.invalidate_channel(channel_id);
+    }
+
+    /// Halved for each recent failure of the channel
+    fn penalty_factor(&self, channel_id: &str) -> f32 {
+        match self.penalties.get(channel_id) {
+            Some(penalty) => 0.5_f32.powf(penalty.failures_at(self.now, self.half_life)),
+            None => 1.0,
+        }
+    }
+
+    /// Estimated probability that the edge can forward the amount, including the penalty of
+    /// previous failures. Channels with a loaded success rate use it regardless of the amount
+    pub(crate) fn get_edge_success_probability(&self, edge: &Edge, amount: usize) -> f32 {
+        let success_prob = match self.reliability.get(&edge.channel_id) {
+            Some(success_rate) => *success_rate,
+            None => 1.0 - Self::get_edge_failure_probabilty(edge, amount).into_inner(),
+        };
+        success_prob * self.penalty_factor(&edge.channel_id)
+    }
+
+    /// Reads historical success rates from a CSV file of channel_id,success_rate rows with an
+    /// optional header. The listed channels' probability is their success rate from then on,
+    /// the others keep the one derived from their capacity
+    pub fn load_reliability(&mut self, path: &std::path::Path) -> Result<(), Box<dyn Error>> {
+        self.reliability.extend(Self::read_reliability(path)?);
+        // the cached paths were chosen with the old probabilities
+        self.route_cache = RouteCache::default();
+        Ok(())
+    }
+
+    /// Success rate per channel read from a CSV file of channel_id,success_rate rows with an
+    /// optional header
+    pub(crate) fn read_reliability(
+        path: &std::path::Path,
+    ) -> Result<HashMap<String, f32>, Box<dyn Error>> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut reliability = HashMap::new();
+        for (idx, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() || (idx == 0 && line.trim_start().starts_with("channel_id")) {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+            if fields.len() != 2 {
+                return Err(format!(
+                    "invalid reliability on line {}: expected 2 columns, got {}",
+                    idx + 1,
+                    fields.len()
+                )
+                .into());
+            }
+            let success_rate = fields[1].parse::<f32>()?;
+            if !(0.0..=1.0).contains(&success_rate) {
+                return Err(format!(
+                    "invalid reliability on line {}: success rate {} is not within [0, 1]",
+                    idx + 1,
+                    success_rate
+                )
+                .into());
+            }
+            reliability.insert(fields[0].to_string(), success_rate);
+        }
+        debug!(
+            "Loaded the reliability of {} channels from {:?}.",
+            reliability.len(),
+            path
+        );
+        Ok(reliability)
+    }
+
+    /// Removes src's channels other than the given one so that every path starts with it
+    pub(crate) fn restrict_first_hop(&mut self, channel_id: &str) {
+        if let Some(edges) = self.graph.edges.get_mut(&self.src) {
+            edges.retain(|e| e.channel_id == channel_id);
+        }
+        self.first_hop = Some(channel_id.to_string());
+    }
+
+    /// Removes the nodes and their channels so that no path routes through them. src and dest
+    /// are kept
+    pub(crate) fn exclude_nodes(&mut self, nodes: &HashSet<ID>) {
+        for node in nodes {
+            if *node != self.src && *node != self.dest {
+                self.graph.remove_node(node);
+                self.excluded_nodes.push(node.clone());
+            }
+        }
+        self.excluded_nodes.sort();
+        self.excluded_nodes.dedup();
+    }
+
+    /// Scales the fee of every channel by a factor drawn from a gaussian around 1 with the
+    /// given standard deviation when weighing paths, as if the fees the sender knows of were
+    /// outdated. Factors below 0 make the channel look free. The fees charged stay the real ones
+    pub(crate) fn perturb_fees(&mut self, sigma: f64) {
+        if sigma <= 0.0 {
+            return;
         }
+        // the channels are visited in a fixed order so that the seed decides the factors
+        let mut edges: Vec<(ID, String)> = self
+            .graph
+            .edges
+            .values()
+            .flatten()
+            .map(|e| (e.source.clone(), e.channel_id.clone()))
+            .collect();
+        edges.sort();
+        let mut decisions = self.decisions.lock().unwrap();
+        for edge in edges {
+            let factor = (1.0 + sigma * decisions.fee_noise()).max(0.0);
+            self.fee_factors.insert(edge, factor);
+        }
+    }
+
+    /// Fingerprint of the settings that change which path the search finds, other than the
+    /// endpoints and the amount. Paths are only taken from the cache for the same one
+    fn cache_context(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        let mut fee_factors: Vec<(&(ID, String), u64)> = self
+            .fee_factors
+            .iter()
+            .map(|(edge, factor)| (edge, factor.to_bits()))
+            .collect();
+        fee_factors.sort();
+        fee_factors.hash(&mut hasher);
+        let mut penalty_factors: Vec<(&String, u32)> = self
+            .penalties
+            .keys()
+            .map(|channel_id| (channel_id, self.penalty_factor(channel_id).to_bits()))
+            .filter(|(_, factor)| *factor != 1.0_f32.to_bits())
+            .collect();
+        penalty_factors.sort();
+        penalty_factors.hash(&mut hasher);
+        if self.last_hop_penalty > 0.0 && !self.used_last_hops.is_empty() {
+            let mut used_last_hops: Vec<&String> = self.used_last_hops.iter().collect();
+            used_last_hops.sort();
+            used_last_hops.dedup();
+            self.last_hop_penalty.to_bits().hash(&mut hasher);
+            used_last_hops.hash(&mut hasher);
+        }
+        self.excluded_nodes.hash(&mut hasher);
+        self.first_hop.hash(&mut hasher);
+        hasher.finish()
     }
 
-    pub(crate) fn find_path(&mut self) -> Option<CandidatePath> {
-        match self.payment_parts {
+    pub(crate) fn find_path(&mut self) -> RouteResult {
+        let context = self.cache_context();
+        let cached = self
+            .route_cache
+            .get(&self.graph, &self.src, &self.dest, self.amount, context);
+        if let Some(mut candidate_path) = cached.filter(|p| self.accepts(p)) {
+            trace!("Using cached path between {} and {}.", self.src, self.dest);
+            // the cached path may have been found for a different amount of the same bucket
+            self.get_aggregated_path_cost(&mut candidate_path, false);
+            return RouteResult::Found(candidate_path);
+        }
+        let candidate_path = match self.payment_parts {
             PaymentParts::Single => self.find_path_single_payment(),
-            PaymentParts::Split => self.find_path_mpp_payment(),
+            PaymentParts::Split | PaymentParts::FeeOptimalSplit | PaymentParts::Amp => {
+                self.find_path_mpp_payment()
+            }
+        };
+        if let Some(candidate_path) = &candidate_path {
+            self.route_cache.insert(
+                &self.graph,
+                &self.src,
+                &self.dest,
+                self.amount,
+                context,
+                candidate_path,
+            );
+        }
+        match candidate_path {
+            Some(candidate_path) => RouteResult::Found(candidate_path),
+            None if self.topology_path_exists => RouteResult::InsufficientLiquidity,
+            None => RouteResult::NoTopology,
+        }
+    }
+
+    pub(super) fn get_edge_weight(&self, edge: &Edge, amount: usize) -> EdgeWeight {
+        match self.routing_metric {
+            RoutingMetric::MinFee => self.get_perceived_fee(0, edge, amount),
+            RoutingMetric::MaxProb => {
+                ordered_float::OrderedFloat(1.0 - self.get_edge_success_probability(edge, amount))
+            }
+            RoutingMetric::MinHops => ordered_float::OrderedFloat(1.0),
+            RoutingMetric::MinTime => ordered_float::OrderedFloat(edge.cltv_expiry_delta as f32),
+            RoutingMetric::Balanced => BalancedScorer::channel_weight(edge, amount),
         }
     }
 
-    pub(super) fn get_edge_weight(edge: &Edge, amount: usize, metric: RoutingMetric) -> EdgeWeight {
-        match metric {
-            RoutingMetric::MinFee => Self::get_edge_fee(edge, amount),
-            RoutingMetric::MaxProb => Self::get_edge_failure_probabilty(edge, amount),
+    /// Extra weight of a channel into dest that earlier parts of the payment already arrived via.
+    /// Spreads the parts across the dest's inbound channels
+    fn reused_last_hop_weight(&self, edge: &Edge) -> EdgeWeight {
+        if edge.destination == self.dest && self.used_last_hops.contains(&edge.channel_id) {
+            ordered_float::OrderedFloat(self.last_hop_penalty)
+        } else {
+            ordered_float::OrderedFloat(0.0)
         }
     }
 
     /// Computes the weight of an edge as done in [LND](https://github.com/lightningnetwork/lnd/blob/290b78e700021e238f7e6bdce6acc80de8d0a64f/routing/pathfind.go#L263)
     /// Used when searching for the shortest path between two nodes.
-    fn get_edge_fee(edge: &Edge, amount: usize) -> EdgeWeight {
+    /// Only the outbound fee, see get_hop_fee for the inbound fee
+    /// Saturates instead of overflowing for huge amounts
+    pub(crate) fn get_edge_fee(edge: &Edge, amount: usize) -> EdgeWeight {
         let risk_factor = 15;
         let millionths = 1000000;
         let billionths = 1000000000;
         let base_fee = edge.fee_base_msat;
-        let prop_fee = amount * edge.fee_proportional_millionths / millionths;
-        let time_lock_penalty = amount * edge.cltv_expiry_delta * risk_factor / billionths;
-        ordered_float::OrderedFloat((base_fee + prop_fee + time_lock_penalty) as f32)
+        let prop_fee = amount.saturating_mul(edge.fee_proportional_millionths) / millionths;
+        let time_lock_penalty = amount
+            .saturating_mul(edge.cltv_expiry_delta)
+            .saturating_mul(risk_factor)
+            / billionths;
+        let fee = base_fee
+            .saturating_add(prop_fee)
+            .saturating_add(time_lock_penalty);
+        ordered_float::OrderedFloat(fee as f32)
+    }
+
+    /// The fee the edge's source charges for forwarding the amount, rounded as the simulation
+    /// sets. It adds its inbound fee for the channel the HTLC arrived on to the edge's outbound
+    /// fee and, like in LND, never charges less than nothing
+    pub(crate) fn get_hop_fee(&self, inbound_fee: isize, edge: &Edge, amount: usize) -> EdgeWeight {
+        let fee = Self::get_edge_fee(edge, amount);
+        if inbound_fee == 0 && self.fee_rounding == FeeRounding::None {
+            return fee;
+        }
+        let fee = (fee.into_inner() as usize).saturating_add_signed(inbound_fee);
+        ordered_float::OrderedFloat(self.fee_rounding.apply(fee) as f32)
+    }
+
+    /// Weight of the edge for a search that reached its source via a channel the source charges
+    /// the inbound fee for. Only fees depend on the arriving channel
+    fn get_hop_weight(&self, inbound_fee: isize, edge: &Edge, amount: usize) -> EdgeWeight {
+        match self.routing_metric {
+            RoutingMetric::MinFee => self.get_perceived_fee(inbound_fee, edge, amount),
+            _ => self.get_edge_weight(edge, amount),
+        }
+    }
+
+    /// The hop fee as the sender believes it to be, scaled by the channel's fee factor
+    fn get_perceived_fee(&self, inbound_fee: isize, edge: &Edge, amount: usize) -> EdgeWeight {
+        let fee = self.get_hop_fee(inbound_fee, edge, amount);
+        match self
+            .fee_factors
+            .get(&(edge.source.clone(), edge.channel_id.clone()))
+        {
+            Some(factor) => ordered_float::OrderedFloat((fee.into_inner() as f64 * factor) as f32),
+            None => fee,
+        }
     }
 
     /// Returns the edge failure probabilty (amt/ cap) of given amount so that the shortest path
@@ -298,12 +1043,12 @@ impl PathFinder {
             candidate_path
         );
         let mut accumulated_amount = self.amount; //amount + due fees
-        let mut accumulated_weight = if self.routing_metric == RoutingMetric::MinFee {
-            0.0
-        } else {
+        let mut accumulated_weight = if self.routing_metric == RoutingMetric::MaxProb {
             1.0
+        } else {
+            0.0
         };
-        let mut accumulated_time = 0; // full timelock delta
+        let mut accumulated_time = self.final_cltv_expiry; // full timelock delta
         let candidate_path_hops: VecDeque<ID> = candidate_path
             .path
             .get_involved_nodes()
@@ -323,21 +1068,19 @@ impl PathFinder {
                     // src charges a fee
                     match self.routing_metric {
                         RoutingMetric::MaxProb => {
-                            accumulated_weight *= 1.0
-                                - Self::get_edge_failure_probabilty(
-                                    &cheapest_edge,
-                                    accumulated_amount,
-                                )
-                                .into_inner()
+                            accumulated_weight *= self
+                                .get_edge_success_probability(&cheapest_edge, accumulated_amount)
                         }
-                        RoutingMetric::MinFee => {
-                            accumulated_weight +=
-                                Self::get_edge_fee(&cheapest_edge, accumulated_amount).into_inner()
+                        _ => {
+                            accumulated_weight += self
+                                .get_edge_weight(&cheapest_edge, accumulated_amount)
+                                .into_inner()
                         }
                     };
-                    let edge_fee = Self::get_edge_fee(&cheapest_edge, accumulated_amount)
+                    let edge_fee = self
+                        .get_hop_fee(0, &cheapest_edge, accumulated_amount)
                         .into_inner() as usize;
-                    accumulated_amount += edge_fee;
+                    accumulated_amount = accumulated_amount.saturating_add(edge_fee);
                     let edge_timelock = cheapest_edge.cltv_expiry_delta;
                     accumulated_time += edge_timelock;
                 }
@@ -349,10 +1092,15 @@ impl PathFinder {
                 );
             } else if node_id.clone() == self.dest {
                 let (dest, src) = (node_id, candidate_path_hops[idx + 1].clone());
-                let cheapest_edge = match self.get_cheapest_edge(dest, &src) {
-                    None => panic!("Edge in path does not exist! {src} -> {dest}"),
-                    Some(e) => e,
-                };
+                // dest receives on its side of the channel the previous hop forwards over
+                let receiving_edge = self
+                    .get_cheapest_edge(&src, dest)
+                    .and_then(|e| self.graph.counterpart(&e));
+                let cheapest_edge =
+                    match receiving_edge.or_else(|| self.get_cheapest_edge(dest, &src)) {
+                        None => panic!("Edge in path does not exist! {src} -> {dest}"),
+                        Some(e) => e,
+                    };
                 candidate_path.path.update_hop(
                     cheapest_edge.source,
                     accumulated_amount,
@@ -367,20 +1115,26 @@ impl PathFinder {
                     None => panic!("Edge in path does not exist! {src} -> {dest}"),
                     Some(e) => e,
                 };
+                // the intermediary's inbound fee is for the channel the HTLC arrives on
+                let prev = candidate_path_hops[idx + 1].clone();
+                let inbound_fee = self
+                    .get_cheapest_edge(&prev, src)
+                    .map_or(0, |e| e.inbound_fee);
                 match self.routing_metric {
                     RoutingMetric::MaxProb => {
-                        accumulated_weight *= 1.0
-                            - Self::get_edge_failure_probabilty(&cheapest_edge, accumulated_amount)
-                                .into_inner()
+                        accumulated_weight *=
+                            self.get_edge_success_probability(&cheapest_edge, accumulated_amount)
                     }
-                    RoutingMetric::MinFee => {
-                        accumulated_weight +=
-                            Self::get_edge_fee(&cheapest_edge, accumulated_amount).into_inner()
+                    _ => {
+                        accumulated_weight += self
+                            .get_hop_weight(inbound_fee, &cheapest_edge, accumulated_amount)
+                            .into_inner()
                     }
                 };
-                let edge_fee =
-                    Self::get_edge_fee(&cheapest_edge, accumulated_amount).into_inner() as usize;
-                accumulated_amount += edge_fee;
+                let edge_fee = self
+                    .get_hop_fee(inbound_fee, &cheapest_edge, accumulated_amount)
+                    .into_inner() as usize;
+                accumulated_amount = accumulated_amount.saturating_add(edge_fee);
                 let edge_timelock = cheapest_edge.cltv_expiry_delta;
                 accumulated_time += edge_timelock;
                 candidate_path.path.update_hop(
@@ -396,6 +1150,28 @@ impl PathFinder {
         candidate_path.time = accumulated_time;
     }
 
+    /// Lowers the amount the path delivers so that the sender pays exactly the original amount
+    /// including fees. Used when the fees come out of the payment's amount
+    /// The fees are those due for the amount left after deducting the fees for the full amount.
+    /// Returns false if the fees consume the entire amount
+    pub(crate) fn deduct_fees_from_amount(&mut self, candidate_path: &mut CandidatePath) -> bool {
+        let total = self.amount;
+        let fees = candidate_path.path_fees();
+        if fees >= total {
+            return false;
+        }
+        self.amount = total - fees;
+        self.get_aggregated_path_cost(candidate_path, false);
+        self.amount = total;
+        // proportional fees are lower for the reduced amount so dest receives the difference
+        let remainder = total.saturating_sub(candidate_path.amount);
+        let dest_idx = candidate_path.path.hops.len() - 1;
+        candidate_path.path.hops[0].1 += remainder;
+        candidate_path.path.hops[dest_idx].1 += remainder;
+        candidate_path.amount += remainder;
+        true
+    }
+
     /// Computes the shortest path beween source and dest using Dijkstra's algorithm
     pub fn shortest_path_from(&self, node: &ID) -> Option<(Vec<ID>, EdgeWeight)> {
         trace!(
@@ -404,8 +1180,127 @@ impl PathFinder {
             self.dest,
             self.routing_metric
         );
-        let successors = |node: &ID| -> Vec<(ID, EdgeWeight)> { self.get_successors(node) };
-        pathfinding::prelude::dijkstra(node, successors, |n| *n == self.dest)
+        let successors =
+            |state: &SearchState| -> Vec<(SearchState, PathCost)> { self.get_successors(state) };
+        let shortest_path =
+            pathfinding::prelude::dijkstra(&(node.clone(), 0), successors, |s| s.0 == self.dest)
+                .map(|(states, cost)| Self::nodes_of(states, cost));
+        match shortest_path {
+            Some((path, weight)) if Self::visits_once(&path) => Some((path, weight)),
+            // a lower inbound fee can make passing a node a second time look cheaper
+            Some(_) => self
+                .k_shortest_paths_from(node, crate::MAX_FILTERED_PATHS)
+                .into_iter()
+                .next(),
+            None => None,
+        }
+    }
+
+    fn nodes_of(states: Vec<SearchState>, cost: PathCost) -> (Vec<ID>, EdgeWeight) {
+        (states.into_iter().map(|s| s.0).collect(), cost.weight)
+    }
+
+    fn visits_once(path: &[ID]) -> bool {
+        let mut visited = HashSet::new();
+        path.iter().all(|node| visited.insert(node))
+    }
+
+    /// Computes the shortest paths from every node to dest by searching backwards from dest and
+    /// caches them for the PathFinder's amount. Searches for the amount to dest, from any src,
+    /// take their path from the tree until a balance changes
+    /// Penalties are those at the time of the call. Not used when ties are broken randomly
+    pub fn precompute_sptree(&mut self, dest: &str) {
+        let dest = dest.to_string();
+        let mut in_edges: HashMap<&ID, Vec<&Edge>> = HashMap::new();
+        for e in self.graph.edges.values().flatten() {
+            in_edges.entry(&e.destination).or_default().push(e);
+        }
+        let mut costs = HashMap::from([(dest.clone(), PathCost::zero())]);
+        let mut next_hops = HashMap::new();
+        let mut queue = BinaryHeap::from([Reverse((PathCost::zero(), dest.clone()))]);
+        while let Some(Reverse((cost, node))) = queue.pop() {
+            if costs.get(&node).is_some_and(|c| *c < cost) {
+                continue;
+            }
+            for e in in_edges.get(&node).into_iter().flatten() {
+                let tie_break = match self.tie_break {
+                    TieBreak::LowerLatency => e.cltv_expiry_delta,
+                    TieBreak::Random => 0,
+                };
+                let total = PathCost {
+                    weight: self.get_edge_weight(e, self.amount),
+                    tie_break,
+                } + cost;
+                if costs.get(&e.source).is_none_or(|c| total < *c) {
+                    costs.insert(e.source.clone(), total);
+                    next_hops.insert(e.source.clone(), (node.clone(), total));
+                    queue.push(Reverse((total, e.source.clone())));
+                }
+            }
+        }
+        trace!(
+            "Precomputed shortest paths of {} nodes to {}.",
+            next_hops.len(),
+            dest
+        );
+        let tree = ShortestPathTree::new(&self.graph, self.amount, next_hops);
+        self.route_cache.trees.insert(dest, tree);
+    }
+
+    /// Shortest path from src to dest taken from a precomputed tree. None if there is no valid
+    /// tree for dest or it does not lead from src to dest without passing src again
+    pub(crate) fn shortest_path_from_tree(&self) -> Option<(Vec<ID>, EdgeWeight)> {
+        // the tree knows neither the random ties, the perturbed fees, the inbound fees nor the
+        // parts already arrived at dest
+        if self.tie_break == TieBreak::Random
+            || !self.fee_factors.is_empty()
+            || (self.routing_metric == RoutingMetric::MinFee
+                && self
+                    .graph
+                    .edges
+                    .values()
+                    .flatten()
+                    .any(|e| e.inbound_fee != 0))
+            || (self.last_hop_penalty > 0.0 && !self.used_last_hops.is_empty())
+        {
+            return None;
+        }
+        let tree = self.route_cache.trees.get(&self.dest)?;
+        if !tree.is_valid(&self.graph, self.amount) {
+            return None;
+        }
+        // like in the search, the src's channels cost nothing but a hop for MaxProb
+        let first_hop = PathCost {
+            weight: if self.routing_metric != RoutingMetric::MaxProb {
+                ordered_float::OrderedFloat(0.0)
+            } else {
+                ordered_float::OrderedFloat(1.0)
+            },
+            tie_break: 0,
+        };
+        let (path, cost) = self
+            .graph
+            .get_edges_for_node(&self.src)?
+            .iter()
+            .filter_map(|e| {
+                let rest = match tree.next_hops.get(&e.destination) {
+                    Some((_, cost)) => *cost,
+                    None if e.destination == self.dest => PathCost::zero(),
+                    None => return None,
+                };
+                let path = tree.path_from(&e.destination, &self.dest)?;
+                (!path.contains(&self.src)).then_some((path, first_hop + rest))
+            })
+            .min_by_key(|(_, cost)| *cost)?;
+        trace!(
+            "Using precomputed path between {} and {}.",
+            self.src,
+            self.dest
+        );
+        Some((
+            std::iter::once(self.src.clone()).chain(path).collect(),
+            cost.weight,
+        ))
     }
 
     /// Computes the k shortest path beween source and dest using Dijkstra's algorithm
@@ -417,32 +1312,137 @@ impl PathFinder {
             self.dest,
             self.routing_metric
         );
-        let successors = |node: &ID| -> Vec<(ID, EdgeWeight)> { self.get_successors(node) };
-        pathfinding::prelude::yen(node, successors, |n| *n == self.dest, k)
+        let successors =
+            |state: &SearchState| -> Vec<(SearchState, PathCost)> { self.get_successors(state) };
+        pathfinding::prelude::yen(&(node.clone(), 0), successors, |s| s.0 == self.dest, k)
+            .into_iter()
+            .map(|(states, cost)| Self::nodes_of(states, cost))
+            .filter(|(path, _)| Self::visits_once(path))
+            .collect()
     }
 
-    fn get_successors(&self, node: &ID) -> Vec<(ID, EdgeWeight)> {
-        let succs = match self.graph.get_edges_for_node(node) {
-            Some(edges) => edges
+    /// Enumerates every loopless path from src to dest with at most max_len channels
+    /// Weights are ignored and parallel channels yield a single path
+    pub fn all_simple_paths(&self, src: &str, dest: &str, max_len: usize) -> Vec<Path> {
+        let mut paths = vec![];
+        let mut current = vec![src.to_string()];
+        let mut visited = HashSet::from([src.to_string()]);
+        self.extend_simple_paths(&mut current, &mut visited, dest, max_len, &mut paths);
+        trace!(
+            "Found {} simple paths between {} and {}.",
+            paths.len(),
+            src,
+            dest
+        );
+        paths
+    }
+
+    /// Depth first search extending the current path by each unvisited successor
+    fn extend_simple_paths(
+        &self,
+        current: &mut Vec<ID>,
+        visited: &mut HashSet<ID>,
+        dest: &str,
+        max_len: usize,
+        paths: &mut Vec<Path>,
+    ) {
+        let node = current[current.len() - 1].clone();
+        if node == dest {
+            let mut path = Path::new(current[0].clone(), node);
+            path.hops = current
                 .iter()
-                .map(|e| {
+                .map(|h| {
                     (
-                        e.destination.clone(),
-                        if e.source != self.src {
-                            Self::get_edge_weight(e, self.amount, self.routing_metric)
-                        } else if self.routing_metric == RoutingMetric::MinFee {
+                        h.clone(),
+                        usize::default(),
+                        usize::default(),
+                        String::default(),
+                    )
+                })
+                .collect();
+            paths.push(path);
+            return;
+        }
+        if current.len() > max_len {
+            return;
+        }
+        let mut successors: Vec<ID> = self
+            .graph
+            .get_outedges(&node)
+            .into_iter()
+            .map(|e| e.destination)
+            .collect();
+        successors.sort();
+        successors.dedup();
+        for next in successors {
+            if visited.insert(next.clone()) {
+                current.push(next.clone());
+                self.extend_simple_paths(current, visited, dest, max_len, paths);
+                current.pop();
+                visited.remove(&next);
+            }
+        }
+    }
+
+    fn get_successors(&self, (node, inbound_fee): &SearchState) -> Vec<(SearchState, PathCost)> {
+        let succs = match self.graph.get_edges_for_node(node) {
+            Some(edges) => {
+                self.edges_touched
+                    .set(self.edges_touched.get() + edges.len());
+                edges
+                    .iter()
+                    .filter(|e| self.is_searchable(e))
+                    .map(|e| {
+                        let weight = if e.source != self.src {
+                            self.get_hop_weight(*inbound_fee, e, self.amount)
+                        } else if self.routing_metric != RoutingMetric::MaxProb {
                             ordered_float::OrderedFloat(0.0)
                         } else {
                             ordered_float::OrderedFloat(1.0)
-                        },
-                    )
-                })
-                .collect(),
+                        } + self.reused_last_hop_weight(e);
+                        let tie_break = match self.tie_break {
+                            // the src's timelock is not part of the path's time
+                            TieBreak::LowerLatency if e.source != self.src => e.cltv_expiry_delta,
+                            TieBreak::LowerLatency => 0,
+                            TieBreak::Random => self.decisions.lock().unwrap().tie_break(),
+                        };
+                        // only fees depend on the channel a node was reached by, dest charges none
+                        let next_inbound_fee = if self.routing_metric == RoutingMetric::MinFee
+                            && e.destination != self.dest
+                        {
+                            e.inbound_fee
+                        } else {
+                            0
+                        };
+                        (
+                            (e.destination.clone(), next_inbound_fee),
+                            PathCost { weight, tie_break },
+                        )
+                    })
+                    .collect()
+            }
             None => Vec::default(),
         };
         succs
     }
 
+    /// Number of edges the searches so far looked at
+    pub fn edges_touched(&self) -> usize {
+        self.edges_touched.get()
+    }
+
+    /// Leaves out the channels that cannot forward the amount before searching, see
+    /// remove_inadequate_edges
+    pub fn prefilter_edges(&mut self) {
+        let edges = Self::remove_inadequate_edges(&self.graph, self.amount);
+        self.graph.set_edges(edges);
+    }
+
+    /// Whether the search may route the amount over the edge, see skip_illiquid_edges
+    fn is_searchable(&self, edge: &Edge) -> bool {
+        !self.skip_illiquid_edges || self.graph.get_available_balance(edge) >= self.amount
+    }
+
     /// Returns the "cheapest" edge between src and dist bearing the routing me in mind
     /// Used after finding the shortest paths and are therefore interested in routing along the
     /// edge
@@ -451,8 +1451,12 @@ impl PathFinder {
         let from_to_outedges = self.graph.get_all_src_dest_edges(from, to);
         let mut cheapest_edge = None;
         let mut min_weight = ordered_float::OrderedFloat(f32::MAX);
-        for edge in from_to_outedges.into_iter() {
-            let edge_weight = Self::get_edge_weight(&edge, self.amount, self.routing_metric);
+        for edge in from_to_outedges
+            .into_iter()
+            .filter(|e| self.is_searchable(e))
+        {
+            let edge_weight =
+                self.get_edge_weight(&edge, self.amount) + self.reused_last_hop_weight(&edge);
             if edge_weight < min_weight {
                 min_weight = edge_weight;
                 cheapest_edge = Some(edge);
@@ -462,6 +1466,11 @@ impl PathFinder {
     }
 
     /// Remove edges that do not meet the minimum criteria (cap < amount) from the graph
+    /// Amounts reserved by shards still in flight are not available
+    /// Every hop forwards at least the amount dest receives, those closer to src also the fees
+    /// of the hops after them. Not knowing the route, the amount is a safe lower bound, so the
+    /// search finds the same paths on the reduced graph while looking at fewer edges
+    /// Parallel edges are removed by channel id so that funded ones remain as routing options
     pub fn remove_inadequate_edges(graph: &Graph, amount: usize) -> HashMap<String, Vec<Edge>> {
         debug!("Removing edges with insufficient funds.");
         let mut copy = graph.clone();
@@ -469,9 +1478,18 @@ impl PathFinder {
         for edge in graph.edges.iter() {
             // iter each node's edges
             for e in edge.1 {
-                if e.balance < amount {
+                if graph.get_available_balance(e) < amount {
                     ctr += 1;
-                    copy.remove_edge(&e.source, &e.destination);
+                    if let Some(src_edges) = copy.edges.get_mut(&e.source) {
+                        src_edges.retain(|out| out.channel_id != e.channel_id);
+                    }
+                    // without a parallel edge left the channel is removed in both directions
+                    if copy
+                        .get_all_src_dest_edges(&e.source, &e.destination)
+                        .is_empty()
+                    {
+                        copy.remove_edge(&e.source, &e.destination);
+                    }
                 }
             }
         }
@@ -484,7 +1502,7 @@ impl PathFinder {
 mod tests {
 
     use super::*;
-    use crate::{core_types::graph::Graph, PaymentParts, RoutingMetric};
+    use crate::{core_types::graph::Graph, payment::Payment, Invoice, PaymentParts, RoutingMetric};
     use approx::*;
     use std::collections::VecDeque;
 
@@ -553,7 +1571,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -572,7 +1590,7 @@ mod tests {
         let payment_parts = PaymentParts::Single;
         let mut path_finder =
             PathFinder::new(src, dest, amount, &graph, routing_metric, payment_parts);
-        let actual = path_finder.find_path();
+        let actual = path_finder.find_path().path();
         assert!(actual.is_some());
         let actual = actual.unwrap();
         let expected_path = Path {
@@ -590,40 +1608,144 @@ mod tests {
             weight: 175.0, // fees (b->c, c->d)
             amount: 5175,  // amount + fees
             time: 55,
+            shard_id: None,
         };
         assert_eq!(actual, expected);
     }
 
     #[test]
-    fn find_max_prob_paths() {
-        let json_file = std::path::Path::new("../test_data/lnbook_example.json");
-        let mut graph = Graph::to_sim_graph(
-            &network_parser::Graph::from_json_file(
-                &json_file,
-                network_parser::GraphSource::Lnresearch,
-            )
-            .unwrap(),
-            network_parser::GraphSource::Lnresearch,
-        );
-        let balance = 70000; // ensure balances are not the reason for failure
-        for (_, edges) in graph.edges.iter_mut() {
+    fn path_filter_forces_a_detour_via_eve() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
             for e in edges {
-                e.balance = balance;
+                e.balance = 70000;
             }
         }
-        let src = String::from("alice");
-        let dest = String::from("dina");
-        let amount = 5000;
-        let routing_metric = RoutingMetric::MaxProb;
         let mut path_finder = PathFinder::new(
-            src,
-            dest,
-            amount,
-            &graph,
+            "bob".to_string(),
+            "carol".to_string(),
+            5000,
+            &simulator.graph,
+            RoutingMetric::MinFee,
+            PaymentParts::Single,
+        );
+        let direct = path_finder.find_path().path().unwrap();
+        assert_eq!(direct.path.get_involved_nodes(), vec!["bob", "carol"]);
+        path_finder.set_path_filter(Box::new(|p: &CandidatePath| {
+            p.path.hops.iter().all(|hop| hop.3 != "bob-carol")
+        }));
+        // the cached path is rejected too
+        let detour = path_finder.find_path().path().unwrap();
+        assert_eq!(
+            detour.path.get_involved_nodes(),
+            vec!["bob", "eve", "carol"]
+        );
+        path_finder.set_path_filter(Box::new(|_: &CandidatePath| false));
+        assert_eq!(path_finder.find_path(), RouteResult::InsufficientLiquidity);
+    }
+
+    #[test]
+    fn simulation_path_filter_applies_to_payments() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+            }
+        }
+        simulator.set_path_filter(Box::new(|p: &CandidatePath| {
+            p.path.hops.iter().all(|hop| hop.3 != "bob-carol")
+        }));
+        let (source, dest) = ("bob".to_string(), "carol".to_string());
+        simulator.add_invoice(Invoice::new(0, 5000, &source, &dest));
+        let mut payment = Payment::new(0, source, dest, 5000, None);
+        assert!(simulator.send_single_payment(&mut payment));
+        assert_eq!(
+            payment.used_paths[0].path.get_involved_nodes(),
+            vec!["bob", "eve", "carol"]
+        );
+    }
+
+    // carol-alice is larger than dave-alice but fails most of the time
+    #[test]
+    fn loaded_reliability_overrides_the_capacity() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = if e.channel_id == "dave-alice" {
+                    100000
+                } else {
+                    1000000
+                };
+            }
+        }
+        let mut path_finder = PathFinder::new(
+            "bob".to_string(),
+            "alice".to_string(),
+            5000,
+            &simulator.graph,
+            RoutingMetric::MaxProb,
+            PaymentParts::Single,
+        );
+        let path = path_finder.find_path().path().unwrap();
+        assert_eq!(
+            path.path.get_involved_nodes(),
+            vec!["bob", "carol", "alice"]
+        );
+        path_finder
+            .load_reliability(std::path::Path::new("../test_data/channel_reliability.csv"))
+            .unwrap();
+        let path = path_finder.find_path().path().unwrap();
+        assert_eq!(path.path.get_involved_nodes(), vec!["bob", "dave", "alice"]);
+        // unlisted channels keep the modelled probability
+        let dave_alice = simulator
+            .graph
+            .get_outedges(&"dave".to_string())
+            .into_iter()
+            .find(|e| e.channel_id == "dave-alice")
+            .unwrap();
+        assert_eq!(
+            path_finder.get_edge_success_probability(&dave_alice, 5000),
+            1.0 - PathFinder::get_edge_failure_probabilty(&dave_alice, 5000).into_inner()
+        );
+        assert!(path_finder
+            .load_reliability(std::path::Path::new("../test_data/fee_overrides.csv"))
+            .is_err());
+    }
+
+    #[test]
+    fn find_max_prob_paths() {
+        let json_file = std::path::Path::new("../test_data/lnbook_example.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let balance = 70000; // ensure balances are not the reason for failure
+        for (_, edges) in graph.edges.iter_mut() {
+            for e in edges {
+                e.balance = balance;
+            }
+        }
+        let src = String::from("alice");
+        let dest = String::from("dina");
+        let amount = 5000;
+        let routing_metric = RoutingMetric::MaxProb;
+        let mut path_finder = PathFinder::new(
+            src,
+            dest,
+            amount,
+            &graph,
             routing_metric,
             PaymentParts::Single,
         );
-        let actual = path_finder.find_path();
+        let actual = path_finder.find_path().path();
         assert!(actual.is_some());
         let actual = actual.unwrap();
         let expected_path = Path {
@@ -641,6 +1763,7 @@ mod tests {
             weight: 1.0,  // prob (b->c, c->d)
             amount: 5175, // amount + fees
             time: 55,
+            shard_id: None,
         };
         // a and b equal if |a - b| <= epsilon
         assert_abs_diff_eq!(expected.weight, actual.weight, epsilon = 0.1f32);
@@ -653,7 +1776,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -666,6 +1789,26 @@ mod tests {
             amount: 10000,
             routing_metric: RoutingMetric::MinFee,
             payment_parts: PaymentParts::Single,
+            topology_path_exists: true,
+            penalties: HashMap::default(),
+            now: Time::default(),
+            half_life: Time::from_secs(crate::PENALTY_HALF_LIFE_IN_SECS),
+            route_cache: RouteCache::default(),
+            tie_break: TieBreak::default(),
+            fee_rounding: FeeRounding::default(),
+            last_hop_penalty: 0.0,
+            used_last_hops: vec![],
+            max_hops: crate::MAX_HOPS,
+            onion_hop_limit: crate::ONION_HOP_LIMIT,
+            final_cltv_expiry: 0,
+            decisions: Default::default(),
+            path_filter: None,
+            reliability: HashMap::default(),
+            fee_factors: HashMap::default(),
+            excluded_nodes: vec![],
+            first_hop: None,
+            skip_illiquid_edges: false,
+            edges_touched: Cell::default(),
         };
         let path = Path {
             src: path_finder.src.clone(),
@@ -676,8 +1819,8 @@ mod tests {
                 ("bob".to_string(), 0, 0, "".to_string()),
             ]),
         };
-        let mut candidate_path = &mut CandidatePath::new_with_path(path);
-        PathFinder::get_aggregated_path_cost(&mut path_finder, &mut candidate_path, false);
+        let candidate_path = &mut CandidatePath::new_with_path(path);
+        PathFinder::get_aggregated_path_cost(&mut path_finder, candidate_path, false);
         let (actual_weight, actual_amount, actual_time) = (
             candidate_path.weight,
             candidate_path.amount,
@@ -700,7 +1843,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -724,7 +1867,7 @@ mod tests {
             routing_metric,
             PaymentParts::Single,
         );
-        if let Some(candidate_path) = path_finder.find_path() {
+        if let RouteResult::Found(candidate_path) = path_finder.find_path() {
             let actual = candidate_path.path_fees();
             let expected = 175;
             assert_eq!(actual, expected);
@@ -747,6 +1890,7 @@ mod tests {
             weight: 175.0, // fees (b->c, c->d)
             amount: 5175,  // amount + fees
             time: 55,
+            shard_id: None,
         };
         let node = "bob".to_string();
         let pred = path.path.get_pred(&node);
@@ -780,7 +1924,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -793,6 +1937,26 @@ mod tests {
             amount: 10000,
             routing_metric: RoutingMetric::MinFee,
             payment_parts: PaymentParts::Single,
+            topology_path_exists: true,
+            penalties: HashMap::default(),
+            now: Time::default(),
+            half_life: Time::from_secs(crate::PENALTY_HALF_LIFE_IN_SECS),
+            route_cache: RouteCache::default(),
+            tie_break: TieBreak::default(),
+            fee_rounding: FeeRounding::default(),
+            last_hop_penalty: 0.0,
+            used_last_hops: vec![],
+            max_hops: crate::MAX_HOPS,
+            onion_hop_limit: crate::ONION_HOP_LIMIT,
+            final_cltv_expiry: 0,
+            decisions: Default::default(),
+            path_filter: None,
+            reliability: HashMap::default(),
+            fee_factors: HashMap::default(),
+            excluded_nodes: vec![],
+            first_hop: None,
+            skip_illiquid_edges: false,
+            edges_touched: Cell::default(),
         };
         let path = Path {
             src: path_finder.src.clone(),
@@ -803,8 +1967,8 @@ mod tests {
                 ("bob".to_string(), 0, 0, "".to_string()),
             ]),
         };
-        let mut candidate_path = &mut CandidatePath::new_with_path(path);
-        path_finder.get_aggregated_path_cost(&mut candidate_path, true);
+        let candidate_path = &mut CandidatePath::new_with_path(path);
+        path_finder.get_aggregated_path_cost(candidate_path, true);
         let (actual_weight, actual_amount, actual_time) = (
             candidate_path.weight,
             candidate_path.amount,
@@ -819,6 +1983,89 @@ mod tests {
         assert_eq!(actual_time, expected_time);
     }
 
+    // alice's own fee policy on alice1 must not make her pay herself
+    #[test]
+    fn sender_pays_no_fee_for_its_own_channel() {
+        let json_file = std::path::Path::new("../test_data/lnbook_example.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let (src, dest) = ("alice".to_string(), "dina".to_string());
+        let amount = 10000;
+        let path_fees = |graph: &Graph, include_src: bool| {
+            let mut path_finder = PathFinder::new(
+                src.clone(),
+                dest.clone(),
+                amount,
+                graph,
+                RoutingMetric::MinFee,
+                PaymentParts::Single,
+            );
+            match path_finder.find_path() {
+                RouteResult::Found(mut candidate_path) => {
+                    path_finder.get_aggregated_path_cost(&mut candidate_path, include_src);
+                    (candidate_path.amount - amount, candidate_path.path_fees())
+                }
+                _ => panic!("Expected a path from alice to dina."),
+            }
+        };
+        // bob and chan charge 100 and 75 msat
+        assert_eq!(path_fees(&graph, false), (175, 175));
+        for e in graph.edges.values_mut().flatten() {
+            if e.channel_id == "alice1" {
+                e.fee_base_msat = 5000;
+            }
+        }
+        assert_eq!(path_fees(&graph, false), (175, 175));
+        // only when alice is treated as an intermediary does her channel's fee count
+        assert_eq!(path_fees(&graph, true).0, 5175);
+    }
+
+    #[test]
+    fn hop_amounts_decrease_by_the_fees() {
+        let json_file = std::path::Path::new("../test_data/lnbook_example.json");
+        let graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let (src, dest) = ("alice".to_string(), "dina".to_string());
+        let amount = 10000;
+        let mut path_finder = PathFinder::new(
+            src,
+            dest,
+            amount,
+            &graph,
+            RoutingMetric::MinFee,
+            PaymentParts::Single,
+        );
+        let candidate_path = path_finder.find_path().path().unwrap();
+        let hop_amounts = candidate_path.hop_amounts();
+        // bob and chan charge 100 and 75 msat
+        let expected = vec![
+            ("alice1".to_string(), 10175),
+            ("bob2".to_string(), 10075),
+            ("chan2".to_string(), 10000),
+        ];
+        assert_eq!(hop_amounts, expected);
+        let hops = &candidate_path.path.hops;
+        for (i, pair) in hop_amounts.windows(2).enumerate() {
+            assert_eq!(pair[0].1 - pair[1].1, hops[i + 1].1 as u64);
+        }
+        assert_eq!(
+            hop_amounts[0].1 as usize,
+            amount + candidate_path.path_fees()
+        );
+    }
+
     #[test]
     fn get_subpath() {
         let path = Path {
@@ -936,4 +2183,821 @@ mod tests {
         assert!(path.is_last_hop(&"chan".to_string()));
         assert!(!path.is_last_hop(&"dina".to_string()));
     }
+
+    #[test]
+    fn route_result_distinguishes_topology_and_liquidity() {
+        let json_file = std::path::Path::new("../test_data/lnbook_example.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let (src, dest, amount) = (String::from("alice"), String::from("dina"), 5000);
+        let mut drained = graph.clone();
+        for edges in drained.edges.values_mut() {
+            for e in edges {
+                e.balance = 0;
+            }
+        }
+        let mut path_finder = PathFinder::new(
+            src.clone(),
+            dest.clone(),
+            amount,
+            &drained,
+            RoutingMetric::MinFee,
+            PaymentParts::Single,
+        );
+        path_finder
+            .graph
+            .set_edges(PathFinder::remove_inadequate_edges(&drained, amount));
+        assert_eq!(path_finder.find_path(), RouteResult::InsufficientLiquidity);
+        // dina's only channel is gone
+        graph.remove_edge(&String::from("chan"), &dest);
+        let mut path_finder = PathFinder::new(
+            src,
+            dest,
+            amount,
+            &graph,
+            RoutingMetric::MinFee,
+            PaymentParts::Single,
+        );
+        assert_eq!(path_finder.find_path(), RouteResult::NoTopology);
+    }
+
+    #[test]
+    fn negative_inbound_fee_makes_path_cheapest() {
+        let json_file = std::path::Path::new("../test_data/trivial_multipath.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for edges in graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                // dave charges a base fee of 500000 towards alice but discounts most of it for
+                // HTLCs arriving from bob
+                if e.channel_id == "bob-dave" {
+                    e.inbound_fee = -500000;
+                }
+            }
+        }
+        let mut path_finder = PathFinder::new(
+            String::from("bob"),
+            String::from("alice"),
+            6000,
+            &graph,
+            RoutingMetric::MinFee,
+            PaymentParts::Single,
+        );
+        let actual = path_finder.find_path().path().unwrap();
+        assert_eq!(
+            actual.path.get_involved_nodes(),
+            vec!["bob".to_string(), "dave".to_string(), "alice".to_string()]
+        );
+        // only the proportional fee remains
+        assert_eq!(actual.path_fees(), 6);
+        assert_eq!(actual.amount, 6006);
+    }
+
+    // eve charges 20 msat towards carol and carol 10 msat towards alice
+    #[test]
+    fn inbound_fees_are_charged_by_the_receiving_intermediary() {
+        let json_file = std::path::Path::new("../test_data/trivial_multipath.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let inbound_fees = |graph: &mut Graph, eve_from_bob: isize, carol_from_eve: isize| {
+            for e in graph.edges.values_mut().flatten() {
+                e.balance = 70000;
+                e.inbound_fee = match e.channel_id.as_str() {
+                    "bob-eve" => eve_from_bob,
+                    "eve-carol" => carol_from_eve,
+                    // alice receives, she charges no fee
+                    "carol-alice" => 1000,
+                    _ => 0,
+                };
+            }
+        };
+        let nodes = vec![
+            "bob".to_string(),
+            "eve".to_string(),
+            "carol".to_string(),
+            "alice".to_string(),
+        ];
+        inbound_fees(&mut graph, 5, -4);
+        let mut path_finder = PathFinder::new(
+            String::from("bob"),
+            String::from("alice"),
+            1000,
+            &graph,
+            RoutingMetric::MinFee,
+            PaymentParts::Single,
+        );
+        let actual = path_finder.candidate_path_from_nodes(nodes.clone());
+        assert_eq!(
+            actual.path.hops[1],
+            ("eve".to_string(), 25, 5, "eve-carol".to_string())
+        );
+        assert_eq!(
+            actual.path.hops[2],
+            ("carol".to_string(), 6, 5, "carol-alice".to_string())
+        );
+        assert_eq!(actual.amount, 1031);
+        // carol's discount cannot exceed her outbound fee
+        inbound_fees(&mut graph, 5, -50);
+        let mut path_finder = PathFinder::new(
+            String::from("bob"),
+            String::from("alice"),
+            1000,
+            &graph,
+            RoutingMetric::MinFee,
+            PaymentParts::Single,
+        );
+        let actual = path_finder.candidate_path_from_nodes(nodes);
+        assert_eq!(
+            actual.path.hops[1],
+            ("eve".to_string(), 25, 5, "eve-carol".to_string())
+        );
+        assert_eq!(
+            actual.path.hops[2],
+            ("carol".to_string(), 0, 5, "carol-alice".to_string())
+        );
+        assert_eq!(actual.amount, 1025);
+    }
+
+    // carol charges 10 msat towards alice but 300 msat towards bob
+    #[test]
+    fn reversed_path_uses_the_reverse_channels() {
+        let json_file = std::path::Path::new("../test_data/trivial_multipath.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for edges in graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+            }
+        }
+        let amount = 1000;
+        let bob_alice = PathFinder::new(
+            "bob".to_string(),
+            "alice".to_string(),
+            amount,
+            &graph,
+            RoutingMetric::MinFee,
+            PaymentParts::Single,
+        )
+        .find_path()
+        .path()
+        .unwrap();
+        assert_eq!(
+            bob_alice.path.get_involved_nodes(),
+            vec!["bob".to_string(), "carol".to_string(), "alice".to_string()]
+        );
+        let reversed = bob_alice.path.reverse(&graph).unwrap();
+        assert_eq!(
+            (reversed.src.as_str(), reversed.dest.as_str()),
+            ("alice", "bob")
+        );
+        let expected = VecDeque::from([
+            ("alice".to_string(), 1300, 5, "alice-carol".to_string()),
+            ("carol".to_string(), 300, 5, "carol-bob".to_string()),
+            ("bob".to_string(), 1000, 0, "bob-carol".to_string()),
+        ]);
+        assert_eq!(reversed.hops, expected);
+        // carol cannot forward the amount towards bob
+        graph.update_channel_balance(&"carol-bob".to_string(), 500);
+        assert!(bob_alice.path.reverse(&graph).is_none());
+    }
+
+    // bob charges 10 msat towards carol while carol charges 300 msat towards bob. Forwarding
+    // nodes charge the fee of their outgoing direction
+    #[test]
+    fn forwarding_fee_uses_outgoing_direction() {
+        let json_file = std::path::Path::new("../test_data/trivial_multipath.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for edges in graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+            }
+        }
+        let amount = 1000;
+        let find = |src: &str, dest: &str| {
+            PathFinder::new(
+                String::from(src),
+                String::from(dest),
+                amount,
+                &graph,
+                RoutingMetric::MinFee,
+                PaymentParts::Single,
+            )
+            .find_path()
+            .path()
+            .unwrap()
+        };
+        // carol's 300 msat towards bob are avoided. Using bob's 10 msat instead would make
+        // alice -> carol -> bob the cheapest path
+        let around_carol_bob = find("alice", "bob");
+        assert_eq!(
+            around_carol_bob.path.get_involved_nodes(),
+            vec![
+                "alice".to_string(),
+                "carol".to_string(),
+                "eve".to_string(),
+                "bob".to_string()
+            ]
+        );
+        assert_eq!(around_carol_bob.path.hops[1].3, "carol-eve");
+        assert_eq!(around_carol_bob.path_fees(), 10 + 3);
+        assert_eq!(around_carol_bob.amount, amount + 13);
+        let via_bob = find("dave", "carol");
+        assert_eq!(
+            via_bob.path.get_involved_nodes(),
+            vec!["dave".to_string(), "bob".to_string(), "carol".to_string()]
+        );
+        assert_eq!(via_bob.path.hops[1].3, "bob-carol");
+        assert_eq!(via_bob.path_fees(), 10);
+        assert_eq!(via_bob.amount, amount + 10);
+    }
+
+    #[test]
+    fn candidate_path_from_channels() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        let graph = &simulator.graph;
+        let channels =
+            |ids: &[&str]| -> Vec<String> { ids.iter().map(|c| c.to_string()).collect() };
+        let actual = CandidatePath::from_channels(
+            graph,
+            "bob",
+            &channels(&["bob-carol", "carol-alice"]),
+            1000,
+        )
+        .unwrap();
+        assert_eq!(
+            actual.path.hops,
+            VecDeque::from([
+                ("bob".to_string(), 1010, 5, "bob-carol".to_string()),
+                ("carol".to_string(), 10, 5, "carol-alice".to_string()),
+                ("alice".to_string(), 1000, 0, "alice-carol".to_string()),
+            ])
+        );
+        assert_eq!(actual.weight, 10.0);
+        assert_eq!(actual.amount, 1010);
+        assert_eq!(actual.time, 5);
+        // same as the path found by the search
+        let found = simulator.candidate_paths(&"bob".to_string(), &"alice".to_string(), 1000, 1);
+        assert_eq!(actual, found[0]);
+        assert_eq!(
+            CandidatePath::from_channels(
+                graph,
+                "bob",
+                &channels(&["bob-carol", "dave-alice"]),
+                1000
+            ),
+            Err(PathError::Disconnected {
+                node: "carol".to_string(),
+                channel_id: "dave-alice".to_string()
+            })
+        );
+        assert_eq!(
+            CandidatePath::from_channels(graph, "bob", &channels(&["bob-zoe"]), 1000),
+            Err(PathError::UnknownChannel("bob-zoe".to_string()))
+        );
+        assert_eq!(
+            CandidatePath::from_channels(
+                graph,
+                "bob",
+                &channels(&["bob-carol", "carol-bob"]),
+                1000
+            ),
+            Err(PathError::RepeatedNode("bob".to_string()))
+        );
+        assert_eq!(
+            CandidatePath::from_channels(graph, "bob", &[], 1000),
+            Err(PathError::Empty)
+        );
+    }
+
+    // a second, cheaper channel between bob and carol as well as a self-loop at bob are added to
+    // the imported graph
+    #[test]
+    fn cheapest_parallel_edge_is_selected() {
+        let json_file = std::path::Path::new("../test_data/trivial_multipath.json");
+        let mut net_graph = network_parser::Graph::from_json_file(
+            json_file,
+            network_parser::GraphSource::Lnresearch,
+        )
+        .unwrap();
+        for (channel_id, src, dest) in [
+            ("bob-carol-2", "bob", "carol"),
+            ("carol-bob-2", "carol", "bob"),
+            ("bob-bob", "bob", "bob"),
+        ] {
+            net_graph
+                .edges
+                .entry(src.to_string())
+                .or_default()
+                .insert(Edge {
+                    channel_id: channel_id.to_string(),
+                    source: src.to_string(),
+                    destination: dest.to_string(),
+                    fee_base_msat: 2,
+                    htlc_maximum_msat: 135000,
+                    ..Default::default()
+                });
+        }
+        let mut graph = Graph::to_sim_graph(&net_graph, network_parser::GraphSource::Lnresearch);
+        for edges in graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+            }
+        }
+        assert!(graph
+            .get_all_src_dest_edges(&String::from("bob"), &String::from("bob"))
+            .is_empty());
+        assert_eq!(
+            graph
+                .get_all_src_dest_edges(&String::from("bob"), &String::from("carol"))
+                .len(),
+            2
+        );
+        let amount = 1000;
+        let mut path_finder = PathFinder::new(
+            String::from("dave"),
+            String::from("carol"),
+            amount,
+            &graph,
+            RoutingMetric::MinFee,
+            PaymentParts::Single,
+        );
+        let actual = path_finder.find_path().path().unwrap();
+        assert_eq!(
+            actual.path.get_involved_nodes(),
+            vec!["dave".to_string(), "bob".to_string(), "carol".to_string()]
+        );
+        assert_eq!(actual.path.hops[1].3, "bob-carol-2");
+        assert_eq!(actual.path_fees(), 2);
+        // the cheaper channel cannot carry the amount so the other one is used
+        graph.update_channel_balance(&String::from("bob-carol-2"), 0);
+        let mut path_finder = PathFinder::new(
+            String::from("dave"),
+            String::from("carol"),
+            amount,
+            &graph,
+            RoutingMetric::MinFee,
+            PaymentParts::Single,
+        );
+        path_finder
+            .graph
+            .set_edges(PathFinder::remove_inadequate_edges(&graph, amount));
+        let actual = path_finder.find_path().path().unwrap();
+        assert_eq!(actual.path.hops[1].3, "bob-carol");
+        assert_eq!(actual.path_fees(), 10);
+    }
+
+    #[test]
+    fn all_simple_paths_up_to_length() {
+        let json_file = std::path::Path::new("../test_data/trivial_multipath.json");
+        let graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let path_finder = PathFinder::new(
+            String::from("bob"),
+            String::from("alice"),
+            1000,
+            &graph,
+            RoutingMetric::MinFee,
+            PaymentParts::Single,
+        );
+        let actual: Vec<Vec<ID>> = path_finder
+            .all_simple_paths("bob", "alice", 3)
+            .iter()
+            .map(|p| p.get_involved_nodes())
+            .collect();
+        let expected = vec![
+            vec!["bob", "carol", "alice"],
+            vec!["bob", "dave", "alice"],
+            vec!["bob", "eve", "carol", "alice"],
+        ];
+        assert_eq!(actual.len(), 3);
+        assert_eq!(actual, expected);
+        assert_eq!(path_finder.all_simple_paths("bob", "alice", 1).len(), 0);
+        assert_eq!(path_finder.all_simple_paths("bob", "alice", 2).len(), 2);
+    }
+
+    #[test]
+    fn failed_channel_success_probability_is_penalised() {
+        let json_file = std::path::Path::new("../test_data/lnbook_example.json");
+        let graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let amount = 1000;
+        let mut path_finder = PathFinder::new(
+            String::from("alice"),
+            String::from("dina"),
+            amount,
+            &graph,
+            RoutingMetric::MaxProb,
+            PaymentParts::Single,
+        );
+        let edge = graph.get_edges_for_node(&String::from("bob")).unwrap()[0].clone();
+        let first_attempt = path_finder.get_edge_success_probability(&edge, amount);
+        path_finder.record_failure(&edge.channel_id);
+        let second_attempt = path_finder.get_edge_success_probability(&edge, amount);
+        assert!(second_attempt < first_attempt);
+        assert_abs_diff_eq!(second_attempt, first_attempt / 2.0, epsilon = 0.0001);
+        path_finder.record_failure(&edge.channel_id);
+        let third_attempt = path_finder.get_edge_success_probability(&edge, amount);
+        assert_abs_diff_eq!(third_attempt, first_attempt / 4.0, epsilon = 0.0001);
+        // half of the penalty is gone after one half life
+        path_finder.now = Time::from_secs(crate::PENALTY_HALF_LIFE_IN_SECS);
+        let recovering = path_finder.get_edge_success_probability(&edge, amount);
+        assert_abs_diff_eq!(recovering, first_attempt / 2.0, epsilon = 0.0001);
+    }
+
+    #[test]
+    fn penalty_decays_over_half_lives() {
+        let graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                std::path::Path::new("../test_data/lnbook_example.json"),
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let amount = 1000;
+        let mut path_finder = PathFinder::new(
+            String::from("alice"),
+            String::from("dina"),
+            amount,
+            &graph,
+            RoutingMetric::MaxProb,
+            PaymentParts::Single,
+        );
+        path_finder.half_life = Time::from_secs(60.0);
+        let unpenalised = match path_finder.find_path() {
+            RouteResult::Found(candidate_path) => candidate_path,
+            _ => panic!("Expected a route"),
+        };
+        let channel_id = unpenalised.path.hops[1].3.clone();
+        path_finder.record_failure(&channel_id);
+        let edge = graph
+            .get_outedges(&unpenalised.path.hops[1].0)
+            .into_iter()
+            .find(|e| e.channel_id == channel_id)
+            .unwrap();
+        let first_attempt = PathFinder::get_edge_failure_probabilty(&edge, amount).into_inner();
+        let penalised = path_finder.get_edge_success_probability(&edge, amount);
+        assert_abs_diff_eq!(penalised, (1.0 - first_attempt) / 2.0, epsilon = 0.0001);
+        // a quarter of the penalty is left after two half lives
+        path_finder.now = Time::from_secs(120.0);
+        let recovered = path_finder.get_edge_success_probability(&edge, amount);
+        assert_abs_diff_eq!(
+            recovered,
+            (1.0 - first_attempt) * 0.5_f32.powf(0.25),
+            epsilon = 0.0001
+        );
+        // after ten half lives the channel is practically as likely to succeed as before
+        path_finder.now = Time::from_secs(600.0);
+        let recovered = path_finder.get_edge_success_probability(&edge, amount);
+        assert_abs_diff_eq!(recovered, 1.0 - first_attempt, epsilon = 0.001);
+        match path_finder.find_path() {
+            RouteResult::Found(candidate_path) => {
+                assert_eq!(candidate_path.path, unpenalised.path)
+            }
+            _ => panic!("Expected a route"),
+        }
+    }
+
+    // bob reaches alice via carol or dave who both charge 10 msat
+    #[test]
+    fn equal_fee_paths_prefer_lower_latency() {
+        let route_via = |dave_alice_timelock: usize| {
+            let mut graph = Graph::to_sim_graph(
+                &network_parser::Graph::from_json_file(
+                    std::path::Path::new("../test_data/trivial_multipath.json"),
+                    network_parser::GraphSource::Lnresearch,
+                )
+                .unwrap(),
+                network_parser::GraphSource::Lnresearch,
+            );
+            for edges in graph.edges.values_mut() {
+                for e in edges {
+                    e.balance = 70000;
+                    if e.channel_id == "dave-alice" {
+                        e.fee_base_msat = 10;
+                        e.fee_proportional_millionths = 0;
+                        e.cltv_expiry_delta = dave_alice_timelock;
+                    }
+                }
+            }
+            let mut path_finder = PathFinder::new(
+                String::from("bob"),
+                String::from("alice"),
+                1000,
+                &graph,
+                RoutingMetric::MinFee,
+                PaymentParts::Single,
+            );
+            match path_finder.find_path() {
+                RouteResult::Found(candidate_path) => {
+                    assert_eq!(candidate_path.path_fees(), 10);
+                    assert_eq!(candidate_path.time, dave_alice_timelock.min(5));
+                    candidate_path.path.get_involved_nodes()[1].clone()
+                }
+                _ => panic!("Expected a route"),
+            }
+        };
+        // carol-alice has a timelock of 5
+        assert_eq!(route_via(1), "dave");
+        assert_eq!(route_via(40), "carol");
+    }
+
+    // carol has little left to forward to alice, dave most of the channel
+    #[test]
+    fn balanced_routing_spares_depleted_channels() {
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                std::path::Path::new("../test_data/trivial_multipath.json"),
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for edges in graph.edges.values_mut() {
+            for e in edges {
+                e.capacity = 100000;
+                e.balance = if e.channel_id == "carol-alice" {
+                    20000
+                } else {
+                    70000
+                };
+            }
+        }
+        let route_via = |routing_metric| {
+            let mut path_finder = PathFinder::new(
+                String::from("bob"),
+                String::from("alice"),
+                10000,
+                &graph,
+                routing_metric,
+                PaymentParts::Single,
+            );
+            let candidate_path = path_finder.find_path().path().unwrap();
+            candidate_path.path.get_involved_nodes()[1].clone()
+        };
+        assert_eq!(route_via(RoutingMetric::MinFee), "carol");
+        assert_eq!(route_via(RoutingMetric::Balanced), "dave");
+        let edge = graph
+            .get_outedges(&"carol".to_string())
+            .into_iter()
+            .find(|e| e.channel_id == "carol-alice")
+            .unwrap();
+        assert_eq!(
+            BalancedScorer::channel_weight(&edge, 10000).into_inner(),
+            0.5
+        );
+    }
+
+    // bob's channel to dave and eve's channels are unfunded, which the search does not see.
+    // carol-alice forwards exactly the amount
+    #[test]
+    fn prefiltered_search_finds_the_same_path_touching_fewer_edges() {
+        let json_file = std::path::Path::new("../test_data/trivial_multipath.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for edges in graph.edges.values_mut() {
+            for e in edges {
+                e.capacity = 1000000;
+                e.balance = if e.channel_id == "bob-dave" || e.source == "eve" {
+                    0
+                } else if e.channel_id == "carol-alice" {
+                    1000
+                } else {
+                    70000
+                };
+            }
+        }
+        let amount = 1000;
+        let path_finder = || {
+            PathFinder::new(
+                String::from("bob"),
+                String::from("alice"),
+                amount,
+                &graph,
+                RoutingMetric::MinFee,
+                PaymentParts::Single,
+            )
+        };
+        let mut unfiltered = path_finder();
+        let mut filtered = path_finder();
+        filtered.prefilter_edges();
+        assert!(filtered.graph.edge_count() < graph.edge_count());
+        let expected = unfiltered.find_path().path().unwrap();
+        let actual = filtered.find_path().path().unwrap();
+        assert_eq!(actual.path.hops, expected.path.hops);
+        assert_eq!(actual.last_hop(), Some("carol-alice".to_string()));
+        assert!(filtered.edges_touched() > 0);
+        assert!(filtered.edges_touched() < unfiltered.edges_touched());
+    }
+
+    #[test]
+    fn precomputed_tree_finds_the_same_path_without_searching() {
+        let json_file = std::path::Path::new("../test_data/trivial_multipath.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for edges in graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+            }
+        }
+        let path_finder = |src: &str, graph: &Graph| {
+            PathFinder::new(
+                src.to_string(),
+                String::from("alice"),
+                1000,
+                graph,
+                RoutingMetric::MinFee,
+                PaymentParts::Single,
+            )
+        };
+        let mut uncached = path_finder("bob", &graph);
+        let expected = uncached.find_path().path().unwrap();
+        // the tree is computed once and used for payments to alice from any src
+        let mut precomputing = path_finder("eve", &graph);
+        precomputing.precompute_sptree("alice");
+        let mut cached = path_finder("bob", &graph);
+        cached.route_cache = precomputing.route_cache.clone();
+        let actual = cached.find_path().path().unwrap();
+        assert_eq!(actual.path.hops, expected.path.hops);
+        assert_eq!(actual.path_fees(), expected.path_fees());
+        assert!(cached.shortest_path_from_tree().is_some());
+        // once a balance changed the search runs again
+        graph.update_channel_balance(&"carol-alice".to_string(), 60000);
+        let mut stale = path_finder("bob", &graph);
+        stale.route_cache = precomputing.route_cache;
+        let actual = stale.find_path().path().unwrap();
+        assert_eq!(actual.path.hops, expected.path.hops);
+        assert!(stale.shortest_path_from_tree().is_none());
+    }
+
+    // paths found with penalties, exclusions or used last hops avoid channels that a search
+    // without them would take, so they are not handed to it
+    #[test]
+    fn cached_paths_are_only_used_in_the_context_they_were_found_in() {
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                std::path::Path::new("../test_data/trivial_multipath.json"),
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for edges in graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+            }
+        }
+        let path_finder = |route_cache: RouteCache| {
+            let mut path_finder = PathFinder::new(
+                String::from("bob"),
+                String::from("alice"),
+                1000,
+                &graph,
+                RoutingMetric::MaxProb,
+                PaymentParts::Single,
+            );
+            path_finder.route_cache = route_cache;
+            path_finder
+        };
+        let mut uncached = path_finder(RouteCache::new(4));
+        let expected = uncached.find_path().path().unwrap().path.hops;
+        let (via, last_hop) = (expected[1].0.clone(), expected[1].3.clone());
+        let mut penalised = path_finder(RouteCache::new(4));
+        penalised.penalties.insert(
+            last_hop.clone(),
+            ChannelPenalty {
+                failures: 5.0,
+                last_failure: Time::default(),
+            },
+        );
+        let mut excluding = path_finder(RouteCache::new(4));
+        excluding.exclude_nodes(&HashSet::from([via]));
+        let mut spreading = path_finder(RouteCache::new(4));
+        spreading.last_hop_penalty = 10.0;
+        spreading.used_last_hops = vec![last_hop];
+        for mut other_context in [penalised, excluding, spreading] {
+            let other_path = other_context.find_path().path().unwrap().path.hops;
+            assert_ne!(other_path, expected);
+            let mut cached = path_finder(other_context.route_cache);
+            assert_eq!(cached.find_path().path().unwrap().path.hops, expected);
+            assert_eq!(cached.route_cache.hits, 0);
+        }
+        // the same context reuses the path
+        let mut cached = path_finder(uncached.route_cache);
+        assert_eq!(cached.find_path().path().unwrap().path.hops, expected);
+        assert_eq!(cached.route_cache.hits, 1);
+    }
+
+    // bob has to pay alice via eve and carol, who charge 20 and 10 msat
+    #[test]
+    fn fees_are_rounded_to_whole_sats_at_each_hop() {
+        let graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                std::path::Path::new("../test_data/trivial_multipath.json"),
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let amount = 1000;
+        let path_with = |fee_rounding: FeeRounding| {
+            let mut path_finder = PathFinder::new(
+                String::from("bob"),
+                String::from("alice"),
+                amount,
+                &graph,
+                RoutingMetric::MinFee,
+                PaymentParts::Single,
+            );
+            for channel_id in ["bob-carol", "bob-dave"] {
+                path_finder.graph.remove_channel(&channel_id.to_string());
+            }
+            path_finder.fee_rounding = fee_rounding;
+            match path_finder.find_path() {
+                RouteResult::Found(candidate_path) => candidate_path,
+                _ => panic!("Expected a route"),
+            }
+        };
+        let hop_fees = |path: &CandidatePath| -> Vec<usize> {
+            path.path
+                .hops
+                .range(1..path.path.hops.len() - 1)
+                .map(|hop| hop.1)
+                .collect()
+        };
+        let exact = path_with(FeeRounding::None);
+        assert_eq!(hop_fees(&exact), vec![20, 10]);
+        assert_eq!(exact.path_fees(), 30);
+        let ceiled = path_with(FeeRounding::CeilSat);
+        assert_eq!(
+            ceiled.path.get_involved_nodes(),
+            exact.path.get_involved_nodes()
+        );
+        assert_eq!(hop_fees(&ceiled), vec![1000, 1000]);
+        assert_eq!(ceiled.path_fees(), 2000);
+        assert_eq!(ceiled.amount, amount + 2000);
+        let floored = path_with(FeeRounding::FloorSat);
+        assert_eq!(floored.path_fees(), 0);
+        assert_eq!(floored.amount, amount);
+    }
 }
@@ -0,0 +1,172 @@
+use crate::{
+    traversal::pathfinding::{CandidatePath, ProbeResult, RouteResult},
+    PaymentParts, Simulation,
+};
+use std::{collections::HashMap, time::Instant};
+
+#[cfg(not(test))]
+use log::{info, trace};
+#[cfg(test)]
+use std::{println as info, println as trace};
+
+impl Simulation {
+    /// Sends a probe for the amount from src to dest. Like a wallet, the prober only knows the
+    /// channels' capacities and learns at which channel the probe fails, if any, as well as how
+    /// much the path could have carried. Balances are never changed
+    pub fn probe(&mut self, src: &str, dest: &str, amount: u64) -> ProbeResult {
+        let amount = amount as usize;
+        let mut path_finder = self.path_finder(&src.to_string(), &dest.to_string(), amount);
+        path_finder.payment_parts = PaymentParts::Single;
+        // channels are filtered by capacity as the balances are unknown to the prober
+        let mut edges = HashMap::new();
+        for (node, node_edges) in path_finder.graph.edges.iter() {
+            let large_enough = node_edges.iter().filter(|e| e.capacity >= amount);
+            edges.insert(node.clone(), large_enough.cloned().collect());
+        }
+        path_finder.graph.set_edges(edges);
+        let pathfinding_start = Instant::now();
+        let route = path_finder.find_path();
+        self.timings.pathfinding += pathfinding_start.elapsed().as_nanos();
+        let candidate_path = match route {
+            RouteResult::Found(candidate_path) => candidate_path,
+            _ => {
+                trace!("No path to probe from {} to {}.", src, dest);
+                return ProbeResult {
+                    failing_channel: None,
+                    max_amount: 0,
+                };
+            }
+        };
+        let failing_channel = self.probe_path(&candidate_path);
+        let max_amount = match failing_channel {
+            None => amount,
+            Some(_) => self.max_probe_amount(src, &candidate_path, amount),
+        };
+        info!(
+            "Probe of {} msat from {} to {} failed at {:?}, the path carries up to {} msat.",
+            amount, src, dest, failing_channel, max_amount
+        );
+        ProbeResult {
+            failing_channel,
+            max_amount: max_amount as u64,
+        }
+    }
+
+    /// The first channel of the path that cannot forward the path's amount. Applies the same
+    /// checks as an actual payment attempt
+    fn probe_path(&self, candidate_path: &CandidatePath) -> Option<String> {
+        let hops = &candidate_path.path.hops;
+        let (sender, out_channel) = (&hops[0].0, &hops[0].3);
+        if self.graph.get_channel_balance(sender, out_channel) <= candidate_path.amount {
+            return Some(out_channel.clone());
+        }
+        let mut remaining = candidate_path.amount;
+        for (id, fees, _, channel_id) in hops.iter().take(hops.len() - 1).skip(1) {
+            if self.graph.get_channel_balance(id, channel_id) <= remaining - fees
+                || !self.graph.channel_can_receive_amount(channel_id, remaining)
+            {
+                return Some(channel_id.clone());
+            }
+            remaining -= fees;
+        }
+        let receive_channel = &hops[hops.len() - 1].3;
+        if !self
+            .graph
+            .channel_can_receive_amount(receive_channel, remaining)
+        {
+            return Some(receive_channel.clone());
+        }
+        None
+    }
+
+    /// Largest amount below the probed one that the path's channels can deliver
+    fn max_probe_amount(&self, src: &str, candidate_path: &CandidatePath, amount: usize) -> usize {
+        let hops = &candidate_path.path.hops;
+        // the dest's hop only names the channel receiving the amount
+        let channels: Vec<String> = hops
+            .iter()
+            .take(hops.len() - 1)
+            .map(|h| h.3.clone())
+            .collect();
+        let (mut low, mut high) = (0, amount);
+        while low < high {
+            let amount = low + (high - low).div_ceil(2);
+            let delivers = CandidatePath::from_channels(&self.graph, src, &channels, amount as u64)
+                .is_ok_and(|path| self.probe_path(&path).is_none());
+            if delivers {
+                low = amount;
+            } else {
+                high = amount - 1;
+            }
+        }
+        low
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probe_finds_the_channel_lacking_liquidity() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 100000;
+                e.capacity = 1000000;
+            }
+        }
+        simulator
+            .graph
+            .update_channel_balance(&"carol-alice".to_string(), 3000);
+        let liquidity = simulator.graph.total_liquidity();
+        let actual = simulator.probe("bob", "alice", 5000);
+        assert_eq!(
+            actual,
+            ProbeResult {
+                failing_channel: Some("carol-alice".to_string()),
+                // carol has to forward less than her balance
+                max_amount: 2999,
+            }
+        );
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+        assert_eq!(
+            simulator
+                .graph
+                .get_channel_balance(&"carol".to_string(), &"carol-alice".to_string()),
+            3000
+        );
+        let actual = simulator.probe("bob", "alice", 2000);
+        assert_eq!(
+            actual,
+            ProbeResult {
+                failing_channel: None,
+                max_amount: 2000,
+            }
+        );
+    }
+
+    // bob's paths to alice all lead via an intermediary
+    #[test]
+    fn searches_respect_the_simulations_max_hops() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 100000;
+                e.capacity = 1000000;
+            }
+        }
+        let (bob, alice) = ("bob".to_string(), "alice".to_string());
+        assert_eq!(simulator.probe("bob", "alice", 2000).max_amount, 2000);
+        simulator.set_max_hops(0);
+        assert_eq!(simulator.probe("bob", "alice", 2000).max_amount, 0);
+        assert!(!simulator.find_route(&bob, &alice, 2000).is_found());
+        assert!(simulator.candidate_paths(&bob, &alice, 2000, 3).is_empty());
+        // direct channels are still found
+        assert!(simulator
+            .find_route(&bob, &"carol".to_string(), 2000)
+            .is_found());
+    }
+}
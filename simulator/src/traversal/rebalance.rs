@@ -0,0 +1,245 @@
+use crate::{
+    core_types::event::PaymentEvent,
+    payment::Payment,
+    time::Time,
+    traversal::pathfinding::{CandidatePath, Path, PathFinder},
+    Invoice, PaymentParts, Simulation, ID,
+};
+
+#[cfg(not(test))]
+use log::{error, info};
+#[cfg(test)]
+use std::{println as info, println as error};
+
+impl Simulation {
+    /// Schedules a payment of the node to itself that leaves through out_channel and comes back
+    /// through in_channel, relative to the current simulation time. Both are the node's side of
+    /// the channel so out_channel's balance decreases by the amount plus fees and in_channel's
+    /// increases by the amount
+    pub fn schedule_rebalance(
+        &mut self,
+        node: String,
+        out_channel: String,
+        in_channel: String,
+        amount: u64,
+        at: Time,
+    ) {
+        self.event_queue.schedule(
+            at,
+            PaymentEvent::Rebalance {
+                node,
+                out_channel,
+                in_channel,
+                amount: amount as usize,
+            },
+        );
+    }
+
+    /// Routes the circular payment of a rebalance and moves the balances like a payment's attempt
+    /// does. False if there is no route or a channel along it lacks the liquidity, amounts in
+    /// flight included, in which case no balance changes
+    pub(crate) fn rebalance(
+        &mut self,
+        node: &ID,
+        out_channel: &String,
+        in_channel: &String,
+        amount: usize,
+    ) -> bool {
+        let channel = |channel_id: &String| {
+            self.graph
+                .get_outedges(node)
+                .into_iter()
+                .find(|e| e.channel_id == *channel_id)
+        };
+        let (Some(out_edge), Some(in_edge)) = (channel(out_channel), channel(in_channel)) else {
+            error!(
+                "Rebalance of {} failing as {} or {} is not one of its channels.",
+                node, out_channel, in_channel
+            );
+            return false;
+        };
+        let (out_peer, in_peer) = (&out_edge.destination, &in_edge.destination);
+        let mut path_finder = self.path_finder(out_peer, in_peer, amount);
+        path_finder.payment_parts = PaymentParts::Single;
+        path_finder.prefilter_edges();
+        path_finder.graph.remove_node(node);
+        let Some(hops) = self.rebalance_hops(&path_finder, node, out_peer, in_peer, amount) else {
+            error!(
+                "Rebalance of {} failing as there is no route from {} back to {}.",
+                node, out_channel, in_channel
+            );
+            return false;
+        };
+        let candidate_path = Self::rebalance_path(node, out_channel, in_channel, &hops, amount);
+        if !self.is_path_viable(&candidate_path) {
+            error!(
+                "Rebalance of {} msat from {} to {} failing due to insufficient liquidity.",
+                amount, out_channel, in_channel
+            );
+            return false;
+        }
+        // the node pays itself, so it issues the invoice the attempt is checked against
+        let invoice = Invoice::new(self.next_payment_id(), amount, node, node);
+        self.add_invoice(invoice.clone());
+        let mut shard =
+            Payment::new(invoice.id, node.clone(), node.clone(), amount, None).to_shard(amount);
+        let (succeeded, transferred) =
+            self.attempt_payment(&mut shard, &candidate_path, &mut path_finder);
+        self.remove_invoice(&invoice);
+        if !succeeded {
+            self.revert_payment(&transferred);
+            error!(
+                "Rebalance of {} msat from {} to {} failed along the route.",
+                amount, out_channel, in_channel
+            );
+            return false;
+        }
+        info!(
+            "{} moved {} msat from {} to {} paying {} msat in fees.",
+            node,
+            amount,
+            out_channel,
+            in_channel,
+            candidate_path.path_fees()
+        );
+        true
+    }
+
+    /// The circular path of a rebalance, leaving the node through out_channel and arriving back
+    /// through in_channel after the intermediaries' hops
+    fn rebalance_path(
+        node: &ID,
+        out_channel: &str,
+        in_channel: &str,
+        hops: &[(ID, String, usize, usize)],
+        amount: usize,
+    ) -> CandidatePath {
+        // what the node sends includes the fees of all intermediaries
+        let total = hops.first().map(|hop| hop.3).unwrap_or(amount);
+        let mut path = Path::new(node.clone(), node.clone());
+        path.hops
+            .push_back((node.clone(), total, 0, out_channel.to_string()));
+        path.hops.extend(
+            hops.iter()
+                .map(|(id, channel_id, fee, _)| (id.clone(), *fee, 0, channel_id.clone())),
+        );
+        path.hops
+            .push_back((node.clone(), amount, 0, in_channel.to_string()));
+        let mut candidate_path = CandidatePath::new_with_path(path);
+        candidate_path.amount = total;
+        candidate_path
+    }
+
+    /// The channels the intermediaries forward over from the node's out peer back to the node,
+    /// along with the fee they charge and the amount they receive (hop, channel_id, fee, received)
+    /// The route is searched with the path finder, which has to leave out the node itself
+    fn rebalance_hops(
+        &self,
+        path_finder: &PathFinder,
+        node: &ID,
+        out_peer: &ID,
+        in_peer: &ID,
+        amount: usize,
+    ) -> Option<Vec<(ID, String, usize, usize)>> {
+        let mut route = if out_peer == in_peer {
+            vec![out_peer.clone()]
+        } else {
+            path_finder.shortest_path_from(out_peer)?.0
+        };
+        route.push(node.clone());
+        // the fees are due for what each intermediary forwards, starting at the node
+        let mut received = amount;
+        let mut hops = vec![];
+        for pair in route.windows(2).rev() {
+            let edge = self
+                .graph
+                .get_all_src_dest_edges(&pair[0], &pair[1])
+                .into_iter()
+                .min_by_key(|e| PathFinder::get_edge_fee(e, received))?;
+            let fee = self
+                .fee_rounding
+                .apply(PathFinder::get_edge_fee(&edge, received).into_inner() as usize);
+            received = received.checked_add(fee)?;
+            hops.push((pair[0].clone(), edge.channel_id, fee, received));
+        }
+        hops.reverse();
+        Some(hops)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // alice moves liquidity from her channel with dave to the one with carol via dave, bob and carol
+    #[test]
+    fn rebalance_moves_liquidity_between_channels() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+                if e.channel_id == "dave-bob" {
+                    e.fee_base_msat = 20;
+                    e.fee_proportional_millionths = 0;
+                }
+            }
+        }
+        let alice = "alice".to_string();
+        let (out_channel, in_channel) = ("alice-dave".to_string(), "alice-carol".to_string());
+        let amount = 20000;
+        let liquidity = simulator.graph.total_liquidity();
+        simulator.schedule_rebalance(
+            alice.clone(),
+            out_channel.clone(),
+            in_channel.clone(),
+            amount,
+            Time::from_secs(10.0),
+        );
+        simulator.run_scheduled(vec![]);
+        let out_balance = simulator.graph.get_channel_balance(&alice, &out_channel);
+        let in_balance = simulator.graph.get_channel_balance(&alice, &in_channel);
+        assert_eq!(in_balance, 70000 + amount as usize);
+        // dave, bob and carol charge 20, 10 and 10 msat on top
+        assert_eq!(out_balance, 70000 - amount as usize - 40);
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+        // a rebalance is not a payment
+        assert_eq!(simulator.total_num_payments, 0);
+    }
+
+    // alice's channel to dave holds enough, but most of it is committed to an HTLC in flight
+    #[test]
+    fn rebalance_respects_amounts_in_flight() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+                if e.channel_id == "dave-bob" {
+                    e.fee_base_msat = 20;
+                    e.fee_proportional_millionths = 0;
+                }
+            }
+        }
+        let alice = "alice".to_string();
+        let (out_channel, in_channel) = ("alice-dave".to_string(), "alice-carol".to_string());
+        simulator.graph.reserve_in_flight(&out_channel, 60000);
+        assert!(!simulator.rebalance(&alice, &out_channel, &in_channel, 20000));
+        assert_eq!(
+            simulator.graph.get_channel_balance(&alice, &out_channel),
+            70000
+        );
+        assert_eq!(
+            simulator.graph.get_channel_balance(&alice, &in_channel),
+            70000
+        );
+        simulator.graph.release_in_flight(&out_channel, 60000);
+        assert!(simulator.rebalance(&alice, &out_channel, &in_channel, 20000));
+        assert_eq!(
+            simulator.graph.get_channel_balance(&alice, &in_channel),
+            90000
+        );
+    }
+}
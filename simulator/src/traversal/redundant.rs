@@ -0,0 +1,226 @@
+use crate::{
+    payment::{FailureReason, Payment},
+    traversal::pathfinding::{CandidatePath, PathFinder, RouteResult},
+    PaymentParts, Simulation, ID,
+};
+use std::time::Instant;
+
+#[cfg(not(test))]
+use log::{error, trace};
+#[cfg(test)]
+use std::{println as error, println as trace};
+
+/// A path along with the balance changes of sending over it
+type RacedCopy = (CandidatePath, Vec<(ID, String, usize)>);
+
+impl Simulation {
+    /// Sends each part of the payment over up to `redundancy` disjoint paths at once. The copy
+    /// with the lowest timelock resolves first and is the only one that counts. The other copies
+    /// are cancelled and their liquidity is released
+    /// The parts are those of an MPP, see send_mpp_payment, and split further when all of their
+    /// copies fail
+    pub fn send_mpp_redundant(&mut self, payment: &mut Payment, redundancy: usize) -> bool {
+        self.send_mpp(payment, None, redundancy.max(1))
+    }
+
+    /// Sends the shard over up to `redundancy` disjoint paths and keeps the copy that resolves
+    /// first. Returns the balance changes of that copy like send_one_payment_with_path does
+    pub(crate) fn send_redundant_shard(
+        &mut self,
+        shard: &mut Payment,
+        redundancy: usize,
+    ) -> (bool, Vec<(ID, String, usize)>) {
+        shard.failing_channel = None;
+        shard.used_paths.clear();
+        let copies = self.find_disjoint_paths(shard, redundancy);
+        if copies.is_empty() {
+            error!(
+                "No paths found for a part of payment {} worth {} msat.",
+                shard.payment_id, shard.amount_msat
+            );
+            shard.succeeded = false;
+            shard.failure_reason = Some(if self.graph.is_reachable(&shard.source, &shard.dest) {
+                FailureReason::InsufficientLiquidity
+            } else {
+                FailureReason::NoTopology
+            });
+            return (false, vec![]);
+        }
+        match self.race_copies(shard, copies) {
+            Some((candidate_path, transfers)) => {
+                shard.succeeded = true;
+                shard.failure_reason = None;
+                shard.used_paths.push(candidate_path);
+                (true, transfers)
+            }
+            None => {
+                shard.succeeded = false;
+                shard.failure_reason = Some(FailureReason::InsufficientLiquidity);
+                (false, vec![])
+            }
+        }
+    }
+
+    /// Up to n paths for the part that share no channel, in the order they were found
+    fn find_disjoint_paths(&mut self, part: &Payment, n: usize) -> Vec<CandidatePath> {
+        let mut path_finder = self.path_finder(&part.source, &part.dest, part.amount_msat);
+        path_finder.payment_parts = PaymentParts::Single;
+        path_finder.prefilter_edges();
+        path_finder.max_hops = part.max_hops.min(self.max_hops);
+        let pathfinding_start = Instant::now();
+        let mut paths = vec![];
+        while paths.len() < n {
+            match path_finder.find_path() {
+                RouteResult::Found(candidate_path) => {
+                    for hop in candidate_path.path.hops.iter() {
+                        path_finder.graph.remove_channel(&hop.3);
+                    }
+                    paths.push(candidate_path);
+                }
+                _ => break,
+            }
+        }
+        self.timings.pathfinding += pathfinding_start.elapsed().as_nanos();
+        trace!(
+            "Found {} disjoint paths for {} msat from {} to {}.",
+            paths.len(),
+            part.amount_msat,
+            part.source,
+            part.dest
+        );
+        paths
+    }
+
+    /// Sends the part over each of the paths and keeps the copy that resolves first. The other
+    /// copies are reverted. None if no copy arrived, the part then fails at the channel the last
+    /// copy failed at
+    fn race_copies(&mut self, part: &mut Payment, copies: Vec<CandidatePath>) -> Option<RacedCopy> {
+        let mut path_finder = PathFinder::new(
+            part.source.clone(),
+            part.dest.clone(),
+            part.amount_msat,
+            &self.graph,
+            self.routing_metric,
+            PaymentParts::Single,
+        );
+        let balance_start = Instant::now();
+        let mut arrived = vec![];
+        for candidate_path in copies {
+            let mut shard = part.to_shard(part.amount_msat);
+            let (success, transfers) =
+                self.attempt_payment(&mut shard, &candidate_path, &mut path_finder);
+            part.htlc_attempts += shard.htlc_attempts;
+            if success {
+                arrived.push((candidate_path, transfers));
+            } else {
+                part.failed_paths.push((
+                    candidate_path,
+                    FailureReason::InsufficientLiquidity,
+                    shard.failing_channel.clone(),
+                ));
+                part.failed_channels.extend(shard.failing_channel.clone());
+                part.failing_channel = shard.failing_channel;
+                self.revert_payment(&transfers);
+            }
+        }
+        let fastest = arrived
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, (candidate_path, _))| candidate_path.time)
+            .map(|(idx, _)| idx);
+        let winner = fastest.map(|idx| arrived.swap_remove(idx));
+        // the slower copies are cancelled
+        for (_, transfers) in arrived.iter() {
+            self.revert_payment(transfers);
+        }
+        self.timings.balance_updates += balance_start.elapsed().as_nanos();
+        winner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{time::Time, Invoice};
+
+    // bob reaches alice via carol or dave. Dave is more expensive but faster
+    #[test]
+    fn redundant_copies_complete_with_the_fastest_path() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+                if e.channel_id == "dave-alice" {
+                    e.fee_base_msat = 20;
+                    e.fee_proportional_millionths = 0;
+                    e.cltv_expiry_delta = 2;
+                }
+            }
+        }
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let amount_msat = 1000;
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        let liquidity = simulator.graph.total_liquidity();
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, None);
+        assert!(simulator.send_mpp_redundant(&mut payment, 2));
+        assert!(payment.succeeded);
+        assert_eq!(payment.num_parts, 1);
+        assert_eq!(payment.used_paths.len(), 1);
+        assert_eq!(payment.completion_time, Time::from_secs(2.0));
+        assert_eq!(payment.used_paths[0].path.get_involved_nodes()[1], "dave");
+        // the copy via carol has been cancelled
+        assert_eq!(
+            simulator
+                .graph
+                .get_channel_balance(&source, &"bob-carol".to_string()),
+            70000
+        );
+        assert!(
+            simulator
+                .graph
+                .get_channel_balance(&source, &"bob-dave".to_string())
+                < 70000
+        );
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+        // without redundancy the cheaper but slower path is taken
+        let mut payment = Payment::new(1, source.clone(), dest.clone(), amount_msat, None);
+        simulator.add_invoice(Invoice::new(1, amount_msat, &source, &dest));
+        assert!(simulator.send_mpp_redundant(&mut payment, 1));
+        assert_eq!(payment.completion_time, Time::from_secs(5.0));
+    }
+
+    // none of bob's channels can carry the whole amount, so the part is split like any other
+    // MPP shard and the halves race their copies
+    #[test]
+    fn redundant_parts_are_split_when_all_copies_fail() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+                if e.channel_id == "dave-alice" {
+                    e.fee_base_msat = 20;
+                    e.fee_proportional_millionths = 0;
+                }
+            }
+        }
+        for (channel_id, balance) in [("bob-carol", 3500), ("bob-dave", 3500), ("bob-eve", 0)] {
+            simulator
+                .graph
+                .update_channel_balance(&channel_id.to_string(), balance);
+        }
+        simulator.set_payment_parts(PaymentParts::Split).unwrap();
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let amount_msat = 6000;
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        let liquidity = simulator.graph.total_liquidity();
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, None);
+        assert!(simulator.send_mpp_redundant(&mut payment, 2));
+        assert_eq!(payment.num_parts, 2);
+        assert_eq!(payment.shard_amounts, vec![6000, 3000, 3000]);
+        assert_eq!(simulator.graph.total_liquidity(), liquidity);
+    }
+}
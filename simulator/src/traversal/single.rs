@@ -1,11 +1,12 @@
 use crate::{
-    core_types::{event::PaymentEvent, time::Time},
-    payment::Payment,
+    core_types::event::PaymentEvent,
+    payment::{FailureReason, Payment},
     traversal::pathfinding::{CandidatePath, Path, PathFinder},
-    Simulation,
+    Simulation, ID,
 };
 
 use log::{error, trace};
+use std::cmp;
 
 impl Simulation {
     /// Sends a single path payment and fails when payment cannot be delivered
@@ -19,19 +20,72 @@ impl Simulation {
         if max_out_balance < payment.amount_msat {
             error!("Payment failing. Sender has no edge with sufficient balance. Amount {}, max balance {}", payment.amount_msat, max_out_balance);
             failed = true;
+            payment.failure_reason = Some(FailureReason::InsufficientLiquidity);
         }
-        // we are not interested in reversing payments here for single path payments
+        let mut jit_channel = None;
         if !failed {
-            succeeded = self.send_one_payment(payment).0;
+            let receive_capacity = self.graph.mpp_receive_capacity(&payment.dest, 1) as usize;
+            if receive_capacity < payment.amount_msat {
+                // the one part cannot combine the channel with what dest can already receive
+                jit_channel = self
+                    .open_jit_channel(payment, 0)
+                    .map(|(channel_id, _)| channel_id);
+            }
+        }
+        if !failed && payment.exceeds_service_fee_budget() {
+            error!(
+                "Rejecting payment {} as its service fee alone exceeds the max fee.",
+                payment.payment_id
+            );
+            failed = true;
+            payment.failure_reason = Some(FailureReason::FeeBudgetExceeded);
+        }
+        if !failed {
+            let to_revert;
+            (succeeded, to_revert) = self.send_one_payment(payment);
+            // a delivered payment is only reversed if its fees turn out to be too high
+            let fee_failure = if !succeeded {
+                None
+            } else if payment.exceeds_fee_budget() {
+                error!(
+                    "Abandoning payment {} as its fees exceed the max fee.",
+                    payment.payment_id
+                );
+                Some(FailureReason::FeeBudgetExceeded)
+            } else if !self.pay_service_fees(payment) {
+                Some(FailureReason::InsufficientLiquidity)
+            } else {
+                None
+            };
+            if fee_failure.is_some() {
+                self.revert_payment(&to_revert);
+                payment.failure_reason = fee_failure;
+                payment.used_paths.clear();
+                payment.succeeded = false;
+                succeeded = false;
+            }
         }
-        let now = self.event_queue.now() + Time::from_secs(crate::SIM_DELAY_IN_SECS);
+        if let Some(channel_id) = jit_channel.filter(|_| !succeeded) {
+            self.close_jit_channel(payment, &channel_id);
+        }
+        let held_for = self.held_htlc_delay(payment);
+        let now = self.event_queue.now() + self.sim_delay + held_for;
         let event = if succeeded {
+            payment.set_completion_time(self.event_queue.now() + held_for);
+            payment.settle();
+            self.reserve_in_flight(&payment.used_paths);
+            self.record_channel_usage(&payment.used_paths);
             PaymentEvent::UpdateSuccesful {
                 payment: payment.to_owned(),
             }
         } else {
             // used paths is empty for failed payments. failed paths maybe
-            assert!(payment.used_paths.is_empty());
+            if !self.check_invariant(
+                payment.used_paths.is_empty(),
+                "Failed payment has used paths.",
+            ) {
+                payment.used_paths.clear();
+            }
             PaymentEvent::UpdateFailed {
                 payment: payment.to_owned(),
             }
@@ -46,7 +100,9 @@ impl PathFinder {
     /// Search for paths from dest to src
     pub(super) fn find_path_single_payment(&mut self) -> Option<CandidatePath> {
         // shortest path from src to dest including src and dest sorted in ascending cost order
-        let shortest_path = self.shortest_path_from(&self.src);
+        let shortest_path = self
+            .shortest_path_from_tree()
+            .or_else(|| self.shortest_path_from(&self.src));
         match shortest_path {
             None => {
                 trace!("No shortest path between {} and {}.", self.src, self.dest);
@@ -54,7 +110,7 @@ impl PathFinder {
             }
             // - calculate total path cost
             Some(shortest_path) => {
-                if shortest_path.0.len() > crate::MAX_HOPS + 2 {
+                if shortest_path.0.len() > self.max_path_len() {
                     error!(
                         "shortest path is too long. len =  {}!",
                         shortest_path.0.len()
@@ -66,19 +122,90 @@ impl PathFinder {
                         "Creating candidate path from {:?} shortest path.",
                         shortest_path
                     );
-                    let mut path = Path::new(self.src.clone(), self.dest.clone());
-                    // the weights and timelock are set as the total path costs are calculated
-                    path.hops = shortest_path
-                        .0
-                        .into_iter()
-                        .map(|h| (h, usize::default(), usize::default(), String::default()))
-                        .collect();
-                    let mut candidate_path = CandidatePath::new_with_path(path);
-                    self.get_aggregated_path_cost(&mut candidate_path, false);
-                    Some(candidate_path)
+                    let candidate_path = self.candidate_path_from_nodes(shortest_path.0);
+                    if self.accepts(&candidate_path) {
+                        Some(candidate_path)
+                    } else {
+                        trace!("Path filter rejected the shortest path, searching on.");
+                        self.find_accepted_path()
+                    }
+                }
+            }
+        }
+    }
+
+    /// The path over a direct channel between src and dest, if one with enough balance is left.
+    /// Needs no search and charges no routing fees
+    pub(crate) fn find_direct_path(&mut self) -> Option<CandidatePath> {
+        if self
+            .graph
+            .get_all_src_dest_edges(&self.src, &self.dest)
+            .is_empty()
+        {
+            return None;
+        }
+        trace!(
+            "Using direct channel between {} and {}.",
+            self.src,
+            self.dest
+        );
+        Some(self.candidate_path_from_nodes(vec![self.src.clone(), self.dest.clone()]))
+            .filter(|p| self.accepts(p))
+    }
+
+    /// The cheapest of the next shortest paths the path filter accepts. Looks at twice as many
+    /// each round until they run out or reach the limit
+    fn find_accepted_path(&mut self) -> Option<CandidatePath> {
+        let max_path_len = self.max_path_len();
+        let mut k = 2;
+        while k <= crate::MAX_FILTERED_PATHS {
+            let paths = self.k_shortest_paths_from(&self.src, k);
+            let exhausted = paths.len() < k;
+            for (nodes, _) in paths.into_iter().filter(|p| p.0.len() <= max_path_len) {
+                let candidate_path = self.candidate_path_from_nodes(nodes);
+                if self.accepts(&candidate_path) {
+                    return Some(candidate_path);
                 }
             }
+            if exhausted {
+                break;
+            }
+            k *= 2;
         }
+        None
+    }
+
+    /// Returns up to k candidate paths in ascending cost order. Paths exceeding the max number of
+    /// hops or rejected by the path filter are skipped
+    pub(crate) fn find_k_shortest_paths(&mut self, k: usize) -> Vec<CandidatePath> {
+        let max_path_len = self.max_path_len();
+        self.k_shortest_paths_from(&self.src, k)
+            .into_iter()
+            .filter(|p| p.0.len() <= max_path_len)
+            .filter_map(|p| {
+                let candidate_path = self.candidate_path_from_nodes(p.0);
+                self.accepts(&candidate_path).then_some(candidate_path)
+            })
+            .collect()
+    }
+
+    /// Max number of nodes of a path including src and dest. Both the max number of
+    /// intermediaries and the hops the onion has room for limit it
+    fn max_path_len(&self) -> usize {
+        cmp::min(self.max_hops + 2, self.onion_hop_limit + 1)
+    }
+
+    /// Creates a candidate path with its total costs from the list of nodes it goes through
+    pub(super) fn candidate_path_from_nodes(&mut self, nodes: Vec<ID>) -> CandidatePath {
+        let mut path = Path::new(self.src.clone(), self.dest.clone());
+        // the weights and timelock are set as the total path costs are calculated
+        path.hops = nodes
+            .into_iter()
+            .map(|h| (h, usize::default(), usize::default(), String::default()))
+            .collect();
+        let mut candidate_path = CandidatePath::new_with_path(path);
+        self.get_aggregated_path_cost(&mut candidate_path, false);
+        candidate_path
     }
 }
 
@@ -97,18 +224,8 @@ mod tests {
         let mut simulator = crate::attempt::tests::init_sim(None, None);
         let amount_msat = 1000;
         let payment = &mut Payment {
-            payment_id: 0,
-            source: source.clone(),
-            dest: dest.clone(),
-            amount_msat,
             succeeded: true,
-            min_shard_amt: 10,
-            htlc_attempts: 0,
-            num_parts: 1,
-            used_paths: Vec::default(),
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-            failed_paths: vec![],
+            ..Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10))
         };
         simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
         assert!(simulator.send_single_payment(payment));
@@ -121,18 +238,8 @@ mod tests {
         let mut simulator = crate::attempt::tests::init_sim(None, None);
         let amount_msat = 1000;
         let payment = &mut Payment {
-            payment_id: 0,
-            source: source.clone(),
-            dest: dest.clone(),
-            amount_msat,
             succeeded: true,
-            min_shard_amt: 10,
-            htlc_attempts: 0,
-            num_parts: 1,
-            used_paths: Vec::default(),
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-            failed_paths: vec![],
+            ..Payment::new(0, source.clone(), dest.clone(), amount_msat, Some(10))
         };
         simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
         assert!(simulator.send_single_payment(payment));
@@ -149,6 +256,7 @@ mod tests {
             weight: 100.0,
             amount: 1100,
             time: 40,
+            shard_id: None,
         };
         assert_eq!(payment.htlc_attempts, 2);
         assert!(payment.succeeded);
@@ -165,22 +273,212 @@ mod tests {
         let source = "alice".to_string();
         let dest = "chan".to_string();
         let mut simulator = crate::attempt::tests::init_sim(None, None);
-        let mut payment = Payment {
-            payment_id: 0,
-            source,
-            dest,
-            amount_msat: amount,
-            succeeded: false,
-            used_paths: vec![],
-            min_shard_amt: 10,
-            htlc_attempts: 0,
-            num_parts: 1,
-            failed_paths: vec![],
-            failed_amounts: Vec::default(),
-            successful_shards: Vec::default(),
-        };
+        let mut payment = Payment::new(0, source, dest, amount, Some(10));
         assert!(!simulator.send_single_payment(&mut payment));
         assert!(!payment.failed_paths.is_empty());
         assert!(payment.used_paths.is_empty());
     }
+
+    // carol-alice can carry one of the payments but not both. The second payment must avoid it
+    // while the first is still in flight
+    #[test]
+    fn overlapping_payments_respect_in_flight_reservations() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 1000000;
+                e.capacity = 10000000;
+            }
+        }
+        let bottleneck = String::from("carol-alice");
+        simulator.graph.update_channel_balance(&bottleneck, 10000);
+        let amount_msat = 6000;
+        let dest = "alice".to_string();
+        let mut first = Payment::new(0, "bob".to_string(), dest.clone(), amount_msat, None);
+        let mut second = Payment::new(1, "eve".to_string(), dest.clone(), amount_msat, None);
+        simulator.add_invoice(Invoice::new(0, amount_msat, &first.source, &dest));
+        simulator.add_invoice(Invoice::new(1, amount_msat, &second.source, &dest));
+        assert!(simulator.send_single_payment(&mut first));
+        let uses_bottleneck =
+            |p: &Payment| p.used_paths[0].path.hops.iter().any(|h| h.3 == bottleneck);
+        assert!(uses_bottleneck(&first));
+        assert_eq!(simulator.graph.get_in_flight(&bottleneck), amount_msat);
+        assert!(simulator.send_single_payment(&mut second));
+        assert!(!uses_bottleneck(&second));
+        simulator.release_in_flight(&first.used_paths);
+        simulator.release_in_flight(&second.used_paths);
+        assert!(simulator.graph.in_flight.is_empty());
+    }
+
+    #[test]
+    fn direct_channel_is_used_without_fees() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for (channel_id, src, dest) in
+            [("bob-alice", "bob", "alice"), ("alice-bob", "alice", "bob")]
+        {
+            simulator
+                .graph
+                .edges
+                .entry(src.to_string())
+                .or_default()
+                .push(crate::Edge {
+                    channel_id: channel_id.to_string(),
+                    source: src.to_string(),
+                    destination: dest.to_string(),
+                    fee_base_msat: 100,
+                    cltv_expiry_delta: 40,
+                    ..Default::default()
+                });
+        }
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+                e.capacity = 1000000;
+            }
+        }
+        let (source, dest) = ("bob".to_string(), "alice".to_string());
+        let amount_msat = 5000;
+        let mut payment = Payment::new(0, source.clone(), dest.clone(), amount_msat, None);
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        assert!(simulator.send_single_payment(&mut payment));
+        assert_eq!(payment.used_paths.len(), 1);
+        let used_path = &payment.used_paths[0];
+        assert_eq!(used_path.path.get_involved_nodes(), vec!["bob", "alice"]);
+        assert_eq!(used_path.path.hops[0].3, "bob-alice");
+        assert_eq!(used_path.path_fees(), 0);
+        assert_eq!(used_path.amount, amount_msat);
+        // without enough balance the search finds a path via carol
+        simulator
+            .graph
+            .update_channel_balance(&"bob-alice".to_string(), 1000);
+        let mut payment = Payment::new(1, source.clone(), dest.clone(), amount_msat, None);
+        simulator.add_invoice(Invoice::new(1, amount_msat, &source, &dest));
+        assert!(simulator.send_single_payment(&mut payment));
+        assert_eq!(
+            payment.used_paths[0].path.get_involved_nodes(),
+            vec!["bob", "carol", "alice"]
+        );
+    }
+
+    #[test]
+    fn candidate_paths_are_listed_without_mutating_the_graph() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        let graph_before = simulator.graph.clone();
+        let actual = simulator.candidate_paths(&"bob".to_string(), &"alice".to_string(), 1000, 2);
+        let nodes: Vec<Vec<ID>> = actual.iter().map(|p| p.path.get_involved_nodes()).collect();
+        assert_eq!(
+            nodes,
+            vec![
+                vec!["bob", "carol", "alice"],
+                vec!["bob", "eve", "carol", "alice"]
+            ]
+        );
+        assert_eq!(actual[0].weight, 10.0);
+        assert_eq!(actual[1].weight, 30.0);
+        assert_eq!(actual[0].amount, 1010);
+        assert_eq!(actual[1].amount, 1030);
+        assert_eq!(simulator.graph.edges.len(), graph_before.edges.len());
+        for (node, edges) in graph_before.edges.iter() {
+            let balances = |edges: &Vec<crate::Edge>| -> Vec<usize> {
+                edges.iter().map(|e| e.balance).collect()
+            };
+            assert_eq!(balances(&simulator.graph.edges[node]), balances(edges));
+        }
+    }
+
+    // n0 reaches n21 via 20 intermediaries, which is within max_hops but takes 21 hops
+    #[test]
+    fn paths_longer_than_the_onion_are_pruned() {
+        let num_nodes = 22;
+        let edge = |from: usize, to: usize| {
+            format!(
+                r#"{{"scid": "n{from}-n{to}", "source": "n{from}", "destination": "n{to}", "fee_base_msat": 1, "fee_proportional_millionths": 0, "htlc_maximum_msat": 1000000, "cltv_expiry_delta": 1}}"#
+            )
+        };
+        let nodes: Vec<String> = (0..num_nodes)
+            .map(|n| format!(r#"{{"id": "n{n}"}}"#))
+            .collect();
+        let adjacency: Vec<String> = (0..num_nodes)
+            .map(|n| {
+                let mut edges = vec![];
+                if n > 0 {
+                    edges.push(edge(n, n - 1));
+                }
+                if n + 1 < num_nodes {
+                    edges.push(edge(n, n + 1));
+                }
+                format!("[{}]", edges.join(","))
+            })
+            .collect();
+        let json_str = format!(
+            r#"{{"nodes": [{}], "adjacency": [{}]}}"#,
+            nodes.join(","),
+            adjacency.join(",")
+        );
+        let graph = crate::graph::Graph::to_sim_graph(
+            &network_parser::Graph::from_json_str(
+                &json_str,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let mut simulator = Simulation::new(
+            0,
+            graph,
+            1000,
+            crate::RoutingMetric::MinFee,
+            crate::PaymentParts::Single,
+            None,
+            &[],
+        );
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 70000;
+            }
+        }
+        let (src, dest) = ("n0".to_string(), "n21".to_string());
+        assert!(crate::MAX_HOPS + 2 >= num_nodes);
+        assert!(simulator.candidate_paths(&src, &dest, 1000, 1).is_empty());
+        // 20 hops fit
+        let paths = simulator.candidate_paths(&src, &"n20".to_string(), 1000, 1);
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].path.hops.len(), 21);
+        simulator.set_onion_hop_limit(27);
+        assert_eq!(simulator.candidate_paths(&src, &dest, 1000, 1).len(), 1);
+    }
+
+    // a payment the sender cannot afford must not carry used paths, the stale one is forced
+    fn payment_violating_an_invariant(simulator: &mut Simulation) -> Payment {
+        let (source, dest) = ("alice".to_string(), "chan".to_string());
+        let amount_msat = 100_000_000;
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        let mut payment = Payment::new(0, source, dest, amount_msat, None);
+        payment.used_paths = vec![CandidatePath::default()];
+        payment
+    }
+
+    #[test]
+    fn invariant_violation_fails_the_payment_unless_strict() {
+        let mut simulator = crate::attempt::tests::init_sim(None, None);
+        simulator.set_strict(false);
+        let mut payment = payment_violating_an_invariant(&mut simulator);
+        assert!(!simulator.send_single_payment(&mut payment));
+        assert!(payment.used_paths.is_empty());
+        assert!(matches!(
+            simulator.event_queue.next(),
+            Some(PaymentEvent::UpdateFailed { .. })
+        ));
+    }
+
+    #[test]
+    #[should_panic]
+    fn invariant_violation_panics_when_strict() {
+        let mut simulator = crate::attempt::tests::init_sim(None, None);
+        let mut payment = payment_violating_an_invariant(&mut simulator);
+        simulator.send_single_payment(&mut payment);
+    }
 }